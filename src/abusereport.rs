@@ -0,0 +1,341 @@
+//! Abuse reports about attacking IPs, built from the stored `messages`.
+//! A report covers one IP, an ISP, a Country or every IP of a time window and is written as
+//! AbuseIPDB bulk-report CSV, as plain-text emails to the RDAP abuse contacts or as X-ARF JSON.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Local};
+use regex::Regex;
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{alerting, config::ReportConfig, migrations::schema::{allowlist, ban, enrichment, username::extract_username}};
+
+/// AbuseIPDB categories Brute-Force and SSH.
+const ABUSEIPDB_CATEGORIES: &str = "18,22";
+/// AbuseIPDB rejects longer comments.
+const ABUSEIPDB_COMMENT_LEN: usize = 1024;
+/// Log lines quoted per IP in emails and X-ARF, the counts cover all of them.
+pub const MAX_EXCERPTS: usize = 20;
+/// Time windows the Stats report popup cycles through, in hours, None is all time.
+pub const WINDOWS: [Option<i64>; 4] = [None, Some(24), Some(24 * 7), Some(24 * 30)];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ReportScope {
+  /// every IP with lines in the time window
+  All,
+  IP(String),
+  ISP(String),
+  Country(String),
+}
+
+impl std::fmt::Display for ReportScope {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ReportScope::All => write!(f, "all IPs"),
+      ReportScope::IP(ip) => write!(f, "IP {}", ip),
+      ReportScope::ISP(isp) => write!(f, "ISP {}", isp),
+      ReportScope::Country(country) => write!(f, "Country {}", country),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ReportFormat {
+  AbuseIPDB,
+  Email,
+  Xarf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReportRequest {
+  pub scope: ReportScope,
+  /// only lines of the last this many hours
+  pub window_hours: Option<i64>,
+  pub format: ReportFormat,
+}
+
+/// What is known about one attacking IP.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Evidence {
+  pub ip: String,
+  pub isp: String,
+  pub country: String,
+  /// from the RDAP cache, empty if unknown
+  pub abuse_email: String,
+  pub network: String,
+  /// (created_at, line), oldest first
+  pub lines: Vec<(String, String)>,
+  /// (username, tries), most tried first
+  pub users: Vec<(String, usize)>,
+  /// (source port, lines), most used first
+  pub ports: Vec<(String, usize)>,
+}
+
+impl Evidence {
+  pub fn first_seen(&self) -> &str {
+    self.lines.first().map(|line| line.0.as_str()).unwrap_or("")
+  }
+
+  pub fn last_seen(&self) -> &str {
+    self.lines.last().map(|line| line.0.as_str()).unwrap_or("")
+  }
+}
+
+/// `2023-12-01 10:00:00 +01:00`, the timezone stays the one the line was stored with.
+pub fn format_timestamp(timestamp: &str) -> String {
+  DateTime::parse_from_rfc3339(timestamp).map(|ts| ts.format("%Y-%m-%d %H:%M:%S %:z").to_string()).unwrap_or(timestamp.to_string())
+}
+
+fn sorted_counts(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+  let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+  counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+  counts
+}
+
+/// Evidence of an IP with its username and port counts while collecting.
+type Tally = (Evidence, HashMap<String, usize>, HashMap<String, usize>);
+
+/// Collects the evidence per IP, leaving out successful logins, fail2ban's own actions and allowlisted IPs.
+pub fn collect(conn: &Connection, scope: &ReportScope, since: Option<&str>) -> Result<Vec<Evidence>> {
+  let (filter, value) = match scope {
+    ReportScope::All => ("?1 = ''", String::new()),
+    ReportScope::IP(ip) => ("ip = ?1", ip.clone()),
+    ReportScope::ISP(isp) => ("isp = ?1", isp.clone()),
+    ReportScope::Country(country) => ("country = ?1", country.clone()),
+  };
+  let mut stmt = conn.prepare(&format!(
+    "SELECT created_at, text, ip, isp, country FROM messages WHERE {} AND created_at >= ?2 ORDER BY ip, created_at", filter))?;
+  let rows = stmt.query_map((value, since.unwrap_or("")), |row| {
+    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?, row.get::<_, String>(4)?))
+  })?;
+
+  let port = Regex::new(r" port (\d+)").unwrap();
+  let mut by_ip: BTreeMap<String, Tally> = BTreeMap::new();
+  for row in rows {
+    let (created_at, text, ip, isp, country) = row?;
+    let entry = by_ip.entry(ip.clone()).or_insert_with(|| (Evidence { ip, isp, country, ..Evidence::default() }, HashMap::new(), HashMap::new()));
+    // fail2ban may deliver several lines at once
+    for line in text.split("++++").map(str::trim).filter(|line| !line.is_empty()) {
+      if alerting::is_accepted(line) || ban::parse_fail2ban_line(line).is_some() {continue;}
+      if let Some(user) = extract_username(line) {
+        *entry.1.entry(user).or_default() += 1;
+      }
+      if let Some(found) = port.captures(line) {
+        *entry.2.entry(found[1].to_string()).or_default() += 1;
+      }
+      entry.0.lines.push((created_at.clone(), line.to_string()));
+    }
+  }
+
+  let mut evidence: Vec<Evidence> = vec![];
+  for (_, (mut found, users, ports)) in by_ip {
+    if found.lines.is_empty() || allowlist::refuse_reason(conn, &found.ip)?.is_some() {continue;}
+    if let Some(rdap) = enrichment::select_rdap(conn, &found.ip)? {
+      found.abuse_email = rdap.abuse_email;
+      found.network = [rdap.name, rdap.cidr].join(" ").trim().to_string();
+    }
+    found.users = sorted_counts(users);
+    found.ports = sorted_counts(ports);
+    evidence.push(found);
+  }
+  Ok(evidence)
+}
+
+fn csv_field(field: &str) -> String {
+  format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+fn counts(counts: &[(String, usize)], limit: usize) -> String {
+  counts.iter().take(limit).map(|(name, count)| format!("{} ({})", name, count)).collect::<Vec<String>>().join(", ")
+}
+
+/// AbuseIPDB bulk-report CSV, one row per IP.
+pub fn to_abuseipdb_csv(evidence: &[Evidence]) -> String {
+  let mut csv = String::from("IP,Categories,ReportDate,Comment\n");
+  for found in evidence {
+    let mut comment = format!("{} failed SSH login attempts between {} and {}", found.lines.len(), format_timestamp(found.first_seen()), format_timestamp(found.last_seen()));
+    if !found.users.is_empty() {
+      comment.push_str(&format!(", users tried: {}", counts(&found.users, 10)));
+    }
+    let comment: String = comment.chars().take(ABUSEIPDB_COMMENT_LEN).collect();
+    csv.push_str(&format!("{},{},{},{}\n", found.ip, csv_field(ABUSEIPDB_CATEGORIES), found.last_seen(), csv_field(&comment)));
+  }
+  csv
+}
+
+fn excerpt(found: &Evidence) -> Vec<String> {
+  let skipped = found.lines.len().saturating_sub(MAX_EXCERPTS);
+  let mut lines: Vec<String> = found.lines.iter().skip(skipped).map(|(ts, line)| format!("{} {}", format_timestamp(ts), line)).collect();
+  if skipped > 0 {
+    lines.insert(0, format!("({} earlier lines left out)", skipped));
+  }
+  lines
+}
+
+/// One email per abuse contact as (contact, text), IPs without a known contact share one with an empty contact.
+pub fn to_emails(evidence: &[Evidence], reporter: &ReportConfig) -> Vec<(String, String)> {
+  let mut by_contact: BTreeMap<&str, Vec<&Evidence>> = BTreeMap::new();
+  for found in evidence {
+    by_contact.entry(found.abuse_email.as_str()).or_default().push(found);
+  }
+  by_contact.into_iter().map(|(contact, found)| {
+    let to = if contact.is_empty() {"(no abuse contact known, look it up with RDAP)"} else {contact};
+    let ips: Vec<&str> = found.iter().map(|found| found.ip.as_str()).collect();
+    let total: usize = found.iter().map(|found| found.lines.len()).sum();
+    let mut mail = format!("To: {}\nSubject: SSH brute force from {}\n\nHello,\n\n", to, ips.join(", "));
+    mail.push_str(&format!("we registered {} failed SSH login attempts from {} address{} in your network. Please investigate and stop this activity.\n",
+      total, found.len(), if found.len() == 1 {""} else {"es"}));
+    for found in found {
+      mail.push_str(&format!("\n{} ({}, {})\n", found.ip, if found.network.is_empty() {&found.isp} else {&found.network}, found.country));
+      mail.push_str(&format!("  attempts     : {}, from {} to {}\n", found.lines.len(), format_timestamp(found.first_seen()), format_timestamp(found.last_seen())));
+      if !found.users.is_empty() {mail.push_str(&format!("  users tried  : {}\n", counts(&found.users, 10)));}
+      if !found.ports.is_empty() {mail.push_str(&format!("  source ports : {}\n", counts(&found.ports, 10)));}
+      mail.push_str("  log excerpt:\n");
+      for line in excerpt(found) {
+        mail.push_str(&format!("    {}\n", line));
+      }
+    }
+    mail.push_str("\nAll times are given with their UTC offset.\n\nRegards,\n");
+    if !reporter.org.is_empty() {mail.push_str(&format!("{}\n", reporter.org));}
+    if !reporter.email.is_empty() {mail.push_str(&format!("{}\n", reporter.email));}
+    (contact.to_string(), mail)
+  }).collect()
+}
+
+/// One X-ARF LoginAttack report per IP.
+pub fn to_xarf(found: &Evidence, reporter: &ReportConfig) -> serde_json::Value {
+  json!({
+    "Version": "2",
+    "ReporterInfo": {
+      "ReporterOrg": reporter.org,
+      "ReporterOrgEmail": reporter.email,
+      "ReporterContactEmail": reporter.email,
+    },
+    "Disclosure": true,
+    "Report": {
+      "ReportClass": "Activity",
+      "ReportType": "LoginAttack",
+      "Date": DateTime::parse_from_rfc3339(found.first_seen()).map(|ts| ts.to_rfc3339()).unwrap_or_default(),
+      "SourceIp": found.ip,
+      "Service": "ssh",
+      "DestinationPort": 22,
+      "Ongoing": false,
+      "Count": found.lines.len(),
+      "Samples": excerpt(found),
+    },
+  })
+}
+
+/// Writes the report into `dir`, returns the files written.
+pub fn write(dir: &Path, request: &ReportRequest, evidence: &[Evidence], reporter: &ReportConfig) -> std::io::Result<Vec<PathBuf>> {
+  std::fs::create_dir_all(dir)?;
+  let prefix = format!("abuse-{}", Local::now().format("%Y%m%d-%H%M%S"));
+  let safe = |name: &str| name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '@' || c == '-' {c} else {'_'}).collect::<String>();
+  let files: Vec<(String, String)> = match request.format {
+    ReportFormat::AbuseIPDB => vec![(format!("{}.csv", prefix), to_abuseipdb_csv(evidence))],
+    ReportFormat::Email => to_emails(evidence, reporter).into_iter()
+      .map(|(contact, mail)| (format!("{}-{}.txt", prefix, if contact.is_empty() {String::from("unknown")} else {safe(&contact)}), mail)).collect(),
+    ReportFormat::Xarf => evidence.iter()
+      .map(|found| (format!("{}-{}.xarf.json", prefix, safe(&found.ip)), serde_json::to_string_pretty(&to_xarf(found, reporter)).unwrap_or_default())).collect(),
+  };
+  let mut written: Vec<PathBuf> = vec![];
+  for (name, content) in files {
+    let path = dir.join(name);
+    std::fs::write(&path, content)?;
+    written.push(path);
+  }
+  Ok(written)
+}
+
+/// Lower bound for `created_at` of the request's time window.
+pub fn since(request: &ReportRequest) -> Option<String> {
+  request.window_hours.map(|hours| (Local::now() - Duration::hours(hours)).to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+
+  use super::*;
+  use crate::migrations::schema::{allowlist::CREATE_ALLOWLIST_DB_SQL, enrichment::{insert_rdap, Rdap, CREATE_RDAP_DB_SQL}};
+
+  fn setup() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute("CREATE TABLE messages (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL, text TEXT NOT NULL, ip TEXT NOT NULL, country TEXT, isp TEXT)", []).unwrap();
+    conn.execute(CREATE_ALLOWLIST_DB_SQL, []).unwrap();
+    conn.execute(CREATE_RDAP_DB_SQL, []).unwrap();
+    let lines = [
+      ("2023-12-01T10:00:00+01:00", "Invalid user admin from 192.0.2.7 port 50022", "192.0.2.7", "Exampleland", "Examplenet"),
+      ("2023-12-01T10:00:05+01:00", "Failed password for root from 192.0.2.7 port 50023 ssh2++++NOTICE [sshd] Ban 192.0.2.7", "192.0.2.7", "Exampleland", "Examplenet"),
+      ("2023-12-01T10:01:00+01:00", "Accepted publickey for deploy from 192.0.2.7 port 50100 ssh2", "192.0.2.7", "Exampleland", "Examplenet"),
+      ("2023-12-02T08:00:00+01:00", "Invalid user oracle from 198.51.100.3 port 40000", "198.51.100.3", "Otherland", "Othernet"),
+    ];
+    for (created_at, text, ip, country, isp) in lines {
+      conn.execute("INSERT INTO messages (created_at, text, ip, country, isp) VALUES (?1, ?2, ?3, ?4, ?5)", (created_at, text, ip, country, isp)).unwrap();
+    }
+    insert_rdap(&conn, &Rdap { ip: String::from("192.0.2.7"), cidr: String::from("192.0.2.0/24"), name: String::from("TEST-NET-1"), abuse_email: String::from("abuse@example.net"), ..Rdap::default() }).unwrap();
+    conn
+  }
+
+  #[test]
+  fn test_collect() {
+    let conn = setup();
+    let evidence = collect(&conn, &ReportScope::IP(String::from("192.0.2.7")), None).unwrap();
+    assert_eq!(evidence.len(), 1);
+    let found = &evidence[0];
+    // the login and fail2ban's ban are no evidence
+    assert_eq!(found.lines.len(), 2);
+    assert_eq!(found.users, vec![(String::from("admin"), 1), (String::from("root"), 1)]);
+    assert_eq!(found.ports, vec![(String::from("50022"), 1), (String::from("50023"), 1)]);
+    assert_eq!(found.abuse_email, "abuse@example.net");
+    assert_eq!(found.network, "TEST-NET-1 192.0.2.0/24");
+
+    assert_eq!(collect(&conn, &ReportScope::Country(String::from("Otherland")), None).unwrap()[0].ip, "198.51.100.3");
+    assert_eq!(collect(&conn, &ReportScope::ISP(String::from("Examplenet")), None).unwrap().len(), 1);
+    assert_eq!(collect(&conn, &ReportScope::All, None).unwrap().len(), 2);
+    assert_eq!(collect(&conn, &ReportScope::All, Some("2023-12-02T00:00:00+01:00")).unwrap().len(), 1);
+
+    allowlist::insert_allowlist_entry(&conn, "192.0.2.0/24", "2023-12-01T00:00:00+01:00").unwrap();
+    assert_eq!(collect(&conn, &ReportScope::All, None).unwrap().len(), 1);
+  }
+
+  #[test]
+  fn test_formats() {
+    let conn = setup();
+    let evidence = collect(&conn, &ReportScope::All, None).unwrap();
+
+    let csv = to_abuseipdb_csv(&evidence);
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows[0], "IP,Categories,ReportDate,Comment");
+    assert_eq!(rows[1], "192.0.2.7,\"18,22\",2023-12-01T10:00:05+01:00,\"2 failed SSH login attempts between 2023-12-01 10:00:00 +01:00 and 2023-12-01 10:00:05 +01:00, users tried: admin (1), root (1)\"");
+
+    let reporter = ReportConfig { org: String::from("Example Ops"), email: String::from("ops@example.org") };
+    let emails = to_emails(&evidence, &reporter);
+    assert_eq!(emails.len(), 2);
+    // unknown contacts sort first
+    assert_eq!(emails[0].0, "");
+    let (contact, mail) = &emails[1];
+    assert_eq!(contact, "abuse@example.net");
+    assert!(mail.starts_with("To: abuse@example.net\nSubject: SSH brute force from 192.0.2.7\n"));
+    assert!(mail.contains("    2023-12-01 10:00:00 +01:00 Invalid user admin from 192.0.2.7 port 50022\n"));
+    assert!(mail.contains("  source ports : 50022 (1), 50023 (1)\n"));
+    assert!(mail.ends_with("Example Ops\nops@example.org\n"));
+
+    let xarf = to_xarf(&evidence[0], &reporter);
+    assert_eq!(xarf["Report"]["ReportType"], "LoginAttack");
+    assert_eq!(xarf["Report"]["SourceIp"], "192.0.2.7");
+    assert_eq!(xarf["Report"]["Count"], 2);
+    assert_eq!(xarf["Report"]["Date"], "2023-12-01T10:00:00+01:00");
+    assert_eq!(xarf["ReporterInfo"]["ReporterOrg"], "Example Ops");
+
+    let dir = std::env::temp_dir().join(format!("succeed2ban-reports-{}", std::process::id()));
+    let request = ReportRequest { scope: ReportScope::All, window_hours: None, format: ReportFormat::Xarf };
+    let written = write(&dir, &request, &evidence, &reporter).unwrap();
+    assert_eq!(written.len(), 2);
+    assert!(written[0].to_string_lossy().ends_with("-192.0.2.7.xarf.json"));
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -1,6 +1,6 @@
 use std::fmt;
 
-use crate::{migrations::schema::{ip::IP, city::City, region::Region, isp::ISP, country::Country, message::MiniMessage}, themes::Themes};
+use crate::{migrations::schema::{ip::IP, city::City, region::Region, isp::ISP, country::Country, message::MiniMessage, username::Username, host::Host, provider::Provider, alert::Alert, allowlist::AllowlistEntry, ban::{ActiveBan, BanRequest}, audit::AuditEntry}, themes::Themes, query::QueryResult, ipdetail::IPDetail, impact::BlockPreview, notifications::NotifyEvent, syslog::SyslogMessage, enrichment::Enrichment, abusereport::ReportRequest, geofetcher::{ParkedLine, RefreshOutcome}, eventstream::StreamEvent, threatintel::ThreatIntel};
 use rusqlite::{Connection, Result};
 
 
@@ -80,23 +80,46 @@ pub enum Action {
   SubmitQuery(String),
   InvalidQuery,
   QueryNotFound(String),
+  GotQueryResult(QueryResult),
 
   // Core
   IONotify(String),
+  /// line received by the syslog listener
+  SyslogLine(SyslogMessage),
   //FetchGeo(gen_structs::Geodata),
 
-  // second string is the line, bool is if it came from IO or DB
-  GotGeo(IP, String, bool),
+  // second string is the line, bool is if it came from IO or DB, last string is the host the line was logged on
+  GotGeo(IP, String, bool, String),
   //
   /// 0: IP, 1: Line, 2: true if from DB, false if fresh
   PassGeo(IP, String, bool),
+  /// answer of the geolocation scheduler for an IP
+  GotGeoLookup(String, Result<IP, String>),
+  /// 0: IPs waiting for their location, 1: lines parked until then
+  GeoQueueDepth(usize, usize),
+  /// the IP of the line is not in the DB, the line waits for its location
+  GeoUnknown(String, ParkedLine),
+  /// a line was stored, with its event for the event stream and the forwarders
+  LineStored(StreamEvent),
+  /// known IPs whose location is older than the TTL
+  GotStaleIPs(Vec<String>),
+  /// a stale IP was looked up again and stored
+  GeoRefreshed(String, RefreshOutcome),
+  /// bans that ran out, true if the IP showed up in the logs while banned
+  GotExpiredBans(Vec<(ActiveBan, bool)>),
+  /// an active ban fail2ban lifted before it expired, it is issued again
+  RestoreBan(ActiveBan),
+  /// output of `fail2ban-client status` for the jail
+  GotJailStatus(String),
+  /// the DB is set up, with lines for the startup screen
+  StorageReady(Vec<String>),
 
   InternalLog(String),
   // Ban Actions
   EnterBan,
   ExitBan,
   RequestBan,
-  BanIP(IP),
+  BanIP(IP, BanRequest),
   Banned(bool),
 
   EnterUnban,
@@ -106,6 +129,40 @@ pub enum Action {
   Unbanned(bool),
 
   Block(IP),
+
+  // Allowlist, IPs and CIDRs that are never banned
+  EnterAllowlist,
+  ExitAllowlist,
+  RequestAllowlist,
+  GotAllowlist(Vec<AllowlistEntry>),
+  AddAllowlistEntry(String),
+  RemoveAllowlistEntry(String),
+
+  // Ban list, currently banned IPs with their expiry
+  EnterBanList,
+  ExitBanList,
+  RequestBanList,
+  GotBanList(Vec<ActiveBan>),
+  /// prolongs the ban of this IP by the configured bantime
+  ExtendBan(String),
+
+  // Audit log of operator actions
+  EnterAuditLog,
+  ExitAuditLog,
+  RequestAuditLog,
+  GotAuditLog(Vec<AuditEntry>),
+
+  // Alerts on successful logins
+  GotAlert(Alert),
+  /// acknowledges all alerts up to and including this id
+  AcknowledgeAlerts(usize),
+
+  // Notification sinks
+  Notify(NotifyEvent),
+
+  // IP detail view
+  RequestIPDetail(String),
+  GotIPDetail(IPDetail),
   //
 
   StartF2BWatcher,
@@ -127,26 +184,54 @@ pub enum Action {
   // Stats
   StatsShow,
   StatsHide,
+  /// 0: Country, Region, City or ISP, 1: name
+  StatsRequestBlockPreview(String, String),
+  StatsGotBlockPreview(BlockPreview),
+  /// rebuilds the Country/Region/City/ISP counters from messages and the ban history
+  RecomputeStatistics,
+  RecomputedStatistics,
+  /// downloads the blocklists that have a url, then reloads all of them
+  RefreshBlocklists,
+  /// 0: the reloaded lists, 1: problems reading them
+  BlocklistsRefreshed(ThreatIntel, Vec<String>),
+  /// 0: IP, 1: names of the blocklists it is on
+  GotTags(String, Vec<String>),
+  /// 0: IP, 1: its reverse DNS and RDAP data
+  GotEnrichment(String, Enrichment),
+  /// writes an abuse report into the data dir
+  GenerateReport(ReportRequest),
+  /// result line shown in the report popup
+  ReportGenerated(String),
 
   StatsGetCountries,
   StatsGetISPs,
   StatsGetRegions,
   StatsGetCities,
+  StatsGetUsernames,
+  StatsGetHosts,
+  StatsGetProviders,
 
   StatsGotCountry(Country, Vec<MiniMessage>),
   StatsGotISP(ISP, Vec<MiniMessage>),
   StatsGotRegion(Region, Vec<MiniMessage>),
   StatsGotCity(City, Vec<MiniMessage>),
+  StatsGotUsername(Username, Vec<MiniMessage>),
+  StatsGotHost(Host, Vec<MiniMessage>),
+  StatsGotProvider(Provider, Vec<MiniMessage>),
 
   StatsBlockCountry(Country),
   StatsBlockRegion(Region),
   StatsBlockCity(City),
   StatsBlockISP(ISP),
+  StatsBlockUsername(Username),
+  StatsBlockProvider(Provider),
 
   StatsUnblockCountry(Country),
   StatsUnblockRegion(Region),
   StatsUnblockCity(City),
   StatsUnblockISP(ISP),
+  StatsUnblockUsername(Username),
+  StatsUnblockProvider(Provider),
 
   StatsGetIP(String),
   StatsGotIP(IP),
@@ -182,6 +267,7 @@ impl<'de> Deserialize<'de> for Action {
           "EnterNormal" => Ok(Action::EnterNormal),
           "EnterTakeAction" => Ok(Action::EnterTakeAction),
           "StartupDone" => Ok(Action::StartupDone),
+          "RefreshBlocklists" => Ok(Action::RefreshBlocklists),
           // Error
           data if data.starts_with("Error(") => {
             let error_msg = data.trim_start_matches("Error(").trim_end_matches(")");
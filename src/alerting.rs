@@ -0,0 +1,96 @@
+//! Classifies successful logins.
+//! An `Accepted` line raises an alert if the IP failed before or the login comes from a country no one logged in from yet.
+
+use rusqlite::{Connection, Result};
+
+use crate::{cidr, migrations::schema::ban};
+
+/// Why a successful login is suspicious.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AlertReason {
+  /// number of failed attempts stored for this IP
+  PriorFailures(usize),
+  /// no earlier successful login came from this country
+  NewCountry(String),
+}
+
+impl std::fmt::Display for AlertReason {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      AlertReason::PriorFailures(num) => write!(f, "{} prior failures", num),
+      AlertReason::NewCountry(country) => write!(f, "first login from {}", country),
+    }
+  }
+}
+
+/// Returns true for sshd lines reporting a successful login.
+pub fn is_accepted(line: &str) -> bool {
+  line.contains("Accepted ")
+}
+
+/// Returns true for sshd and PAM lines reporting a failed login and for fail2ban finding or banning an IP.
+/// Session and disconnect lines of successful logins are no failures.
+pub fn is_failure(line: &str) -> bool {
+  if line.contains("Failed password") || line.contains("Invalid user") || line.contains("authentication failure") || line.contains("] Found ") {
+    return true;
+  }
+  matches!(ban::parse_fail2ban_line(line), Some((_, ban::Fail2banEvent::Ban, _)))
+}
+
+/// Returns true if the IP matches an entry of the known-good list, entries are IPs or CIDRs.
+pub fn is_known_good(ip: &str, known_good: &[String]) -> bool {
+  known_good.iter().any(|entry| cidr::ip_in_cidr(ip, entry))
+}
+
+/// Compares a successful login against the stored history, has to run before the line itself is stored.
+pub fn classify_accepted(conn: &Connection, ip: &str, country: &str) -> Result<Vec<AlertReason>> {
+  let mut reasons: Vec<AlertReason> = vec![];
+
+  let mut stmt = conn.prepare("SELECT text FROM messages WHERE ip = ?1")?;
+  let texts = stmt.query_map([ip], |row| row.get::<_, String>(0))?.collect::<Result<Vec<String>>>()?;
+  // fail2ban may deliver several lines at once
+  let failures = texts.iter().flat_map(|text| text.split("++++")).filter(|line| is_failure(line)).count();
+  if failures > 0 {
+    reasons.push(AlertReason::PriorFailures(failures));
+  }
+
+  let accepted_from_country: usize = conn.query_row(
+    "SELECT COUNT(*) FROM messages WHERE country = ?1 AND text LIKE '%Accepted %'",
+    [country],
+    |row| row.get(0),
+  )?;
+  if accepted_from_country == 0 {
+    reasons.push(AlertReason::NewCountry(country.to_string()));
+  }
+
+  Ok(reasons)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_is_known_good() {
+    let known_good = vec![String::from("10.0.0.0/8"), String::from("192.168.1.7")];
+    assert!(is_known_good("10.1.2.3", &known_good));
+    assert!(is_known_good("192.168.1.7", &known_good));
+    assert!(!is_known_good("192.168.1.8", &known_good));
+    assert!(is_accepted("Accepted publickey for deploy from 10.1.2.3 port 22 ssh2"));
+    assert!(!is_accepted("Failed password for root from 10.1.2.3 port 22 ssh2"));
+  }
+
+  #[test]
+  fn test_is_failure() {
+    assert!(is_failure("Failed password for root from 10.1.2.3 port 22 ssh2"));
+    assert!(is_failure("Invalid user admin from 10.1.2.3 port 22"));
+    assert!(is_failure("pam_unix(sshd:auth): authentication failure; logname= uid=0 euid=0 tty=ssh ruser= rhost=10.1.2.3"));
+    assert!(is_failure("fail2ban.filter [812]: INFO [sshd] Found 10.1.2.3"));
+    assert!(is_failure("fail2ban.actions [812]: NOTICE [sshd] Ban 10.1.2.3"));
+    assert!(!is_failure("fail2ban.actions [812]: NOTICE [sshd] Unban 10.1.2.3"));
+    assert!(!is_failure("Accepted publickey for deploy from 10.1.2.3 port 22 ssh2"));
+    assert!(!is_failure("pam_unix(sshd:session): session opened for user deploy(uid=1000) by (uid=0)"));
+    assert!(!is_failure("Received disconnect from 10.1.2.3 port 22:11: disconnected by user"));
+    assert!(!is_failure("Disconnected from user deploy 10.1.2.3 port 22"));
+  }
+}
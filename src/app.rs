@@ -25,6 +25,8 @@ use crate::{
   geofetcher,
   gen_structs,
   migrations::schema::ip::IP,
+  notifications::{EventKind, NotifyEvent},
+  metrics::METRICS,
 };
 
 use regex::Regex;
@@ -85,8 +87,7 @@ impl App {
 
     let mut jctl_sender: Option<mpsc::UnboundedSender<bool>>= Option::None;
 
-    let mut tui = tui::Tui::new()?.tick_rate(self.tick_rate).frame_rate(self.frame_rate);
-    // tui.mouse(true);
+    let mut tui = tui::Tui::new()?.tick_rate(self.tick_rate).frame_rate(self.frame_rate).mouse(true);
     tui.enter()?;
 
     for component in self.components.iter_mut() {
@@ -101,6 +102,26 @@ impl App {
       component.init(tui.size()?)?;
     }
 
+    if let Some(listen) = self.config.syslog.listen.clone() {
+      let syslog_tx = action_tx.clone();
+      let programs = self.config.syslog.programs.clone();
+      let (senders, errors) = crate::syslog::parse_senders(&self.config.syslog.allowed_senders);
+      for error in errors {
+        action_tx.send(Action::InternalLog(format!(" ❌ {}", error)))?;
+      }
+      if senders.is_empty() {
+        action_tx.send(Action::InternalLog(String::from(" ❌ syslog.allowed_senders is empty, every message is dropped")))?;
+      }
+      tokio::spawn(async move {
+        if let Err(e) = tasks::listen_syslog(listen.clone(), programs, senders, syslog_tx.clone()).await {
+          let failure = format!("syslog receiver on {} failed: {}", listen, e);
+          syslog_tx.send(Action::InternalLog(format!(" ❌ {}", failure))).unwrap_or_default();
+          syslog_tx.send(Action::Notify(NotifyEvent::now(EventKind::Watcher, "", failure))).unwrap_or_default();
+        }
+      });
+      action_tx.send(Action::InternalLog(String::from(" ✔ STARTED syslog receiver")))?;
+    }
+
 
 
     loop {
@@ -137,7 +158,9 @@ impl App {
         }
       }
 
+      let mut drained: u64 = 0;
       while let Ok(action) = action_rx.try_recv() {
+        drained += 1;
         if action != Action::Tick && action != Action::Render {
           log::debug!("{action:?}");
         }
@@ -197,7 +220,12 @@ impl App {
               // set up watcher
               let (_tx, _rx) = std::sync::mpsc::channel();
               let mut watcher: notify::INotifyWatcher = notify::RecommendedWatcher::new(_tx, notify::Config::default())?;
-              watcher.watch(path.as_ref(), notify::RecursiveMode::NonRecursive)?;
+              if let Err(e) = watcher.watch(path.as_ref(), notify::RecursiveMode::NonRecursive) {
+                let failure = format!("fail2ban watcher failed on {}: {}", path, e);
+                action_tx.send(Action::InternalLog(format!(" ❌ {}", failure)))?;
+                action_tx.send(Action::Notify(NotifyEvent::now(EventKind::Watcher, "", failure)))?;
+                continue;
+              }
 
 
               let filewatcher = tokio::spawn(async move  {
@@ -319,11 +347,11 @@ impl App {
           };
         }
       }
+      METRICS.actions_drained.store(drained, std::sync::atomic::Ordering::Relaxed);
       if self.should_suspend {
         tui.suspend()?;
         action_tx.send(Action::Resume)?;
-        tui = tui::Tui::new()?.tick_rate(self.tick_rate).frame_rate(self.frame_rate);
-        // tui.mouse(true);
+        tui = tui::Tui::new()?.tick_rate(self.tick_rate).frame_rate(self.frame_rate).mouse(true);
         tui.enter()?;
       } else if self.should_quit {
         tui.stop()?;
@@ -0,0 +1,143 @@
+//! IPv4 network helpers.
+//! Used by the query language and everything else that has to match an IP against a network.
+
+use std::fmt;
+
+/// Converts a dotted IPv4 string into its numeric representation.
+pub fn ip_to_u32(ip: &str) -> Option<u32> {
+  let octets: Vec<&str> = ip.trim().split('.').collect();
+  if octets.len() != 4 {
+    return None;
+  }
+  let mut num: u32 = 0;
+  for octet in octets {
+    let val = octet.parse::<u8>().ok()?;
+    num = (num << 8) | u32::from(val);
+  }
+  Some(num)
+}
+
+/// Converts a numeric IPv4 back into its dotted representation.
+pub fn u32_to_ip(num: u32) -> String {
+  format!("{}.{}.{}.{}", num >> 24, (num >> 16) & 0xff, (num >> 8) & 0xff, num & 0xff)
+}
+
+/// An IPv4 network in CIDR notation, a plain IP is treated as /32.
+#[derive(Default, Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Cidr {
+  pub network: u32,
+  pub prefix: u8,
+}
+
+impl Cidr {
+  pub fn parse(raw: &str) -> Option<Cidr> {
+    let raw = raw.trim();
+    let (ip, prefix) = match raw.split_once('/') {
+      Some((ip, prefix)) => (ip, prefix.parse::<u8>().ok()?),
+      None => (raw, 32),
+    };
+    if prefix > 32 {
+      return None;
+    }
+    let ip = ip_to_u32(ip)?;
+    Some(Cidr { network: ip & Cidr::mask(prefix), prefix })
+  }
+
+  pub fn mask(prefix: u8) -> u32 {
+    if prefix == 0 {
+      0
+    } else {
+      u32::MAX << (32 - u32::from(prefix))
+    }
+  }
+
+  pub fn contains_u32(&self, ip: u32) -> bool {
+    ip & Cidr::mask(self.prefix) == self.network
+  }
+
+  pub fn contains(&self, ip: &str) -> bool {
+    match ip_to_u32(ip) {
+      Some(ip) => self.contains_u32(ip),
+      None => false,
+    }
+  }
+
+  /// True if both networks share at least one address.
+  pub fn overlaps(&self, other: &Cidr) -> bool {
+    let mask = Cidr::mask(self.prefix.min(other.prefix));
+    self.network & mask == other.network & mask
+  }
+
+  /// Highest address of this network.
+  pub fn last(&self) -> u32 {
+    self.network | !Cidr::mask(self.prefix)
+  }
+
+  /// Number of addresses covered by this network.
+  pub fn size(&self) -> u64 {
+    1u64 << (32 - u32::from(self.prefix))
+  }
+}
+
+impl fmt::Display for Cidr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", u32_to_ip(self.network), self.prefix)
+  }
+}
+
+/// Checks if `ip` is inside `cidr`, returns false if either can not be parsed.
+pub fn ip_in_cidr(ip: &str, cidr: &str) -> bool {
+  match Cidr::parse(cidr) {
+    Some(cidr) => cidr.contains(ip),
+    None => false,
+  }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct TrieNode<T> {
+  /// index of the child for bit 0 and 1, 0 if there is none since the root is never a child
+  children: [u32; 2],
+  /// value of the network ending at this node
+  value: Option<T>,
+}
+
+/// Binary trie over IPv4 networks, a lookup walks at most 32 nodes no matter how many networks are stored.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PrefixTrie<T> {
+  nodes: Vec<TrieNode<T>>,
+}
+
+impl<T> Default for PrefixTrie<T> {
+  fn default() -> Self {
+    Self { nodes: vec![TrieNode { children: [0, 0], value: None }] }
+  }
+}
+
+impl<T> PrefixTrie<T> {
+  /// Value of the network, inserted with `default` if there is none yet.
+  pub fn entry(&mut self, network: Cidr, default: impl FnOnce() -> T) -> &mut T {
+    let mut idx = 0;
+    for depth in 0..network.prefix {
+      let bit = ((network.network >> (31 - depth)) & 1) as usize;
+      if self.nodes[idx].children[bit] == 0 {
+        self.nodes.push(TrieNode { children: [0, 0], value: None });
+        self.nodes[idx].children[bit] = (self.nodes.len() - 1) as u32;
+      }
+      idx = self.nodes[idx].children[bit] as usize;
+    }
+    self.nodes[idx].value.get_or_insert_with(default)
+  }
+
+  /// Values of all networks containing the IP, shortest prefix first.
+  pub fn matches(&self, ip: u32) -> Vec<&T> {
+    let mut idx = 0;
+    let mut values: Vec<&T> = self.nodes[0].value.iter().collect();
+    for depth in 0..32 {
+      let bit = ((ip >> (31 - depth)) & 1) as usize;
+      idx = self.nodes[idx].children[bit] as usize;
+      if idx == 0 {break;}
+      values.extend(self.nodes[idx].value.iter());
+    }
+    values
+  }
+}
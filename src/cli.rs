@@ -18,4 +18,19 @@ pub struct Cli {
     default_value_t = 4.0
   )]
   pub frame_rate: f64,
+
+  #[arg(long, help = "Replay the stored history through the configured rules and print what would have fired")]
+  pub test_rules: bool,
+
+  #[arg(long, help = "Rebuild the Country/Region/City/ISP counters from the stored lines and ban history, then exit")]
+  pub recompute_statistics: bool,
+
+  #[arg(long, help = "Download the blocklists that have a url to their path, then exit")]
+  pub refresh_blocklists: bool,
+
+  #[arg(long, value_name = "FILE", help = "Export the operator audit log as CSV, - for stdout, then exit")]
+  pub export_audit: Option<PathBuf>,
+
+  #[arg(long, value_name = "FILE", help = "Write every stored event as NDJSON to the file, - for stdout while the TUI draws on stderr")]
+  pub events_out: Option<PathBuf>,
 }
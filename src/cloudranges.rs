@@ -0,0 +1,228 @@
+//! Published IP ranges of cloud and hosting providers, configured with `cloud_ranges` in config.json5.
+//! The files are downloaded by the user (AWS ip-ranges.json, GCP cloud.json, Azure service tags,
+//! Oracle public_ip_ranges.json, DigitalOcean google.csv), incoming IPs are tagged with provider, region and service.
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::cidr::{self, Cidr, PrefixTrie};
+use crate::migrations::schema::provider::CloudRange;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RangeFormat {
+  /// `ip-ranges.json`
+  Aws,
+  /// `cloud.json`
+  Gcp,
+  /// `ServiceTags_Public_*.json`
+  Azure,
+  /// `public_ip_ranges.json`
+  Oracle,
+  /// the `google.csv` geofeed
+  DigitalOcean,
+}
+
+impl RangeFormat {
+  /// Provider name shown in Stats.
+  pub fn provider(&self) -> &'static str {
+    match self {
+      RangeFormat::Aws => "AWS",
+      RangeFormat::Gcp => "GCP",
+      RangeFormat::Azure => "Azure",
+      RangeFormat::Oracle => "Oracle",
+      RangeFormat::DigitalOcean => "DigitalOcean",
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct RangeFile {
+  pub format: RangeFormat,
+  pub path: PathBuf,
+}
+
+fn text(value: &Value) -> String {
+  value.as_str().unwrap_or("").to_string()
+}
+
+fn range(format: RangeFormat, region: String, service: String) -> CloudRange {
+  CloudRange { provider: format.provider().to_string(), region, service }
+}
+
+/// Parses one range file, IPv6 networks are skipped.
+pub fn parse(format: RangeFormat, content: &str) -> Result<Vec<(Cidr, CloudRange)>, String> {
+  if format == RangeFormat::DigitalOcean {
+    // prefix,country,subdivision,city,postal
+    return Ok(content.lines().filter_map(|line| {
+      let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+      let network = Cidr::parse(fields.first()?)?;
+      let region = fields.iter().skip(1).take(3).rev().find(|field| !field.is_empty()).unwrap_or(&"");
+      Some((network, range(format, region.to_string(), String::new())))
+    }).collect());
+  }
+
+  let json: Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+  let mut ranges: Vec<(Cidr, CloudRange)> = vec![];
+  let empty = vec![];
+  match format {
+    RangeFormat::Aws => {
+      for prefix in json["prefixes"].as_array().unwrap_or(&empty) {
+        let Some(network) = prefix["ip_prefix"].as_str().and_then(Cidr::parse) else {continue};
+        ranges.push((network, range(format, text(&prefix["region"]), text(&prefix["service"]))));
+      }
+    },
+    RangeFormat::Gcp => {
+      for prefix in json["prefixes"].as_array().unwrap_or(&empty) {
+        let Some(network) = prefix["ipv4Prefix"].as_str().and_then(Cidr::parse) else {continue};
+        ranges.push((network, range(format, text(&prefix["scope"]), text(&prefix["service"]))));
+      }
+    },
+    RangeFormat::Azure => {
+      for tag in json["values"].as_array().unwrap_or(&empty) {
+        let properties = &tag["properties"];
+        let mut service = text(&properties["systemService"]);
+        if service.is_empty() {
+          service = text(&tag["name"]).split('.').next().unwrap_or("").to_string();
+        }
+        for prefix in properties["addressPrefixes"].as_array().unwrap_or(&empty) {
+          let Some(network) = prefix.as_str().and_then(Cidr::parse) else {continue};
+          ranges.push((network, range(format, text(&properties["region"]), service.clone())));
+        }
+      }
+    },
+    RangeFormat::Oracle => {
+      for region in json["regions"].as_array().unwrap_or(&empty) {
+        for prefix in region["cidrs"].as_array().unwrap_or(&empty) {
+          let Some(network) = prefix["cidr"].as_str().and_then(Cidr::parse) else {continue};
+          let tags: Vec<String> = prefix["tags"].as_array().unwrap_or(&empty).iter().map(text).collect();
+          ranges.push((network, range(format, text(&region["region"]), tags.join(","))));
+        }
+      }
+    },
+    RangeFormat::DigitalOcean => unreachable!(),
+  }
+  Ok(ranges)
+}
+
+/// Catch-all tags like `AMAZON` or `AzureCloud` that also cover the specific services.
+fn is_generic(range: &CloudRange) -> bool {
+  range.service.is_empty() || range.service == "AMAZON" || range.service.starts_with("AzureCloud")
+}
+
+/// All loaded provider ranges.
+#[derive(Clone, Default)]
+pub struct CloudRanges {
+  ranges: Vec<CloudRange>,
+  /// index into ranges per network
+  trie: PrefixTrie<usize>,
+}
+
+impl CloudRanges {
+  /// Reads every range file, returns one message per problem.
+  pub fn load(files: &[RangeFile]) -> (Self, Vec<String>) {
+    let mut errors: Vec<String> = vec![];
+    let mut clouds = CloudRanges::default();
+    for file in files.iter() {
+      let parsed = std::fs::read_to_string(&file.path).map_err(|e| e.to_string()).and_then(|content| parse(file.format, &content));
+      match parsed {
+        Ok(ranges) => {
+          for (network, range) in ranges {
+            clouds.insert(network, range);
+          }
+        },
+        Err(e) => errors.push(format!("Cloud ranges {}: {}: {}", file.format.provider(), file.path.display(), e)),
+      }
+    }
+    (clouds, errors)
+  }
+
+  fn insert(&mut self, network: Cidr, range: CloudRange) {
+    let next = self.ranges.len();
+    let idx = *self.trie.entry(network, || next);
+    if idx == next {
+      self.ranges.push(range);
+    } else if is_generic(&self.ranges[idx]) && !is_generic(&range) {
+      // the same network is listed for the catch-all and the specific service
+      self.ranges[idx] = range;
+    }
+  }
+
+  /// Most specific range containing the IP.
+  pub fn lookup(&self, ip: &str) -> Option<&CloudRange> {
+    let ip = cidr::ip_to_u32(ip)?;
+    self.trie.matches(ip).last().map(|idx| &self.ranges[**idx])
+  }
+
+  pub fn len(&self) -> usize {
+    self.ranges.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.ranges.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+
+  use super::*;
+
+  fn cloud(provider: &str, region: &str, service: &str) -> CloudRange {
+    CloudRange { provider: provider.to_string(), region: region.to_string(), service: service.to_string() }
+  }
+
+  #[test]
+  fn test_parse() {
+    let aws = r#"{"syncToken":"1","prefixes":[{"ip_prefix":"3.5.140.0/22","region":"ap-northeast-2","service":"AMAZON","network_border_group":"ap-northeast-2"}],"ipv6_prefixes":[{"ipv6_prefix":"2600:1f14::/35","region":"us-west-2","service":"EC2"}]}"#;
+    assert_eq!(parse(RangeFormat::Aws, aws).unwrap(), vec![(Cidr::parse("3.5.140.0/22").unwrap(), cloud("AWS", "ap-northeast-2", "AMAZON"))]);
+
+    let gcp = r#"{"prefixes":[{"ipv4Prefix":"34.80.0.0/15","service":"Google Cloud","scope":"asia-east1"},{"ipv6Prefix":"2600:1900:4000::/44","service":"Google Cloud","scope":"us-central1"}]}"#;
+    assert_eq!(parse(RangeFormat::Gcp, gcp).unwrap(), vec![(Cidr::parse("34.80.0.0/15").unwrap(), cloud("GCP", "asia-east1", "Google Cloud"))]);
+
+    let azure = r#"{"values":[{"name":"AzureCloud.westeurope","properties":{"region":"westeurope","systemService":"","addressPrefixes":["13.69.0.0/17","2603:1020::/47"]}},{"name":"Storage.WestEurope","properties":{"region":"westeurope","systemService":"AzureStorage","addressPrefixes":["13.69.40.0/22"]}}]}"#;
+    assert_eq!(parse(RangeFormat::Azure, azure).unwrap(), vec![
+      (Cidr::parse("13.69.0.0/17").unwrap(), cloud("Azure", "westeurope", "AzureCloud")),
+      (Cidr::parse("13.69.40.0/22").unwrap(), cloud("Azure", "westeurope", "AzureStorage")),
+    ]);
+
+    let oracle = r#"{"regions":[{"region":"eu-frankfurt-1","cidrs":[{"cidr":"130.61.0.0/16","tags":["OCI","OSN"]}]}]}"#;
+    assert_eq!(parse(RangeFormat::Oracle, oracle).unwrap(), vec![(Cidr::parse("130.61.0.0/16").unwrap(), cloud("Oracle", "eu-frankfurt-1", "OCI,OSN"))]);
+
+    let digitalocean = "5.101.96.0/21,NL,NL-NH,Amsterdam,1098\n2a03:b0c0::/32,NL,NL-NH,Amsterdam,1098\n45.55.0.0/19,US,,,\n";
+    assert_eq!(parse(RangeFormat::DigitalOcean, digitalocean).unwrap(), vec![
+      (Cidr::parse("5.101.96.0/21").unwrap(), cloud("DigitalOcean", "Amsterdam", "")),
+      (Cidr::parse("45.55.0.0/19").unwrap(), cloud("DigitalOcean", "US", "")),
+    ]);
+
+    assert!(parse(RangeFormat::Aws, "<html>").is_err());
+  }
+
+  #[test]
+  fn test_lookup() {
+    let dir = std::env::temp_dir().join(format!("succeed2ban-cloudranges-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("ip-ranges.json"), r#"{"prefixes":[
+      {"ip_prefix":"3.0.0.0/9","region":"GLOBAL","service":"AMAZON"},
+      {"ip_prefix":"3.8.0.0/14","region":"eu-west-2","service":"AMAZON"},
+      {"ip_prefix":"3.8.0.0/14","region":"eu-west-2","service":"EC2"},
+      {"ip_prefix":"3.8.0.0/14","region":"eu-west-2","service":"AMAZON"}
+    ]}"#).unwrap();
+    let files = vec![
+      RangeFile { format: RangeFormat::Aws, path: dir.join("ip-ranges.json") },
+      RangeFile { format: RangeFormat::Gcp, path: dir.join("missing.json") },
+    ];
+    let (clouds, errors) = CloudRanges::load(&files);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(clouds.len(), 2);
+    assert_eq!(clouds.lookup("3.8.1.2"), Some(&cloud("AWS", "eu-west-2", "EC2")));
+    assert_eq!(clouds.lookup("3.100.0.1"), Some(&cloud("AWS", "GLOBAL", "AMAZON")));
+    assert_eq!(clouds.lookup("192.0.2.1"), None);
+    assert_eq!(clouds.lookup("not an ip"), None);
+  }
+}
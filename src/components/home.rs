@@ -1,6 +1,6 @@
 pub mod ui;
 pub mod utils;
-use utils::{centered_rect, map_range};
+use utils::{centered_rect, map_range, rect_contains, wrap_styled_line};
 
 pub mod structs;
 use structs::{StyledLine, PointData, IPListItem};
@@ -11,10 +11,10 @@ use actions::{style_incoming_message, parse_passed_geo};
 pub mod enums;
 use enums::*;
 
-use std::{collections::HashMap, time::Duration, ops::Index};
+use std::{collections::{HashMap, VecDeque}, time::Duration, ops::Index};
 
 use color_eyre::eyre::Result;
-use crossterm::event::{KeyCode, KeyEvent, ModifierKeyCode};
+use crossterm::event::{KeyCode, KeyEvent, ModifierKeyCode, MouseButton, MouseEvent, MouseEventKind};
 use futures::{TryFutureExt, FutureExt};
 use ratatui::{prelude::*, widgets::*};
 use serde::{Deserialize, Serialize};
@@ -30,6 +30,15 @@ use crate::{
   geofetcher, gen_structs::StatefulList,
   themes, animations, migrations::schema,
   migrations::schema::ip::IP,
+  migrations::schema::message::Message,
+  migrations::schema::alert::Alert,
+  migrations::schema::allowlist::AllowlistEntry,
+  migrations::schema::ban::{ActiveBan, BanRequest},
+  migrations::schema::audit::AuditEntry,
+  cidr::Cidr,
+  query::{self, QueryResult},
+  ipdetail::IPDetail,
+  enrichment::Enrichment,
   action_handlers::list_actions,
   animations::Animation, components::home::ui::create_internal_logs,
 };
@@ -74,6 +83,8 @@ pub struct Home<'a> {
 
   iplist: StatefulList<IPListItem>,
   iplist_capacity: usize,
+  /// blocklists per IP
+  ip_tags: HashMap<String, Vec<String>>,
 
   infotext: String,
   elapsed_notify: usize,
@@ -84,6 +95,21 @@ pub struct Home<'a> {
   stored_styled_iostreamed: StatefulList<(StyledLine, String, String)>,
   iostreamed_capacity: usize,
   iostreamed_capacity_input: String,
+  wrapmode: WrapMode,
+  io_hscroll: usize,
+
+  // pause freezes I/O, IP list and map, incoming geo is held back until resumed
+  paused: bool,
+  paused_backlog: VecDeque<(IP, String, bool)>,
+  paused_new: usize,
+  /// (IPs, lines) waiting for a geolocation lookup
+  geo_queue: (usize, usize),
+
+  // pane areas of the last draw, used to route mouse events
+  area_iolist: Rect,
+  area_iplist: Rect,
+  area_actions: Rect,
+  area_map: Rect,
 
   apptheme: themes::Theme,
 
@@ -100,6 +126,20 @@ pub struct Home<'a> {
   querystring: String,
   queryerror: String,
   anim_querycursor: Animation<&'a str>,
+  query_result: QueryResult,
+  query_results: StatefulList<Message>,
+  showing_query_results: bool,
+  ip_detail: IPDetail,
+  /// reverse DNS and RDAP data of the IP in the detail view
+  ip_detail_enrichment: Enrichment,
+  // unacknowledged login alerts, shown as banner until acknowledged
+  alerts: Vec<Alert>,
+
+  allowlist: StatefulList<AllowlistEntry>,
+  allowlist_input: String,
+  allowlist_error: String,
+  banlist: StatefulList<ActiveBan>,
+  audit_log: StatefulList<AuditEntry>,
 
   ipstring: String,
   iperror: String,
@@ -151,6 +191,9 @@ impl<'a> Home<'a> {
       ("monitor-fail2ban", String::from("inactive")),
       ("Stats", String::from(" E ")),
       ("Query", String::from(" Q ")),
+      ("Allowlist", String::from(" Z ")),
+      ("Banned", String::from(" R ")),
+      ("Audit log", String::from(" Y ")),
       ("Help", String::from(" W ")),
       ("Exit", String::from("Esc | Ctrl+C")),
     ]);
@@ -169,7 +212,8 @@ impl<'a> Home<'a> {
     self.iomode = IOMode::Follow;
     
     self.anim_querycursor = Animation::with_items(vec![""," "]);
-    self.queryerror = String::from("Enter IP");
+    self.queryerror = String::from("Enter query, e.g. country:CN user:root");
+    self.query_results = StatefulList::with_items(vec![]);
 
     self.iostreamed_capacity = 100;
     self.available_themes = themes::Themes::default();
@@ -320,14 +364,25 @@ impl<'a> Home<'a> {
   }
 
   fn submit_query(&mut self) -> bool {
-    // check if valid IP else return false
-    if self.apptheme.ipregex.is_match(&self.querystring) {
-      self.command_tx.clone().unwrap().send(Action::SubmitQuery(self.querystring.clone())).unwrap_or_else(|err|{
-        println!("Error submitting query from Home {}", err);
-      });
-      return true;
+    // check if query parses else return false, startup compiles it again to sql
+    match query::parse(&self.querystring) {
+      Ok(_) => {
+        self.command_tx.clone().unwrap().send(Action::SubmitQuery(self.querystring.clone())).unwrap_or_else(|err|{
+          error!("Error submitting query from Home {}", err);
+        });
+        true
+      },
+      Err(e) => {
+        self.queryerror = e;
+        false
+      },
     }
-    false
+  }
+
+  fn close_query_results(&mut self) {
+    self.showing_query_results = false;
+    self.query_results = StatefulList::with_items(vec![]);
+    self.query_result = QueryResult::default();
   }
 
   fn popup_ban(&mut self)-> impl Widget + '_ {
@@ -425,6 +480,25 @@ impl<'a> Home<'a> {
     false
   }
 
+  fn submit_allowlist_entry(&mut self) -> bool {
+    // store the normalized network, 10.1.2.3/8 becomes 10.0.0.0/8
+    match Cidr::parse(&self.allowlist_input) {
+      Some(cidr) => {
+        let entry = if cidr.prefix == 32 {crate::cidr::u32_to_ip(cidr.network)} else {cidr.to_string()};
+        self.command_tx.clone().unwrap().send(Action::AddAllowlistEntry(entry)).unwrap_or_else(|err|{
+          error!("Error submitting allowlist entry from Home {}", err);
+        });
+        self.allowlist_input = String::from("");
+        self.allowlist_error = String::from("Added!");
+        true
+      },
+      None => {
+        self.allowlist_error = String::from("Invalid IP or CIDR");
+        false
+      },
+    }
+  }
+
   fn toggle_f2bwatcher(&mut self, action_idx: usize) -> Action {
     // check if is active
     if self.f2brunning {
@@ -452,14 +526,82 @@ impl<'a> Home<'a> {
       }
   }
 
+  fn toggle_pause(&mut self) -> Result<()> {
+    if self.paused {
+      self.resume()?;
+    } else {
+      self.paused = true;
+      self.paused_new = 0;
+    }
+    Ok(())
+  }
+
+  /// Catches up on everything received while paused.
+  fn resume(&mut self) -> Result<()> {
+    self.paused = false;
+    self.paused_new = 0;
+    while let Some((ip, line, from_db)) = self.paused_backlog.pop_front() {
+      parse_passed_geo(self, ip, line, from_db)?;
+    }
+    Ok(())
+  }
+
+  /// Selects the IP whose map point is closest to the clicked cell, within a few cells.
+  fn select_ip_on_map(&mut self, x: u16, y: u16) -> bool {
+    // canvas is drawn inside a border with x bounds [-180, 180] and y bounds [-90, 90]
+    let inner = Rect::new(self.area_map.x + 1, self.area_map.y + 1, self.area_map.width.saturating_sub(2), self.area_map.height.saturating_sub(2));
+    if inner.width == 0 || inner.height == 0 || !rect_contains(inner, x, y) {
+      return false;
+    }
+    let cell_w = 360. / f64::from(inner.width);
+    let cell_h = 180. / f64::from(inner.height);
+    let lon = -180. + (f64::from(x - inner.x) + 0.5) * cell_w;
+    let lat = 90. - (f64::from(y - inner.y) + 0.5) * cell_h;
+
+    const MAX_CELLS: f64 = 3.;
+    let mut closest: Option<(usize, f64)> = None;
+    for (idx, item) in self.iplist.items.iter().enumerate() {
+      // distance in cells, so the tolerance is the same in both directions
+      let dx = (item.pointdata.lon - lon) / cell_w;
+      let dy = (item.pointdata.lat - lat) / cell_h;
+      let dist = (dx * dx + dy * dy).sqrt();
+      if dist <= MAX_CELLS && closest.is_none_or(|(_, d)| dist < d) {
+        closest = Some((idx, dist));
+      }
+    }
+    match closest {
+      Some((idx, _)) => {
+        self.iplist.state.select(Some(idx));
+        self.iplist.items[idx].pointdata.refresh();
+        self.selected_ip = self.iplist.items[idx].IP.ip.clone();
+        true
+      },
+      None => false,
+    }
+  }
+
+  fn cycle_wrapmode(&mut self) {
+    self.io_hscroll = 0;
+    self.wrapmode = match self.wrapmode {
+      WrapMode::Truncate => WrapMode::Wrap,
+      WrapMode::Wrap => WrapMode::Scroll,
+      WrapMode::Scroll => WrapMode::Truncate,
+    };
+  }
+
   fn clear_lists(&mut self) {
     self.iplist.items = vec![];
     self.stored_styled_iostreamed.items = vec![];
+    self.paused_backlog.clear();
+    self.paused_new = 0;
   }
 
   fn set_io_capacity_to(&mut self, new_capacity:usize) {
     self.iostreamed_capacity = new_capacity;
     self.stored_styled_iostreamed.trim_to_length(self.iostreamed_capacity);
+    while self.paused_backlog.len() > self.iostreamed_capacity {
+      self.paused_backlog.pop_front();
+    }
   }
 
   fn add_to_capacitystring(&mut self, ch: char) {
@@ -504,6 +646,67 @@ impl Component for Home<'_> {
     Ok(())
   }
 
+  fn handle_mouse_events(&mut self, mouse: MouseEvent) -> Result<Option<Action>> {
+    if !self.startup_complete || self.showing_stats {
+      return Ok(None);
+    }
+    // popups keep the focus
+    match self.displaymode {
+      DisplayMode::Normal | DisplayMode::Logs | DisplayMode::Map => {},
+      _ => return Ok(None),
+    }
+    let (x, y) = (mouse.column, mouse.row);
+    let action = match mouse.kind {
+      MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+        let down = mouse.kind == MouseEventKind::ScrollDown;
+        if rect_contains(self.area_iplist, x, y) {
+          if down {Action::IPsNext} else {Action::IPsPrevious}
+        } else if rect_contains(self.area_actions, x, y) {
+          if down {Action::ActionsNext} else {Action::ActionsPrevious}
+        } else if rect_contains(self.area_iolist, x, y) {
+          if self.showing_query_results {
+            if down {self.query_results.next();} else {self.query_results.previous();}
+            Action::Render
+          } else if down {Action::LogsNext} else {Action::LogsPrevious}
+        } else {
+          Action::Blank
+        }
+      },
+      MouseEventKind::Down(MouseButton::Left) => {
+        if rect_contains(self.area_iplist, x, y) {
+          // click-to-focus, the IP list is active in Normal mode
+          // two rows per IP, a third for its blocklists
+          let ip_tags = &self.ip_tags;
+          if let Some(idx) = self.iplist.select_at_row(self.area_iplist, y, |item| if ip_tags.get(&item.IP.ip).is_some_and(|tags| !tags.is_empty()) {3} else {2}) {
+            self.iplist.items[idx].pointdata.refresh();
+            self.selected_ip = self.iplist.items[idx].IP.ip.clone();
+          }
+          Action::EnterNormal
+        } else if rect_contains(self.area_actions, x, y) {
+          self.available_actions.select_at_row(self.area_actions, y, |_| 2); // every action spans two lines
+          Action::EnterTakeAction
+        } else if rect_contains(self.area_iolist, x, y) {
+          if self.showing_query_results {
+            self.query_results.select_at_row(self.area_iolist, y, |_| 1);
+            self.mode = Mode::QueryResults;
+          } else {
+            let (wrapmode, width) = (self.wrapmode, self.area_iolist.width as usize);
+            self.stored_styled_iostreamed.select_at_row(self.area_iolist, y, |item| {
+              if wrapmode == WrapMode::Wrap {wrap_styled_line(&item.0, width.saturating_sub(5)).len()} else {1}
+            });
+          }
+          Action::Render
+        } else if self.select_ip_on_map(x, y) {
+          Action::Render
+        } else {
+          Action::Blank
+        }
+      },
+      _ => Action::Blank,
+    };
+    Ok(Some(action))
+  }
+
   fn handle_key_events(&mut self, key: KeyEvent) -> Result<Option<Action>> {
     self.last_events.push(key.clone());
     // Do matching of general keychars first to deduplicate
@@ -511,14 +714,27 @@ impl Component for Home<'_> {
     let mut action: Action = Action::Blank;
     if self.startup_complete && !self.showing_stats { // fully loaded
       match key.code {
+        KeyCode::Esc if self.mode == Mode::Query => return Ok(Some(Action::ExitQuery)),
+        KeyCode::Esc if self.mode == Mode::QueryResults => {self.close_query_results(); return Ok(Some(Action::EnterNormal))},
         KeyCode::Esc => return Ok(Some(Action::Quit)),
-        KeyCode::Char(keychar) => {
+        KeyCode::Char(keychar) if self.mode != Mode::Query => { // query input takes all chars
           match keychar {
             // General Hotkeys
             'W'|'w' => {if self.displaymode == DisplayMode::Help {self.displaymode = DisplayMode::Normal;} else {self.displaymode = DisplayMode::Help;} return Ok(Some(Action::Blank))},
             'Q'|'q' => {if self.displaymode == DisplayMode::Query {return Ok(Some(Action::ExitQuery))} else {return Ok(Some(Action::EnterQuery))} },
             'E'|'e' => {return Ok(Some(Action::StatsShow))},
             'C'|'c' => {return Ok(Some(Action::ConfirmClearLists)) },
+            'V'|'v' => {
+              if self.displaymode == DisplayMode::IPDetail {self.displaymode = DisplayMode::Normal; return Ok(Some(Action::Blank))}
+              if self.selected_ip.is_empty() {return Ok(Some(Action::Blank))}
+              self.ip_detail = IPDetail::default();
+              self.ip_detail_enrichment = Enrichment::default();
+              self.displaymode = DisplayMode::IPDetail;
+              return Ok(Some(Action::RequestIPDetail(self.selected_ip.clone())))
+            },
+            // inline ban / unban from the IP detail view, the DB state may be stale so it is not checked here
+            'B'|'b' if self.displaymode == DisplayMode::IPDetail => {return Ok(Some(Action::BanIP(IP { is_banned: false, ..self.ip_detail.ip.clone() }, BanRequest::manual("IP detail"))))},
+            'U'|'u' if self.displaymode == DisplayMode::IPDetail => {return Ok(Some(Action::UnbanIP(IP { is_banned: true, ..self.ip_detail.ip.clone() })))},
             'B'|'b' => {if self.displaymode == DisplayMode::Ban {self.ipstring = String::from(""); return Ok(Some(Action::ExitBan))} else {self.ipstring = self.selected_ip.clone(); return Ok(Some(Action::EnterBan))}},
             'U'|'u' => {if self.displaymode == DisplayMode::Unban {self.ipstring = String::from(""); return Ok(Some(Action::ExitUnban))} else {self.ipstring = self.selected_ip.clone(); return Ok(Some(Action::EnterUnban))}},
             'M'|'m' => {if self.displaymode == DisplayMode::Map {self.displaymode = DisplayMode::Normal;} else {self.displaymode = DisplayMode::Map;} return Ok(Some(Action::Blank))},
@@ -539,6 +755,21 @@ impl Component for Home<'_> {
             // IOMode switching
             'F'|'f' => {self.iomode = IOMode::Follow; return Ok(Some(Action::Blank))},
             'G'|'g' => {self.iomode = IOMode::Static; return Ok(Some(Action::Blank))},
+            // Long line handling
+            'O'|'o' => {self.cycle_wrapmode(); return Ok(Some(Action::Render))},
+            '<' => {self.io_hscroll = self.io_hscroll.saturating_sub(10); return Ok(Some(Action::Render))},
+            '>' => {if self.wrapmode == WrapMode::Scroll {self.io_hscroll += 10;} return Ok(Some(Action::Render))},
+            ' ' => {self.toggle_pause()?; return Ok(Some(Action::Render))},
+            'Z'|'z' => {if self.displaymode == DisplayMode::Allowlist {return Ok(Some(Action::ExitAllowlist))} else {return Ok(Some(Action::EnterAllowlist))}},
+            'R'|'r' => {if self.displaymode == DisplayMode::BanList {return Ok(Some(Action::ExitBanList))} else {return Ok(Some(Action::EnterBanList))}},
+            'Y'|'y' => {if self.displaymode == DisplayMode::AuditLog {return Ok(Some(Action::ExitAuditLog))} else {return Ok(Some(Action::EnterAuditLog))}},
+            'X'|'x' => {
+              let Some(last) = self.alerts.last() else {return Ok(Some(Action::Blank))};
+              let up_to_id = last.id;
+              self.alerts.clear();
+              return Ok(Some(Action::AcknowledgeAlerts(up_to_id)))
+            },
+            'I'|'i' => {if self.displaymode == DisplayMode::LineDetail {self.displaymode = DisplayMode::Normal;} else {self.displaymode = DisplayMode::LineDetail;} return Ok(Some(Action::Blank))},
             _ => {}
           }
         },
@@ -584,6 +815,9 @@ impl Component for Home<'_> {
                 "monitor-journalctl" => {self.toggle_jctlwatcher(action_idx)},
                 "Stats" => {return Ok(Some(Action::StatsShow))},
                 "Query" => {if self.displaymode == DisplayMode::Query {Action::ExitQuery} else {Action::EnterQuery}},
+                "Allowlist" => {if self.displaymode == DisplayMode::Allowlist {Action::ExitAllowlist} else {Action::EnterAllowlist}},
+                "Banned" => {if self.displaymode == DisplayMode::BanList {Action::ExitBanList} else {Action::EnterBanList}},
+                "Audit log" => {if self.displaymode == DisplayMode::AuditLog {Action::ExitAuditLog} else {Action::EnterAuditLog}},
                 "Help" => {if self.displaymode == DisplayMode::Help {self.displaymode = DisplayMode::Normal;} else {self.displaymode = DisplayMode::Help;} Action::Blank},
                 "Exit" => {Action::Quit},
                 _ => {Action::Blank},
@@ -606,26 +840,9 @@ impl Component for Home<'_> {
               Mode::Processing => {Action::EnterNormal},
               _ => {Action::EnterNormal},
             }}
-            KeyCode::Char(keychar) => {
-              match keychar {
-                // Digits & Dot
-                '1' => {self.add_to_querystring('1'); Action::Render}, // Action render makes it feel way more responsive
-                '2' => {self.add_to_querystring('2'); Action::Render},
-                '3' => {self.add_to_querystring('3'); Action::Render},
-                '4' => {self.add_to_querystring('4'); Action::Render}, 
-                '5' => {self.add_to_querystring('5'); Action::Render}, 
-                '6' => {self.add_to_querystring('6'); Action::Render}, 
-                '7' => {self.add_to_querystring('7'); Action::Render}, 
-                '8' => {self.add_to_querystring('8'); Action::Render}, 
-                '9' => {self.add_to_querystring('9'); Action::Render},
-                '0' => {self.add_to_querystring('0'); Action::Render},
-                '.' => {self.add_to_querystring('.'); Action::Render},
-                _ => {//Action::Render
-                  Action::Blank}
-              }
-            },
+            KeyCode::Char(keychar) => {self.add_to_querystring(keychar); Action::Render}, // Action render makes it feel way more responsive
             KeyCode::Backspace => {self.rm_last_char_from_querystring(); Action::Render},
-            KeyCode::Enter => {if self.submit_query() {Action::Blank} else {Action::InvalidQuery}}, // print something to the querybox, best -> mark invalid chars / num chars
+            KeyCode::Enter => {self.submit_query(); Action::Render}, // an invalid query leaves its parse error in the querybox
             _ => {
               self.input.handle_event(&crossterm::event::Event::Key(key));
               //Action::Render
@@ -633,6 +850,17 @@ impl Component for Home<'_> {
             },
           }      
         },
+        Mode::QueryResults => {
+          match key.code {
+            KeyCode::Down => {self.query_results.next(); Action::Render},
+            KeyCode::Up => {self.query_results.previous(); Action::Render},
+            KeyCode::Tab | KeyCode::BackTab | KeyCode::Backspace | KeyCode::Left => {self.close_query_results(); Action::EnterNormal},
+            _ => {
+              self.input.handle_event(&crossterm::event::Event::Key(key));
+              Action::Blank
+            },
+          }
+        },
         Mode::ConfirmClear => {
           match key.code {
             KeyCode::Char(keychar) => {
@@ -715,6 +943,54 @@ impl Component for Home<'_> {
           },
         }      
         },
+        Mode::AuditLog => {
+          match key.code {
+            KeyCode::Tab | KeyCode::BackTab => {Action::ExitAuditLog},
+            KeyCode::Up => {self.audit_log.previous(); Action::Render},
+            KeyCode::Down => {self.audit_log.next(); Action::Render},
+            _ => {Action::Blank},
+          }
+        },
+        Mode::BanList => {
+          match key.code {
+            KeyCode::Tab | KeyCode::BackTab => {Action::ExitBanList},
+            KeyCode::Up => {self.banlist.previous(); Action::Render},
+            KeyCode::Down => {self.banlist.next(); Action::Render},
+            KeyCode::Enter => {
+              match self.banlist.state.selected() {
+                Some(idx) if idx < self.banlist.items.len() => {Action::ExtendBan(self.banlist.items[idx].ip.clone())},
+                _ => {Action::Blank},
+              }
+            },
+            KeyCode::Delete => {
+              match self.banlist.state.selected() {
+                Some(idx) if idx < self.banlist.items.len() => {Action::UnbanIP(IP { ip: self.banlist.items[idx].ip.clone(), is_banned: true, ..IP::default() })},
+                _ => {Action::Blank},
+              }
+            },
+            _ => {Action::Blank},
+          }
+        },
+        Mode::Allowlist => {
+          match key.code {
+            KeyCode::Tab | KeyCode::BackTab => {Action::ExitAllowlist},
+            KeyCode::Up => {self.allowlist.previous(); Action::Render},
+            KeyCode::Down => {self.allowlist.next(); Action::Render},
+            KeyCode::Char(keychar) if keychar.is_ascii_digit() || keychar == '.' || keychar == '/' => {self.allowlist_input.push(keychar); Action::Render},
+            KeyCode::Backspace => {self.allowlist_input.pop(); Action::Render},
+            KeyCode::Enter => {self.submit_allowlist_entry(); Action::Render},
+            KeyCode::Delete => {
+              match self.allowlist.state.selected() {
+                Some(idx) if idx < self.allowlist.items.len() => {Action::RemoveAllowlistEntry(self.allowlist.items[idx].entry.clone())},
+                _ => {Action::Blank},
+              }
+            },
+            _ => {
+              self.input.handle_event(&crossterm::event::Event::Key(key));
+              Action::Blank
+            },
+          }
+        },
         Mode::Unban => {  match key.code {
         KeyCode::Tab => {self.displaymode = DisplayMode::Normal; 
         match self.last_mode {
@@ -771,6 +1047,40 @@ impl Component for Home<'_> {
       Action::ExitBan => {self.mode = self.last_mode; self.displaymode = DisplayMode::Normal;},
       Action::EnterUnban => {self.last_mode = self.mode; self.mode = Mode::Unban; self.iperror = String::default(); self.displaymode = DisplayMode::Unban;},
       Action::ExitUnban => {self.mode = self.last_mode; self.displaymode = DisplayMode::Normal;},
+      Action::EnterAllowlist => {
+        self.last_mode = self.mode; self.mode = Mode::Allowlist; self.displaymode = DisplayMode::Allowlist;
+        self.allowlist_error = String::default();
+        self.command_tx.clone().unwrap().send(Action::RequestAllowlist)?;
+      },
+      Action::ExitAllowlist => {self.mode = self.last_mode; self.displaymode = DisplayMode::Normal; self.allowlist_input = String::from("");},
+      Action::GotAllowlist(x) => {
+        let selected = self.allowlist.state.selected();
+        self.allowlist = StatefulList::with_items(x);
+        if !self.allowlist.items.is_empty() {
+          self.allowlist.state.select(Some(selected.unwrap_or(0).min(self.allowlist.items.len() - 1)));
+        }
+      },
+      Action::EnterAuditLog => {
+        self.last_mode = self.mode; self.mode = Mode::AuditLog; self.displaymode = DisplayMode::AuditLog;
+        self.command_tx.clone().unwrap().send(Action::RequestAuditLog)?;
+      },
+      Action::ExitAuditLog => {self.mode = self.last_mode; self.displaymode = DisplayMode::Normal;},
+      Action::GotAuditLog(x) => {
+        self.audit_log = StatefulList::with_items(x);
+        if !self.audit_log.items.is_empty() {self.audit_log.state.select(Some(0));}
+      },
+      Action::EnterBanList => {
+        self.last_mode = self.mode; self.mode = Mode::BanList; self.displaymode = DisplayMode::BanList;
+        self.command_tx.clone().unwrap().send(Action::RequestBanList)?;
+      },
+      Action::ExitBanList => {self.mode = self.last_mode; self.displaymode = DisplayMode::Normal;},
+      Action::GotBanList(x) => {
+        let selected = self.banlist.state.selected();
+        self.banlist = StatefulList::with_items(x);
+        if !self.banlist.items.is_empty() {
+          self.banlist.state.select(Some(selected.unwrap_or(0).min(self.banlist.items.len() - 1)));
+        }
+      },
       Action::InternalLog(x) => {self.internal_logs.items.push(x); self.internal_logs.trim_to_length(10); self.internal_logs.next();},
       Action::StartupGotHome(x) => {
         let lat = x.lat.clone().parse::<f64>().unwrap();
@@ -832,10 +1142,32 @@ impl Component for Home<'_> {
       Action::IONotify(x) => {self.elapsed_notify += 1;},
 
       Action::InvalidQuery => {self.queryerror = String::from("Invalid Query!");},
-      Action::QueryNotFound(x) => {self.queryerror = format!("IP not found: {}", x);},
-      Action::SubmitQuery(x) => {self.querystring = String::from("") ;self.queryerror = format!("Querying IP: {}", x);},
+      Action::QueryNotFound(x) => {self.queryerror = format!("No results for: {}", x);},
+      Action::SubmitQuery(x) => {self.querystring = String::from("") ;self.queryerror = format!("Querying: {}", x);},
+      Action::GotQueryResult(x) => {
+        self.queryerror = format!("Found {} messages from {} IPs", x.total, x.num_ips);
+        self.query_results = StatefulList::with_items(x.messages.clone());
+        self.query_results.next();
+        self.query_result = x;
+        self.showing_query_results = true;
+        self.mode = Mode::QueryResults; self.last_mode = Mode::Normal; self.displaymode = DisplayMode::Normal;
+      },
+
+      Action::GeoQueueDepth(ips, lines) => {self.geo_queue = (ips, lines);},
+      Action::PassGeo(x,y, z) => {
+        if self.paused {
+          // backlog is bounded like the I/O list itself, oldest entries would be trimmed on resume anyway
+          self.paused_new += 1;
+          self.paused_backlog.push_back((x, y, z));
+          while self.paused_backlog.len() > self.iostreamed_capacity {
+            self.paused_backlog.pop_front();
+          }
+        } else {
+          parse_passed_geo(self, x.clone(), y.clone(), z)?;
+        }
+      },
 
-      Action::PassGeo(x,y, z) => {parse_passed_geo(self, x.clone(), y.clone(), z)?;},
+      Action::GotTags(x, y) => {self.ip_tags.insert(x, y);},
 
       // Stats
       Action::StatsShow => {self.showing_stats = true;},
@@ -847,11 +1179,11 @@ impl Component for Home<'_> {
         if sel_ip.is_some() {
           banip = self.iplist.items[sel_ip.unwrap()].IP.clone();
           if banip.ip == self.ipstring {
-            self.command_tx.clone().unwrap().send(Action::BanIP(banip))?;
+            self.command_tx.clone().unwrap().send(Action::BanIP(banip, BanRequest::manual("Ban popup")))?;
           } else {
             let mut _ip = IP::default();
             _ip.ip = self.ipstring.clone();
-            self.command_tx.clone().unwrap().send(Action::BanIP(_ip))?;
+            self.command_tx.clone().unwrap().send(Action::BanIP(_ip, BanRequest::manual("Ban popup")))?;
           }
 
           
@@ -876,15 +1208,28 @@ impl Component for Home<'_> {
           //todo!()
         }
       },
+      Action::Banned(_) | Action::Unbanned(_) if self.displaymode == DisplayMode::BanList => {
+        self.command_tx.clone().unwrap().send(Action::RequestBanList)?;
+      },
       Action::Banned(x) => {
-        if x {self.infotext = String::from("BANNED");
+        if self.displaymode == DisplayMode::IPDetail {
+          // stay in the detail view and show the new state
+          self.command_tx.clone().unwrap().send(Action::RequestIPDetail(self.ip_detail.ip.ip.clone()))?;
+        } else if x {self.infotext = String::from("BANNED");
           list_actions::schedule_generic_action(self.command_tx.clone().unwrap(), Action::ExitBan);
         }
       },
       Action::Unbanned(x) => {
-        if x {self.infotext = String::from("BANNED");}
+        if self.displaymode == DisplayMode::IPDetail {
+          self.command_tx.clone().unwrap().send(Action::RequestIPDetail(self.ip_detail.ip.ip.clone()))?;
+        } else {
+          if x {self.infotext = String::from("BANNED");}
           list_actions::schedule_generic_action(self.command_tx.clone().unwrap(), Action::ExitUnban);
+        }
       },
+      Action::GotIPDetail(x) => {self.ip_detail = x;},
+      Action::GotEnrichment(x, y) if x == self.ip_detail.ip.ip => {self.ip_detail_enrichment = y;},
+      Action::GotAlert(x) => {self.alerts.push(x);},
       _ => {},
     }
     Ok(None)
@@ -894,8 +1239,10 @@ impl Component for Home<'_> {
     
     if self.startup_complete && !self.showing_stats && self.displaymode != DisplayMode::Map{
 
-      for item in &mut self.iplist.items  {
-        item.pointdata.decay_point(self.apptheme.decay_time);
+      if !self.paused {
+        for item in &mut self.iplist.items  {
+          item.pointdata.decay_point(self.apptheme.decay_time);
+        }
       }
       
       self.elapsed_rticks += 1;
@@ -913,10 +1260,21 @@ impl Component for Home<'_> {
         self.elapsed_frames = 0.;
       }
   
+      // login alerts push everything down until acknowledged
+      let mut main_area = f.size();
+      if !self.alerts.is_empty() {
+        let alert_layout = Layout::default()
+          .direction(Direction::Vertical)
+          .constraints([Constraint::Length(3), Constraint::Min(0)])
+          .split(f.size());
+        f.render_widget(ui::create_alert_banner(self), alert_layout[0]);
+        main_area = alert_layout[1];
+      }
+
       let layout = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Percentage(20), Constraint::Percentage(80)])
-        .split(f.size());
+        .split(main_area);
   
   
       let left_layout = Layout::default()
@@ -940,22 +1298,33 @@ impl Component for Home<'_> {
         // Ip not fullscreened logs show map
         f.render_widget(self.map_canvas(&right_layout[0]), right_layout[0]);
       }
+      self.area_map = if self.displaymode == DisplayMode::Logs {Rect::default()} else {right_layout[0]};
+      self.area_iolist = right_layout[1];
+      self.area_iplist = left_layout[1];
+      self.area_actions = left_layout[2];
 
   
       let actionlist = ui::create_action_list(self.available_actions.clone(), &self.apptheme, self.mode, self.last_mode, self.selected_ip.clone());
   
-      let iplist = ui::create_ip_list(self.iplist.clone(), &self.apptheme, self.mode, self.last_mode);
+      let iplist = ui::create_ip_list(self.iplist.clone(), &self.ip_tags, &self.apptheme, self.mode, self.last_mode);
 
       let term_w = right_layout[1].width as usize;
   
       let iolist = ui::create_io_list(self.stored_styled_iostreamed.clone(), 
-        self.iostreamed_capacity, &self.apptheme, term_w, self.available_actions.clone(), self.selected_ip.clone(), self.elapsed_rticks.clone());
+        self.iostreamed_capacity, &self.apptheme, term_w, self.available_actions.clone(), self.selected_ip.clone(), self.elapsed_rticks.clone(), self.wrapmode, self.io_hscroll, if self.paused {Some(self.paused_new)} else {None}, self.geo_queue);
   
       // Draw Map to right_upper = 0
       
   
-      // Draw Read file to right_lower = 1
-      f.render_stateful_widget(iolist, right_layout[1], &mut self.stored_styled_iostreamed.state); // CHANGED 
+      // Draw Read file to right_lower = 1, query results take its place while shown
+      if self.showing_query_results {
+        let resultlist = ui::create_query_results(self);
+        let mut resultstate = self.query_results.state.clone();
+        f.render_stateful_widget(resultlist, right_layout[1], &mut resultstate);
+        self.query_results.state = resultstate;
+      } else {
+        f.render_stateful_widget(iolist, right_layout[1], &mut self.stored_styled_iostreamed.state); // CHANGED 
+      }
       // f.render_widget(iolist, right_layout[1]);
       
       f.render_widget(create_internal_logs(self), left_layout[0]);
@@ -967,17 +1336,43 @@ impl Component for Home<'_> {
       // display popups/overlays
       match self.displaymode {
         DisplayMode::Help => {
-          let p_area = centered_rect(f.size(), 35, 50);
+          let p_area = centered_rect(f.size(), 35, 60);
           f.render_widget(Clear, p_area);
           f.render_widget(ui::create_help_popup(self),p_area);
           },
         DisplayMode::Query => {
           self.anim_querycursor.next();
-          let p_area = centered_rect(f.size(), 20, 7);
+          let p_area = centered_rect(f.size(), 40, 12);
           f.render_widget(Clear, p_area);
           f.render_widget(ui::create_query_popup(self),p_area);
         },
         DisplayMode::Stats => {},
+        DisplayMode::IPDetail => {
+          let p_area = centered_rect(f.size(), 60, 60);
+          f.render_widget(Clear, p_area);
+          ui::draw_ip_detail_popup(self, f, p_area);
+        },
+        DisplayMode::AuditLog => {
+          let p_area = centered_rect(f.size(), 80, 60);
+          f.render_widget(Clear, p_area);
+          ui::draw_audit_popup(self, f, p_area);
+        },
+        DisplayMode::BanList => {
+          let p_area = centered_rect(f.size(), 70, 50);
+          f.render_widget(Clear, p_area);
+          ui::draw_banlist_popup(self, f, p_area);
+        },
+        DisplayMode::Allowlist => {
+          self.anim_querycursor.next();
+          let p_area = centered_rect(f.size(), 40, 40);
+          f.render_widget(Clear, p_area);
+          ui::draw_allowlist_popup(self, f, p_area);
+        },
+        DisplayMode::LineDetail => {
+          let p_area = centered_rect(f.size(), 60, 40);
+          f.render_widget(Clear, p_area);
+          f.render_widget(ui::create_line_detail_popup(self), p_area);
+        },
 
         DisplayMode::ConfirmClear => {
           let p_area = centered_rect(f.size(), 20, 5);
@@ -1025,8 +1420,10 @@ impl Component for Home<'_> {
         // Draw only Map
         self.mode = Mode::Normal; // Prevents executing actions silently on map screen
         // Make new layout
-        for item in &mut self.iplist.items  {
-          item.pointdata.decay_point(self.apptheme.decay_time);
+        if !self.paused {
+          for item in &mut self.iplist.items  {
+            item.pointdata.decay_point(self.apptheme.decay_time);
+          }
         }
         
         self.elapsed_rticks += 1;
@@ -1111,6 +1508,10 @@ impl Component for Home<'_> {
         f.render_widget(Clear, area);
         //let num_lines: f32 = rng.gen_range(-1..1.);
         f.render_widget(self.map_canvas(&map_layout[1]), map_layout[1]);
+        self.area_map = map_layout[1];
+        self.area_iolist = Rect::default();
+        self.area_iplist = Rect::default();
+        self.area_actions = Rect::default();
         f.render_widget(Paragraph::new(self.bg_text.clone()).block(Block::default().border_style(self.apptheme.styles_app.border_style)).bg(self.apptheme.colors_app.background_darkest.color), map_layout[0]);
         f.render_widget(Paragraph::new(self.bg_text_2.clone()).block(Block::default().border_style(self.apptheme.styles_app.border_style)).bg(self.apptheme.colors_app.background_darkest.color), map_layout[2]);
 
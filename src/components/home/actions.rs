@@ -22,7 +22,7 @@ pub fn style_incoming_message(home: &mut Home, msg: String) {
     if tmp_line.is_empty() {
       continue;
     }
-    let mut thisline: StyledLine = StyledLine::default();
+    let mut thisline: StyledLine = StyledLine { raw: tmp_line.to_string(), ..Default::default() };
     // do word_map matching first then regex match splitting
     // look for ip quickly to send it out to the list
     let results: Vec<&str> = home.apptheme.ipregex
@@ -85,6 +85,52 @@ pub fn style_incoming_message(home: &mut Home, msg: String) {
 
 }
 
+/// Extracts the fields shown in the line detail popup from a raw journalctl or fail2ban line.
+pub fn parse_line_fields(home: &Home, raw: &str) -> Vec<(String, String)> {
+  let mut fields: Vec<(String, String)> = vec![];
+  let words: Vec<&str> = raw.split_whitespace().collect();
+
+  // journalctl: "Dec 12 10:11:12 host sshd[1234]: ...", fail2ban: "2023-12-12 10:11:12,345 fail2ban.actions [123]: NOTICE [sshd] Ban 1.2.3.4"
+  if let Some(process) = words.iter().find(|w| w.ends_with("]:") || (w.ends_with(':') && w.contains('['))) {
+    fields.push((String::from("Process"), process.trim_end_matches(':').to_string()));
+  }
+  let event = [
+    "Failed password", "Invalid user", "Accepted password", "Accepted publickey", "Connection closed", "Disconnected from",
+    "authentication failure", "Found", "Unban", "Ban",
+  ]
+  .into_iter()
+  .find(|e| raw.contains(e));
+  if let Some(event) = event {
+    fields.push((String::from("Event"), event.to_string()));
+  }
+
+  let ip = home.apptheme.ipregex.captures(raw).and_then(|c| c.get(1)).map(|m| m.as_str().to_string());
+  if let Some(ip) = ip.clone() {
+    fields.push((String::from("IP"), ip));
+  }
+  for (idx, word) in words.iter().enumerate() {
+    let next = words.get(idx + 1);
+    match (*word, next) {
+      ("port", Some(port)) => fields.push((String::from("Port"), port.to_string())),
+      ("user", Some(user)) => fields.push((String::from("User"), user.to_string())),
+      ("for", Some(user)) if words.get(idx + 2) == Some(&"from") => fields.push((String::from("User"), user.to_string())),
+      _ => {},
+    }
+  }
+  fields.dedup();
+
+  // geodata is only available while the IP is still in the IP list
+  if let Some(ip) = ip {
+    if let Some(item) = home.iplist.items.iter().find(|i| i.IP.ip == ip) {
+      fields.push((String::from("Country"), item.IP.country.clone()));
+      fields.push((String::from("Region"), item.IP.region.clone()));
+      fields.push((String::from("City"), item.IP.city.clone()));
+      fields.push((String::from("ISP"), item.IP.isp.clone()));
+    }
+  }
+  fields
+}
+
 /// Parses received IP geodata, calculates direction to home, passes to style message
 pub fn parse_passed_geo(home: &mut Home, x: IP, y: String, z: bool) -> Result<()> {
   
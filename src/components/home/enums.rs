@@ -7,10 +7,14 @@ pub enum Mode {
   TakeAction,
   Processing,
   Query,
+  QueryResults,
   ConfirmClear,
   SetIOCapacity,
   Ban,
   Unban,
+  Allowlist,
+  BanList,
+  AuditLog,
 }
 
 
@@ -35,6 +39,11 @@ pub enum DisplayMode {
   Unban,
   Map,
   Logs,
+  LineDetail,
+  IPDetail,
+  Allowlist,
+  BanList,
+  AuditLog,
 }
 
 #[derive(Default, Copy, Clone, PartialEq, Eq)]
@@ -43,3 +52,11 @@ pub enum IOMode {
   Follow, // will jump to freshly received IP
   Static, // will stay at selected IP
 }
+
+#[derive(Default, Copy, Clone, PartialEq, Eq)]
+pub enum WrapMode {
+  #[default]
+  Truncate, // long lines are cut at the pane edge
+  Wrap,     // long lines continue on the next row
+  Scroll,   // long lines can be scrolled horizontally
+}
@@ -5,6 +5,8 @@ use ratatui::prelude::Style;
 #[derive(Default, Clone)]
 pub struct StyledLine {
   pub words: Vec<(String, Style)>,
+  /// line as received, before styling
+  pub raw: String,
 }
 
 #[derive(Clone, PartialEq)]
@@ -1,5 +1,13 @@
-use super::{themes::Theme, Home, Mode, StyledLine, IPListItem, PointData, IP, DrawMode, IOMode, Animation};
+use std::collections::HashMap;
+
+use super::{themes::Theme, Home, Mode, StyledLine, IPListItem, PointData, IP, DrawMode, IOMode, WrapMode, Animation};
+use super::utils::{wrap_styled_line, scroll_styled_line};
+use super::actions::parse_line_fields;
 use crate::gen_structs::StatefulList;
+use crate::ipdetail::ACTIVITY_DAYS;
+use crate::migrations::schema::allowlist::ssh_client_ip;
+use crate::migrations::schema::ban;
+use crate::tui::Frame;
 use ratatui::{prelude::*, widgets::*};
 
 pub fn create_internal_logs<'a>(home: &'a Home) -> List<'a> {
@@ -24,6 +32,7 @@ pub fn create_internal_logs<'a>(home: &'a Home) -> List<'a> {
 
 // LISTS // ---------------------------------------------------------------- //
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_io_list<'a>(
   st_st_io: StatefulList<(StyledLine, String, String)>,
   iostreamed_capacity: usize,
@@ -32,6 +41,10 @@ pub fn create_io_list<'a>(
   av_actions: StatefulList<(&'a str, String)>,
   selected_ip: String,
   elapsed_rticks: usize,
+  wrapmode: WrapMode,
+  hscroll: usize,
+  paused_new: Option<usize>,
+  geo_queue: (usize, usize),
 ) -> List<'a> {
   const ANIMSYMBOLS: [&'static str; 4] = ["|", "/", "―", "\\"];
 
@@ -39,11 +52,19 @@ pub fn create_io_list<'a>(
     .items
     .iter()
     .map(|i| {
-      let mut line: Line = Line::default();
-      for word in i.0.words.clone() {
-        let cspan = Span::styled(word.0, word.1);
-        line.spans.push(cspan);
-      }
+      // borders and highlight symbol take 5 columns
+      let mut lines: Vec<Line> = match wrapmode {
+        WrapMode::Truncate => {
+          let mut line: Line = Line::default();
+          for word in i.0.words.clone() {
+            let cspan = Span::styled(word.0, word.1);
+            line.spans.push(cspan);
+          }
+          vec![line]
+        },
+        WrapMode::Wrap => wrap_styled_line(&i.0, term_w.saturating_sub(5)),
+        WrapMode::Scroll => vec![scroll_styled_line(&i.0, hscroll)],
+      };
 
       let mut bg_style: Style;
       if i.1 == "Journal" {
@@ -66,15 +87,17 @@ pub fn create_io_list<'a>(
         
       }
 
-      let line_w = line.width();
-      if line_w < term_w {
-        // fill line with whitespaces
-        let dif = term_w - line_w;
-        let cspan = Span::styled(str::repeat(" ", dif), Style::default().fg(theme.colors_app.text_color.color));
-        line.spans.push(cspan);
+      for line in lines.iter_mut() {
+        let line_w = line.width();
+        if line_w < term_w {
+          // fill line with whitespaces
+          let dif = term_w - line_w;
+          let cspan = Span::styled(str::repeat(" ", dif), Style::default().fg(theme.colors_app.text_color.color));
+          line.spans.push(cspan);
+        }
+        line.patch_style(bg_style);
       }
-      line.patch_style(bg_style);
-      ListItem::new(line)
+      ListItem::new(Text::from(lines))
     })
     .collect();
 
@@ -99,6 +122,28 @@ pub fn create_io_list<'a>(
       },
     ),
     Span::styled(" ] ", default_text_style),
+    Span::styled(
+      match wrapmode {
+        WrapMode::Truncate => String::from(""),
+        WrapMode::Wrap => String::from("[ Wrap ] "),
+        WrapMode::Scroll => format!("[ Scroll: {} ] ", hscroll),
+      },
+      Style::default().fg(theme.colors_app.accent_color_b_mid.color),
+    ),
+    Span::styled(
+      match paused_new {
+        Some(num) => format!("[ PAUSED +{} new ] ", num),
+        None => String::from(""),
+      },
+      Style::default().fg(theme.colors_app.warn_color.color),
+    ),
+    Span::styled(
+      match geo_queue {
+        (0, _) => String::from(""),
+        (ips, lines) => format!("[ Locating {} IPs, {} lines waiting ] ", ips, lines),
+      },
+      Style::default().fg(theme.colors_app.accent_color_b_mid.color),
+    ),
   ]);
 
   let iolist_selected_idx = st_st_io.state.selected();
@@ -145,7 +190,7 @@ pub fn create_io_list<'a>(
   iolist
 }
 
-pub fn create_ip_list(iplist: StatefulList<IPListItem>, theme: &Theme, mode: Mode, last_mode:Mode) -> List<'_> {
+pub fn create_ip_list<'a>(iplist: StatefulList<IPListItem>, ip_tags: &HashMap<String, Vec<String>>, theme: &'a Theme, mode: Mode, last_mode:Mode) -> List<'a> {
   let ips: Vec<ListItem> = iplist      // .items
   .items
   .iter()
@@ -160,6 +205,9 @@ pub fn create_ip_list(iplist: StatefulList<IPListItem>, theme: &Theme, mode: Mod
             .italic()
             .into(),
       );
+      if let Some(tags) = ip_tags.get(&i.IP.ip).filter(|tags| !tags.is_empty()) {
+        lines.push(Line::styled(format!("  [{}]", tags.join(", ")), Style::default().fg(theme.colors_app.warn_color.color)));
+      }
       ListItem::new(lines).style(Style::default().fg(theme.colors_app.text_color.color))
   })
   .collect();
@@ -270,7 +318,7 @@ pub fn create_help_popup<'a>(home: &'a Home) -> impl Widget + 'a {
   helptext.push(                Line::from(Span::styled(format!("Arrowkeys:    Select        Select item in IPs or Actions dependent on mode"), linestyle)));
   helptext.push(                Line::from(Span::styled(format!("Tab:          Mode          Switch Mode between IP-List & Actions"), linestyle_alt)));
   helptext.push(                Line::from(Span::styled(format!("W|w:          Help          Toggle help"), linestyle)));
-  helptext.push(                Line::from(Span::styled(format!("Q|q:          Query         Toggle query input for IP data from db"), linestyle_alt)));
+  helptext.push(                Line::from(Span::styled(format!("Q|q:          Query         Toggle query input, e.g. country:CN AND user:root"), linestyle_alt)));
   helptext.push(                Line::from(Span::styled(format!("B|b:          Ban           Ban entered IP"), linestyle)));
   helptext.push(                Line::from(Span::styled(format!("U|u:          Unban         Unban entered IP"), linestyle_alt)));
   helptext.push(                Line::from(Span::styled(format!("E|e:          Stats         Switch to Stats-Screen"), linestyle)));
@@ -278,6 +326,12 @@ pub fn create_help_popup<'a>(home: &'a Home) -> impl Widget + 'a {
   helptext.push(                Line::from(Span::styled(format!("M|m:          Map           Maximizes Map"), linestyle)));
   helptext.push(                Line::from(Span::styled(format!("C|c:          Clear         Clears IP and I/O Lists"), linestyle_alt)));
   helptext.push(                Line::from(Span::styled(format!("Enter:        Execute       Context dependent selection or execution"), linestyle)));
+  helptext.push(                Line::from(Span::styled("Mouse:        Select        Click selects and focuses, wheel scrolls, click a map point", linestyle_alt)));
+  helptext.push(                Line::from(Span::styled("V|v:          IP Detail     History of selected IP, B|U ban or unban inline", linestyle)));
+  helptext.push(                Line::from(Span::styled("X|x:          Acknowledge   Acknowledge the login alerts in the banner", linestyle_alt)));
+  helptext.push(                Line::from(Span::styled("Z|z:          Allowlist     IPs and CIDRs that are never banned, incl. own SSH session", linestyle)));
+  helptext.push(                Line::from(Span::styled("R|r:          Banned        Current bans with time left, Enter extends, Del unbans", linestyle_alt)));
+  helptext.push(                Line::from(Span::styled("Y|y:          Audit log     Operator actions with fail2ban-client output", linestyle)));
   let mut hheader =   Line::from(                     format!("---           Drawmode      ---                                           {}                -", active_drawmode)); // for more spaces bc inserted string has six characters
   hheader.patch_style(headerstyle);
   helptext.push(hheader);
@@ -293,11 +347,22 @@ pub fn create_help_popup<'a>(home: &'a Home) -> impl Widget + 'a {
   helptext.push(                Line::from(Span::styled(format!("L|l:          Last          Select latest line in I/O Streamed"), linestyle_alt)));
   helptext.push(                Line::from(Span::styled(format!("P|p:          Unselect      Reset line selection in I/O Streamed"), linestyle)));
   helptext.push(                Line::from(Span::styled(format!("+|-:          Set Capacity  Input a new capacity for I/O Streamed"), linestyle_alt)));
+  helptext.push(                Line::from(Span::styled("O|o:          Wrap Mode     Cycle long lines between Truncate, Wrap and Scroll", linestyle)));
+  helptext.push(                Line::from(Span::styled("<|>:          Scroll        Scroll long lines horizontally in Scroll mode", linestyle_alt)));
+  helptext.push(                Line::from(Span::styled("Space:        Pause         Freeze I/O, IPs and map, press again to catch up", linestyle)));
+  helptext.push(                Line::from(Span::styled("I|i:          Detail        Show raw line and parsed fields of selected line", linestyle_alt)));
   let mut hheader =   Line::from(                     format!("---           IO-Mode       ---                                           {}                -", active_iomode)); // four more spaces bc inserted string has six characters
   hheader.patch_style(headerstyle);
   helptext.push(hheader);
   helptext.push(                Line::from(Span::styled(format!("F|f:          Follow        Auto-selects the last received IP"), linestyle)));
   helptext.push(                Line::from(Span::styled(format!("G|g:          Static        Selection stays where you left it"), linestyle_alt)));   
+  let mut hheader =   Line::from(                     "---           Query         ---                                                                 -");
+  hheader.patch_style(headerstyle);
+  helptext.push(hheader);
+  helptext.push(                Line::from(Span::styled("Terms:        ip: cidr: country: isp: user: since: until: banned:", linestyle)));
+  helptext.push(                Line::from(Span::styled("Operators:    AND OR ( )    Terms without operator are AND-ed", linestyle_alt)));
+  helptext.push(                Line::from(Span::styled("Arrowkeys:    Results       Navigate the query results", linestyle)));
+  helptext.push(                Line::from(Span::styled("Tab|Backsp.:  Close         Close the query results and return to I/O Streamed", linestyle_alt)));
 
   let infoblock = Paragraph::new(helptext)
   .set_style(Style::default())
@@ -328,6 +393,12 @@ pub fn create_query_popup<'a>(home: &'a Home)-> impl Widget + 'a {
   queryerror.patch_style(Style::default().bg(home.apptheme.colors_app.background_mid.color).fg(home.apptheme.colors_app.text_color.color));
   querytext.push(queryerror);
 
+  let hintstyle = Style::default().fg(home.apptheme.colors_app.text_color.shade(-0.5));
+  querytext.push(Line::from(""));
+  querytext.push(Line::from(Span::styled("ip:1.2.3.4  cidr:1.2.3.0/24  country:CN  isp:\"Digital Ocean\"", hintstyle)));
+  querytext.push(Line::from(Span::styled("user:root  since:2023-12-01|12h|7d  until:2023-12-24  banned:true", hintstyle)));
+  querytext.push(Line::from(Span::styled("Combine with AND / OR and ( ), e.g. country:CN AND (user:root OR user:admin)", hintstyle)));
+
   let querybox = Paragraph::new(querytext)
   .set_style(Style::default())
   .block(Block::default()
@@ -338,6 +409,304 @@ pub fn create_query_popup<'a>(home: &'a Home)-> impl Widget + 'a {
 
 }
 
+pub fn create_query_results<'a>(home: &'a Home) -> List<'a> {
+  let results: Vec<ListItem> = home
+    .query_results
+    .items
+    .iter()
+    .map(|msg| {
+      let timestamp = chrono::DateTime::parse_from_rfc3339(&msg.created_at).map(|ts| ts.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or(msg.created_at.clone());
+      let line = Line::from(vec![
+        Span::styled(format!("{} ", timestamp), Style::default().fg(home.apptheme.colors_app.text_color.shade(-0.5))),
+        Span::styled(format!("{:<15} ", msg.ip), Style::default().fg(home.apptheme.colors_app.accent_color_a.color)),
+        Span::styled(format!("{:<12.12} ", msg.country), Style::default().fg(home.apptheme.colors_app.accent_color_b_mid.color)),
+        Span::styled(format!("{:<12.12} ", msg.host), Style::default().fg(home.apptheme.colors_app.accent_color_a.color)),
+        Span::styled(msg.text.replace("++++", " ").trim().to_string(), Style::default().fg(home.apptheme.colors_app.text_color.color)),
+      ]);
+      ListItem::new(line)
+    })
+    .collect();
+
+  let selected = match home.query_results.state.selected() {
+    Some(idx) => idx + 1,
+    None => 0,
+  };
+  let title = format!("QUERY: {} [ {} : {} / {} | {} IPs | {} Countries ]", home.query_result.query, selected, home.query_results.items.len(), home.query_result.total, home.query_result.num_ips, home.query_result.num_countries);
+
+  let border_style = if home.mode == Mode::QueryResults {home.apptheme.styles_app.active_border_style} else {home.apptheme.styles_app.border_style};
+
+  List::new(results)
+    .bg(home.apptheme.colors_app.background_darkest.color)
+    .block(Block::default().borders(Borders::ALL).border_style(border_style).title(title))
+    .highlight_style(home.apptheme.styles_app.highlight_item_style)
+    .highlight_symbol(">> ")
+}
+
+pub fn create_line_detail_popup<'a>(home: &'a Home) -> impl Widget + 'a {
+  let keystyle = Style::default().fg(home.apptheme.colors_app.accent_color_b_mid.color);
+  let linestyle = Style::default().fg(home.apptheme.colors_app.text_color.color);
+
+  let mut detailtext: Vec<Line> = vec![];
+  if let Some(idx) = home.stored_styled_iostreamed.state.selected() {
+    let (styledline, source, _) = &home.stored_styled_iostreamed.items[idx];
+    detailtext.push(Line::from(vec![Span::styled(format!("{:<10}", "Source"), keystyle), Span::styled(source.clone(), linestyle)]));
+    for (key, value) in parse_line_fields(home, &styledline.raw) {
+      detailtext.push(Line::from(vec![Span::styled(format!("{:<10}", key), keystyle), Span::styled(value, linestyle)]));
+    }
+    detailtext.push(Line::from(""));
+    detailtext.push(Line::from(Span::styled("Raw line:", keystyle)));
+    detailtext.push(Line::from(Span::styled(styledline.raw.trim().to_string(), linestyle)));
+  } else {
+    detailtext.push(Line::from(Span::styled("Select a line in I/O Streamed first (J|K)", linestyle)));
+  }
+
+  Paragraph::new(detailtext)
+  .wrap(Wrap { trim: false })
+  .block(Block::default()
+  .bg(home.apptheme.colors_app.background_darkest.color)
+  .borders(Borders::ALL)
+  .border_style(Style::default().fg(home.apptheme.colors_app.text_color.color))
+  .title("Line Detail"))
+}
+
+pub fn draw_ip_detail_popup(home: &Home, f: &mut Frame<'_>, area: Rect) {
+  let detail = &home.ip_detail;
+  let keystyle = Style::default().fg(home.apptheme.colors_app.accent_color_b_mid.color);
+  let linestyle = Style::default().fg(home.apptheme.colors_app.text_color.color);
+  let blockedstyle = Style::default().fg(home.apptheme.colors_app.error_color.color);
+  let fmt_ts = |ts: &str| chrono::DateTime::parse_from_rfc3339(ts).map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or(ts.to_string());
+
+  let block = Block::default()
+    .bg(home.apptheme.colors_app.background_darkest.color)
+    .borders(Borders::ALL)
+    .border_style(Style::default().fg(home.apptheme.colors_app.text_color.color))
+    .title(format!("IP Detail: {}", if detail.ip.ip.is_empty() {home.selected_ip.as_str()} else {detail.ip.ip.as_str()}))
+    .title(block::Title::from(" B: Ban | U: Unban | V: Close ").alignment(Alignment::Right));
+  let inner = block.inner(area);
+  f.render_widget(block, area);
+
+  let layout = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([Constraint::Min(8), Constraint::Length(4)])
+    .split(inner);
+  let columns = Layout::default()
+    .direction(Direction::Horizontal)
+    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+    .split(layout[0]);
+
+  // left: overview, sources and block states
+  let mut left: Vec<Line> = vec![];
+  let field = |key: &str, value: String| Line::from(vec![Span::styled(format!("{:<12}", key), keystyle), Span::styled(value, linestyle)]);
+  left.push(field("Location", format!("{}, {}, {} ({})", detail.ip.city, detail.ip.region, detail.ip.country, detail.ip.countrycode)));
+  left.push(field("ISP", detail.ip.isp.clone()));
+  left.push(field("ASN", if detail.ip.asn.is_empty() {String::from("-")} else {detail.ip.asn.clone()}));
+  left.push(field("Located", fmt_ts(&detail.ip.resolved_at)));
+  // None until the workers looked the IP up
+  let pending = |value: Option<&str>| match value {
+    Some("") => String::from("-"),
+    Some(value) => value.to_string(),
+    None => String::from("..."),
+  };
+  let rdap = home.ip_detail_enrichment.rdap.as_ref();
+  left.push(field("Hostname", pending(home.ip_detail_enrichment.ptr.as_ref().map(|ptr| ptr.hostname.as_str()))));
+  left.push(field("Network", pending(rdap.map(|rdap| rdap.name.as_str()))));
+  left.push(field("Allocation", pending(rdap.map(|rdap| rdap.cidr.as_str()))));
+  left.push(field("Abuse", pending(rdap.map(|rdap| rdap.abuse_email.as_str()))));
+  left.push(field("Banned", format!("{} ({} times)", if detail.ip.is_banned {"yes"} else {"no"}, detail.ip.banned_times)));
+  left.push(field("First seen", fmt_ts(&detail.first_seen)));
+  left.push(field("Last seen", fmt_ts(&detail.last_seen)));
+  left.push(field("Hits", detail.total.to_string()));
+  for (source, hits) in detail.hits_per_source.iter() {
+    left.push(field(&format!("  {}", source), hits.to_string()));
+  }
+  left.push(Line::from(""));
+  left.push(Line::from(Span::styled("Block state", keystyle)));
+  for (kind, name, is_blocked) in detail.block_states.iter() {
+    left.push(Line::from(vec![
+      Span::styled(format!("  {:<10}", kind), keystyle),
+      Span::styled(format!("{} ", name), linestyle),
+      if *is_blocked {Span::styled("BLOCKED", blockedstyle)} else {Span::styled("-", linestyle)},
+    ]));
+  }
+  f.render_widget(Paragraph::new(left).wrap(Wrap { trim: false }), columns[0]);
+
+  // right: usernames and ban history
+  let mut right: Vec<Line> = vec![];
+  right.push(Line::from(Span::styled("Usernames tried", keystyle)));
+  if detail.usernames.is_empty() {right.push(Line::from(Span::styled("  -", linestyle)));}
+  for (user, tries) in detail.usernames.iter().take(8) {
+    right.push(Line::from(Span::styled(format!("  {:<16} {}", user, tries), linestyle)));
+  }
+  right.push(Line::from(""));
+  right.push(Line::from(Span::styled("Ban history", keystyle)));
+  if detail.ban_history.is_empty() {right.push(Line::from(Span::styled("  -", linestyle)));}
+  for record in detail.ban_history.iter().rev().take(8) {
+    let until = match &record.unbanned_at {
+      Some(unbanned_at) => Span::styled(format!(" - {}", fmt_ts(unbanned_at)), linestyle),
+      None => Span::styled(String::from(" - banned"), blockedstyle),
+    };
+    let by = if record.actor == record.source {record.source.clone()} else {format!("{} by {}", record.source, record.actor)};
+    right.push(Line::from(vec![Span::styled(format!("  {}", fmt_ts(&record.banned_at)), linestyle), until, Span::styled(format!(" [{}] {}", record.jail, by), linestyle)]));
+  }
+  if !detail.location_history.is_empty() {
+    right.push(Line::from(""));
+    right.push(Line::from(Span::styled("Location history", keystyle)));
+    for change in detail.location_history.iter().rev().take(4) {
+      right.push(Line::from(Span::styled(format!("  {} {}, {} ({}) -> {}, {} ({})", fmt_ts(&change.changed_at),
+        change.old_city, change.old_country, change.old_isp, change.new_city, change.new_country, change.new_isp), linestyle)));
+    }
+  }
+  f.render_widget(Paragraph::new(right), columns[1]);
+
+  let sparkline = Sparkline::default()
+    .block(Block::default().borders(Borders::TOP).border_style(home.apptheme.styles_app.border_style).title(format!("Activity, last {} days", ACTIVITY_DAYS)))
+    .data(&detail.activity)
+    .style(Style::default().fg(home.apptheme.colors_app.accent_color_a.color));
+  f.render_widget(sparkline, layout[1]);
+}
+
+pub fn draw_allowlist_popup(home: &Home, f: &mut Frame<'_>, area: Rect) {
+  let keystyle = Style::default().fg(home.apptheme.colors_app.accent_color_b_mid.color);
+  let linestyle = Style::default().fg(home.apptheme.colors_app.text_color.color);
+  let querycursor = home.anim_querycursor.state.selected().map(|idx| home.anim_querycursor.keyframes[idx]).unwrap_or("");
+
+  let block = Block::default()
+    .bg(home.apptheme.colors_app.background_darkest.color)
+    .borders(Borders::ALL)
+    .border_style(Style::default().fg(home.apptheme.colors_app.text_color.color))
+    .title("Allowlist - never banned")
+    .title(block::Title::from(" Enter: Add | Del: Remove | Tab: Close ").alignment(Alignment::Right));
+  let inner = block.inner(area);
+  f.render_widget(block, area);
+
+  let layout = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([Constraint::Length(3), Constraint::Min(1)])
+    .split(inner);
+
+  let mut inputtext: Vec<Line> = vec![];
+  inputtext.push(Line::from(vec![
+    Span::styled(format!("Add IP or CIDR: {}", home.allowlist_input), linestyle),
+    Span::styled(querycursor, Style::default().bg(home.apptheme.colors_app.background_brightest.color)),
+  ]));
+  inputtext.push(Line::from(Span::styled(format!("Status: {}", home.allowlist_error), linestyle)));
+  // the current SSH session is always protected, it is not stored
+  let session = match ssh_client_ip() {
+    Some(ip) => format!("Current SSH session {} is always protected", ip),
+    None => String::from("No SSH session detected"),
+  };
+  inputtext.push(Line::from(Span::styled(session, keystyle)));
+  f.render_widget(Paragraph::new(inputtext), layout[0]);
+
+  let entries: Vec<ListItem> = home.allowlist.items.iter().map(|entry| {
+    let added = chrono::DateTime::parse_from_rfc3339(&entry.created_at).map(|t| t.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+    ListItem::new(Line::from(vec![Span::styled(format!("{:<20}", entry.entry), linestyle), Span::styled(format!("added {}", added), keystyle)]))
+  }).collect();
+  let list = List::new(entries)
+    .block(Block::default().borders(Borders::TOP).border_style(home.apptheme.styles_app.border_style).title(format!("{} entries", home.allowlist.items.len())))
+    .highlight_style(home.apptheme.styles_app.highlight_item_style)
+    .highlight_symbol(">> ");
+  let mut state = home.allowlist.state.clone();
+  f.render_stateful_widget(list, layout[1], &mut state);
+}
+
+pub fn draw_banlist_popup(home: &Home, f: &mut Frame<'_>, area: Rect) {
+  let keystyle = Style::default().fg(home.apptheme.colors_app.accent_color_b_mid.color);
+  let linestyle = Style::default().fg(home.apptheme.colors_app.text_color.color);
+  let now = chrono::offset::Local::now().fixed_offset();
+
+  let block = Block::default()
+    .bg(home.apptheme.colors_app.background_darkest.color)
+    .borders(Borders::ALL)
+    .border_style(Style::default().fg(home.apptheme.colors_app.text_color.color))
+    .title(format!("Banned - {} IPs", home.banlist.items.len()))
+    .title(block::Title::from(" Enter: Extend | Del: Unban | Tab: Close ").alignment(Alignment::Right));
+  let inner = block.inner(area);
+  f.render_widget(block, area);
+
+  let layout = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([Constraint::Length(1), Constraint::Min(1)])
+    .split(inner);
+  f.render_widget(Paragraph::new(Line::from(Span::styled(format!("   {:<19}{:<12}{:<8}{:<8}Reason", "IP", "Remaining", "Jail", "Origin"), keystyle))), layout[0]);
+
+  let entries: Vec<ListItem> = home.banlist.items.iter().map(|active| {
+    let remaining = active.remaining(now).map(ban::format_duration).unwrap_or(String::from("permanent"));
+    let escalated = if active.escalations > 0 {format!(" (escalated {}x)", active.escalations)} else {String::new()};
+    ListItem::new(Line::from(vec![
+      Span::styled(format!("{:<19}", active.ip), linestyle),
+      Span::styled(format!("{:<12}", remaining), keystyle),
+      Span::styled(format!("{:<8}{:<8}", active.jail, active.origin.as_str()), linestyle),
+      Span::styled(format!("{}{}", active.reason, escalated), linestyle),
+    ]))
+  }).collect();
+  let list = List::new(entries)
+    .highlight_style(home.apptheme.styles_app.highlight_item_style)
+    .highlight_symbol(">> ");
+  let mut state = home.banlist.state.clone();
+  f.render_stateful_widget(list, layout[1], &mut state);
+}
+
+pub fn draw_audit_popup(home: &Home, f: &mut Frame<'_>, area: Rect) {
+  let keystyle = Style::default().fg(home.apptheme.colors_app.accent_color_b_mid.color);
+  let linestyle = Style::default().fg(home.apptheme.colors_app.text_color.color);
+  let warnstyle = Style::default().fg(home.apptheme.colors_app.warn_color.color);
+
+  let block = Block::default()
+    .bg(home.apptheme.colors_app.background_darkest.color)
+    .borders(Borders::ALL)
+    .border_style(Style::default().fg(home.apptheme.colors_app.text_color.color))
+    .title(format!("Audit log - latest {} actions", home.audit_log.items.len()))
+    .title(block::Title::from(" Export: --export-audit <file> | Tab: Close ").alignment(Alignment::Right));
+  let inner = block.inner(area);
+  f.render_widget(block, area);
+
+  let layout = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([Constraint::Min(1), Constraint::Length(5)])
+    .split(inner);
+
+  let entries: Vec<ListItem> = home.audit_log.items.iter().map(|entry| {
+    let at = chrono::DateTime::parse_from_rfc3339(&entry.created_at).map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default();
+    let resultstyle = if entry.result == "ok" {keystyle} else {warnstyle};
+    ListItem::new(Line::from(vec![
+      Span::styled(format!("{} {:<10}", at, entry.user), linestyle),
+      Span::styled(format!("{:<24}{:<20}", entry.action, entry.target), linestyle),
+      Span::styled(entry.result.clone(), resultstyle),
+    ]))
+  }).collect();
+  let list = List::new(entries)
+    .highlight_style(home.apptheme.styles_app.highlight_item_style)
+    .highlight_symbol(">> ");
+  let mut state = home.audit_log.state.clone();
+  f.render_stateful_widget(list, layout[0], &mut state);
+
+  let output = home.audit_log.state.selected().and_then(|idx| home.audit_log.items.get(idx)).map(|entry| entry.output.clone()).unwrap_or_default();
+  let output = Paragraph::new(output)
+    .style(linestyle)
+    .wrap(Wrap { trim: false })
+    .block(Block::default().borders(Borders::TOP).border_style(home.apptheme.styles_app.border_style).title("fail2ban-client output"));
+  f.render_widget(output, layout[1]);
+}
+
+/// Banner on top of Home, shows the latest unacknowledged login alert.
+pub fn create_alert_banner<'a>(home: &'a Home) -> impl Widget + 'a {
+  let alertstyle = Style::default().fg(home.apptheme.colors_app.text_color.color).bg(home.apptheme.colors_app.warn_color.color);
+  let mut lines: Vec<Line> = vec![];
+  if let Some(alert) = home.alerts.last() {
+    lines.push(Line::from(vec![
+      Span::styled(format!(" {} ", alert.created_at), alertstyle.add_modifier(Modifier::BOLD)),
+      Span::styled(format!("Accepted login for {} from {} ({}): {}", alert.username, alert.ip, alert.country, alert.reason), alertstyle),
+    ]));
+  }
+  Paragraph::new(lines)
+    .style(alertstyle)
+    .block(Block::default()
+      .borders(Borders::ALL)
+      .border_style(alertstyle)
+      .title(format!(" {} LOGIN ALERT ({} unacknowledged) - press X to acknowledge ", home.apptheme.symbol_error, home.alerts.len())))
+}
+
 pub fn create_clearlist_popup(theme: &Theme)  -> impl Widget + '_  {
 
   let mut clearlisttext: Vec<Line> = vec![];
@@ -1,4 +1,8 @@
 use ratatui::{prelude::*, widgets::*};
+use textwrap::core::{Fragment, Word};
+use textwrap::WordSeparator;
+
+use super::StyledLine;
 
 pub fn centered_rect(r: Rect, percent_x: u16, percent_y: u16) -> Rect {
   let popup_layout = Layout::default()
@@ -20,6 +24,75 @@ pub fn centered_rect(r: Rect, percent_x: u16, percent_y: u16) -> Rect {
     .split(popup_layout[1])[1]
 }
 
+/// Checks if the terminal cell at `x`, `y` lies within `r`.
+pub fn rect_contains(r: Rect, x: u16, y: u16) -> bool {
+  x >= r.x && x < r.x + r.width && y >= r.y && y < r.y + r.height
+}
+
 pub fn map_range(from_range: (f64, f64), to_range: (f64, f64), s: f64) -> f64 {
   to_range.0 + (s - from_range.0) * (to_range.1 - to_range.0) / (from_range.1 - from_range.0)
 }
+
+/// A word of a styled line, keeps the style of the span it was cut from while wrapping.
+#[derive(Debug)]
+struct StyledWord<'a> {
+  word: Word<'a>,
+  style: Style,
+}
+
+impl Fragment for StyledWord<'_> {
+  fn width(&self) -> f64 {
+    self.word.width()
+  }
+  fn whitespace_width(&self) -> f64 {
+    self.word.whitespace_width()
+  }
+  fn penalty_width(&self) -> f64 {
+    self.word.penalty_width()
+  }
+}
+
+/// Wraps a styled line to `width` columns without losing the style of its words, words longer than a row get broken apart.
+pub fn wrap_styled_line(line: &StyledLine, width: usize) -> Vec<Line<'static>> {
+  let width = width.max(1);
+  let mut words: Vec<StyledWord> = vec![];
+  for (text, style) in line.words.iter() {
+    for word in WordSeparator::AsciiSpace.find_words(text) {
+      for part in word.break_apart(width) {
+        words.push(StyledWord { word: part, style: *style });
+      }
+    }
+  }
+
+  let wrapped = textwrap::wrap_algorithms::wrap_first_fit(&words, &[width as f64]);
+  wrapped
+    .iter()
+    .map(|row| {
+      let mut spans: Vec<Span> = vec![];
+      for (idx, styled) in row.iter().enumerate() {
+        spans.push(Span::styled(styled.word.word.to_string(), styled.style));
+        // trailing whitespace of the last word in a row is dropped
+        if idx + 1 < row.len() && !styled.word.whitespace.is_empty() {
+          spans.push(Span::styled(styled.word.whitespace.to_string(), styled.style));
+        }
+      }
+      Line::from(spans)
+    })
+    .collect()
+}
+
+/// Cuts the first `offset` characters off a styled line, used for horizontal scrolling.
+pub fn scroll_styled_line(line: &StyledLine, offset: usize) -> Line<'static> {
+  let mut skip = offset;
+  let mut spans: Vec<Span> = vec![];
+  for (text, style) in line.words.iter() {
+    let len = text.chars().count();
+    if skip >= len {
+      skip -= len;
+      continue;
+    }
+    spans.push(Span::styled(text.chars().skip(skip).collect::<String>(), *style));
+    skip = 0;
+  }
+  Line::from(spans)
+}
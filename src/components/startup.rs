@@ -1,14 +1,14 @@
 /// Startup Component
-/// Acquires db connection
+/// Starts the storage service
 /// Sets up initial db
 /// 
 /// 
-/// Holds the DB connection and handles queries.
+/// Hands queries and writes to the storage service, nothing in update waits on the DB.
 
 use std::sync::OnceLock;
 
 
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, path::Path, time::Duration};
 
 use color_eyre::eyre::Result;
 use crossterm::event::{KeyCode, KeyEvent};
@@ -22,8 +22,8 @@ use tui_input::{backend::crossterm::EventHandler, Input};
 use super::{Component, Frame};
 use crate::gen_structs::StatefulList;
 use crate::themes::ThemeContainer;
-use crate::{action::Action, config::key_event_to_string, themes, animations::Animation, migrations::schema, geofetcher};
-use crate::migrations::schema::{message, isp, city, region, country, ip};
+use crate::{action::Action, config::{key_event_to_string, Config}, alerting, themes, animations::Animation, migrations::schema, geofetcher::{self, GeoLookups, GeoRefresh, ParkedLine, ParkedLines, RefreshOutcome}, query, ipdetail, impact, utils, rules::{self, RuleAction, RulesEngine}, notifications::{self, EventKind, NotifyEvent, Notifier}, metrics::{self, METRICS}, eventstream::{self, EventStream, EventWriter}, forwarding::{self, ForwardHandle}, threatintel::{self, ThreatIntel}, cloudranges::CloudRanges, enrichment::{self, Enricher, Enrichment}, abusereport, storage::{self, Storage}};
+use crate::migrations::schema::{message, isp, city, region, country, ip, username, host, provider, enrichment as enrichment_cache, alert, allowlist, ban::{self, BanOrigin, BanRequest}, statistics, audit};
 
 use local_ip_address::local_ip; // maybe use this https://crates.io/crates/get_if_addrs, no curl https://ident.me/
 
@@ -38,6 +38,14 @@ use chrono;
 
 use regex::Regex;
 
+/// Pause between asking fail2ban for the banned IPs of the jail.
+const JAIL_STATUS_INTERVAL: Duration = Duration::from_secs(5);
+/// Wait before retrying a failed unban of an expired ban, doubled after every attempt up to MAX_UNBAN_BACKOFF.
+const UNBAN_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_UNBAN_BACKOFF: Duration = Duration::from_secs(3600);
+/// Unbans of an expired ban tried before it is left to the operator, until the next start.
+const MAX_UNBAN_ATTEMPTS: u32 = 5;
+
 pub fn map_range(from_range: (f64, f64), to_range: (f64, f64), s: f64) -> f64 {
     to_range.0 + (s - from_range.0) * (to_range.1 - to_range.0) / (from_range.1 - from_range.0)
   }
@@ -73,8 +81,29 @@ pub struct Startup <'a>{
 
   points: Vec<(f64,f64,f64,f64)>,
 
-  // db connection,
-  dbconn: Option<Connection>,
+  /// owns the db connection, started with StartupConnect
+  storage: Option<Storage>,
+  config: Config,
+  /// shared with the storage jobs that store lines
+  rules: Arc<std::sync::Mutex<RulesEngine>>,
+  notifier: Notifier,
+  /// NDJSON output of every stored event
+  event_stream: Option<EventStream>,
+  /// queues of the SIEM forwarders
+  forwarders: Vec<ForwardHandle>,
+  threat_intel: ThreatIntel,
+  cloud_ranges: CloudRanges,
+  /// reverse DNS and RDAP workers, None if disabled
+  enricher: Option<Enricher>,
+  /// host name lines from the local log file and journal are stored with
+  local_host: String,
+  /// expired bans whose unban is underway, with the time of the last attempt and the number of attempts
+  pending_unbans: HashMap<String, (std::time::Instant, u32)>,
+  /// a look for expired bans is underway
+  expiring_bans: bool,
+  /// output of `fail2ban-client status` for the jail, asked for again every JAIL_STATUS_INTERVAL
+  jail_status: String,
+  jail_status_at: Option<std::time::Instant>,
 
   
   log_messages: Vec<String>,
@@ -86,7 +115,14 @@ pub struct Startup <'a>{
 
   // tmp
   last_ip: String,
-  fetching_ips:Vec<String>,
+  /// lines of IPs whose location is being looked up
+  parked_lines: ParkedLines,
+  /// geolocation scheduler, started with the config
+  geo_lookups: Option<GeoLookups>,
+  /// located IPs whose first line is still on its way to the DB, lines that found no location meanwhile take this one
+  located: HashMap<String, ip::IP>,
+  /// known IPs being looked up again because their location expired
+  geo_refresh: GeoRefresh,
   //stored_geo: Vec<ip::IP>,
 
   // startup line
@@ -99,7 +135,9 @@ pub struct Startup <'a>{
 
 impl <'a> Startup <'a> {
   pub fn new() -> Self {
-    Self::default().set_items()
+    let mut this = Self::default().set_items();
+    this.local_host = utils::local_hostname();
+    this
   }
 
   fn set_items(mut self) -> Self {
@@ -133,7 +171,6 @@ impl <'a> Startup <'a> {
     -#4.f,f&))â07c-9,l)c&#4g,/c%)â%h
     lf0ß4dl09f7/mms#.d2hmf44gf-c-10m"]);
     self.countdown_to_start = 10;
-    self.fetching_ips = vec![];
     self.available_themes.items = themes::Themes::default().theme_collection;
     self
   }
@@ -141,23 +178,83 @@ impl <'a> Startup <'a> {
   pub fn create_db(&mut self) {
     let dt = Utc::now();
     self.log_messages.push(format!("{}            init db", dt.to_string()));
+    let local_host = self.local_host.clone();
+    let cloud_ranges = self.cloud_ranges.clone();
+    let tx = self.action_tx.clone().unwrap();
+    self.store(move |conn| {
+      let mut log_messages: Vec<String> = vec![];
 
-    self.dbconn.as_ref().unwrap().execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
+      conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
 
-    self.dbconn.as_ref().unwrap().execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
+      conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
 
-    self.dbconn.as_ref().unwrap().execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up city db");
+      conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up city db");
 
-    self.dbconn.as_ref().unwrap().execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
+      conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
 
-    self.dbconn.as_ref().unwrap().execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+      conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+      ip::migrate_ip_resolved_at(conn).expect("Error adding resolved_at to IPs");
+      ip::migrate_ip_refresh_failed_at(conn).expect("Error adding refresh_failed_at to IPs");
+      ip::migrate_ip_asn(conn).expect("Error adding asn to IPs");
 
-    self.dbconn.as_ref().unwrap().execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up IP db");
+      conn.execute(ip::CREATE_IPHISTORY_DB_SQL, []).expect("Error setting up IP history db");
 
-    let dt = Utc::now();
-    self.log_messages.push(format!("{}            db ready", dt.to_string()));
+      conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up IP db");
+      message::migrate_message_host(conn, &local_host).expect("Error adding host to messages");
+      message::migrate_message_ip_num(conn).expect("Error adding ip_num to messages");
+      conn.execute_batch(message::CREATE_MESSAGE_INDEXES_SQL).expect("Error setting up message indexes");
+
+      conn.execute(username::CREATE_USERNAME_DB_SQL, []).expect("Error setting up username db");
+
+      conn.execute(username::CREATE_USERNAME_MESSAGE_DB_SQL, []).expect("Error setting up username db");
+      conn.execute(username::CREATE_USERNAME_MESSAGE_INDEX_SQL, []).expect("Error setting up username index");
+
+      conn.execute(alert::CREATE_ALERT_DB_SQL, []).expect("Error setting up alert db");
+
+      conn.execute(allowlist::CREATE_ALLOWLIST_DB_SQL, []).expect("Error setting up allowlist db");
+
+      conn.execute(ban::CREATE_ACTIVEBAN_DB_SQL, []).expect("Error setting up activeban db");
+
+      conn.execute(ban::CREATE_BANS_DB_SQL, []).expect("Error setting up bans db");
+
+      conn.execute_batch(audit::CREATE_AUDIT_DB_SQL).expect("Error setting up audit db");
+
+      conn.execute(provider::CREATE_PROVIDER_DB_SQL, []).expect("Error setting up provider db");
 
+      conn.execute(provider::CREATE_IPCLOUD_DB_SQL, []).expect("Error setting up ipcloud db");
 
+      conn.execute(enrichment_cache::CREATE_PTR_DB_SQL, []).expect("Error setting up ptr db");
+
+      conn.execute(enrichment_cache::CREATE_RDAP_DB_SQL, []).expect("Error setting up rdap db");
+
+      // messages stored before usernames were tracked
+      let backfilled = username::backfill_usernames(conn).unwrap_or(0);
+      if backfilled > 0 {
+        let dt = Utc::now();
+        log_messages.push(format!("{}            linked {} usernames", dt, backfilled));
+      }
+      // IPs seen before their provider's ranges were configured
+      if !cloud_ranges.is_empty() {
+        let mut tagged = 0;
+        for ip in provider::get_ips_without_cloud(conn).unwrap_or_default() {
+          let Some(range) = cloud_ranges.lookup(&ip) else {continue};
+          if provider::insert_ip_cloud(conn, &ip, range).is_ok() {tagged += 1;}
+        }
+        if tagged > 0 {
+          let dt = Utc::now();
+          log_messages.push(format!("{}            tagged {} cloud IPs", dt, tagged));
+        }
+      }
+      // fail2ban lines stored before the ban history existed, the counters are rebuilt from it once
+      let backfilled = ban::backfill_bans(conn).unwrap_or(0);
+      if backfilled > 0 {
+        statistics::recompute_statistics(conn).unwrap_or_default();
+        let dt = Utc::now();
+        log_messages.push(format!("{}            recorded {} past bans", dt, backfilled));
+      }
+      tx.send(Action::StorageReady(log_messages)).unwrap_or_default();
+      Ok(())
+    });
   }
 
   pub fn get_initial_stats(&mut self) {
@@ -173,6 +270,12 @@ impl <'a> Startup <'a> {
       tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
       tx.send(Action::StatsGetISPs).expect("Failed to get ISPs on Startup");  
       tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;    
+      tx.send(Action::StatsGetUsernames).expect("Failed to get Usernames on Startup");
+      tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+      tx.send(Action::StatsGetHosts).expect("Failed to get Hosts on Startup");
+      tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+      tx.send(Action::StatsGetProviders).expect("Failed to get Providers on Startup");
+      tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
     });
     self.log_messages.push(format!("{}            Deciphering binaries", dt.to_string()));
   }
@@ -225,6 +328,262 @@ impl <'a> Startup <'a> {
     self.last_events.drain(..);
   }
 
+  /// Blocklist names and the provider range of the IP.
+  fn ip_tags(&self, ip: &str) -> Vec<String> {
+    let mut tags = self.threat_intel.tags(ip);
+    tags.extend(self.cloud_ranges.lookup(ip).map(|range| range.to_string()));
+    tags
+  }
+
+  /// Queues a job for the storage service, jobs before StartupConnect are dropped.
+  fn store(&self, job: impl FnOnce(&Connection) -> ConnectionResult<()> + Send + 'static) {
+    if let Some(storage) = &self.storage {storage.run(job);}
+  }
+
+  /// Loads a Stats list with the message timestamps of each entry, Stats gets them one at a time.
+  fn send_stats<T: Send + 'static>(&self, load: fn(&Connection) -> ConnectionResult<Vec<T>>, timestamps: fn(&Connection, &T) -> ConnectionResult<Vec<message::MiniMessage>>, got: fn(T, Vec<message::MiniMessage>) -> Action) {
+    let (Some(storage), Some(tx)) = (self.storage.clone(), self.action_tx.clone()) else {return};
+    tokio::spawn(async move {
+      let entries = storage.request(move |conn| {
+        let mut entries = vec![];
+        for entry in load(conn)? {
+          let stamps = timestamps(conn, &entry).unwrap_or(vec![]);
+          entries.push((entry, stamps));
+        }
+        Ok(entries)
+      }).await.unwrap_or_default();
+      for (entry, stamps) in entries {
+        tokio::time::sleep(Duration::from_millis(10)).await; // Debounce
+        tx.send(got(entry, stamps)).unwrap_or_default();
+      }
+    });
+  }
+
+  /// Queues a job and audits it with its result, a failing job is rolled back and audited with its error.
+  fn store_audited(&self, action: &str, target: &str, job: impl FnOnce(&Connection) -> ConnectionResult<()> + Send + 'static) {
+    let timestamp = chrono::offset::Local::now().to_rfc3339();
+    let (user, action, target) = (utils::local_user(), action.to_string(), target.to_string());
+    self.store(move |conn| {
+      let (result, output) = match storage::savepoint(conn, || job(conn)) {
+        Ok(()) => (audit::RESULT_OK, String::new()),
+        Err(e) => (audit::RESULT_FAILED, e.to_string()),
+      };
+      audit::insert_audit_entry(conn, &timestamp, &user, &action, &target, result, &output)
+    });
+  }
+
+  /// Looks for expired bans, GotExpiredBans unbans or prolongs them.
+  fn expire_bans(&mut self) {
+    if self.expiring_bans || self.storage.is_none() {return;}
+    self.expiring_bans = true;
+    let tx = self.action_tx.clone().unwrap();
+    self.store(move |conn| {
+      let now = chrono::offset::Local::now().fixed_offset();
+      let expired_bans = ban::get_expired_bans(conn, now).unwrap_or_default().into_iter()
+        .map(|expired| {
+          let reoffended = ban::count_messages_while_banned(conn, &expired).unwrap_or_default() > 0;
+          (expired, reoffended)
+        }).collect();
+      tx.send(Action::GotExpiredBans(expired_bans)).unwrap_or_default();
+      Ok(())
+    });
+  }
+
+  /// Unbans expired bans, or prolongs them if the IP kept showing up in the logs while banned.
+  fn unban_or_escalate(&mut self, expired_bans: Vec<(ban::ActiveBan, bool)>) {
+    self.expiring_bans = false;
+    let tx = self.action_tx.clone().unwrap();
+    let now = chrono::offset::Local::now().fixed_offset();
+    self.pending_unbans.retain(|ip, _| expired_bans.iter().any(|(expired, _)| &expired.ip == ip));
+    for (mut expired, reoffended) in expired_bans {
+      // fail2ban-client may be slow or fail, retry with backoff and give up after a few attempts
+      let attempts = self.pending_unbans.get(&expired.ip).map_or(0, |(_, attempts)| *attempts);
+      if attempts >= MAX_UNBAN_ATTEMPTS {continue;}
+      if self.pending_unbans.get(&expired.ip).is_some_and(|(at, _)| at.elapsed() < (UNBAN_BACKOFF * 2u32.pow(attempts - 1)).min(MAX_UNBAN_BACKOFF)) {continue;}
+
+      let factor = self.config.bans.escalation_factor;
+      if factor > 1 && reoffended {
+        let mut bantime = expired.bantime().unwrap_or(chrono::Duration::hours(1)) * factor as i32;
+        if let Some(max) = self.config.bans.max_bantime.as_deref().and_then(|max| query::parse_duration(max).ok()) {
+          bantime = bantime.min(max);
+        }
+        expired.banned_at = now.to_rfc3339();
+        expired.expires_at = Some((now + bantime).to_rfc3339());
+        expired.escalations += 1;
+        let escalated = expired.clone();
+        self.store(move |conn| {
+          ban::upsert_active_ban(conn, &escalated).unwrap_or_default();
+          Ok(())
+        });
+        tx.send(Action::InternalLog(format!(" {} Ban of {} escalated to {}", self.apptheme.symbol_ban, expired.ip, ban::format_duration(bantime)))).expect("LOG: Escalate ban message failed to send");
+        // the jail's own bantime may have run out in the meantime
+        tokio::task::spawn_blocking(move || {
+          let _ = fail2ban_client(&expired.jail, "banip", &expired.ip);
+        });
+      } else {
+        self.pending_unbans.insert(expired.ip.clone(), (std::time::Instant::now(), attempts + 1));
+        if attempts == 0 {
+          tx.send(Action::InternalLog(format!(" {} Ban of {} expired", self.apptheme.symbol_unblock, expired.ip))).expect("LOG: Expired ban message failed to send");
+        } else if attempts + 1 == MAX_UNBAN_ATTEMPTS {
+          tx.send(Action::InternalLog(format!(" {} Unban of {} failed {} times, last try until restart", self.apptheme.symbol_error, expired.ip, attempts))).expect("LOG: Unban retry message failed to send");
+        }
+        tx.send(Action::UnbanIP(ip::IP { ip: expired.ip, is_banned: true, ..ip::IP::default() })).expect("Expired ban unban failed to send");
+      }
+    }
+  }
+
+  /// Asks fail2ban for the banned IPs of the jail in the background, lines of these IPs count as banned.
+  fn refresh_jail_status(&mut self) {
+    if self.jail_status_at.is_some_and(|at| at.elapsed() < JAIL_STATUS_INTERVAL) {return;}
+    self.jail_status_at = Some(std::time::Instant::now());
+    let Some(tx) = self.action_tx.clone() else {return};
+    let jail = self.config.bans.jail.clone();
+    tokio::task::spawn_blocking(move || {
+      tx.send(Action::GotJailStatus(fail2ban_status(&jail))).unwrap_or_default();
+    });
+  }
+
+  /// Starts a round of looking up IPs again whose location is older than the TTL.
+  fn refresh_geodata(&mut self) {
+    let ttl_days = self.config.geolocation.ttl_days;
+    let now = std::time::Instant::now();
+    if ttl_days <= 0 || !self.geo_refresh.is_due(now) || self.storage.is_none() || self.geo_lookups.is_none() {return;}
+    // not due again while the stale IPs are being looked for
+    self.geo_refresh.start(&[], now);
+    let resolved_before = (chrono::offset::Local::now() - chrono::Duration::days(ttl_days)).to_rfc3339();
+    let tx = self.action_tx.clone().unwrap();
+    self.store(move |conn| {
+      let stale = ip::get_stale_ips(conn, &resolved_before, geofetcher::REFRESH_BATCH).unwrap_or_default();
+      tx.send(Action::GotStaleIPs(stale)).unwrap_or_default();
+      Ok(())
+    });
+  }
+
+  /// Stores the new lookup of a stale IP, a move to another location updates the counters in the same job.
+  fn store_refreshed_geo(&mut self, ip: &str, result: Result<ip::IP, String>) {
+    let Ok(mut fresh) = result else {
+      self.finish_refresh(ip, RefreshOutcome::Failed);
+      return;
+    };
+    fresh.ip = ip.to_string();
+    let tx = self.action_tx.clone().unwrap();
+    let symb = self.apptheme.symbol_reqwest.clone();
+    let resolved_at = chrono::offset::Local::now().to_rfc3339();
+    self.store(move |conn| {
+      let moved = (|| -> ConnectionResult<Option<ip::IPChange>> {
+        statistics::insert_location(conn, &fresh)?;
+        let change = ip::update_ip_location(conn, &fresh, &resolved_at)?;
+        if let Some(change) = &change {
+          statistics::move_ip_aggregates(conn, change)?;
+        }
+        Ok(change)
+      })();
+      let outcome = match &moved {
+        Ok(Some(change)) => {
+          tx.send(Action::InternalLog(format!(" {} IP {} moved from {}, {} ({}) to {}, {} ({})", symb, fresh.ip,
+            change.old_city, change.old_country, change.old_isp, change.new_city, change.new_country, change.new_isp))).unwrap_or_default();
+          RefreshOutcome::Moved
+        },
+        Ok(None) => RefreshOutcome::Unchanged,
+        Err(_) => RefreshOutcome::Failed,
+      };
+      tx.send(Action::GeoRefreshed(fresh.ip, outcome)).unwrap_or_default();
+      // a failed move is rolled back as a whole
+      moved.map(|_| ())
+    });
+  }
+
+  /// Counts the stored lookup of a stale IP, logs the summary once the round is complete.
+  fn finish_refresh(&mut self, ip: &str, outcome: RefreshOutcome) {
+    if outcome == RefreshOutcome::Failed {
+      let (ip, failed_at) = (ip.to_string(), chrono::offset::Local::now().to_rfc3339());
+      self.store(move |conn| ip::mark_refresh_failed(conn, &ip, &failed_at));
+    }
+    if let Some(summary) = self.geo_refresh.finish(ip, outcome) {
+      let tx = self.action_tx.clone().unwrap();
+      tx.send(Action::InternalLog(format!(" {} {}", self.apptheme.symbol_db, summary))).expect("LOG: Refresh message failed to send");
+    }
+  }
+
+  /// Runs a log line through geolocation, GotGeo stores it. `host` is the host the line was logged on.
+  fn ingest_line(&mut self, x: String, host: String) -> Result<()> {
+    // got new line
+    let re = self.apptheme.ipregex.clone();
+
+    let results: Vec<&str> = re
+      .captures_iter(&x)
+      .filter_map(|capture| capture.get(1).map(|m| m.as_str()))
+      .collect();
+    let cip: &str;
+    // filtered for IP
+
+
+    if !results.is_empty() {
+      cip = results[0];
+      // string contained an IPv4
+      let mut is_banned = false;
+      // other hosts' fail2ban bans in their own firewall
+      if x.contains("Ban") && host == self.local_host {
+        is_banned = true;
+      }
+
+      // fail2ban's banned IPs, asked for in the background
+      if jail_lists(&self.jail_status, cip) {
+        is_banned = true;
+      }
+      self.last_ip = String::from(cip);
+
+      let ip = String::from(cip);
+      let parked = ParkedLine { line: x.clone(), host, is_banned, parked_at: chrono::offset::Local::now().to_rfc3339() };
+      let tx = self.action_tx.clone().unwrap();
+      self.store(move |conn| {
+        match ip::select_ip(conn, &ip).unwrap_or_default() {
+          Some(mut maybe_data) => {
+            // data is stored
+            metrics::inc(&METRICS.geo_db_hits);
+            maybe_data.is_banned = parked.is_banned;
+            tx.send(Action::GotGeo(maybe_data, parked.line, true, parked.host)).unwrap_or_default();  // return true, GeoData came from DB
+          },
+          // we have to fetch the data, the line waits until the location is known
+          None => {tx.send(Action::GeoUnknown(ip, parked)).unwrap_or_default();},
+        }
+        Ok(())
+      });
+
+
+    } else {
+      // results were empty, might happen if journalctl sends error message -> in that case just insert the last ip into the message and try again
+      metrics::inc(&METRICS.parse_failures);
+      // only for local lines, the last IP may belong to a different host otherwise
+      if !self.last_ip.is_empty() && host == self.local_host {
+        let msg = x.clone();
+        let msg = format!("{} for {}", msg, self.last_ip);
+        self.action_tx.clone().unwrap().send(Action::IONotify(msg))?;
+      }
+
+    }
+    Ok(())
+  }
+
+  /// Parks a line whose IP is not in the DB until the location of the IP is known.
+  fn park_line(&mut self, ip: String, parked: ParkedLine) -> Result<()> {
+    if let Some(geodata) = self.located.get(&ip) {
+      // located meanwhile, the first line of the IP is still on its way to the DB
+      let mut geodata = geodata.clone();
+      geodata.created_at = parked.parked_at;
+      geodata.is_banned = parked.is_banned;
+      self.update(Action::GotGeo(geodata, parked.line, true, parked.host))?;
+      return Ok(());
+    }
+    if self.parked_lines.park(&ip, parked) {
+      metrics::inc(&METRICS.geo_fetches);
+      if let Some(lookups) = &self.geo_lookups {lookups.request(&ip);}
+    }
+    let (ips, lines) = self.parked_lines.depth();
+    self.action_tx.clone().unwrap().send(Action::GeoQueueDepth(ips, lines))?;
+    Ok(())
+  }
+
   pub fn render_tick(&mut self) {
     log::debug!("Render Tick");
     self.elapsed_frames += 1.;
@@ -288,6 +647,41 @@ impl Component for Startup <'_> {
     Ok(())
   }
 
+  fn register_config_handler(&mut self, config: Config) -> Result<()> {
+    for error in rules::validate(&config.rules) {
+      error!("{}", error);
+    }
+    self.rules = Arc::new(std::sync::Mutex::new(RulesEngine::new(config.rules.clone())));
+    for error in notifications::validate(&config.notifications) {
+      error!("{}", error);
+    }
+    self.notifier = Notifier::new(config.notifications.clone());
+    if let Some(path) = &config.event_stream.output {
+      match EventWriter::open(path).and_then(|writer| EventStream::spawn(writer, self.action_tx.clone())) {
+        Ok(stream) => self.event_stream = Some(stream),
+        Err(e) => error!("Event stream {}: {}", path.display(), e),
+      }
+    }
+    let (threat_intel, errors) = ThreatIntel::load(&config.blocklists);
+    for error in errors {
+      error!("{}", error);
+    }
+    self.threat_intel = threat_intel;
+    let (cloud_ranges, errors) = CloudRanges::load(&config.cloud_ranges);
+    for error in errors {
+      error!("{}", error);
+    }
+    self.cloud_ranges = cloud_ranges;
+    self.geo_lookups = Some(geofetcher::spawn(config.geolocation.clone(), self.action_tx.clone()));
+    for error in forwarding::validate(&config.forwarders) {
+      error!("{}", error);
+    }
+    let spill_dir = utils::get_data_dir();
+    self.forwarders = config.forwarders.iter().map(|forwarder| forwarding::spawn(forwarder.clone(), &spill_dir, self.action_tx.clone())).collect();
+    self.config = config;
+    Ok(())
+  }
+
   fn handle_key_events(&mut self, key: KeyEvent) -> Result<Option<Action>> {
     self.last_events.push(key.clone());
     let action = match self.mode {
@@ -313,12 +707,20 @@ impl Component for Startup <'_> {
   fn update(&mut self, action: Action) -> Result<Option<Action>> {
     let tx = self.action_tx.clone().unwrap();
     match action {
-      Action::Tick => {self.tick()},
+      Action::Tick => {self.tick(); self.expire_bans(); self.refresh_geodata(); self.refresh_jail_status();},
       Action::Render => self.render_tick(),
       Action::StartupDone => {self.mode = Mode::Completed;
         let tx = self.action_tx.clone().unwrap();
         let fetchmsg = format!(" ✔ Startup Complete");
         tx.send(Action::InternalLog(fetchmsg)).expect("Fetchlog message failed to send");
+        // alerts stay until acknowledged, also across restarts
+        self.store(move |conn| {
+          let alerts = alert::get_unacknowledged_alerts(conn).unwrap_or(vec![]);
+          for alert in alerts {
+            tx.send(Action::GotAlert(alert)).unwrap_or_default();
+          }
+          Ok(())
+        });
       }
       Action::StartupConnect => {
         let dt = Utc::now();
@@ -368,9 +770,14 @@ impl Component for Startup <'_> {
 
         self.log_messages.push(format!("{}            Connecting to db", dt.to_string()));
 
-        let conn = Connection::open("iplogs.db")?;
-
-        self.dbconn = Some(conn);
+        let storage = Storage::open(Path::new("iplogs.db"), query::register_sql_functions)?;
+        if self.config.enrichment.enabled {
+          self.enricher = Some(enrichment::spawn(self.config.enrichment.clone(), storage.clone(), self.action_tx.clone()));
+        }
+        if let Some(listen) = self.config.metrics.listen.clone() {
+          serve_metrics(listen, storage.clone(), self.action_tx.clone().unwrap());
+        }
+        self.storage = Some(storage);
         self.create_db();
 
         self.get_initial_stats();
@@ -378,462 +785,761 @@ impl Component for Startup <'_> {
         self.mode = Mode::Done;
         
       },
-      Action::IONotify(ref x) => {
-        // got new line
-        let x = x.clone().deref().to_string();
-        let re = self.apptheme.ipregex.clone();
-
-        let results: Vec<&str> = re
-          .captures_iter(&x)
-          .filter_map(|capture| capture.get(1).map(|m| m.as_str()))
-          .collect();
-        let cip: &str;
-        // filtered for IP
-
-
-        if !results.is_empty() {
-          cip = results[0];
-          // string contained an IPv4
-          let mut is_banned = false;
-          if x.contains("Ban") {
-            is_banned = true;
-          }
-
-          if self.last_ip != String::from(cip) {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            // check if is banned
-            let output = std::process::Command::new("fail2ban-client")
-              .arg("status")
-              .arg("sshd")
-              // Tell the OS to record the command's output
-              .stdout(std::process::Stdio::piped())
-              // execute the command, wait for it to complete, then capture the output
-              .output()
-              // Blow up if the OS was unable to start the program
-              .unwrap();
-    
-            // extract the raw bytes that we captured and interpret them as a string
-            let stdout = String::from_utf8(output.stdout).unwrap();
-            
-            if stdout.contains(cip) {
-              is_banned = true;
+      Action::IONotify(x) => {
+        self.ingest_line(x, self.local_host.clone())?;
+      },
+      Action::SyslogLine(msg) => {
+        self.ingest_line(msg.line, msg.hostname)?;
+      },
+      Action::StorageReady(lines) => {
+        self.log_messages.extend(lines);
+        let dt = Utc::now();
+        self.log_messages.push(format!("{}            db ready", dt.to_string()));
+      },
+      Action::GeoUnknown(x, parked) => {
+        self.park_line(x, parked)?;
+      },
+      Action::GotGeoLookup(x, result) if self.geo_refresh.is_pending(&x) => {
+        self.store_refreshed_geo(&x, result);
+      },
+      Action::GeoRefreshed(x, outcome) => {
+        self.finish_refresh(&x, outcome);
+      },
+      Action::GotStaleIPs(stale) => {
+        if let Some(lookups) = &self.geo_lookups {
+          for ip in stale.iter() {
+            lookups.request(ip);
+          }
+        }
+        self.geo_refresh.start(&stale, std::time::Instant::now());
+      },
+      Action::GotExpiredBans(expired_bans) => {
+        self.unban_or_escalate(expired_bans);
+      },
+      Action::GotJailStatus(status) => {
+        self.jail_status = status;
+      },
+      Action::GotGeoLookup(x, result) => {
+        let parked = self.parked_lines.release(&x);
+        let tx = self.action_tx.clone().unwrap();
+        match result {
+          Ok(geodata) => {
+            // stored right away, so a line coming in meanwhile finds the IP in the DB instead of asking again.
+            // The first line stores the IP, the others count as warnings of a stored IP like lines that came in later
+            let first_banned = parked.first().is_some_and(|line| line.is_banned);
+            if !parked.is_empty() {
+              self.located.insert(x.clone(), geodata.clone());
             }
-          };
-
-          //let mut is_in_list: bool = false;
-          let conn = self.dbconn.as_ref().unwrap();
-          //let conn2 = conn.clone();
-
-          let mut maybe_data = ip::select_ip(conn, cip).unwrap_or_default().take().unwrap_or_default();
-
-        
-          if maybe_data == ip::IP::default() {
-            // we have to fetch the data
-            let timestamp = chrono::offset::Local::now().to_rfc3339();
-  
-
-            self.last_ip = String::from(cip);
-
-            let req_ip = cip.to_string();
-            let sender = self.action_tx.clone().unwrap();
-            self.fetching_ips.push(req_ip.clone());
+            for (idx, line) in parked.into_iter().enumerate() {
+              let mut geodata = geodata.clone();
+              geodata.created_at = line.parked_at;
+              geodata.is_banned = line.is_banned;
+              geodata.banned_times = if first_banned {1} else {0};
+              geodata.warnings = idx.max(1);
+              self.update(Action::GotGeo(geodata, line.line, idx > 0, line.host))?;
+            }
+          },
+          Err(e) => {
+            tx.send(Action::InternalLog(format!(" {} Location of {} not found, {} lines dropped: {}", self.apptheme.symbol_error, x, parked.len(), e))).expect("LOG: Geolocation error failed to send");
+          },
+        }
+        let (ips, lines) = self.parked_lines.depth();
+        tx.send(Action::GeoQueueDepth(ips, lines)).expect("GeoQueueDepth failed to send");
+      },
+      Action::GotGeo(x, y, z, host) => {
+        // Guard: if GeoData is from DB we return immediately, to not insert it again -> yes ofc insert it again.. how else to update u dingus?!
+        //if z {return Ok(Option::None);}
+        let tags = self.ip_tags(&x.ip);
+        let listed = self.threat_intel.auto_ban(&x.ip);
+        let cloud = self.cloud_ranges.lookup(&x.ip).cloned();
+        if let Some(enricher) = &self.enricher {enricher.request(&x.ip);}
+        let known_good = alerting::is_known_good(&x.ip, &self.config.alerts.known_good);
+        let rules = self.rules.clone();
+        let local_host = self.local_host.clone();
+        let theme = self.apptheme.clone();
+        let tx = self.action_tx.clone().unwrap();
+        // one job per line, the line's Actions are sent from the storage thread
+        self.store(move |conn| {
+          let started = std::time::Instant::now();
 
-            let handle = tokio::task::spawn(async move {
-              // perform some work here...
-              
-              let geodat = geofetcher::fetch_geolocation(req_ip.as_str()).await.unwrap_or(serde_json::Value::default());
 
-              let geoip = String::from(geodat.get("query").unwrap().as_str().unwrap());
-              let geolat = geodat.get("lat").unwrap().as_number().unwrap().to_string(); // CRASH ON PI
-              let geolon = geodat.get("lon").unwrap().as_number().unwrap().to_string();
-              let geoisp = String::from(geodat.get("isp").unwrap().as_str().unwrap());
-  
-              let geocountry = String::from(geodat.get("country").unwrap().as_str().unwrap());
-              let geocity = String::from(geodat.get("city").unwrap().as_str().unwrap());
-              let geocountrycode = String::from(geodat.get("countryCode").unwrap().as_str().unwrap());
-              let georegionname = String::from(geodat.get("regionName").unwrap().as_str().unwrap());
-  
-  
-              let mut geodata: ip::IP = ip::IP::default();
-              geodata.created_at = timestamp;
-              geodata.ip = geoip;
-              geodata.lat = geolat;
-              geodata.lon = geolon;
-              geodata.isp = geoisp;
-              geodata.is_banned = is_banned;
-              geodata.banned_times = match is_banned {false => 0, true => 1};
-              geodata.country = geocountry;
-              geodata.countrycode = geocountrycode;
-              geodata.city = geocity;
-              geodata.region = georegionname;
-              geodata.warnings = 1;
+          //let mut ip = ip::select_ip(conn, x.ip.as_str()).unwrap_or_default().unwrap_or_default(); // check if freshly acquired geodata has ip thats in db already, already done
+          let ip_in_db: bool = z;
 
+          //if ip == ip::IP::default() {
+          //  ip_in_db = false;
+          //}
+          let mut ip = x.clone();
 
-            
-              sender.send(Action::GotGeo(geodata, x.clone(), false)).unwrap_or_default(); // false, GeoData was acquired freshly
-            });
-            
+          let mut country = country::select_country(conn, x.country.as_str()).unwrap_or_default().unwrap_or_default();
+          if country == country::Country::default() {
+            country::insert_new_country(conn, x.country.as_str(), Some(x.countrycode.as_str()), Some(0), Some(1), false)?;
           }
           else {
-            // data is stored
-            self.last_ip = String::from(cip);
-            maybe_data.is_banned = is_banned;
-            self.action_tx.clone().unwrap().send(Action::GotGeo(maybe_data, x.clone(), true))?;  // return true, GeoData came from DB
+            country.warnings += 1;
+            country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), country.is_blocked)?;
           }
 
-
-        } else {
-          // results were empty, might happen if journalctl sends error message -> in that case just insert the last ip into the message and try again
-          if !self.last_ip.is_empty() {
-            let msg = x.clone();
-            let msg = format!("{} for {}", msg, self.last_ip);
-            self.action_tx.clone().unwrap().send(Action::IONotify(msg))?;
+          let mut region = region::select_region(conn, x.region.as_str()).unwrap_or_default().unwrap_or_default();
+          if region == region::Region::default() {
+            region::insert_new_region(conn, x.region.as_str(), x.country.as_str(), Some(0), Some(1), false)?;
+          }
+          else {
+            region.warnings += 1;
+            region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), region.is_blocked)?;
           }
 
-        }
-
-      },
-      Action::GotGeo(x, y, z) => {
-        // Guard: if GeoData is from DB we return immediately, to not insert it again -> yes ofc insert it again.. how else to update u dingus?!
-        //if z {return Ok(Option::None);} 
-      
-        let conn = self.dbconn.as_ref().unwrap();
-
-        //let mut ip = ip::select_ip(conn, x.ip.as_str()).unwrap_or_default().unwrap_or_default(); // check if freshly acquired geodata has ip thats in db already, already done
-        let ip_in_db: bool = z;
-
-        //if ip == ip::IP::default() {
-        //  ip_in_db = false;
-        //}
-        let mut ip = x.clone();
-
-        let mut country = country::select_country(conn, x.country.as_str()).unwrap_or_default().unwrap_or_default();
-        if country == country::Country::default() {
-          let _ = country::insert_new_country(conn, x.country.as_str(), Some(x.countrycode.as_str()), match x.is_banned {false => Some(0), true => Some(1)}, Some(1), false).unwrap();
-        }
-        else {
-          country.warnings += 1;
-          if !ip_in_db && x.is_banned {country.banned += 1;}
-          let _ = country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), country.is_blocked).unwrap();
-        }
-
-        let mut region = region::select_region(conn, x.region.as_str()).unwrap_or_default().unwrap_or_default();
-        if region == region::Region::default() {
-          let _ = region::insert_new_region(conn, x.region.as_str(), x.country.as_str(), match x.is_banned {false => Some(0), true => Some(1)}, Some(1), false).unwrap();
-        }
-        else {
-          region.warnings += 1;
-          if !ip_in_db && x.is_banned {region.banned += 1;}
-          let _ = region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), region.is_blocked).unwrap();
-        }
-
-        let mut city = city::select_city(conn, x.city.as_str()).unwrap_or_default().unwrap_or_default();
-        if city == city::City::default() {
-          let _ = city::insert_new_city(conn, x.city.as_str(), x.country.as_str(), x.region.as_str(), match x.is_banned {false => Some(0), true => Some(1)}, Some(1), false).unwrap();
-        }
-        else {
-          city.warnings += 1;
-          if !ip_in_db && x.is_banned {city.banned += 1;}
-          let _ = city::insert_new_city(conn, city.name.as_str(), city.country.as_str(),city.region.as_str(), Some(city.banned), Some(city.warnings), city.is_blocked).unwrap();
-        }
+          let mut city = city::select_city(conn, x.city.as_str()).unwrap_or_default().unwrap_or_default();
+          if city == city::City::default() {
+            city::insert_new_city(conn, x.city.as_str(), x.country.as_str(), x.region.as_str(), Some(0), Some(1), false)?;
+          }
+          else {
+            city.warnings += 1;
+            city::insert_new_city(conn, city.name.as_str(), city.country.as_str(),city.region.as_str(), Some(city.banned), Some(city.warnings), city.is_blocked)?;
+          }
 
-        let mut isp: isp::ISP = isp::select_isp(conn, x.isp.as_str()).unwrap_or_default().unwrap_or_default();
-        if isp == isp::ISP::default() {
-          let _ = isp::insert_new_ISP(conn, x.isp.as_str(), match x.is_banned {false => Some(0), true => Some(1)}, Some(1), x.country.as_str(), false).unwrap();
-        }
-        else {
-          isp.warnings += 1;
-          if !ip_in_db && x.is_banned {isp.banned += 1;}
-          let _ = isp::insert_new_ISP(conn, isp.name.as_str(), Some(isp.banned), Some(isp.warnings), x.country.as_str(), isp.is_blocked).unwrap();
-        }
+          let mut isp: isp::ISP = isp::select_isp(conn, x.isp.as_str()).unwrap_or_default().unwrap_or_default();
+          if isp == isp::ISP::default() {
+            isp::insert_new_ISP(conn, x.isp.as_str(), Some(0), Some(1), x.country.as_str(), false)?;
+          }
+          else {
+            isp.warnings += 1;
+            isp::insert_new_ISP(conn, isp.name.as_str(), Some(isp.banned), Some(isp.warnings), x.country.as_str(), isp.is_blocked)?;
+          }
         
-        if !ip_in_db {
-          let _ = ip::insert_new_IP(conn, 
-            x.ip.as_str(), x.created_at.as_str(), 
-            x.lon.as_str(), x.lat.as_str(), 
-            x.isp.as_str(), x.city.as_str(), 
-            Some(x.region.as_str()), x.country.as_str(),
-            Some(x.countrycode.as_str()), x.banned_times, 
-              x.is_banned, x.warnings).unwrap();
-        }
-        else {
-          // ip is in db
-          ip.warnings += 1;
-          let _ = ip::insert_new_IP(conn,
-            x.ip.as_str(), x.created_at.as_str(), 
-            x.lon.as_str(), x.lat.as_str(), 
-            x.isp.as_str(), x.city.as_str(), 
-            Some(x.region.as_str()), x.country.as_str(),
-            Some(x.countrycode.as_str()), x.banned_times, 
-              x.is_banned, ip.warnings).unwrap();
-        }
+          if !ip_in_db {
+            ip::insert_new_IP(conn, 
+              x.ip.as_str(), x.created_at.as_str(), 
+              x.lon.as_str(), x.lat.as_str(), 
+              x.isp.as_str(), x.city.as_str(), 
+              Some(x.region.as_str()), x.country.as_str(),
+              Some(x.countrycode.as_str()), x.banned_times, 
+                x.is_banned, x.warnings)?;
+            ip::set_ip_asn(conn, &x.ip, &x.asn)?;
+          }
+          else {
+            // ip is in db, counted on from what is stored, an earlier line may have been stored since it was read
+            ip.warnings = ip::select_ip(conn, &x.ip).unwrap_or_default().map_or(ip.warnings, |stored| stored.warnings) + 1;
+            ip::insert_new_IP(conn,
+              x.ip.as_str(), x.created_at.as_str(), 
+              x.lon.as_str(), x.lat.as_str(), 
+              x.isp.as_str(), x.city.as_str(), 
+              Some(x.region.as_str()), x.country.as_str(),
+              Some(x.countrycode.as_str()), x.banned_times, 
+                x.is_banned, ip.warnings)?;
+          }
 
-        let mut is_jctl: bool = true;
-        let mut is_ban: bool = false;
-        if y.contains("++++") {
-          is_jctl = false;
-          if y.contains("Ban") {
-            is_ban = true;
+          let mut is_jctl: bool = true;
+          let mut is_ban: bool = false;
+          if y.contains("++++") {
+            is_jctl = false;
+            if y.contains("Ban") {
+              is_ban = true;
+            }
           }
-        }
-        let tx = self.action_tx.clone().unwrap();
-        tx.send(Action::PassGeo(ip.clone(), y.clone(), z)).expect("PassGeo failed to send");
-        let symb = if z {self.apptheme.symbol_db.clone()} else {self.apptheme.symbol_reqwest.clone()};
-        let fetchmsg = format!(" {} Got location for IP {} ", symb, ip.ip);
-        tx.send(Action::InternalLog(fetchmsg)).expect("Fetchlog message failed to send");
+          // fail2ban may deliver several lines at once
+          let mut usernames: Vec<String> = y.split("++++").filter_map(username::extract_username).collect();
+          usernames.sort();
+          usernames.dedup();
+          let blocked_usernames: Vec<String> = usernames.iter()
+            .filter(|name| username::select_username(conn, name).unwrap_or_default().is_some_and(|u| u.is_blocked))
+            .cloned().collect();
+
+          if let Some(range) = &cloud {
+            provider::insert_ip_cloud(conn, &x.ip, range).unwrap_or_default();
+          }
+          let blocked_provider = cloud.filter(|range| provider::is_provider_blocked(conn, &range.provider).unwrap_or_default());
 
-        if country.is_blocked || city.is_blocked || isp.is_blocked || region.is_blocked {         
-          tx.send(Action::BanIP(x.clone())).expect("Block failed to send");
-          let timestamp = chrono::offset::Local::now().to_rfc3339();
+          if !tags.is_empty() {
+            tx.send(Action::GotTags(x.ip.clone(), tags.clone())).unwrap_or_default();
+          }
+          tx.send(Action::PassGeo(ip.clone(), y.clone(), z)).unwrap_or_default();
+          let symb = if z {theme.symbol_db.clone()} else {theme.symbol_reqwest.clone()};
+          let fetchmsg = format!(" {} Got location for IP {} ", symb, ip.ip);
+          tx.send(Action::InternalLog(fetchmsg)).unwrap_or_default();
+
+          let is_blocked = country.is_blocked || city.is_blocked || isp.is_blocked || region.is_blocked || !blocked_usernames.is_empty() || !listed.is_empty() || blocked_provider.is_some();
+          let refused = if is_blocked {allowlist::refuse_reason(conn, &x.ip).unwrap_or_else(|e| Some(format!("allowlist check failed: {}", e)))} else {None};
           let mut reasons: Vec<String> = vec![];
           if country.is_blocked {reasons.push(format!("Country: {}", country.name));}
           if region.is_blocked {reasons.push(format!("Region: {}", region.name));}
           if city.is_blocked {reasons.push(format!("City: {}", city.name));}
           if isp.is_blocked {reasons.push(format!("ISP: {}", isp.name));}
+          for name in blocked_usernames.iter() {reasons.push(format!("Username: {}", name));}
+          for name in listed.iter() {reasons.push(format!("List: {}", name));}
+          if let Some(range) = &blocked_provider {reasons.push(format!("Provider: {}", range.provider));}
+          if let Some(reason) = &refused {
+            tx.send(Action::InternalLog(format!(" {} Refused to block IP {}: {}", theme.symbol_error, ip.ip, reason))).unwrap_or_default();
+          } else if is_blocked {
+            let timestamp = chrono::offset::Local::now().to_rfc3339();
+            let request = BanRequest { bantime: None, reason: reasons.join(", "), origin: BanOrigin::Block };
+            tx.send(Action::BanIP(x.clone(), request)).unwrap_or_default();
 
-          //let blockmsg = format!("{}    [succeed2ban.filter]      Blocked IP {} - Filter [ {} ] ", timestamp, ip.ip, reasons.join(" "));
-          //tx.send(Action::IONotify(blockmsg)).expect("Blocklog message failed to send");
+            //let blockmsg = format!("{}    [succeed2ban.filter]      Blocked IP {} - Filter [ {} ] ", timestamp, ip.ip, reasons.join(" "));
+            //tx.send(Action::IONotify(blockmsg)).unwrap_or_default();
 
-          let blockmsg = format!(" {} Blocked IP {} :", self.apptheme.symbol_block, ip.ip);
-          tx.send(Action::InternalLog(blockmsg)).expect("Blocklog message failed to send");
-          for reason in reasons {
-            let blockmsg = format!(" {} Blocked {} ",self.apptheme.symbol_block , reason);
-            tx.send(Action::InternalLog(blockmsg)).expect("Blocklog message failed to send");
+            let blockmsg = format!(" {} Blocked IP {} :", theme.symbol_block, ip.ip);
+            tx.send(Action::InternalLog(blockmsg)).unwrap_or_default();
+            for reason in reasons.iter() {
+              let blockmsg = format!(" {} Blocked {} ",theme.symbol_block , reason);
+              tx.send(Action::InternalLog(blockmsg)).unwrap_or_default();
+            }
           }
-        }
 
-        let timestamp = chrono::offset::Local::now().to_rfc3339();
+          let timestamp = chrono::offset::Local::now().to_rfc3339();
+
+          // successful logins are compared against the history before this line becomes part of it
+          for line in y.split("++++").filter(|line| alerting::is_accepted(line)) {
+            let user = username::extract_username(line).unwrap_or_default();
+            let reasons = if known_good {vec![]} else {
+              alerting::classify_accepted(conn, &x.ip, &x.country).unwrap_or_default()
+            };
+            let reason = reasons.iter().map(|r| r.to_string()).collect::<Vec<String>>().join("; ");
+            let login = if reason.is_empty() {format!("Login of {} from {} ({})", user, x.ip, x.country)} else {format!("Login of {} from {} ({}) - {}", user, x.ip, x.country, reason)};
+            tx.send(Action::Notify(NotifyEvent::now(EventKind::Login, &x.ip, login))).unwrap_or_default();
+            if reasons.is_empty() {continue;}
+            match alert::insert_new_alert(conn, &timestamp, &x.ip, &user, &x.country, &reason, line) {
+              Ok(alert) => {
+                tx.send(Action::InternalLog(format!(" {} Login alert: {} from {} - {}", theme.symbol_error, user, x.ip, reason))).unwrap_or_default();
+                tx.send(Action::GotAlert(alert)).unwrap_or_default();
+              },
+              Err(e) => {tx.send(Action::InternalLog(format!(" {} Storing alert failed: {}", theme.symbol_error, e))).unwrap_or_default();},
+            }
+          }
 
-        let _ = message::insert_new_message(conn, Option::None, &timestamp, &y, &x.ip, &x.country, &x.region, &x.city, &x.isp, is_jctl, is_ban).unwrap();
+          message::insert_new_message(conn, Option::None, &timestamp, &y, &x.ip, &x.country, &x.region, &x.city, &x.isp, is_jctl, is_ban, &host)?;
+          let message_id = conn.last_insert_rowid();
+          for name in usernames.iter() {
+            let _ = username::insert_username_for_message(conn, name, message_id);
+          }
+          let source = if host != local_host {"syslog"} else if is_jctl {"journalctl"} else {"fail2ban"};
+          metrics::inc(match source {"syslog" => &METRICS.events_syslog, "journalctl" => &METRICS.events_journalctl, _ => &METRICS.events_fail2ban});
+
+          // bans and unbans fail2ban did on its own, ours are already recorded and only get their log line.
+          // Those of other hosts did not happen in the local jail
+          for line in y.split("++++").filter(|_| host == local_host) {
+            match ban::parse_fail2ban_line(line) {
+              Some((jail, ban::Fail2banEvent::Ban, banned_ip)) => {
+                metrics::inc(&METRICS.bans_fail2ban);
+                ban::record_ban(conn, &banned_ip, &jail, &timestamp, ban::SOURCE_FAIL2BAN, "", ban::SOURCE_FAIL2BAN).unwrap_or_default();
+              },
+              Some((jail, ban::Fail2banEvent::Unban, unbanned_ip)) => {
+                let now = chrono::offset::Local::now().fixed_offset();
+                match ban::fail2ban_unbanned(conn, &unbanned_ip, &jail, &timestamp, now) {
+                  // the jail's bantime is shorter than ours
+                  Ok(Some(active)) => tx.send(Action::RestoreBan(active)).unwrap_or_default(),
+                  Ok(None) => ip::set_ip_banned(conn, &unbanned_ip, false).unwrap_or_default(),
+                  Err(e) => error!("Recording unban of {} failed: {}", unbanned_ip, e),
+                }
+              },
+              None => {},
+            }
+          }
+          statistics::refresh_banned_for_ip(conn, &x.ip).unwrap_or_default();
+
+          let hits = match rules.lock().expect("Rules engine lock poisoned").process(conn, message_id) {
+            Ok(hits) => hits,
+            Err(e) => {
+              tx.send(Action::InternalLog(format!(" {} Rules failed: {}", theme.symbol_error, e))).unwrap_or_default();
+              vec![]
+            },
+          };
+          let fired: Vec<String> = hits.iter().map(|hit| hit.rule.clone()).collect();
+          for hit in hits {
+            tx.send(Action::InternalLog(format!(" ⚙ Rule {} fired: {} events for {} → {:?}", hit.rule, hit.count, hit.key, hit.action))).unwrap_or_default();
+            tx.send(Action::Notify(NotifyEvent::now(EventKind::Rule, &x.ip, hit.to_string()))).unwrap_or_default();
+            match hit.action {
+              RuleAction::Ban => {
+                let target = ip::IP { ip: hit.target.clone(), is_banned: false, ..ip::IP::default() };
+                let request = BanRequest {
+                  bantime: hit.bantime.map(|bantime| bantime.num_seconds()),
+                  reason: format!("Rule {}: {} events for {}", hit.rule, hit.count, hit.key),
+                  origin: BanOrigin::Rule,
+                };
+                tx.send(Action::BanIP(target, request)).unwrap_or_default();
+              },
+              RuleAction::Alert => {
+                let user = usernames.first().cloned().unwrap_or_default();
+                let reason = format!("Rule {}: {} events for {}", hit.rule, hit.count, hit.key);
+                if let Ok(alert) = alert::insert_new_alert(conn, &hit.at, &x.ip, &user, &x.country, &reason, &y) {
+                  tx.send(Action::GotAlert(alert)).unwrap_or_default();
+                }
+              },
+              RuleAction::Log => {},
+            }
+          }
 
+          let event = eventstream::StreamEvent {
+            schema: eventstream::SCHEMA_VERSION,
+            id: message_id,
+            at: timestamp.clone(),
+            source: source.to_string(),
+            host: host.clone(),
+            lines: y.split("++++").filter(|line| !line.is_empty()).map(String::from).collect(),
+            usernames: usernames.clone(),
+            banned: ip.is_banned,
+            ip: ip.clone(),
+            blocked_by: reasons,
+            block_refused: refused,
+            rules: fired,
+            tags,
+          };
+          // written to the event stream and forwarded by the component, it holds their state
+          tx.send(Action::LineStored(event)).unwrap_or_default();
+          METRICS.db_write.observe(started.elapsed());
 
-        //self.stored_geo.push(x.clone()); 
+          //self.stored_geo.push(x.clone());
+          Ok(())
+        });
       },
-      Action::SubmitQuery(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let ip = ip::select_ip(conn, x.as_str()).unwrap_or_default().unwrap_or_default();
-
+      Action::LineStored(event) => {
+        // stored, later lines of the IP find it in the DB
+        self.located.remove(&event.ip.ip);
         let tx = self.action_tx.clone().unwrap();
-
-        let opmsgs = message::select_message_by_ip(conn, x.as_str()).unwrap();
-        let mut actmsgs: Vec<message::Message> = vec![];
-
-        for opmsg in opmsgs {
-          let msg = opmsg.unwrap_or(message::Message::default());
-          if msg != message::Message::default() {actmsgs.push(msg);}
+        for forwarder in self.forwarders.iter() {
+          if let Err(e) = forwarder.send(event.clone()) {
+            tx.send(Action::InternalLog(format!(" {} Forwarder {} dropped event {}: {}", self.apptheme.symbol_error, forwarder.name, event.id, e))).expect("Forwarder error failed to send");
+          }
+        }
+        // the writer thread reports its failure
+        if self.event_stream.as_ref().is_some_and(|stream| !stream.send(event)) {
+          self.event_stream = None;
         }
-
-        if ip == ip::IP::default() {
-          // send back query not found
-          tx.send(Action::QueryNotFound(x)).expect("QueryNotFound failed to send!");
-        } else {
-          // spawn thread to send debounced messages
-          tokio::spawn(async move{
-            for msg in actmsgs {
-              tx.send(Action::PassGeo(ip.clone(), msg.text, true)).expect("PassGeo failed to send on query!"); 
-              tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;}
-              // inefficient but else but require me to set up a duplicate receiver or refactor receive function
-          });
-        }        
       },
-      Action::StatsGetCountries => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let countries = country::get_all_countries(conn).unwrap_or(vec![]);
+      Action::SubmitQuery(x) => {
         let tx = self.action_tx.clone().unwrap();
-        tokio::spawn(async move {
-          let conn = Connection::open("iplogs.db").expect("Async thread DB connection failed");
-          for country in countries {
-            std::thread::sleep(std::time::Duration::from_millis(10)); // Debounce
-            let timestamps = message::get_message_timestamps_by_country(&conn, &country.name).unwrap_or(vec![]);
-            tx.send(Action::StatsGotCountry(country, timestamps)).expect("Failed to send Country to Stats");
-         }
+        let symb_error = self.apptheme.symbol_error.clone();
+        self.store(move |conn| {
+          match query::run_query(conn, x.as_str(), query::QUERY_RESULT_LIMIT) {
+            Ok(result) if result.total > 0 => {
+              tx.send(Action::GotQueryResult(result)).unwrap_or_default();
+            },
+            Ok(_) => {
+              tx.send(Action::QueryNotFound(x)).unwrap_or_default();
+            },
+            Err(e) => {
+              tx.send(Action::InternalLog(format!(" {} Query failed: {}", symb_error, e))).unwrap_or_default();
+              tx.send(Action::QueryNotFound(x)).unwrap_or_default();
+            },
+          }
+          Ok(())
         });
-
+      },
+      Action::StatsGetCountries => {
+        self.send_stats(country::get_all_countries, |conn, country| message::get_message_timestamps_by_country(conn, &country.name), Action::StatsGotCountry);
       },
       Action::StatsGetRegions => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let regions = region::get_all_regions(conn).unwrap_or(vec![]);
-        let tx = self.action_tx.clone().unwrap();
-        tokio::spawn(async move {
-          let conn = Connection::open("iplogs.db").expect("Async thread DB connection failed");
-          for region in regions {
-            std::thread::sleep(std::time::Duration::from_millis(10)); // Debounce
-            let timestamps = message::get_message_timestamps_by_region(&conn, &region.name).unwrap_or(vec![]);
-            tx.send(Action::StatsGotRegion(region, timestamps)).expect("Failed to send Region to Stats");
-         }
-        });
-
+        self.send_stats(region::get_all_regions, |conn, region| message::get_message_timestamps_by_region(conn, &region.name), Action::StatsGotRegion);
       },
       Action::StatsGetISPs => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let isps = isp::get_all_isps(conn).unwrap_or(vec![]);
-        let tx = self.action_tx.clone().unwrap();
-        tokio::spawn(async move {
-          let conn = Connection::open("iplogs.db").expect("Async thread DB connection failed");
-          for isp in isps {
-            std::thread::sleep(std::time::Duration::from_millis(10)); // Debounce
-            let timestamps = message::get_message_timestamps_by_isp(&conn, &isp.name).unwrap_or(vec![]);
-            tx.send(Action::StatsGotISP(isp, timestamps)).expect("Failed to send ISP to Stats");
-         }
-        });
+        self.send_stats(isp::get_all_isps, |conn, isp| message::get_message_timestamps_by_isp(conn, &isp.name), Action::StatsGotISP);
       },
       Action::StatsGetCities => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let cities = city::get_all_cities(conn).unwrap_or(vec![]);
-        let tx = self.action_tx.clone().unwrap();
-        tokio::spawn(async move {
-          let conn = Connection::open("iplogs.db").expect("Async thread DB connection failed");
-          for city in cities {
-            std::thread::sleep(std::time::Duration::from_millis(10)); // Debounce
-            let timestamps = message::get_message_timestamps_by_city(&conn, &city.name).unwrap_or(vec![]);
-            tx.send(Action::StatsGotCity(city, timestamps)).expect("Failed to send City to Stats");
-         }
-        });
+        self.send_stats(city::get_all_cities, |conn, city| message::get_message_timestamps_by_city(conn, &city.name), Action::StatsGotCity);
+      },
+      Action::StatsGetUsernames => {
+        self.send_stats(username::get_all_usernames, |conn, user| message::get_message_timestamps_by_username(conn, &user.name), Action::StatsGotUsername);
+      },
+      Action::StatsGetHosts => {
+        self.send_stats(host::get_all_hosts, |conn, host| message::get_message_timestamps_by_host(conn, &host.name), Action::StatsGotHost);
+      },
+      Action::StatsGetProviders => {
+        self.send_stats(provider::get_all_providers, |conn, provider| message::get_message_timestamps_by_provider(conn, &provider.name), Action::StatsGotProvider);
       },
 
       Action::StatsBlockCountry(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let country = country::select_country(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as blocked
-        let _ = country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), true).unwrap();
+        let name = x.name.clone();
+        self.store_audited("block country", &x.name, move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let country = country::select_country(conn, name.as_str())?.unwrap_or_default();
+          // insert new as blocked
+          country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), true)
+        });
         let fetchmsg = format!(" {} Blocked Country: {}", self.apptheme.symbol_block, &x.name);
         tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block Country message failed to send");
       },
       Action::StatsUnblockCountry(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let country = country::select_country(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as unblocked
-        let _ = country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), false).unwrap();
+        let name = x.name.clone();
+        self.store_audited("unblock country", &x.name, move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let country = country::select_country(conn, name.as_str())?.unwrap_or_default();
+          // insert new as unblocked
+          country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), false)
+        });
         let fetchmsg = format!(" {} Unblocked Country: {}", self.apptheme.symbol_unblock, &x.name);
         tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock Country message failed to send");
       },      
       Action::StatsBlockRegion(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let region = region::select_region(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as blocked
-        let _ = region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), true).unwrap();
+        let name = x.name.clone();
+        self.store_audited("block region", &x.name, move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let region = region::select_region(conn, name.as_str())?.unwrap_or_default();
+          // insert new as blocked
+          region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), true)
+        });
         let fetchmsg = format!(" {} Blocked Region: {}", self.apptheme.symbol_block, &x.name);
         tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block Region message failed to send");
       },
       Action::StatsUnblockRegion(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let region = region::select_region(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as unblocked
-        let _ = region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), false).unwrap();
+        let name = x.name.clone();
+        self.store_audited("unblock region", &x.name, move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let region = region::select_region(conn, name.as_str())?.unwrap_or_default();
+          // insert new as unblocked
+          region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), false)
+        });
         let fetchmsg = format!(" {} Unblocked Region: {}", self.apptheme.symbol_unblock, &x.name);
         tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock Region message failed to send");
       }, 
       Action::StatsBlockCity(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let city = city::select_city(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as blocked
-        let _ = city::insert_new_city(conn, city.name.as_str(), city.country.as_str(), city.region.as_str(),Some(city.banned), Some(city.warnings), true).unwrap();
+        let name = x.name.clone();
+        self.store_audited("block city", &x.name, move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let city = city::select_city(conn, name.as_str())?.unwrap_or_default();
+          // insert new as blocked
+          city::insert_new_city(conn, city.name.as_str(), city.country.as_str(), city.region.as_str(),Some(city.banned), Some(city.warnings), true)
+        });
         let fetchmsg = format!(" {} Blocked City: {}", self.apptheme.symbol_block, &x.name);
         tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block City message failed to send");
       },
       Action::StatsUnblockCity(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let city = city::select_city(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as unblocked
-        let _ = city::insert_new_city(conn, city.name.as_str(), city.country.as_str(), city.region.as_str(),Some(city.banned), Some(city.warnings), false).unwrap();
+        let name = x.name.clone();
+        self.store_audited("unblock city", &x.name, move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let city = city::select_city(conn, name.as_str())?.unwrap_or_default();
+          // insert new as unblocked
+          city::insert_new_city(conn, city.name.as_str(), city.country.as_str(), city.region.as_str(),Some(city.banned), Some(city.warnings), false)
+        });
         let fetchmsg = format!(" {} Unblocked City: {}", self.apptheme.symbol_unblock, &x.name);
         tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock City message failed to send");
       },    
       Action::StatsBlockISP(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let isp = isp::select_isp(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as blocked
-        let _ = isp::insert_new_ISP(conn, isp.name.as_str(),Some(isp.banned), Some(isp.warnings),isp.country.as_str(), true).unwrap();
+        let name = x.name.clone();
+        self.store_audited("block isp", &x.name, move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let isp = isp::select_isp(conn, name.as_str())?.unwrap_or_default();
+          // insert new as blocked
+          isp::insert_new_ISP(conn, isp.name.as_str(),Some(isp.banned), Some(isp.warnings),isp.country.as_str(), true)
+        });
         let fetchmsg = format!(" {} Blocked ISP: {}", self.apptheme.symbol_unblock, &x.name);
         tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block ISP message failed to send");
       },
       Action::StatsUnblockISP(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let isp = isp::select_isp(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as unblocked
-        let _ = isp::insert_new_ISP(conn, isp.name.as_str(), Some(isp.banned), Some(isp.warnings),isp.country.as_str(), false).unwrap();
+        let name = x.name.clone();
+        self.store_audited("unblock isp", &x.name, move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let isp = isp::select_isp(conn, name.as_str())?.unwrap_or_default();
+          // insert new as unblocked
+          isp::insert_new_ISP(conn, isp.name.as_str(), Some(isp.banned), Some(isp.warnings),isp.country.as_str(), false)
+        });
         let fetchmsg = format!(" {} Unblocked ISP: {}", self.apptheme.symbol_unblock, &x.name);
         tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock ISP message failed to send");
       }, 
-      Action::StatsGetIP(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let ipdata = ip::select_ip(conn, x.as_str()).unwrap_or_default().take().unwrap_or_default();
-        if ipdata != ip::IP::default() {
-          let tx = self.action_tx.clone().unwrap();
-          tx.send(Action::StatsGotIP(ipdata)).expect("Failed to send IP data back to Stats");
-        }
+      Action::StatsBlockUsername(x) => {
+        let name = x.name.clone();
+        self.store_audited("block username", &x.name, move |conn| username::set_username_blocked(conn, name.as_str(), true));
+        let fetchmsg = format!(" {} Blocked Username: {}", self.apptheme.symbol_block, &x.name);
+        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block Username message failed to send");
       },
-
-      Action::BanIP(x) => {
-        if !x.is_banned {
+      Action::StatsUnblockUsername(x) => {
+        let name = x.name.clone();
+        self.store_audited("unblock username", &x.name, move |conn| username::set_username_blocked(conn, name.as_str(), false));
+        let fetchmsg = format!(" {} Unblocked Username: {}", self.apptheme.symbol_unblock, &x.name);
+        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock Username message failed to send");
+      },
+      Action::StatsBlockProvider(x) => {
+        let name = x.name.clone();
+        self.store_audited("block provider", &x.name, move |conn| provider::set_provider_blocked(conn, name.as_str(), true));
+        let fetchmsg = format!(" {} Blocked Provider: {}", self.apptheme.symbol_block, &x.name);
+        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block Provider message failed to send");
+      },
+      Action::StatsUnblockProvider(x) => {
+        let name = x.name.clone();
+        self.store_audited("unblock provider", &x.name, move |conn| provider::set_provider_blocked(conn, name.as_str(), false));
+        let fetchmsg = format!(" {} Unblocked Provider: {}", self.apptheme.symbol_unblock, &x.name);
+        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock Provider message failed to send");
+      },
+      Action::RequestAllowlist => {
+        self.store(move |conn| {
+          let entries = allowlist::get_allowlist(conn).unwrap_or(vec![]);
+          tx.send(Action::GotAllowlist(entries)).unwrap_or_default();
+          Ok(())
+        });
+      },
+      Action::AddAllowlistEntry(x) => {
+        let timestamp = chrono::offset::Local::now().to_rfc3339();
+        let entry = x.clone();
+        self.store_audited("allowlist add", &x, move |conn| allowlist::insert_allowlist_entry(conn, &entry, &timestamp));
+        tx.send(Action::InternalLog(format!(" {} Allowlisted: {}", self.apptheme.symbol_unblock, x))).expect("LOG: Allowlist message failed to send");
+        tx.send(Action::RequestAllowlist).expect("RequestAllowlist failed to send");
+      },
+      Action::RemoveAllowlistEntry(x) => {
+        let entry = x.clone();
+        self.store_audited("allowlist remove", &x, move |conn| allowlist::remove_allowlist_entry(conn, &entry));
+        tx.send(Action::InternalLog(format!(" {} Removed from allowlist: {}", self.apptheme.symbol_block, x))).expect("LOG: Allowlist message failed to send");
+        tx.send(Action::RequestAllowlist).expect("RequestAllowlist failed to send");
+      },
+      Action::Notify(event) => {
+        for sink in self.notifier.route(&event, std::time::Instant::now()) {
           let tx = self.action_tx.clone().unwrap();
-          let symb = self.apptheme.symbol_ban.clone();
+          let symb = self.apptheme.symbol_error.clone();
+          let event = event.clone();
           tokio::spawn(async move {
-            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-            // check if is banned
-            let output = std::process::Command::new("fail2ban-client")
-              .arg("set")
-              .arg("sshd")
-              .arg("banip")
-              .arg(&x.ip)
-              // Tell the OS to record the command's output
-              .stdout(std::process::Stdio::piped())
-              // execute the command, wait for it to complete, then capture the output
-              .output()
-              // Blow up if the OS was unable to start the program
-              .unwrap();
-    
-            // extract the raw bytes that we captured and interpret them as a string
-            let stdout = String::from_utf8(output.stdout).unwrap();
-            if stdout.contains("0") {
-              tx.send(Action::Banned(true)).expect("Failed to Ban ...");
-              let fetchmsg = format!(" {} Banned IP: {}", symb, &x.ip);
-              tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Ban IP message failed to send");
-            } else {
-              tx.send(Action::Banned(false)).expect("Failed to Ban ...");
+            if let Err(e) = notifications::deliver(&sink, &event).await {
+              tx.send(Action::InternalLog(format!(" {} Notification to {} failed: {}", symb, sink.name, e))).expect("LOG: Notification error failed to send");
             }
           });
         }
       },
+      Action::AcknowledgeAlerts(x) => {
+        let symb_error = self.apptheme.symbol_error.clone();
+        self.store(move |conn| {
+          if let Err(e) = alert::acknowledge_alerts(conn, x) {
+            tx.send(Action::InternalLog(format!(" {} Acknowledging alerts failed: {}", symb_error, e))).unwrap_or_default();
+          }
+          Ok(())
+        });
+      },
+      Action::RequestIPDetail(x) => {
+        // looked up again if the cache expired, the detail view updates on GotEnrichment
+        if let Some(enricher) = &self.enricher {enricher.request(&x);}
+        let symb_error = self.apptheme.symbol_error.clone();
+        self.store(move |conn| {
+          match ipdetail::collect_ip_detail(conn, x.as_str()) {
+            Ok(detail) => {
+              tx.send(Action::GotIPDetail(detail)).unwrap_or_default();
+              tx.send(Action::GotEnrichment(x.clone(), Enrichment::load(conn, &x).unwrap_or_default())).unwrap_or_default();
+            },
+            Err(e) => {tx.send(Action::InternalLog(format!(" {} IP detail failed: {}", symb_error, e))).unwrap_or_default();},
+          }
+          Ok(())
+        });
+      },
+      Action::StatsGetIP(x) => {
+        let tags = self.ip_tags(&x);
+        if let Some(enricher) = &self.enricher {enricher.request(&x);}
+        self.store(move |conn| {
+          if let Some(ipdata) = ip::select_ip(conn, x.as_str()).unwrap_or_default() {
+            tx.send(Action::StatsGotIP(ipdata)).unwrap_or_default();
+            tx.send(Action::GotTags(x.clone(), tags)).unwrap_or_default();
+            tx.send(Action::GotEnrichment(x.clone(), Enrichment::load(conn, &x).unwrap_or_default())).unwrap_or_default();
+          }
+          Ok(())
+        });
+      },
+
+      Action::BanIP(x, request) => {
+        // every ban goes through here, whether it came from the popup, Stats, a block or a rule
+        let Some(storage) = self.storage.clone() else {return Ok(None)};
+        let symb = self.apptheme.symbol_ban.clone();
+        let symb_error = self.apptheme.symbol_error.clone();
+        let bantime = request.bantime.map(chrono::Duration::seconds)
+          .or_else(|| self.config.bans.bantime.as_deref().and_then(|bantime| query::parse_duration(bantime).ok()));
+        let now = chrono::offset::Local::now().fixed_offset();
+        let active = ban::ActiveBan {
+          ip: x.ip.clone(),
+          jail: self.config.bans.jail.clone(),
+          banned_at: now.to_rfc3339(),
+          expires_at: bantime.map(|bantime| (now + bantime).to_rfc3339()),
+          reason: request.reason,
+          origin: request.origin,
+          escalations: 0,
+        };
+        tokio::spawn(async move {
+          let target = x.ip.clone();
+          if let Some(reason) = storage.request(move |conn| allowlist::refuse_reason(conn, &target)).await.unwrap_or_else(|e| Some(format!("allowlist check failed: {}", e))) {
+            tx.send(Action::InternalLog(format!(" {} Refused to ban IP {}: {}", symb_error, x.ip, reason))).unwrap_or_default();
+            audit_entry(&storage, &format!("ban ({})", active.origin.as_str()), &x.ip, &format!("{}: {}", audit::RESULT_REFUSED, reason), "");
+            tx.send(Action::Banned(false)).unwrap_or_default();
+            return;
+          }
+          if x.is_banned {return;}
+          tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+          let (jail, ip) = (active.jail.clone(), x.ip.clone());
+          let (banned, output) = tokio::task::spawn_blocking(move || fail2ban_client(&jail, "banip", &ip)).await.unwrap_or_default();
+          let result = if banned {audit::RESULT_OK} else {audit::RESULT_FAILED};
+          audit_entry(&storage, &format!("ban ({})", active.origin.as_str()), &x.ip, result, &output);
+          if !banned {
+            tx.send(Action::Banned(false)).unwrap_or_default();
+            return;
+          }
+          storage.run(move |conn| {
+            ip::set_ip_banned(conn, &x.ip, true).unwrap_or_default();
+            ban::upsert_active_ban(conn, &active).unwrap_or_default();
+            ban::record_ban(conn, &x.ip, &active.jail, &active.banned_at, ban::SOURCE_SUCCEED2BAN, &active.reason, &utils::local_user()).unwrap_or_default();
+            statistics::refresh_banned_for_ip(conn, &x.ip).unwrap_or_default();
+            metrics::inc(&METRICS.bans);
+            tx.send(Action::Banned(true)).unwrap_or_default();
+            let duration = bantime.map(ban::format_duration).unwrap_or(String::from("permanent"));
+            let fetchmsg = format!(" {} Banned IP: {} ({}, {})", symb, &x.ip, duration, active.origin.as_str());
+            tx.send(Action::InternalLog(fetchmsg)).unwrap_or_default();
+            let kind = if active.origin == BanOrigin::Block {EventKind::Block} else {EventKind::Ban};
+            let message = format!("Banned IP {} ({}, {}): {}", &x.ip, duration, active.origin.as_str(), active.reason);
+            tx.send(Action::Notify(NotifyEvent::now(kind, &x.ip, message))).unwrap_or_default();
+            Ok(())
+          });
+        });
+      },
       Action::UnbanIP(x) => {
         if x.is_banned {
-          let tx = self.action_tx.clone().unwrap();
+          let Some(storage) = self.storage.clone() else {return Ok(None)};
           let symb = self.apptheme.symbol_unblock.clone();
+          let default_jail = self.config.bans.jail.clone();
           tokio::spawn(async move {
+            let target = x.ip.clone();
+            let jail = storage.request(move |conn| ban::select_active_ban(conn, &target)).await.unwrap_or_default()
+              .map(|active| active.jail).unwrap_or(default_jail);
             tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-            // check if is banned
-            let output = std::process::Command::new("fail2ban-client")
-              .arg("set")
-              .arg("sshd")
-              .arg("unbanip")
-              .arg(&x.ip)
-              // Tell the OS to record the command's output
-              .stdout(std::process::Stdio::piped())
-              // execute the command, wait for it to complete, then capture the output
-              .output()
-              // Blow up if the OS was unable to start the program
-              .unwrap();
-    
-            // extract the raw bytes that we captured and interpret them as a string
-            let stdout = String::from_utf8(output.stdout).unwrap();
-            if stdout.contains("0") {
-              tx.send(Action::Unbanned(true)).expect("Failed to Unban !!!");
-              let fetchmsg = format!(" {} Unbanned IP: {}", symb, &x.ip);
-              tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unban IP message failed to send");
-            } else {
-              tx.send(Action::Unbanned(false)).expect("Failed to Unban !!!");
+            let (unban_jail, ip) = (jail.clone(), x.ip.clone());
+            let (mut unbanned, mut output) = tokio::task::spawn_blocking(move || fail2ban_client(&unban_jail, "unbanip", &ip)).await.unwrap_or_default();
+            if !unbanned {
+              // the jail's own bantime may have released it already
+              let status_jail = jail.clone();
+              let status = tokio::task::spawn_blocking(move || fail2ban_status(&status_jail)).await.unwrap_or_default();
+              if !status.is_empty() && !jail_lists(&status, &x.ip) {
+                unbanned = true;
+                output = format!("{}not banned in jail {}", output, jail);
+              }
+            }
+            let result = if unbanned {audit::RESULT_OK} else {audit::RESULT_FAILED};
+            audit_entry(&storage, "unban", &x.ip, result, &output);
+            if !unbanned {
+              tx.send(Action::Unbanned(false)).unwrap_or_default();
+              return;
             }
+            storage.run(move |conn| {
+              let timestamp = chrono::offset::Local::now().to_rfc3339();
+              ip::set_ip_banned(conn, &x.ip, false).unwrap_or_default();
+              ban::remove_active_ban(conn, &x.ip).unwrap_or_default();
+              ban::record_unban(conn, &x.ip, &jail, &timestamp).unwrap_or_default();
+              metrics::inc(&METRICS.unbans);
+              tx.send(Action::Unbanned(true)).unwrap_or_default();
+              let fetchmsg = format!(" {} Unbanned IP: {}", symb, &x.ip);
+              tx.send(Action::InternalLog(fetchmsg)).unwrap_or_default();
+              Ok(())
+            });
           });
         }
-      },      
+      },
+      Action::StatsRequestBlockPreview(kind, name) => {
+        let jail = self.config.bans.jail.clone();
+        let symb_error = self.apptheme.symbol_error.clone();
+        self.store(move |conn| {
+          match impact::preview_block(conn, &kind, &name, &jail) {
+            Ok(preview) => {tx.send(Action::StatsGotBlockPreview(preview)).unwrap_or_default();},
+            Err(e) => {tx.send(Action::InternalLog(format!(" {} Block preview failed: {}", symb_error, e))).unwrap_or_default();},
+          }
+          Ok(())
+        });
+      },
+      Action::RecomputeStatistics => {
+        let symb = self.apptheme.symbol_db.clone();
+        let symb_error = self.apptheme.symbol_error.clone();
+        self.store(move |conn| {
+          match statistics::recompute_statistics(conn) {
+            Ok(()) => {
+              tx.send(Action::InternalLog(format!(" {} Recomputed statistics", symb))).unwrap_or_default();
+              tx.send(Action::RecomputedStatistics).unwrap_or_default();
+            },
+            Err(e) => {tx.send(Action::InternalLog(format!(" {} Recomputing statistics failed: {}", symb_error, e))).unwrap_or_default();},
+          }
+          Ok(())
+        });
+      },
+      Action::RefreshBlocklists => {
+        let tx = self.action_tx.clone().unwrap();
+        let lists = self.config.blocklists.clone();
+        let symb = self.apptheme.symbol_reqwest.clone();
+        let symb_error = self.apptheme.symbol_error.clone();
+        tokio::spawn(async move {
+          for result in threatintel::refresh(&lists).await {
+            let msg = match result {
+              Ok(message) => format!(" {} {}", symb, message),
+              Err(message) => format!(" {} {}", symb_error, message),
+            };
+            tx.send(Action::InternalLog(msg)).expect("LOG: Blocklist refresh message failed to send");
+          }
+          // reading and parsing the lists may take a while
+          let Ok((threat_intel, errors)) = tokio::task::spawn_blocking(move || ThreatIntel::load(&lists)).await else {return};
+          tx.send(Action::BlocklistsRefreshed(threat_intel, errors)).unwrap_or_default();
+        });
+      },
+      Action::GenerateReport(request) => {
+        let Some(storage) = self.storage.clone() else {return Ok(None)};
+        let reporter = self.config.reports.clone();
+        let symb = self.apptheme.symbol_db.clone();
+        let symb_error = self.apptheme.symbol_error.clone();
+        tokio::spawn(async move {
+          let dir = utils::get_data_dir().join("reports");
+          let (scope, since) = (request.scope.clone(), abusereport::since(&request));
+          let result = storage.request(move |conn| abusereport::collect(conn, &scope, since.as_deref())).await
+            .and_then(|evidence| abusereport::write(&dir, &request, &evidence, &reporter).map(|files| (evidence.len(), files)).map_err(|e| e.to_string()));
+          let (symb, msg) = match &result {
+            Ok((0, _)) => (symb, format!("Report for {}: nothing to report", request.scope)),
+            Ok((ips, files)) => (symb, format!("Report for {}: {} IPs in {} files written to {}", request.scope, ips, files.len(), dir.display())),
+            Err(e) => (symb_error, format!("Report for {} failed: {}", request.scope, e)),
+          };
+          match &result {
+            Ok(_) => audit_entry(&storage, "abuse report", &request.scope.to_string(), audit::RESULT_OK, &msg),
+            Err(e) => audit_entry(&storage, "abuse report", &request.scope.to_string(), audit::RESULT_FAILED, e),
+          }
+          tx.send(Action::InternalLog(format!(" {} {}", symb, msg))).expect("LOG: Report message failed to send");
+          tx.send(Action::ReportGenerated(msg)).expect("ReportGenerated failed to send");
+        });
+      },
+      Action::RestoreBan(active) => {
+        tx.send(Action::InternalLog(format!(" {} fail2ban unbanned {} early, banning it again", self.apptheme.symbol_ban, active.ip))).expect("LOG: Restore ban message failed to send");
+        let symb = self.apptheme.symbol_error.clone();
+        tokio::spawn(async move {
+          let (jail, ip) = (active.jail.clone(), active.ip.clone());
+          let (banned, output) = tokio::task::spawn_blocking(move || fail2ban_client(&jail, "banip", &ip)).await.unwrap_or_default();
+          if !banned {
+            tx.send(Action::InternalLog(format!(" {} Banning {} again failed: {}", symb, active.ip, output.trim()))).unwrap_or_default();
+          }
+        });
+      },
+      Action::BlocklistsRefreshed(threat_intel, errors) => {
+        for error in errors {
+          tx.send(Action::InternalLog(format!(" {} {}", self.apptheme.symbol_error, error))).expect("LOG: Blocklist error failed to send");
+        }
+        for (name, entries) in threat_intel.summary() {
+          tx.send(Action::InternalLog(format!(" {} Blocklist {}: {} networks loaded", self.apptheme.symbol_db, name, entries))).expect("LOG: Blocklist message failed to send");
+        }
+        self.threat_intel = threat_intel;
+      },
+      Action::RequestAuditLog => {
+        self.store(move |conn| {
+          let entries = audit::get_audit_entries(conn, Some(audit::AUDIT_VIEW_LIMIT)).unwrap_or(vec![]);
+          tx.send(Action::GotAuditLog(entries)).unwrap_or_default();
+          Ok(())
+        });
+      },
+      Action::RequestBanList => {
+        self.store(move |conn| {
+          let bans = ban::get_active_bans(conn).unwrap_or(vec![]);
+          tx.send(Action::GotBanList(bans)).unwrap_or_default();
+          Ok(())
+        });
+      },
+      Action::ExtendBan(x) => {
+        let extension = self.config.bans.bantime.as_deref().and_then(|bantime| query::parse_duration(bantime).ok()).unwrap_or(chrono::Duration::hours(1));
+        let symb = self.apptheme.symbol_ban.clone();
+        let user = utils::local_user();
+        self.store(move |conn| {
+          if let Some(mut active) = ban::select_active_ban(conn, &x)? {
+            // permanent bans stay permanent
+            if let Some(expires_at) = active.expires_at.as_deref().and_then(|expires_at| chrono::DateTime::parse_from_rfc3339(expires_at).ok()) {
+              let now = chrono::offset::Local::now().fixed_offset();
+              active.expires_at = Some((expires_at.max(now) + extension).to_rfc3339());
+              ban::upsert_active_ban(conn, &active)?;
+              tx.send(Action::InternalLog(format!(" {} Extended ban of {} by {}", symb, x, ban::format_duration(extension)))).unwrap_or_default();
+              audit::insert_audit_entry(conn, &now.to_rfc3339(), &user, &format!("extend ban by {}", ban::format_duration(extension)), &x, audit::RESULT_OK, "")?;
+            }
+          }
+          tx.send(Action::RequestBanList).unwrap_or_default();
+          Ok(())
+        });
+      },
 
       _ => (),
     }
@@ -985,4 +1691,71 @@ impl Component for Startup <'_> {
 
     Ok(())
   }
-}
\ No newline at end of file
+}
+
+/// Queues an audit log entry, usable from tasks that only hold the storage service.
+fn audit_entry(storage: &Storage, action: &str, target: &str, result: &str, output: &str) {
+  let timestamp = chrono::offset::Local::now().to_rfc3339();
+  let (user, action, target, result, output) = (utils::local_user(), action.to_string(), target.to_string(), result.to_string(), output.to_string());
+  storage.run(move |conn| {
+    if let Err(e) = audit::insert_audit_entry(conn, &timestamp, &user, &action, &target, &result, &output) {
+      error!("Writing audit entry failed: {}", e);
+    }
+    Ok(())
+  });
+}
+
+/// Serves `/metrics` on `listen` in the background.
+fn serve_metrics(listen: String, storage: Storage, tx: UnboundedSender<Action>) {
+  tokio::spawn(async move {
+    match tokio::net::TcpListener::bind(&listen).await {
+      Ok(listener) => {
+        tx.send(Action::InternalLog(format!(" ✔ Serving metrics on http://{}/metrics", listen))).unwrap_or_default();
+        if let Err(e) = metrics::serve(listener, Some(storage)).await {
+          tx.send(Action::InternalLog(format!(" ❌ Metrics endpoint stopped: {}", e))).unwrap_or_default();
+        }
+      },
+      Err(e) => {tx.send(Action::InternalLog(format!(" ❌ Metrics endpoint on {} failed: {}", listen, e))).unwrap_or_default();},
+    }
+  });
+}
+
+/// Output of `fail2ban-client status <jail>`, which lists the banned IPs. Empty if fail2ban could not be asked.
+fn fail2ban_status(jail: &str) -> String {
+  let output = std::process::Command::new("fail2ban-client")
+    .arg("status")
+    .arg(jail)
+    .stdout(std::process::Stdio::piped())
+    .stderr(std::process::Stdio::null())
+    .output();
+  match output {
+    Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+    Err(e) => {error!("Running fail2ban-client failed: {}", e); String::new()},
+  }
+}
+
+/// Whether the output of `fail2ban-client status <jail>` lists the IP as banned.
+fn jail_lists(status: &str, ip: &str) -> bool {
+  status.split_whitespace().any(|word| word == ip)
+}
+
+/// Runs `fail2ban-client set <jail> <command> <ip>`, true if fail2ban reported success, along with its output.
+fn fail2ban_client(jail: &str, command: &str, ip: &str) -> (bool, String) {
+  let output = std::process::Command::new("fail2ban-client")
+    .arg("set")
+    .arg(jail)
+    .arg(command)
+    .arg(ip)
+    // Tell the OS to record the command's output
+    .stdout(std::process::Stdio::piped())
+    .stderr(std::process::Stdio::piped())
+    .output();
+  match output {
+    Ok(output) => {
+      let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+      let stderr = String::from_utf8_lossy(&output.stderr);
+      (stdout.contains('0'), format!("{}{}", stdout, stderr))
+    },
+    Err(e) => {error!("Running fail2ban-client failed: {}", e); (false, e.to_string())},
+  }
+}
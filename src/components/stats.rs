@@ -13,7 +13,7 @@ use std::fmt::UpperExp;
 use std::{collections::HashMap, time::Duration};
 
 use color_eyre::eyre::Result;
-use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
 use log::error;
 use ratatui::widgets::block::Title;
 use ratatui::{prelude::*, widgets::*};
@@ -24,9 +24,9 @@ use tui_input::{backend::crossterm::EventHandler, Input};
 use chrono::{self, Datelike};
 
 use super::{Component, Frame};
-use crate::{action::Action, config::key_event_to_string, components::home::utils::centered_rect};
+use crate::{action::Action, config::key_event_to_string, components::home::utils::{centered_rect, rect_contains}, impact::BlockPreview, enrichment::Enrichment, abusereport::{self, ReportFormat, ReportRequest, ReportScope}};
 
-use crate::{migrations::schema::{city::City, region::Region, isp::ISP, country::Country, message::MiniMessage, ip::IP},
+use crate::{migrations::schema::{city::City, region::Region, isp::ISP, country::Country, message::MiniMessage, ip::IP, username::Username, host::Host, provider::Provider},
 themes::Theme, gen_structs::StatefulList, themes::Themes};
 
 
@@ -67,16 +67,36 @@ pub struct Stats {
   pub cities: StatefulList<(City, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
   pub isps: StatefulList<(ISP, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
   pub ips: StatefulList<StatIP>,
+  pub users: StatefulList<(Username, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
+  pub hosts: StatefulList<(Host, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
+  pub providers: StatefulList<(Provider, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
   pub selected_ip: IP,
+  /// blocklists the selected IP is on
+  pub selected_ip_tags: Vec<String>,
+  /// reverse DNS and RDAP data of the selected IP
+  pub selected_ip_enrichment: Enrichment,
   //
   pub countries_sort: SortState,
   pub regions_sort: SortState,
   pub cities_sort: SortState,
   pub isps_sort: SortState,
   pub ips_sort: SortState,
+  pub users_sort: SortState,
+  pub hosts_sort: SortState,
+  pub providers_sort: SortState,
   //
   pub apptheme: Theme,
   pub available_themes: Themes,
+  /// list and overview area per selection mode of the last draw, used to route mouse events
+  pub pane_areas: Vec<(SelectionMode, Rect, Rect)>,
+  /// impact of the block waiting for confirmation
+  pub block_preview: Option<BlockPreview>,
+  /// report covers every IP instead of the selected item
+  pub report_all: bool,
+  /// index into abusereport::WINDOWS
+  pub report_window: usize,
+  /// result of the last report
+  pub report_status: String,
 }
 
 impl <'a> Stats  {
@@ -92,6 +112,9 @@ impl <'a> Stats  {
     this.cities = StatefulList::with_items(vec![]);
     this.isps = StatefulList::with_items(vec![]);
     this.ips = StatefulList::with_items(vec![]);
+    this.users = StatefulList::with_items(vec![]);
+    this.hosts = StatefulList::with_items(vec![]);
+    this.providers = StatefulList::with_items(vec![]);
 
     this.full_regions = vec![];
     this.full_cities = vec![];
@@ -221,6 +244,42 @@ impl <'a> Stats  {
     self.selected_ip();
   }
 
+  pub fn selected_user(&mut self) {
+    // find selected username
+    let Some(sel_idx) = self.users.state.selected() else {return;};
+    let Some(sel_user) = self.users.items.get(sel_idx) else {return;};
+    // top IPs for this username first
+    let mut sel_user_ips = sel_user.2.clone();
+    sel_user_ips.sort_by_key(|statip| std::cmp::Reverse(statip.warnings));
+
+    self.ips.unselect();
+    self.ips = StatefulList::with_items(sel_user_ips);
+    self.ips.next();
+    self.selected_ip();
+  }
+
+  pub fn selected_host(&mut self) {
+    let Some(sel_host) = self.hosts.state.selected().and_then(|idx| self.hosts.items.get(idx)) else {return;};
+    let mut sel_host_ips = sel_host.2.clone();
+    sel_host_ips.sort_by_key(|statip| std::cmp::Reverse(statip.warnings));
+
+    self.ips.unselect();
+    self.ips = StatefulList::with_items(sel_host_ips);
+    self.ips.next();
+    self.selected_ip();
+  }
+
+  pub fn selected_provider(&mut self) {
+    let Some(sel_provider) = self.providers.state.selected().and_then(|idx| self.providers.items.get(idx)) else {return;};
+    let mut sel_provider_ips = sel_provider.2.clone();
+    sel_provider_ips.sort_by_key(|statip| std::cmp::Reverse(statip.warnings));
+
+    self.ips.unselect();
+    self.ips = StatefulList::with_items(sel_provider_ips);
+    self.ips.next();
+    self.selected_ip();
+  }
+
   pub fn selected_ip(&mut self) {
     // find selected ip
     let sel_idx = self.ips.state.selected();
@@ -231,6 +290,58 @@ impl <'a> Stats  {
     tx.send(Action::StatsGetIP(sel_ip)).expect("Failed to reuest IP from Stats");
   }
 
+  /// Loads the details for the selected entry of the given list.
+  pub fn selected_by_mode(&mut self, selection_mode: SelectionMode) {
+    match selection_mode {
+      SelectionMode::Country => self.selected_country(),
+      SelectionMode::Region => self.selected_region(),
+      SelectionMode::City => self.selected_city(),
+      SelectionMode::ISP => self.selected_isp(),
+      SelectionMode::IP => self.selected_ip(),
+      SelectionMode::User => self.selected_user(),
+      SelectionMode::Host => self.selected_host(),
+      SelectionMode::Provider => self.selected_provider(),
+    }
+  }
+
+  pub fn scroll_by_mode(&mut self, selection_mode: SelectionMode, down: bool) {
+    match (selection_mode, down) {
+      (SelectionMode::Country, true) => self.countries.next(),
+      (SelectionMode::Country, false) => self.countries.previous(),
+      (SelectionMode::Region, true) => self.regions.next(),
+      (SelectionMode::Region, false) => self.regions.previous(),
+      (SelectionMode::City, true) => self.cities.next(),
+      (SelectionMode::City, false) => self.cities.previous(),
+      (SelectionMode::ISP, true) => self.isps.next(),
+      (SelectionMode::ISP, false) => self.isps.previous(),
+      (SelectionMode::IP, true) => self.ips.next(),
+      (SelectionMode::IP, false) => self.ips.previous(),
+      (SelectionMode::User, true) => self.users.next(),
+      (SelectionMode::User, false) => self.users.previous(),
+      (SelectionMode::Host, true) => self.hosts.next(),
+      (SelectionMode::Host, false) => self.hosts.previous(),
+      (SelectionMode::Provider, true) => self.providers.next(),
+      (SelectionMode::Provider, false) => self.providers.previous(),
+    }
+    self.selected_by_mode(selection_mode);
+  }
+
+  pub fn click_by_mode(&mut self, selection_mode: SelectionMode, area: Rect, row: u16) {
+    let selected = match selection_mode {
+      SelectionMode::Country => self.countries.select_at_row(area, row, |_| 1),
+      SelectionMode::Region => self.regions.select_at_row(area, row, |_| 1),
+      SelectionMode::City => self.cities.select_at_row(area, row, |_| 1),
+      SelectionMode::ISP => self.isps.select_at_row(area, row, |_| 1),
+      SelectionMode::IP => self.ips.select_at_row(area, row, |_| 1),
+      SelectionMode::User => self.users.select_at_row(area, row, |_| 1),
+      SelectionMode::Host => self.hosts.select_at_row(area, row, |_| 1),
+      SelectionMode::Provider => self.providers.select_at_row(area, row, |_| 1),
+    };
+    if selected.is_some() {
+      self.selected_by_mode(selection_mode);
+    }
+  }
+
   pub fn get_timestamps_from_msgs(&self, msgs: Vec<MiniMessage>) -> Vec<String> {
     let mut timestamps: Vec<String> = vec![];
     if msgs.is_empty() {return timestamps;}
@@ -240,6 +351,36 @@ impl <'a> Stats  {
     timestamps
   }
 
+  /// Asks for the impact of blocking the selected Country, Region, City, ISP or Provider, shown in the confirm popup.
+  fn request_block_preview(&mut self) -> Option<Action> {
+    self.block_preview = None;
+    let (kind, name) = match self.selection_mode {
+      SelectionMode::Country => ("Country", self.countries.state.selected().and_then(|idx| self.countries.items.get(idx)).map(|item| item.0.name.clone())),
+      SelectionMode::Region => ("Region", self.regions.state.selected().and_then(|idx| self.regions.items.get(idx)).map(|item| item.0.name.clone())),
+      SelectionMode::City => ("City", self.cities.state.selected().and_then(|idx| self.cities.items.get(idx)).map(|item| item.0.name.clone())),
+      SelectionMode::ISP => ("ISP", self.isps.state.selected().and_then(|idx| self.isps.items.get(idx)).map(|item| item.0.name.clone())),
+      SelectionMode::Provider => ("Provider", self.providers.state.selected().and_then(|idx| self.providers.items.get(idx)).map(|item| item.0.name.clone())),
+      SelectionMode::IP | SelectionMode::User | SelectionMode::Host => return None,
+    };
+    Some(Action::StatsRequestBlockPreview(kind.to_string(), name?))
+  }
+
+  /// What an abuse report would cover, Regions, Cities, Usernames, Hosts and Providers report all IPs.
+  pub fn report_scope(&self) -> ReportScope {
+    if self.report_all {return ReportScope::All;}
+    let scope = match self.selection_mode {
+      SelectionMode::Country => self.countries.state.selected().and_then(|idx| self.countries.items.get(idx)).map(|item| ReportScope::Country(item.0.name.clone())),
+      SelectionMode::ISP => self.isps.state.selected().and_then(|idx| self.isps.items.get(idx)).map(|item| ReportScope::ISP(item.0.name.clone())),
+      SelectionMode::IP => self.ips.state.selected().and_then(|idx| self.ips.items.get(idx)).map(|item| ReportScope::IP(item.ip.clone())),
+      _ => None,
+    };
+    scope.unwrap_or(ReportScope::All)
+  }
+
+  fn report_request(&self, format: ReportFormat) -> Action {
+    Action::GenerateReport(ReportRequest { scope: self.report_scope(), window_hours: abusereport::WINDOWS[self.report_window], format })
+  }
+
   pub fn block_by_selected_mode(&mut self) -> Result<()> {
 
     match self.block_mode {
@@ -250,6 +391,9 @@ impl <'a> Stats  {
           SelectionMode::City => {actions::block_selected_city(self)?;},
           SelectionMode::ISP => {actions::block_selected_isp(self)?;},
           SelectionMode::IP => {actions::block_selected_ip(self)?;},
+          SelectionMode::User => {actions::block_selected_user(self)?;},
+          SelectionMode::Host => {},
+          SelectionMode::Provider => {actions::block_selected_provider(self)?;},
         }
       },
       BlockMode::Unblock => {
@@ -259,6 +403,9 @@ impl <'a> Stats  {
           SelectionMode::City => {actions::unblock_selected_city(self)?;},
           SelectionMode::ISP => {actions::unblock_selected_isp(self)?;},
           SelectionMode::IP => {actions::unblock_selected_ip(self)?;},
+          SelectionMode::User => {actions::unblock_selected_user(self)?;},
+          SelectionMode::Host => {},
+          SelectionMode::Provider => {actions::unblock_selected_provider(self)?;},
         }
       },
     }
@@ -292,6 +439,29 @@ impl Component for Stats {
     Ok(())
   }
 
+  fn handle_mouse_events(&mut self, mouse: MouseEvent) -> Result<Option<Action>> {
+    if !self.showing_stats || self.mode != Mode::Normal || self.display_mode != DisplayMode::Normal {
+      return Ok(None);
+    }
+    let (x, y) = (mouse.column, mouse.row);
+    let Some((selection_mode, list_area, _)) = self.pane_areas.iter().find(|(_, list, overview)| rect_contains(*list, x, y) || rect_contains(*overview, x, y)).copied() else {
+      return Ok(None);
+    };
+    match mouse.kind {
+      MouseEventKind::ScrollDown => {self.selection_mode = selection_mode; self.scroll_by_mode(selection_mode, true);},
+      MouseEventKind::ScrollUp => {self.selection_mode = selection_mode; self.scroll_by_mode(selection_mode, false);},
+      MouseEventKind::Down(MouseButton::Left) => {
+        // click-to-focus, clicking a list entry also selects it
+        self.selection_mode = selection_mode;
+        if rect_contains(list_area, x, y) {
+          self.click_by_mode(selection_mode, list_area, y);
+        }
+      },
+      _ => return Ok(None),
+    }
+    Ok(Some(Action::Render))
+  }
+
   fn handle_key_events(&mut self, key: KeyEvent) -> Result<Option<Action>> {
     
     self.last_events.push(key.clone());
@@ -306,11 +476,13 @@ impl Component for Stats {
                     match keychar {
                         'E'|'e' => {return Ok(Some(Action::StatsHide));},
                         'W'|'w' => {if self.display_mode == DisplayMode::Help {self.display_mode = DisplayMode::Normal;} else {self.display_mode = DisplayMode::Help;} return Ok(Some(Action::Blank))},
-                        'B'|'b' => {if self.mode == Mode::Block {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal; } else {self.mode = Mode::Block; self.display_mode = DisplayMode::Confirm; self.block_mode = BlockMode::Block;}},
+                        'B'|'b' => {if self.mode == Mode::Block {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal; } else {self.mode = Mode::Block; self.display_mode = DisplayMode::Confirm; self.block_mode = BlockMode::Block; return Ok(self.request_block_preview());}},
                         'U'|'u' => {if self.mode == Mode::Block {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal; } else {self.mode = Mode::Block; self.display_mode = DisplayMode::Confirm; self.block_mode = BlockMode::Unblock;}},
                         'A'|'a' => {self.sort_mode = SortMode::Alphabetical; self.sort_by_selected_mode()?;},
                         'S'|'s' => {self.sort_mode = SortMode::NumWarns; self.sort_by_selected_mode()?;},
                         'D'|'d' => {self.sort_mode = SortMode::Blocked; self.sort_by_selected_mode()?;},
+                        'C'|'c' => {return Ok(Some(Action::RecomputeStatistics));},
+                        'X'|'x' => {self.mode = Mode::Report; self.display_mode = DisplayMode::Report; self.report_status = String::new(); return Ok(Some(Action::Render));},
                         _ => {self.input.handle_event(&crossterm::event::Event::Key(key));},
                     }
                 }
@@ -326,7 +498,7 @@ impl Component for Stats {
                     match keychar {
                         'E'|'e' => {return Ok(Some(Action::StatsHide));},
                         'W'|'w' => {if self.display_mode == DisplayMode::Help {self.display_mode = DisplayMode::Normal;} else {self.display_mode = DisplayMode::Help;} return Ok(Some(Action::Blank))},
-                        'B'|'b' => {if self.mode == Mode::Block {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal; } else {self.mode = Mode::Block; self.display_mode = DisplayMode::Confirm; self.block_mode = BlockMode::Block;}},
+                        'B'|'b' => {if self.mode == Mode::Block {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal; } else {self.mode = Mode::Block; self.display_mode = DisplayMode::Confirm; self.block_mode = BlockMode::Block; return Ok(self.request_block_preview());}},
                         'U'|'u' => {if self.mode == Mode::Block {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal; } else {self.mode = Mode::Block; self.display_mode = DisplayMode::Confirm; self.block_mode = BlockMode::Unblock;}},
                         'Y'|'y' => {self.block_by_selected_mode()?; self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal;},
                         'N'|'n' => {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal;},
@@ -337,7 +509,29 @@ impl Component for Stats {
                   self.input.handle_event(&crossterm::event::Event::Key(key));
                 },
               }
-            },            
+            },
+            Mode::Report => {
+              // the popup keeps the selection, so no list navigation here
+              let action = match key.code {
+                KeyCode::Esc => {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal; Action::Render},
+                KeyCode::Char(keychar) => {
+                  match keychar {
+                    'O'|'o' => {self.report_all = !self.report_all; Action::Render},
+                    'T'|'t' => {self.report_window = (self.report_window + 1) % abusereport::WINDOWS.len(); Action::Render},
+                    '1' => self.report_request(ReportFormat::AbuseIPDB),
+                    '2' => self.report_request(ReportFormat::Email),
+                    '3' => self.report_request(ReportFormat::Xarf),
+                    'X'|'x'|'N'|'n' => {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal; Action::Render},
+                    _ => Action::Blank,
+                  }
+                },
+                _ => Action::Blank,
+              };
+              if let Action::GenerateReport(request) = &action {
+                self.report_status = format!("Writing report for {} ...", request.scope);
+              }
+              return Ok(Some(action));
+            },
         };
 
         match self.selection_mode {
@@ -345,7 +539,7 @@ impl Component for Stats {
                 match key.code {
                     KeyCode::Up => {self.countries.previous(); self.selected_country();},
                     KeyCode::Down => {self.countries.next(); self.selected_country();},
-                    KeyCode::BackTab => {self.selection_mode = SelectionMode::IP;},
+                    KeyCode::BackTab => {self.selection_mode = SelectionMode::Provider;},
                     KeyCode::Tab => {self.selection_mode = SelectionMode::Region;},
                     KeyCode::Char(keychar) => {
                         match keychar {
@@ -406,10 +600,55 @@ impl Component for Stats {
                   KeyCode::Up => {self.ips.previous(); self.selected_ip();},
                   KeyCode::Down => {self.ips.next(); self.selected_ip();},
                   KeyCode::BackTab => {self.selection_mode = SelectionMode::ISP;},
-                  KeyCode::Tab => {self.selection_mode = SelectionMode::Country;},
+                  KeyCode::Tab => {self.selection_mode = SelectionMode::User;},
                   _ => {self.input.handle_event(&crossterm::event::Event::Key(key));},
                   }
           },
+            SelectionMode::User => {
+                match key.code {
+                    KeyCode::Up => {self.users.previous(); self.selected_user();},
+                    KeyCode::Down => {self.users.next(); self.selected_user();},
+                    KeyCode::BackTab => {self.selection_mode = SelectionMode::IP;},
+                    KeyCode::Tab => {self.selection_mode = SelectionMode::Host;},
+                    KeyCode::Char(keychar) => {
+                        match keychar {
+                            'R'|'r' => {return Ok(Some(Action::StatsGetUsernames))},
+                            _ => {self.input.handle_event(&crossterm::event::Event::Key(key));},
+                        }
+                    },
+                    _ => {},
+                    }
+            },
+            SelectionMode::Host => {
+                match key.code {
+                    KeyCode::Up => {self.hosts.previous(); self.selected_host();},
+                    KeyCode::Down => {self.hosts.next(); self.selected_host();},
+                    KeyCode::BackTab => {self.selection_mode = SelectionMode::User;},
+                    KeyCode::Tab => {self.selection_mode = SelectionMode::Provider;},
+                    KeyCode::Char(keychar) => {
+                        match keychar {
+                            'R'|'r' => {return Ok(Some(Action::StatsGetHosts))},
+                            _ => {self.input.handle_event(&crossterm::event::Event::Key(key));},
+                        }
+                    },
+                    _ => {},
+                    }
+            },
+            SelectionMode::Provider => {
+                match key.code {
+                    KeyCode::Up => {self.providers.previous(); self.selected_provider();},
+                    KeyCode::Down => {self.providers.next(); self.selected_provider();},
+                    KeyCode::BackTab => {self.selection_mode = SelectionMode::Host;},
+                    KeyCode::Tab => {self.selection_mode = SelectionMode::Country;},
+                    KeyCode::Char(keychar) => {
+                        match keychar {
+                            'R'|'r' => {return Ok(Some(Action::StatsGetProviders))},
+                            _ => {self.input.handle_event(&crossterm::event::Event::Key(key));},
+                        }
+                    },
+                    _ => {},
+                    }
+            },
         }
     }
 
@@ -419,6 +658,8 @@ impl Component for Stats {
   fn update(&mut self, action: Action) -> Result<Option<Action>> {
         match action {
             Action::StatsShow => {self.showing_stats = true;},
+            Action::RecomputedStatistics => {refresh_countries(self.action_tx.clone().unwrap())?;},
+            Action::StatsGotBlockPreview(x) => {self.block_preview = Some(x);},
             Action::StatsHide => {self.showing_stats = false;},
             Action::Tick => self.tick(),
             Action::Render => self.render_tick(),
@@ -438,6 +679,9 @@ impl Component for Stats {
             Action::StatsGetRegions => {self.regions.unselect(); self.regions = StatefulList::with_items(vec![]); self.full_regions = vec![];},
             Action::StatsGetCities => {self.cities.unselect(); self.cities = StatefulList::with_items(vec![]); self.full_cities = vec![];},
             Action::StatsGetISPs => {self.isps.unselect(); self.isps = StatefulList::with_items(vec![]); self.full_isps = vec![];},
+            Action::StatsGetUsernames => {self.users.unselect(); self.users = StatefulList::with_items(vec![]);},
+            Action::StatsGetHosts => {self.hosts.unselect(); self.hosts = StatefulList::with_items(vec![]);},
+            Action::StatsGetProviders => {self.providers.unselect(); self.providers = StatefulList::with_items(vec![]);},
 
             Action::StatsGotCountry(x, y) => {
               let timestamps = convert_strings_to_utc(self.get_timestamps_from_msgs(y.clone()));
@@ -455,7 +699,22 @@ impl Component for Stats {
               let timestamps = convert_strings_to_utc(self.get_timestamps_from_msgs(y.clone()));
               let statips = get_msgs_per_ip(y);              
               self.full_isps.push((x, timestamps, statips));},
-            Action::StatsGotIP(x) => {self.selected_ip = x;},
+            Action::StatsGotUsername(x, y) => {
+              let timestamps = convert_strings_to_utc(self.get_timestamps_from_msgs(y.clone()));
+              let statips = get_msgs_per_ip(y);
+              self.users.items.push((x, timestamps, statips));},
+            Action::StatsGotHost(x, y) => {
+              let timestamps = convert_strings_to_utc(self.get_timestamps_from_msgs(y.clone()));
+              let statips = get_msgs_per_ip(y);
+              self.hosts.items.push((x, timestamps, statips));},
+            Action::StatsGotProvider(x, y) => {
+              let timestamps = convert_strings_to_utc(self.get_timestamps_from_msgs(y.clone()));
+              let statips = get_msgs_per_ip(y);
+              self.providers.items.push((x, timestamps, statips));},
+            Action::StatsGotIP(x) => {self.selected_ip = x; self.selected_ip_tags = vec![]; self.selected_ip_enrichment = Enrichment::default();},
+            Action::GotTags(x, y) if x == self.selected_ip.ip => {self.selected_ip_tags = y;},
+            Action::GotEnrichment(x, y) if x == self.selected_ip.ip => {self.selected_ip_enrichment = y;},
+            Action::ReportGenerated(x) => {self.report_status = x;},
             Action::SelectTheme(x) => {self.select_new_theme(x)},   
             _ => (),
         }
@@ -472,20 +731,37 @@ impl Component for Stats {
         f.render_widget(bg, rect);
 
         let layout_a = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(rect);
-        let layout_left = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20)].as_ref()).direction(Direction::Vertical).split(layout_a[0]);
-        let layout_right = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20)].as_ref()).direction(Direction::Vertical).split(layout_a[1]);
+        let layout_left = Layout::default().constraints([Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8)].as_ref()).direction(Direction::Vertical).split(layout_a[0]);
+        let layout_right = Layout::default().constraints([Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8), Constraint::Ratio(1, 8)].as_ref()).direction(Direction::Vertical).split(layout_a[1]);
 
         let layout_country = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[0]);
         let layout_region = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[1]);
         let layout_city = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[2]);
         let layout_isp = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[3]);
         let layout_ip = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[4]);
+        let layout_user = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[5]);
+        let layout_host = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[6]);
+        let layout_provider = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[7]);
+
+        self.pane_areas = vec![
+          (SelectionMode::Country, layout_left[0], layout_right[0]),
+          (SelectionMode::Region, layout_left[1], layout_right[1]),
+          (SelectionMode::City, layout_left[2], layout_right[2]),
+          (SelectionMode::ISP, layout_left[3], layout_right[3]),
+          (SelectionMode::IP, layout_left[4], layout_right[4]),
+          (SelectionMode::User, layout_left[5], layout_right[5]),
+          (SelectionMode::Host, layout_left[6], layout_right[6]),
+          (SelectionMode::Provider, layout_left[7], layout_right[7]),
+        ];
 
         let countrylist = ui::make_country_list(self);
         let regionlist = ui::make_region_list(self);
         let citylist = ui::make_city_list(self);
         let isplist = ui::make_isp_list(self);
         let iplist = ui::make_ip_list(self);
+        let userlist = ui::make_user_list(self);
+        let hostlist = ui::make_host_list(self);
+        let providerlist = ui::make_provider_list(self);
 
         // timestamp chart == barchart -> bar for every day with number of messages 
         let sel_country = self.countries.state.selected();
@@ -534,23 +810,58 @@ impl Component for Stats {
             let dtbars_ip = ui::create_barchart(&self.apptheme, bars, "Log entries per Day");
             f.render_widget(dtbars_ip, layout_ip[1]);
 
-            let overview = ui::make_ip_overview(&self.apptheme, self.selected_ip.clone());
+            let overview = ui::make_ip_overview(&self.apptheme, self.selected_ip.clone(), &self.selected_ip_tags, &self.selected_ip_enrichment);
             f.render_widget(overview, layout_ip[0]);
         }        
 
+        if let Some(sel_user) = self.users.state.selected().and_then(|idx| self.users.items.get(idx)) {
+            let bars = ui::make_bars_for_timestamps(&self.apptheme, sel_user.1.clone());
+            let dtbars_user = ui::create_barchart(&self.apptheme, bars, "Log entries per Day");
+            f.render_widget(dtbars_user, layout_user[1]);
+
+            let overview = ui::make_user_overview(self);
+            f.render_widget(overview, layout_user[0]);
+        }
+
+        if let Some(sel_host) = self.hosts.state.selected().and_then(|idx| self.hosts.items.get(idx)) {
+            let bars = ui::make_bars_for_timestamps(&self.apptheme, sel_host.1.clone());
+            let dtbars_host = ui::create_barchart(&self.apptheme, bars, "Log entries per Day");
+            f.render_widget(dtbars_host, layout_host[1]);
+
+            let overview = ui::make_host_overview(self);
+            f.render_widget(overview, layout_host[0]);
+        }
+
+        if let Some(sel_provider) = self.providers.state.selected().and_then(|idx| self.providers.items.get(idx)) {
+            let bars = ui::make_bars_for_timestamps(&self.apptheme, sel_provider.1.clone());
+            let dtbars_provider = ui::create_barchart(&self.apptheme, bars, "Log entries per Day");
+            f.render_widget(dtbars_provider, layout_provider[1]);
+
+            let overview = ui::make_provider_overview(self);
+            f.render_widget(overview, layout_provider[0]);
+        }
+
         f.render_stateful_widget(countrylist, layout_left[0], &mut self.countries.state);
         f.render_stateful_widget(regionlist, layout_left[1], &mut self.regions.state);
         f.render_stateful_widget(citylist, layout_left[2], &mut self.cities.state);
         f.render_stateful_widget(isplist, layout_left[3], &mut self.isps.state);
         f.render_stateful_widget(iplist, layout_left[4], &mut self.ips.state);
+        f.render_stateful_widget(userlist, layout_left[5], &mut self.users.state);
+        f.render_stateful_widget(hostlist, layout_left[6], &mut self.hosts.state);
+        f.render_stateful_widget(providerlist, layout_left[7], &mut self.providers.state);
 
         match self.display_mode {
           DisplayMode::Confirm => {
             let block_mode = if self.block_mode == BlockMode::Block {true} else {false};
-            let p_area = centered_rect(f.size(), 40, 7);
+            let p_area = if block_mode && self.block_preview.is_some() {centered_rect(f.size(), 60, 45)} else {centered_rect(f.size(), 40, 7)};
             f.render_widget(Clear, p_area);
             f.render_widget(ui::popup_un_block_selected(self, block_mode),p_area);
           },
+          DisplayMode::Report => {
+            let p_area = centered_rect(f.size(), 50, 12);
+            f.render_widget(Clear, p_area);
+            f.render_widget(ui::popup_report(self), p_area);
+          },
           DisplayMode::Help => {
             let p_area = centered_rect(f.size(), 35, 30);
             f.render_widget(Clear, p_area);
@@ -1,7 +1,7 @@
 //! Defines functions for Stats that happen on Key Events
 use color_eyre::eyre::Result;
 use tokio::sync::mpsc::UnboundedSender;
-use crate::{action::Action, migrations::schema::ip::IP};
+use crate::{action::Action, migrations::schema::{ip::IP, ban::BanRequest}};
 use tokio::time::{self, Duration};
 use super::{Stats, enums::{SelectionMode, SortMode, SortState}};
 
@@ -16,6 +16,12 @@ pub fn refresh_countries(tx: UnboundedSender<Action>) -> Result<()> {
     tx.send(Action::StatsGetCities).expect("Failed to refresh cities; E404");
     time::sleep(Duration::from_millis(25)).await;
     tx.send(Action::StatsGetISPs).expect("Failed to refresh ISPs; E404");
+    time::sleep(Duration::from_millis(25)).await;
+    tx.send(Action::StatsGetUsernames).expect("Failed to refresh usernames; E404");
+    time::sleep(Duration::from_millis(25)).await;
+    tx.send(Action::StatsGetHosts).expect("Failed to refresh hosts; E404");
+    time::sleep(Duration::from_millis(25)).await;
+    tx.send(Action::StatsGetProviders).expect("Failed to refresh providers; E404");
     time::sleep(Duration::from_millis(5)).await;
     let fetchmsg = format!(" 🔃 Refreshed Stats ");
     tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Refresh stats message failed to send");
@@ -116,7 +122,7 @@ pub fn block_selected_ip(stats: &mut Stats) -> Result<()> {
   let sel_ip = stats.selected_ip.clone();
   if sel_ip.is_banned {return Ok(())}
 
-  tx.send(Action::BanIP(sel_ip)).expect("Failed to send request to block IP");
+  tx.send(Action::BanIP(sel_ip, BanRequest::manual("Stats"))).expect("Failed to send request to block IP");
   stats.selected_ip.is_banned = true;
   Ok(())
 }
@@ -134,6 +140,46 @@ pub fn unblock_selected_ip(stats: &mut Stats) -> Result<()> {
   Ok(())
 }
 
+// BLOCKING Username// --------------------------------------------------------------- //
+pub fn block_selected_user(stats: &mut Stats) -> Result<()> {
+  if stats.users.items.is_empty() {return Ok(())}
+  let tx = stats.action_tx.clone().unwrap();
+  let sel_idx = stats.users.state.selected().unwrap();
+  let sel_user = stats.users.items[sel_idx].clone().0;
+  tx.send(Action::StatsBlockUsername(sel_user)).expect("Failed to send request to block Username");
+  stats.users.items[sel_idx].0.is_blocked = true;
+  Ok(())
+}
+
+pub fn unblock_selected_user(stats: &mut Stats) -> Result<()> {
+  if stats.users.items.is_empty() {return Ok(())}
+  let tx = stats.action_tx.clone().unwrap();
+  let sel_idx = stats.users.state.selected().unwrap();
+  let sel_user = stats.users.items[sel_idx].clone().0;
+  tx.send(Action::StatsUnblockUsername(sel_user)).expect("Failed to send request to unblock Username");
+  stats.users.items[sel_idx].0.is_blocked = false;
+  Ok(())
+}
+
+// BLOCKING Provider// --------------------------------------------------------------- //
+pub fn block_selected_provider(stats: &mut Stats) -> Result<()> {
+  let Some(sel_idx) = stats.providers.state.selected().filter(|idx| *idx < stats.providers.items.len()) else {return Ok(())};
+  let tx = stats.action_tx.clone().unwrap();
+  let sel_provider = stats.providers.items[sel_idx].clone().0;
+  tx.send(Action::StatsBlockProvider(sel_provider)).expect("Failed to send request to block Provider");
+  stats.providers.items[sel_idx].0.is_blocked = true;
+  Ok(())
+}
+
+pub fn unblock_selected_provider(stats: &mut Stats) -> Result<()> {
+  let Some(sel_idx) = stats.providers.state.selected().filter(|idx| *idx < stats.providers.items.len()) else {return Ok(())};
+  let tx = stats.action_tx.clone().unwrap();
+  let sel_provider = stats.providers.items[sel_idx].clone().0;
+  tx.send(Action::StatsUnblockProvider(sel_provider)).expect("Failed to send request to unblock Provider");
+  stats.providers.items[sel_idx].0.is_blocked = false;
+  Ok(())
+}
+
 // SORT ALPHANUM// --------------------------------------------------------------- //
 pub fn sort_by_alphabetical(stats: &mut Stats) -> Result<()> {
   let _ =  match stats.selection_mode {
@@ -212,6 +258,51 @@ pub fn sort_by_alphabetical(stats: &mut Stats) -> Result<()> {
         _ => {stats.ips_sort = SortState::Alphabetical;},
       }
     },
+    SelectionMode::User => {
+      match stats.users_sort {
+        SortState::AlphabeticalRev => {
+          stats.users.items.sort_by(|a, b|
+            a.0.name.partial_cmp(&b.0.name).unwrap());
+          stats.users.items.reverse();
+          stats.users_sort = SortState::Alphabetical;},
+        SortState::Alphabetical => {
+          stats.users.items.sort_by(|a, b|
+            a.0.name.partial_cmp(&b.0.name).unwrap());
+          stats.users_sort = SortState::AlphabeticalRev;
+        },
+        _ => {stats.users_sort = SortState::Alphabetical;},
+      }
+    },
+    SelectionMode::Host => {
+      match stats.hosts_sort {
+        SortState::AlphabeticalRev => {
+          stats.hosts.items.sort_by(|a, b|
+            a.0.name.partial_cmp(&b.0.name).unwrap());
+          stats.hosts.items.reverse();
+          stats.hosts_sort = SortState::Alphabetical;},
+        SortState::Alphabetical => {
+          stats.hosts.items.sort_by(|a, b|
+            a.0.name.partial_cmp(&b.0.name).unwrap());
+          stats.hosts_sort = SortState::AlphabeticalRev;
+        },
+        _ => {stats.hosts_sort = SortState::Alphabetical;},
+      }
+    },
+    SelectionMode::Provider => {
+      match stats.providers_sort {
+        SortState::AlphabeticalRev => {
+          stats.providers.items.sort_by(|a, b|
+            a.0.name.partial_cmp(&b.0.name).unwrap());
+          stats.providers.items.reverse();
+          stats.providers_sort = SortState::Alphabetical;},
+        SortState::Alphabetical => {
+          stats.providers.items.sort_by(|a, b|
+            a.0.name.partial_cmp(&b.0.name).unwrap());
+          stats.providers_sort = SortState::AlphabeticalRev;
+        },
+        _ => {stats.providers_sort = SortState::Alphabetical;},
+      }
+    },
   };
   Ok(())
 }
@@ -294,6 +385,51 @@ pub fn sort_by_numwarn(stats: &mut Stats) -> Result<()> {
         _ => {stats.ips_sort = SortState::NumWarns;},
       }
     },
+    SelectionMode::User => {
+      match stats.users_sort {
+        SortState::NumWarnsRev => {
+          stats.users.items.sort_by(|a, b|
+            a.0.warnings.partial_cmp(&b.0.warnings).unwrap());
+          stats.users.items.reverse();
+          stats.users_sort = SortState::NumWarns;},
+        SortState::NumWarns => {
+          stats.users.items.sort_by(|a, b|
+            a.0.warnings.partial_cmp(&b.0.warnings).unwrap());
+          stats.users_sort = SortState::NumWarnsRev;
+        },
+        _ => {stats.users_sort = SortState::NumWarns;},
+      }
+    },
+    SelectionMode::Host => {
+      match stats.hosts_sort {
+        SortState::NumWarnsRev => {
+          stats.hosts.items.sort_by(|a, b|
+            a.0.warnings.partial_cmp(&b.0.warnings).unwrap());
+          stats.hosts.items.reverse();
+          stats.hosts_sort = SortState::NumWarns;},
+        SortState::NumWarns => {
+          stats.hosts.items.sort_by(|a, b|
+            a.0.warnings.partial_cmp(&b.0.warnings).unwrap());
+          stats.hosts_sort = SortState::NumWarnsRev;
+        },
+        _ => {stats.hosts_sort = SortState::NumWarns;},
+      }
+    },
+    SelectionMode::Provider => {
+      match stats.providers_sort {
+        SortState::NumWarnsRev => {
+          stats.providers.items.sort_by(|a, b|
+            a.0.warnings.partial_cmp(&b.0.warnings).unwrap());
+          stats.providers.items.reverse();
+          stats.providers_sort = SortState::NumWarns;},
+        SortState::NumWarns => {
+          stats.providers.items.sort_by(|a, b|
+            a.0.warnings.partial_cmp(&b.0.warnings).unwrap());
+          stats.providers_sort = SortState::NumWarnsRev;
+        },
+        _ => {stats.providers_sort = SortState::NumWarns;},
+      }
+    },
   };
   Ok(())
 }
@@ -376,6 +512,38 @@ pub fn sort_by_blocked(stats: &mut Stats) -> Result<()> {
         _ => {stats.ips_sort = SortState::Blocked;},
       }
     },
+    SelectionMode::User => {
+      match stats.users_sort {
+        SortState::BlockedRev => {
+          stats.users.items.sort_by(|a, b|
+            a.0.is_blocked.partial_cmp(&b.0.is_blocked).unwrap());
+          stats.users.items.reverse();
+          stats.users_sort = SortState::Blocked;},
+        SortState::Blocked => {
+          stats.users.items.sort_by(|a, b|
+            a.0.is_blocked.partial_cmp(&b.0.is_blocked).unwrap());
+          stats.users_sort = SortState::BlockedRev;
+        },
+        _ => {stats.users_sort = SortState::Blocked;},
+      }
+    },
+    // hosts cannot be blocked
+    SelectionMode::Host => {},
+    SelectionMode::Provider => {
+      match stats.providers_sort {
+        SortState::BlockedRev => {
+          stats.providers.items.sort_by(|a, b|
+            a.0.is_blocked.partial_cmp(&b.0.is_blocked).unwrap());
+          stats.providers.items.reverse();
+          stats.providers_sort = SortState::Blocked;},
+        SortState::Blocked => {
+          stats.providers.items.sort_by(|a, b|
+            a.0.is_blocked.partial_cmp(&b.0.is_blocked).unwrap());
+          stats.providers_sort = SortState::BlockedRev;
+        },
+        _ => {stats.providers_sort = SortState::Blocked;},
+      }
+    },
   };
   Ok(())
 }
@@ -8,6 +8,7 @@ pub enum Mode {
   Normal,
   Processing,
   Block,
+  Report,
 }
 
 #[derive(Default, Copy, Clone, PartialEq, Eq)]
@@ -25,6 +26,9 @@ pub enum SelectionMode {
   City,
   ISP,
   IP,
+  User,
+  Host,
+  Provider,
 }
 
 #[derive(Default, Copy, Clone, PartialEq, Eq)]
@@ -59,4 +63,5 @@ pub enum DisplayMode {
   Normal,
   Help,
   Confirm,
+  Report,
 }
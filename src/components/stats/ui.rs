@@ -1,6 +1,9 @@
 use super::{SelectionMode, SortState, Stats};
-use crate::migrations::schema::{city::City, country::Country, ip::IP, isp::ISP, message::MiniMessage, region::Region};
+use crate::migrations::schema::{city::City, country::Country, ip::IP, isp::ISP, message::MiniMessage, region::Region, username::Username, host::Host, provider::Provider};
 use crate::{gen_structs::StatefulList, themes::Theme};
+use crate::impact::{BlockPreview, ACTIVE_HOURS, RECENT_LOGIN_DAYS};
+use crate::enrichment::Enrichment;
+use crate::abusereport;
 use chrono::{DateTime, Datelike, FixedOffset};
 use color_eyre::owo_colors::OwoColorize;
 use ratatui::widgets::block::Title;
@@ -224,6 +227,113 @@ pub fn make_ip_list<'a>(stats: &Stats) -> List<'a> {
   iplist
 }
 
+pub fn make_user_list<'a>(stats: &Stats) -> List<'a> {
+  let av_users: Vec<ListItem> = stats
+    .users
+    .items
+    .iter()
+    .map(|i| {
+      let is_blocked = i.0.is_blocked;
+      let mut line = Line::from(format!("{} ({})", i.0.name, i.0.warnings));
+      line.patch_style(if is_blocked {
+        Style::default().fg(stats.apptheme.colors_app.text_color.color).bg(stats.apptheme.colors_app.warn_color.color)
+      } else {
+        Style::default().fg(stats.apptheme.colors_app.text_color.color)
+      });
+      ListItem::new(line)
+    })
+    .collect();
+  let sel_item = stats.users.state.selected().and_then(|idx| stats.users.items.get(idx)).map(|u| u.0.clone()).unwrap_or_default();
+  let sort_indicator = make_sort_state_indicator(&stats.apptheme, stats.users_sort);
+  // Create a List from all list items and highlight the currently selected one
+  let userlist: List<'_> = List::new(av_users)
+    .bg(stats.apptheme.colors_app.background_darkest.color)
+    .block(
+      Block::default()
+        .borders(Borders::ALL)
+        .border_style(match stats.selection_mode {
+          SelectionMode::User => stats.apptheme.styles_app.active_border_style,
+          _ => stats.apptheme.styles_app.border_style,
+        })
+        .title(Title::from("Usernames").alignment(Alignment::Left))
+        .title(Title::from(sort_indicator).alignment(Alignment::Right)),
+    )
+    .highlight_style(if sel_item.is_blocked {
+      stats.apptheme.styles_app.highlight_item_style.bg(stats.apptheme.colors_app.warn_color.color).fg(stats.apptheme.colors_app.text_color.color)
+    } else {
+      stats.apptheme.styles_app.highlight_item_style
+    })
+    .highlight_symbol(">> ");
+
+  userlist
+}
+
+pub fn make_host_list<'a>(stats: &Stats) -> List<'a> {
+  let av_hosts: Vec<ListItem> = stats
+    .hosts
+    .items
+    .iter()
+    .map(|i| ListItem::new(Line::styled(format!("{} ({})", display_host(&i.0), i.0.warnings), Style::default().fg(stats.apptheme.colors_app.text_color.color))))
+    .collect();
+  let sort_indicator = make_sort_state_indicator(&stats.apptheme, stats.hosts_sort);
+  List::new(av_hosts)
+    .bg(stats.apptheme.colors_app.background_darkest.color)
+    .block(
+      Block::default()
+        .borders(Borders::ALL)
+        .border_style(match stats.selection_mode {
+          SelectionMode::Host => stats.apptheme.styles_app.active_border_style,
+          _ => stats.apptheme.styles_app.border_style,
+        })
+        .title(Title::from("Hosts").alignment(Alignment::Left))
+        .title(Title::from(sort_indicator).alignment(Alignment::Right)),
+    )
+    .highlight_style(stats.apptheme.styles_app.highlight_item_style)
+    .highlight_symbol(">> ")
+}
+
+pub fn make_provider_list<'a>(stats: &Stats) -> List<'a> {
+  let av_providers: Vec<ListItem> = stats
+    .providers
+    .items
+    .iter()
+    .map(|i| {
+      let mut line = Line::from(format!("{} ({})", i.0.name, i.0.warnings));
+      line.patch_style(if i.0.is_blocked {
+        Style::default().fg(stats.apptheme.colors_app.text_color.color).bg(stats.apptheme.colors_app.warn_color.color)
+      } else {
+        Style::default().fg(stats.apptheme.colors_app.text_color.color)
+      });
+      ListItem::new(line)
+    })
+    .collect();
+  let sel_item: Provider = stats.providers.state.selected().and_then(|idx| stats.providers.items.get(idx)).map(|p| p.0.clone()).unwrap_or_default();
+  let sort_indicator = make_sort_state_indicator(&stats.apptheme, stats.providers_sort);
+  List::new(av_providers)
+    .bg(stats.apptheme.colors_app.background_darkest.color)
+    .block(
+      Block::default()
+        .borders(Borders::ALL)
+        .border_style(match stats.selection_mode {
+          SelectionMode::Provider => stats.apptheme.styles_app.active_border_style,
+          _ => stats.apptheme.styles_app.border_style,
+        })
+        .title(Title::from("Providers").alignment(Alignment::Left))
+        .title(Title::from(sort_indicator).alignment(Alignment::Right)),
+    )
+    .highlight_style(if sel_item.is_blocked {
+      stats.apptheme.styles_app.highlight_item_style.bg(stats.apptheme.colors_app.warn_color.color).fg(stats.apptheme.colors_app.text_color.color)
+    } else {
+      stats.apptheme.styles_app.highlight_item_style
+    })
+    .highlight_symbol(">> ")
+}
+
+/// Lines stored before hosts were recorded have no host.
+fn display_host(host: &Host) -> &str {
+  if host.name.is_empty() {"(unknown)"} else {&host.name}
+}
+
 pub fn make_sort_state_indicator<'a>(theme: &Theme, sort_state: SortState) -> Line<'a> {
   let sortstate: (u8, &str, Style) = match sort_state {
     SortState::Alphabetical => (0, "⬆", theme.styles_app.active_border_style),
@@ -473,7 +583,36 @@ pub fn make_isp_overview(stats: &Stats) -> impl Widget + '_ {
   .set_style(Style::new().bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
 }
 
-pub fn make_ip_overview(theme: &Theme, sel_ip: IP) -> impl Widget + '_ {
+pub fn make_user_overview(stats: &Stats) -> impl Widget + '_ {
+  // get totals
+  let mut total_warn: u32 = 0;
+  let mut total_banned: u32 = 0;
+
+  let mut paragraph = Paragraph::new(vec![]);
+
+  let sel_idx = stats.users.state.selected();
+  if let Some(sel_idx) = sel_idx {
+    for tuple in stats.users.items.iter() {
+      total_banned = total_banned.saturating_add(tuple.0.banned.try_into().unwrap_or(0));
+      total_warn = total_warn.saturating_add(tuple.0.warnings.try_into().unwrap_or(0));
+    }
+    let tuple = stats.users.items[sel_idx].clone();
+    paragraph = make_overview_paragraph(
+      "User",
+      &stats.apptheme,
+      &tuple.0.name,
+      tuple.0.warnings.try_into().unwrap_or(0),
+      total_warn,
+      tuple.0.banned.try_into().unwrap_or(0),
+      total_banned,
+      tuple.0.is_blocked,
+    );
+  }
+  paragraph.block(Block::default().borders(Borders::ALL).title("User Stats").bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
+  .set_style(Style::new().bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
+}
+
+pub fn make_ip_overview<'a>(theme: &'a Theme, sel_ip: IP, tags: &[String], enrichment: &Enrichment) -> impl Widget + 'a {
   // get totals
   if sel_ip == IP::default() {
     return Paragraph::new(vec![]);
@@ -491,7 +630,7 @@ pub fn make_ip_overview(theme: &Theme, sel_ip: IP) -> impl Widget + '_ {
   let isp = selected_ip.isp;
 
   let default_text_style = Style::default().fg(theme.colors_app.text_color.color);
-  let lines: Vec<Line> = vec![
+  let mut lines: Vec<Line> = vec![
     Line::from(vec![Span::styled(format!(" Selected     :"), Style::default().bg(theme.colors_app.background_darkest.color))]),
     Line::from(vec![Span::styled(format!(" {}", ip), Style::default().fg(theme.colors_app.accent_color_a_var.color))]),
     Line::from(vec![if is_banned {
@@ -504,6 +643,18 @@ pub fn make_ip_overview(theme: &Theme, sel_ip: IP) -> impl Widget + '_ {
     Line::from(vec![Span::styled(format!(" {city}, {region}, {country} ",), default_text_style)]),
     Line::from(vec![Span::styled(format!(" {isp} "), default_text_style)]),
   ];
+  if !tags.is_empty() {
+    lines.push(Line::from(vec![Span::styled(format!(" Lists        : {}", tags.join(", ")), Style::default().fg(theme.colors_app.warn_color.color))]));
+  }
+  if let Some(ptr) = enrichment.ptr.as_ref().filter(|ptr| !ptr.hostname.is_empty()) {
+    lines.push(Line::from(vec![Span::styled(format!(" Hostname     : {}", ptr.hostname), default_text_style)]));
+  }
+  if let Some(rdap) = &enrichment.rdap {
+    lines.push(Line::from(vec![Span::styled(format!(" Network      : {} {}", rdap.name, rdap.cidr), default_text_style)]));
+    if !rdap.abuse_email.is_empty() {
+      lines.push(Line::from(vec![Span::styled(format!(" Abuse        : {}", rdap.abuse_email), default_text_style)]));
+    }
+  }
 
   let paragraph = Paragraph::new(lines);
 
@@ -539,6 +690,10 @@ pub fn popup_help(theme: &Theme) -> impl Widget + '_ {
   helptext.push(Line::from(Span::styled(format!("E|e:          Back          Return to main screen"), linestyle_alt)));
   helptext.push(Line::from(Span::styled(format!("B|b:          Block         Blocks all IPs for selected"), linestyle)));
   helptext.push(Line::from(Span::styled(format!("U|u:          Unblock       Lifts the Block for selected"), linestyle_alt)));
+  helptext.push(Line::from(Span::styled("                            (Usernames: bans every IP trying it, Providers: every IP in its ranges, Hosts cannot be blocked)", linestyle_alt)));
+  helptext.push(Line::from(Span::styled("Mouse:        Select        Click selects and focuses a List, wheel scrolls it", linestyle)));
+  helptext.push(Line::from(Span::styled("C|c:          Recompute     Rebuilds all counters from stored lines and ban history", linestyle_alt)));
+  helptext.push(Line::from(Span::styled("X|x:          Report        Writes an abuse report for the selected IP, ISP or Country", linestyle)));
   let mut hheader = Line::from(format!("---           Sorting      ---                                                                 -"
   ));
   hheader.patch_style(headerstyle);
@@ -558,6 +713,60 @@ pub fn popup_help(theme: &Theme) -> impl Widget + '_ {
   infoblock
 }
 
+pub fn make_host_overview(stats: &Stats) -> impl Widget + '_ {
+  // get totals
+  let mut total_warn: u32 = 0;
+  let mut total_banned: u32 = 0;
+
+  let mut paragraph = Paragraph::new(vec![]);
+
+  if let Some(sel_host) = stats.hosts.state.selected().and_then(|idx| stats.hosts.items.get(idx)) {
+    for tuple in stats.hosts.items.iter() {
+      total_banned = total_banned.saturating_add(tuple.0.banned.try_into().unwrap_or(0));
+      total_warn = total_warn.saturating_add(tuple.0.warnings.try_into().unwrap_or(0));
+    }
+    paragraph = make_overview_paragraph(
+      "Host",
+      &stats.apptheme,
+      display_host(&sel_host.0),
+      sel_host.0.warnings.try_into().unwrap_or(0),
+      total_warn,
+      sel_host.0.banned.try_into().unwrap_or(0),
+      total_banned,
+      false,
+    );
+  }
+  paragraph.block(Block::default().borders(Borders::ALL).title("Host Stats").bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
+  .set_style(Style::new().bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
+}
+
+pub fn make_provider_overview(stats: &Stats) -> impl Widget + '_ {
+  // get totals
+  let mut total_warn: u32 = 0;
+  let mut total_banned: u32 = 0;
+
+  let mut paragraph = Paragraph::new(vec![]);
+
+  if let Some(sel_provider) = stats.providers.state.selected().and_then(|idx| stats.providers.items.get(idx)) {
+    for tuple in stats.providers.items.iter() {
+      total_banned = total_banned.saturating_add(tuple.0.banned.try_into().unwrap_or(0));
+      total_warn = total_warn.saturating_add(tuple.0.warnings.try_into().unwrap_or(0));
+    }
+    paragraph = make_overview_paragraph(
+      "Provider",
+      &stats.apptheme,
+      &sel_provider.0.name,
+      sel_provider.0.warnings.try_into().unwrap_or(0),
+      total_warn,
+      sel_provider.0.banned.try_into().unwrap_or(0),
+      total_banned,
+      sel_provider.0.is_blocked,
+    );
+  }
+  paragraph.block(Block::default().borders(Borders::ALL).title("Provider Stats").bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
+  .set_style(Style::new().bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
+}
+
 pub fn popup_un_block_selected(stats: &Stats, is_block: bool) -> impl Widget + '_ {
   let smode = stats.selection_mode;
 
@@ -567,6 +776,9 @@ pub fn popup_un_block_selected(stats: &Stats, is_block: bool) -> impl Widget + '
     SelectionMode::City => "City",
     SelectionMode::ISP => "ISP",
     SelectionMode::IP => "IP",
+    SelectionMode::User => "Username",
+    SelectionMode::Host => "Host",
+    SelectionMode::Provider => "Provider",
   };
   let sel_str = match smode {
     SelectionMode::Country => {
@@ -614,6 +826,20 @@ pub fn popup_un_block_selected(stats: &Stats, is_block: bool) -> impl Widget + '
         sel_item
       }
     },
+    SelectionMode::User => {
+      if stats.users.items.is_empty() {
+        String::new()
+      } else {
+        let sel_idx = stats.users.state.selected().unwrap();
+        stats.users.items[sel_idx].0.name.clone()
+      }
+    },
+    SelectionMode::Host => {
+      stats.hosts.state.selected().and_then(|idx| stats.hosts.items.get(idx)).map(|host| host.0.name.clone()).unwrap_or_default()
+    },
+    SelectionMode::Provider => {
+      stats.providers.state.selected().and_then(|idx| stats.providers.items.get(idx)).map(|provider| provider.0.name.clone()).unwrap_or_default()
+    },
   };
 
   let default_text_style = Style::default().fg(stats.apptheme.colors_app.text_color.color);
@@ -627,6 +853,11 @@ pub fn popup_un_block_selected(stats: &Stats, is_block: bool) -> impl Widget + '
   }
 
   let mut clearlisttext: Vec<Line> = vec![];
+  if is_block {
+    if let Some(preview) = stats.block_preview.as_ref().filter(|preview| preview.name == sel_str) {
+      clearlisttext.extend(block_preview_lines(stats, preview));
+    }
+  }
   let clearlistline = Line::from(vec![
     Span::styled(format!("Press "), default_text_style),
     Span::styled(format!("Y | y "), Style::default().fg(stats.apptheme.colors_app.confirm_color.color)),
@@ -651,6 +882,79 @@ pub fn popup_un_block_selected(stats: &Stats, is_block: bool) -> impl Widget + '
   clearlistbox
 }
 
+/// Scope, time window and format choice of an abuse report.
+pub fn popup_report(stats: &Stats) -> impl Widget + '_ {
+  let default_text_style = Style::default().fg(stats.apptheme.colors_app.text_color.color);
+  let keystyle = Style::default().fg(stats.apptheme.colors_app.accent_color_b_mid.color);
+  let window = match abusereport::WINDOWS[stats.report_window] {
+    Some(hours) if hours % 24 == 0 => format!("last {} days", hours / 24),
+    Some(hours) => format!("last {} hours", hours),
+    None => String::from("all time"),
+  };
+  let key_line = |key: &str, text: String| Line::from(vec![
+    Span::styled(format!("{:<4}", key), keystyle),
+    Span::styled(text, default_text_style),
+  ]);
+
+  let lines: Vec<Line> = vec![
+    key_line("O", format!("Scope  : {}", stats.report_scope())),
+    key_line("T", format!("Window : {}", window)),
+    Line::from(""),
+    key_line("1", String::from("AbuseIPDB bulk-report CSV")),
+    key_line("2", String::from("Emails to the RDAP abuse contacts")),
+    key_line("3", String::from("X-ARF JSON per IP")),
+    Line::from(""),
+    Line::from(Span::styled(stats.report_status.clone(), default_text_style)),
+  ];
+
+  Paragraph::new(lines)
+    .wrap(Wrap { trim: true })
+    .block(
+      Block::default()
+        .borders(Borders::ALL)
+        .title("[ Abuse report, X | x to close ]")
+        .style(default_text_style.bg(stats.apptheme.colors_app.background_darkest.color))
+        .title_alignment(Alignment::Center),
+    )
+}
+
+/// Impact of a block, so a whole country of colleagues is not locked out by mistake.
+fn block_preview_lines<'a>(stats: &Stats, preview: &BlockPreview) -> Vec<Line<'a>> {
+  let default_text_style = Style::default().fg(stats.apptheme.colors_app.text_color.color);
+  let warnstyle = Style::default().fg(stats.apptheme.colors_app.warn_color.color).add_modifier(Modifier::BOLD);
+  let keystyle = Style::default().fg(stats.apptheme.colors_app.accent_color_b_mid.color);
+
+  let mut lines: Vec<Line> = vec![];
+  lines.push(Line::from(Span::styled(
+    format!("{} known IPs, {} active in the last {}h, {} already banned", preview.known_ips, preview.active_ips, ACTIVE_HOURS, preview.already_banned),
+    default_text_style,
+  )));
+  if !preview.recent_logins.is_empty() {
+    lines.push(Line::from(Span::styled(
+      format!("WARNING: successful logins in the last {} days from {}", RECENT_LOGIN_DAYS, preview.recent_logins.join(", ")),
+      warnstyle,
+    )));
+  }
+  for (ip, reason) in preview.allowlisted.iter() {
+    lines.push(Line::from(Span::styled(format!("Never banned: {} ({})", ip, reason), keystyle)));
+  }
+  lines.push(Line::from(""));
+  if preview.num_commands == 0 {
+    lines.push(Line::from(Span::styled("No fail2ban commands would run for known IPs", default_text_style)));
+  } else {
+    lines.push(Line::from(Span::styled("Runs once each IP shows up again:", default_text_style)));
+    for command in preview.commands.iter() {
+      lines.push(Line::from(Span::styled(command.clone(), keystyle)));
+    }
+    if preview.num_commands > preview.commands.len() {
+      lines.push(Line::from(Span::styled(format!("... and {} more", preview.num_commands - preview.commands.len()), keystyle)));
+    }
+  }
+  lines.push(Line::from("New IPs located in it are banned the same way."));
+  lines.push(Line::from(""));
+  lines
+}
+
 // CHARTS // ---------------------------------------------------------------- //
 
 pub fn make_bars_for_timestamps<'a>(theme: &Theme, timestamps: Vec<DateTime<FixedOffset>>) -> Vec<Bar<'a>> {
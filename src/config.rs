@@ -11,7 +11,7 @@ use serde::{
 };
 use serde_json::Value as JsonValue;
 
-use crate::{action::Action, mode::Mode};
+use crate::{action::Action, mode::Mode, rules::Rule, notifications::Sink, forwarding::Forwarder, threatintel::Blocklist, cloudranges::RangeFile};
 
 const CONFIG: &str = include_str!("../.config/config.json5");
 
@@ -31,6 +31,194 @@ pub struct Config {
   pub keybindings: KeyBindings,
   #[serde(default)]
   pub styles: Styles,
+  #[serde(default)]
+  pub alerts: AlertConfig,
+  #[serde(default)]
+  pub rules: Vec<Rule>,
+  #[serde(default)]
+  pub bans: BanConfig,
+  #[serde(default)]
+  pub notifications: Vec<Sink>,
+  #[serde(default)]
+  pub metrics: MetricsConfig,
+  #[serde(default)]
+  pub event_stream: EventStreamConfig,
+  #[serde(default)]
+  pub syslog: SyslogConfig,
+  #[serde(default)]
+  pub forwarders: Vec<Forwarder>,
+  #[serde(default)]
+  pub blocklists: Vec<Blocklist>,
+  #[serde(default)]
+  pub cloud_ranges: Vec<RangeFile>,
+  #[serde(default)]
+  pub enrichment: EnrichmentConfig,
+  #[serde(default)]
+  pub reports: ReportConfig,
+  #[serde(default)]
+  pub geolocation: GeoConfig,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AlertConfig {
+  /// IPs or CIDRs whose successful logins never raise an alert
+  #[serde(default)]
+  pub known_good: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct MetricsConfig {
+  /// address of the Prometheus `/metrics` endpoint like `127.0.0.1:9464`, disabled if unset
+  #[serde(default)]
+  pub listen: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct EventStreamConfig {
+  /// file every stored event is appended to as NDJSON, `-` for stdout, overridden by `--events-out`
+  #[serde(default)]
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SyslogConfig {
+  /// UDP and TCP address the syslog receiver listens on like `0.0.0.0:5514`, disabled if unset
+  #[serde(default)]
+  pub listen: Option<String>,
+  /// only messages of programs starting with one of these are processed
+  #[serde(default = "default_syslog_programs")]
+  pub programs: Vec<String>,
+  /// IPs and CIDRs messages are accepted from, everything else is dropped
+  #[serde(default)]
+  pub allowed_senders: Vec<String>,
+}
+
+fn default_syslog_programs() -> Vec<String> {
+  vec![String::from("sshd"), String::from("fail2ban")]
+}
+
+impl Default for SyslogConfig {
+  fn default() -> Self {
+    Self { listen: None, programs: default_syslog_programs(), allowed_senders: vec![] }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GeoConfig {
+  /// ip-api compatible service, `/json/<ip>` and `/batch` are appended
+  #[serde(default = "default_geo_api_url")]
+  pub api_url: String,
+  #[serde(default = "default_requests_per_minute")]
+  pub requests_per_minute: u32,
+  #[serde(default = "default_batch_requests_per_minute")]
+  pub batch_requests_per_minute: u32,
+  /// IPs asked for in one batch request, 1 never uses the batch endpoint
+  #[serde(default = "default_batch_size")]
+  pub batch_size: usize,
+  /// known IPs are looked up again once their location is older than this, 0 never does
+  #[serde(default = "default_geo_ttl_days")]
+  pub ttl_days: i64,
+}
+
+fn default_geo_api_url() -> String {
+  String::from("http://ip-api.com")
+}
+
+fn default_requests_per_minute() -> u32 {
+  45
+}
+
+fn default_batch_requests_per_minute() -> u32 {
+  15
+}
+
+fn default_batch_size() -> usize {
+  100
+}
+
+fn default_geo_ttl_days() -> i64 {
+  30
+}
+
+impl Default for GeoConfig {
+  fn default() -> Self {
+    Self { api_url: default_geo_api_url(), requests_per_minute: default_requests_per_minute(), batch_requests_per_minute: default_batch_requests_per_minute(), batch_size: default_batch_size(), ttl_days: default_geo_ttl_days() }
+  }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ReportConfig {
+  /// organisation the abuse reports are sent by
+  #[serde(default)]
+  pub org: String,
+  /// address abuse desks answer to
+  #[serde(default)]
+  pub email: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EnrichmentConfig {
+  /// reverse DNS and RDAP lookups of every new IP
+  #[serde(default = "default_true")]
+  pub enabled: bool,
+  /// DNS server PTR records are asked from like `1.1.1.1:53`, the first nameserver of /etc/resolv.conf if unset
+  #[serde(default)]
+  pub resolver: Option<String>,
+  /// the IP is appended to this
+  #[serde(default = "default_rdap_url")]
+  pub rdap_url: String,
+  #[serde(default = "default_ptr_ttl_hours")]
+  pub ptr_ttl_hours: i64,
+  #[serde(default = "default_rdap_ttl_hours")]
+  pub rdap_ttl_hours: i64,
+}
+
+fn default_true() -> bool {
+  true
+}
+
+fn default_rdap_url() -> String {
+  String::from("https://rdap.org/ip/")
+}
+
+fn default_ptr_ttl_hours() -> i64 {
+  24
+}
+
+fn default_rdap_ttl_hours() -> i64 {
+  24 * 7
+}
+
+impl Default for EnrichmentConfig {
+  fn default() -> Self {
+    Self { enabled: true, resolver: None, rdap_url: default_rdap_url(), ptr_ttl_hours: default_ptr_ttl_hours(), rdap_ttl_hours: default_rdap_ttl_hours() }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BanConfig {
+  /// fail2ban jail bans are added to
+  #[serde(default = "default_jail")]
+  pub jail: String,
+  /// default ban duration like `1h`, bans are permanent if unset
+  #[serde(default)]
+  pub bantime: Option<String>,
+  /// an expiring ban is prolonged by this factor if the IP kept showing up in the logs, 0 or 1 disables it
+  #[serde(default)]
+  pub escalation_factor: u32,
+  /// upper limit for escalated bans
+  #[serde(default)]
+  pub max_bantime: Option<String>,
+}
+
+fn default_jail() -> String {
+  String::from("sshd")
+}
+
+impl Default for BanConfig {
+  fn default() -> Self {
+    Self { jail: default_jail(), bantime: None, escalation_factor: 0, max_bantime: None }
+  }
 }
 
 impl Config {
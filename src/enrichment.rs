@@ -0,0 +1,413 @@
+//! Reverse DNS and RDAP enrichment of IPs, configured with `enrichment` in config.json5.
+//! Two background workers resolve PTR records and fetch the registration data of the network (name, allocation,
+//! abuse contact). Results are cached in the `ptr` and `rdap` tables and only looked up again once their TTL expired.
+
+use std::time::Duration;
+
+use chrono::Local;
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::{action::Action, cidr::{self, Cidr}, config::EnrichmentConfig, migrations::schema::enrichment::{self, Ptr, Rdap}, storage::Storage};
+
+/// IPs waiting per worker, more are dropped until the worker caught up.
+const QUEUE_SIZE: usize = 1000;
+/// DNS answers and RDAP responses are given up on after this long.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pause between RDAP requests, the registries rate limit per client.
+const RDAP_INTERVAL: Duration = Duration::from_secs(1);
+/// Used if no resolver is configured and /etc/resolv.conf has none.
+const FALLBACK_RESOLVER: &str = "127.0.0.53:53";
+
+/// Everything the workers found out about an IP.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Enrichment {
+  pub ptr: Option<Ptr>,
+  pub rdap: Option<Rdap>,
+}
+
+impl Enrichment {
+  pub fn load(conn: &Connection, ip: &str) -> Result<Self> {
+    Ok(Self { ptr: enrichment::select_ptr(conn, ip)?, rdap: enrichment::select_rdap(conn, ip)? })
+  }
+}
+
+/// First nameserver of /etc/resolv.conf.
+fn system_resolver() -> String {
+  std::fs::read_to_string("/etc/resolv.conf").unwrap_or_default().lines()
+    .filter_map(|line| line.trim().strip_prefix("nameserver"))
+    .map(str::trim)
+    .find(|server| cidr::ip_to_u32(server).is_some())
+    .map(|server| format!("{}:53", server))
+    .unwrap_or(String::from(FALLBACK_RESOLVER))
+}
+
+/// `4.3.2.1.in-addr.arpa` for `1.2.3.4`.
+fn ptr_name(ip: &str) -> Option<String> {
+  cidr::ip_to_u32(ip)?;
+  let octets: Vec<&str> = ip.trim().split('.').rev().collect();
+  Some(format!("{}.in-addr.arpa", octets.join(".")))
+}
+
+fn encode_query(id: u16, name: &str) -> Vec<u8> {
+  let mut packet: Vec<u8> = vec![];
+  packet.extend(id.to_be_bytes());
+  // recursion desired, one question
+  packet.extend([0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+  for label in name.split('.') {
+    packet.push(label.len() as u8);
+    packet.extend(label.as_bytes());
+  }
+  // QTYPE PTR, QCLASS IN
+  packet.extend([0x00, 0x00, 0x0c, 0x00, 0x01]);
+  packet
+}
+
+/// Reads a possibly compressed name, returns it and the position after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+  let mut labels: Vec<String> = vec![];
+  let mut end: Option<usize> = None;
+  // compression pointers may form a loop, give up after a bounded number of steps
+  for _ in 0..128 {
+    let len = *packet.get(pos)? as usize;
+    if len & 0xc0 == 0xc0 {
+      let target = ((len & 0x3f) << 8) | *packet.get(pos + 1)? as usize;
+      end.get_or_insert(pos + 2);
+      pos = target;
+    } else if len == 0 {
+      return Some((labels.join("."), end.unwrap_or(pos + 1)));
+    } else {
+      labels.push(String::from_utf8_lossy(packet.get(pos + 1..pos + 1 + len)?).to_string());
+      pos += 1 + len;
+    }
+  }
+  None
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Option<u16> {
+  Some(u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]))
+}
+
+/// Hostname of the first PTR answer, None for NXDOMAIN or an answer without PTR records.
+fn parse_ptr_response(packet: &[u8], id: u16) -> Result<Option<String>, String> {
+  let malformed = || String::from("malformed DNS answer");
+  if read_u16(packet, 0).ok_or_else(malformed)? != id {
+    return Err(String::from("DNS answer to another query"));
+  }
+  let flags = read_u16(packet, 2).ok_or_else(malformed)?;
+  match flags & 0x000f {
+    0 => {},
+    3 => return Ok(None),
+    rcode => return Err(format!("DNS error {}", rcode)),
+  }
+  let questions = read_u16(packet, 4).ok_or_else(malformed)?;
+  let answers = read_u16(packet, 6).ok_or_else(malformed)?;
+  let mut pos = 12;
+  for _ in 0..questions {
+    pos = read_name(packet, pos).ok_or_else(malformed)?.1 + 4;
+  }
+  for _ in 0..answers {
+    pos = read_name(packet, pos).ok_or_else(malformed)?.1;
+    let rtype = read_u16(packet, pos).ok_or_else(malformed)?;
+    let rdlength = read_u16(packet, pos + 8).ok_or_else(malformed)? as usize;
+    let rdata = pos + 10;
+    if rtype == 12 {
+      return Ok(Some(read_name(packet, rdata).ok_or_else(malformed)?.0));
+    }
+    pos = rdata + rdlength;
+  }
+  Ok(None)
+}
+
+/// Asks `resolver` for the PTR record of the IP.
+pub async fn resolve_ptr(resolver: &str, ip: &str) -> Result<Option<String>, String> {
+  let name = ptr_name(ip).ok_or(format!("{} is no IPv4 address", ip))?;
+  let id: u16 = rand::random();
+  let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+  socket.connect(resolver).await.map_err(|e| format!("{}: {}", resolver, e))?;
+  socket.send(&encode_query(id, &name)).await.map_err(|e| format!("{}: {}", resolver, e))?;
+  let mut buf = [0u8; 1500];
+  let len = tokio::time::timeout(LOOKUP_TIMEOUT, socket.recv(&mut buf)).await
+    .map_err(|_| format!("{} did not answer", resolver))?
+    .map_err(|e| format!("{}: {}", resolver, e))?;
+  parse_ptr_response(&buf[..len], id)
+}
+
+/// The network covering exactly `start` to `end`, None if the range is no single network.
+fn range_to_cidr(start: &str, end: &str) -> Option<Cidr> {
+  let (start, end) = (cidr::ip_to_u32(start)?, cidr::ip_to_u32(end)?);
+  let size = u64::from(end.checked_sub(start)?) + 1;
+  if !size.is_power_of_two() || u64::from(start) % size != 0 {
+    return None;
+  }
+  Some(Cidr { network: start, prefix: 32 - size.trailing_zeros() as u8 })
+}
+
+fn vcard_email(entity: &Value) -> Option<String> {
+  entity["vcardArray"][1].as_array()?.iter()
+    .find(|property| property[0].as_str() == Some("email"))
+    .and_then(|property| property[3].as_str())
+    .map(String::from)
+}
+
+/// Email of the first entity with the abuse role, registries nest it below the registrant.
+fn abuse_email(entities: &Value) -> Option<String> {
+  for entity in entities.as_array()? {
+    let is_abuse = entity["roles"].as_array().is_some_and(|roles| roles.iter().any(|role| role.as_str() == Some("abuse")));
+    if let Some(email) = vcard_email(entity).filter(|_| is_abuse) {
+      return Some(email);
+    }
+    if let Some(email) = abuse_email(&entity["entities"]) {
+      return Some(email);
+    }
+  }
+  None
+}
+
+/// Picks the fields we keep from an RDAP ip network response.
+pub fn parse_rdap(ip: &str, response: &Value) -> Rdap {
+  let text = |value: &Value| value.as_str().unwrap_or("").to_string();
+  let cidrs: Vec<Cidr> = response["cidr0_cidrs"].as_array().map(|cidrs| cidrs.iter()
+    .filter_map(|entry| Cidr::parse(&format!("{}/{}", entry["v4prefix"].as_str()?, entry["length"].as_u64()?)))
+    .collect()).unwrap_or_default();
+  let (start, end) = (text(&response["startAddress"]), text(&response["endAddress"]));
+  let cidr = cidrs.iter().find(|network| network.contains(ip)).copied()
+    .or_else(|| range_to_cidr(&start, &end))
+    .map(|network| network.to_string())
+    .unwrap_or(if start.is_empty() {String::new()} else {format!("{} - {}", start, end)});
+  Rdap {
+    ip: ip.to_string(),
+    cidr,
+    name: text(&response["name"]),
+    handle: text(&response["handle"]),
+    abuse_email: abuse_email(&response["entities"]).unwrap_or_default(),
+    fetched_at: Local::now().to_rfc3339(),
+  }
+}
+
+/// Fetches the registration data of the IP from `{rdap_url}{ip}`.
+pub async fn fetch_rdap(rdap_url: &str, ip: &str) -> Result<Rdap, String> {
+  let url = format!("{}{}", rdap_url, ip);
+  let response = reqwest::Client::new().get(&url).header("Accept", "application/rdap+json").timeout(LOOKUP_TIMEOUT).send().await.map_err(|e| e.to_string())?;
+  if !response.status().is_success() {
+    return Err(format!("{} answered {}", url, response.status()));
+  }
+  let value: Value = response.json().await.map_err(|e| format!("{}: {}", url, e))?;
+  Ok(parse_rdap(ip, &value))
+}
+
+/// Queues of the enrichment workers.
+#[derive(Clone)]
+pub struct Enricher {
+  ptr_queue: mpsc::Sender<String>,
+  rdap_queue: mpsc::Sender<String>,
+}
+
+impl Enricher {
+  /// Queues the IP for both workers. Cached entries are skipped by the workers, IPs that do not fit
+  /// in a full queue are requested again the next time they show up.
+  pub fn request(&self, ip: &str) {
+    self.ptr_queue.try_send(ip.to_string()).unwrap_or_default();
+    self.rdap_queue.try_send(ip.to_string()).unwrap_or_default();
+  }
+}
+
+/// Sends problems once until the lookups work again.
+struct FailureLog {
+  name: &'static str,
+  failing: bool,
+  tx: Option<UnboundedSender<Action>>,
+}
+
+impl FailureLog {
+  fn result<T>(&mut self, result: &Result<T, String>) {
+    let message = match (result, self.failing) {
+      (Err(e), false) => format!(" {} lookups failing: {}", self.name, e),
+      (Ok(_), true) => format!(" {} lookups work again", self.name),
+      _ => return,
+    };
+    self.failing = result.is_err();
+    if let Some(tx) = &self.tx {tx.send(Action::InternalLog(message)).unwrap_or_default();}
+  }
+
+  fn found(&self, ip: &str, found: Result<Enrichment, String>) {
+    let Some(tx) = &self.tx else {return};
+    if let Ok(found) = found {
+      tx.send(Action::GotEnrichment(ip.to_string(), found)).unwrap_or_default();
+    }
+  }
+}
+
+async fn ptr_worker(config: EnrichmentConfig, storage: Storage, mut rx: mpsc::Receiver<String>, mut log: FailureLog) {
+  let resolver = config.resolver.clone().unwrap_or_else(system_resolver);
+  let ttl = chrono::Duration::hours(config.ptr_ttl_hours);
+  while let Some(ip) = rx.recv().await {
+    let now = Local::now().fixed_offset();
+    let cached = {let ip = ip.clone(); storage.request(move |conn| enrichment::select_ptr(conn, &ip)).await};
+    if cached.ok().flatten().is_some_and(|ptr| enrichment::is_fresh(&ptr.resolved_at, ttl, now)) {continue;}
+    let result = match resolve_ptr(&resolver, &ip).await {
+      Ok(hostname) => {
+        let ptr = Ptr { ip: ip.clone(), hostname: hostname.unwrap_or_default(), resolved_at: now.to_rfc3339() };
+        storage.request(move |conn| {
+          enrichment::insert_ptr(conn, &ptr)?;
+          Enrichment::load(conn, &ptr.ip)
+        }).await
+      },
+      Err(e) => Err(e),
+    };
+    log.result(&result);
+    log.found(&ip, result);
+  }
+}
+
+async fn rdap_worker(config: EnrichmentConfig, storage: Storage, mut rx: mpsc::Receiver<String>, mut log: FailureLog) {
+  let ttl = chrono::Duration::hours(config.rdap_ttl_hours);
+  while let Some(ip) = rx.recv().await {
+    let now = Local::now().fixed_offset();
+    let fresh = move |rdap: &Rdap| enrichment::is_fresh(&rdap.fetched_at, ttl, now);
+    // cached, or another IP of the same allocation was looked up already
+    let cached = {
+      let ip = ip.clone();
+      storage.request(move |conn| {
+        if enrichment::select_rdap(conn, &ip)?.is_some_and(|rdap| fresh(&rdap)) {return Ok(None);}
+        let Some(known) = enrichment::select_rdap_for_network(conn, &ip)?.filter(fresh) else {return Ok(Some(false))};
+        enrichment::insert_rdap(conn, &Rdap { ip: ip.clone(), ..known })?;
+        Ok(Some(true))
+      }).await
+    };
+    match cached {
+      Ok(None) => continue,
+      Ok(Some(true)) => {
+        let found = {let ip = ip.clone(); storage.request(move |conn| Enrichment::load(conn, &ip)).await};
+        log.found(&ip, found);
+        continue;
+      },
+      Ok(Some(false)) | Err(_) => {},
+    }
+    let result = match fetch_rdap(&config.rdap_url, &ip).await {
+      Ok(rdap) => storage.request(move |conn| {
+        enrichment::insert_rdap(conn, &rdap)?;
+        Enrichment::load(conn, &rdap.ip)
+      }).await,
+      Err(e) => Err(e),
+    };
+    log.result(&result);
+    log.found(&ip, result);
+    tokio::time::sleep(RDAP_INTERVAL).await;
+  }
+}
+
+/// Starts both workers, they store through the storage service and announce results with GotEnrichment.
+pub fn spawn(config: EnrichmentConfig, storage: Storage, tx: Option<UnboundedSender<Action>>) -> Enricher {
+  let (ptr_queue, ptr_rx) = mpsc::channel::<String>(QUEUE_SIZE);
+  let (rdap_queue, rdap_rx) = mpsc::channel::<String>(QUEUE_SIZE);
+  tokio::spawn(ptr_worker(config.clone(), storage.clone(), ptr_rx, FailureLog { name: "Reverse DNS", failing: false, tx: tx.clone() }));
+  tokio::spawn(rdap_worker(config, storage, rdap_rx, FailureLog { name: "RDAP", failing: false, tx }));
+  Enricher { ptr_queue, rdap_queue }
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+  use super::*;
+
+  /// Answers every query with a PTR record for `host.example.net`, or NXDOMAIN for 192.0.2.2.
+  async fn dns_stand_in(socket: UdpSocket) {
+    let mut buf = [0u8; 512];
+    loop {
+      let Ok((len, peer)) = socket.recv_from(&mut buf).await else {return};
+      let query = &buf[..len];
+      let (name, end) = read_name(query, 12).unwrap();
+      let mut answer = query[..end + 4].to_vec();
+      answer[2] = 0x81;
+      if name == "2.2.0.192.in-addr.arpa" {
+        answer[3] = 0x83;
+      } else {
+        answer[3] = 0x80;
+        answer[7] = 1;
+        // name pointer to the question, PTR, IN, TTL 300, rdata
+        answer.extend([0xc0, 0x0c, 0x00, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x12]);
+        answer.extend([4, b'h', b'o', b's', b't', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'n', b'e', b't', 0]);
+      }
+      socket.send_to(&answer, peer).await.unwrap();
+    }
+  }
+
+  const RDAP_RESPONSE: &str = r#"{"objectClassName":"ip network","handle":"NET-192-0-2-0-1","startAddress":"192.0.2.0","endAddress":"192.0.2.255","name":"TEST-NET-1",
+    "entities":[{"roles":["registrant"],"vcardArray":["vcard",[["fn",{},"text","Example Org"]]],
+      "entities":[{"roles":["abuse"],"vcardArray":["vcard",[["version",{},"text","4.0"],["email",{},"text","abuse@example.net"]]]}]}]}"#;
+
+  #[test]
+  fn test_parse() {
+    assert_eq!(ptr_name("192.0.2.1"), Some(String::from("1.2.0.192.in-addr.arpa")));
+    assert_eq!(ptr_name("2001:db8::1"), None);
+    assert_eq!(range_to_cidr("192.0.2.0", "192.0.2.255"), Cidr::parse("192.0.2.0/24"));
+    assert_eq!(range_to_cidr("192.0.2.0", "192.0.3.127"), None);
+
+    let rdap = parse_rdap("192.0.2.7", &serde_json::from_str(RDAP_RESPONSE).unwrap());
+    assert_eq!((rdap.cidr.as_str(), rdap.name.as_str(), rdap.handle.as_str(), rdap.abuse_email.as_str()), ("192.0.2.0/24", "TEST-NET-1", "NET-192-0-2-0-1", "abuse@example.net"));
+    let ripe = r#"{"startAddress":"198.51.100.0","endAddress":"198.51.101.127","name":"ODD","cidr0_cidrs":[{"v4prefix":"198.51.100.0","length":24},{"v4prefix":"198.51.101.0","length":25}]}"#;
+    let rdap = parse_rdap("198.51.101.9", &serde_json::from_str(ripe).unwrap());
+    assert_eq!(rdap.cidr, "198.51.101.0/25");
+    assert_eq!(rdap.abuse_email, "");
+  }
+
+  #[tokio::test]
+  async fn test_lookups() {
+    let dns = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let resolver = dns.local_addr().unwrap().to_string();
+    tokio::spawn(dns_stand_in(dns));
+    assert_eq!(resolve_ptr(&resolver, "192.0.2.1").await, Ok(Some(String::from("host.example.net"))));
+    assert_eq!(resolve_ptr(&resolver, "192.0.2.2").await, Ok(None));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let rdap_url = format!("http://{}/ip/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+      loop {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 1024];
+        let len = stream.read(&mut buf).await.unwrap();
+        let found = String::from_utf8_lossy(&buf[..len]).starts_with("GET /ip/192.0.2.7 ");
+        let (status, body) = if found {("200 OK", RDAP_RESPONSE)} else {("404 Not Found", "{}")};
+        stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body).as_bytes()).await.unwrap();
+      }
+    });
+    assert_eq!(fetch_rdap(&rdap_url, "192.0.2.7").await.unwrap().abuse_email, "abuse@example.net");
+    assert!(fetch_rdap(&rdap_url, "203.0.113.1").await.is_err());
+
+    // the workers cache both and reuse the allocation for a second IP of it
+    let storage = Storage::open(std::path::Path::new(":memory:"), |conn| {
+      conn.execute(enrichment::CREATE_PTR_DB_SQL, [])?;
+      conn.execute(enrichment::CREATE_RDAP_DB_SQL, []).map(|_| ())
+    }).unwrap();
+    let config = EnrichmentConfig { resolver: Some(resolver), rdap_url, ..EnrichmentConfig::default() };
+    let (tx, mut rx) = mpsc::unbounded_channel::<Action>();
+    let enricher = spawn(config, storage.clone(), Some(tx));
+    enricher.request("192.0.2.7");
+    let mut found: Vec<Enrichment> = vec![];
+    while found.len() < 2 {
+      if let Some(Action::GotEnrichment(ip, enrichment)) = rx.recv().await {
+        assert_eq!(ip, "192.0.2.7");
+        found.push(enrichment);
+      }
+    }
+    let cached = storage.request(|conn| Enrichment::load(conn, "192.0.2.7")).await.unwrap();
+    assert_eq!(cached.ptr.unwrap().hostname, "host.example.net");
+    assert_eq!(cached.rdap.unwrap().cidr, "192.0.2.0/24");
+
+    enricher.request("192.0.2.8");
+    enricher.request("192.0.2.7");
+    let mut rdap_found = false;
+    while !rdap_found {
+      if let Some(Action::GotEnrichment(ip, enrichment)) = rx.recv().await {
+        assert_eq!(ip, "192.0.2.8");
+        rdap_found = enrichment.rdap.is_some_and(|rdap| rdap.abuse_email == "abuse@example.net");
+      }
+    }
+  }
+}
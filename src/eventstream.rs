@@ -0,0 +1,155 @@
+//! NDJSON event stream, every stored event is written as one JSON object per line.
+//! Enabled with `--events-out` or `event_stream.output` in config.json5, `-` writes to stdout, which stays free
+//! while the TUI draws on stderr.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{action::Action, migrations::schema::ip::IP};
+
+/// Bumped whenever a field changes meaning or is removed, new fields keep the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StreamEvent {
+  pub schema: u32,
+  /// id of the stored message
+  pub id: i64,
+  pub at: String,
+  /// `fail2ban`, `journalctl` or `syslog`
+  pub source: String,
+  /// host the line was logged on
+  #[serde(default)]
+  pub host: String,
+  pub lines: Vec<String>,
+  pub usernames: Vec<String>,
+  /// geolocation and counters of the IP
+  pub ip: IP,
+  /// whether the IP was banned when the event came in
+  pub banned: bool,
+  /// blocked Country, Region, City, ISP or Username that matched, e.g. `Country: Atlantis`
+  pub blocked_by: Vec<String>,
+  /// why a matching block was not applied, e.g. an allowlist entry
+  pub block_refused: Option<String>,
+  /// names of the rules that fired
+  pub rules: Vec<String>,
+  /// names of the blocklists the IP is on
+  #[serde(default)]
+  pub tags: Vec<String>,
+}
+
+pub struct EventWriter {
+  out: Box<dyn Write + Send>,
+}
+
+impl EventWriter {
+  /// Opens the output, `-` is stdout, files are appended to.
+  pub fn open(path: &Path) -> std::io::Result<Self> {
+    let out: Box<dyn Write + Send> = if path.as_os_str() == "-" {
+      Box::new(std::io::stdout())
+    } else {
+      Box::new(OpenOptions::new().create(true).append(true).open(path)?)
+    };
+    Ok(Self { out })
+  }
+
+  pub fn write(&mut self, event: &StreamEvent) -> std::io::Result<()> {
+    let line = serde_json::to_string(event)?;
+    writeln!(self.out, "{}", line)?;
+    self.out.flush()
+  }
+}
+
+/// Queue of the thread writing the stream, a slow reader of the output holds up only that thread.
+pub struct EventStream {
+  queue: mpsc::Sender<StreamEvent>,
+}
+
+impl EventStream {
+  /// Writes events on their own thread until the output fails, the failure is sent as InternalLog.
+  pub fn spawn(mut writer: EventWriter, tx: Option<UnboundedSender<Action>>) -> std::io::Result<Self> {
+    let (queue, rx) = mpsc::channel::<StreamEvent>();
+    std::thread::Builder::new().name(String::from("event stream")).spawn(move || {
+      while let Ok(event) = rx.recv() {
+        if let Err(e) = writer.write(&event) {
+          if let Some(tx) = &tx {
+            tx.send(Action::InternalLog(format!(" ❌ Event stream failed, stopped writing it: {}", e))).unwrap_or_default();
+          }
+          return;
+        }
+      }
+    })?;
+    Ok(Self { queue })
+  }
+
+  /// Queues the event, false once the writer stopped.
+  pub fn send(&self, event: StreamEvent) -> bool {
+    self.queue.send(event).is_ok()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+
+  use super::*;
+
+  #[test]
+  fn test_write_ndjson() {
+    let path = std::env::temp_dir().join(format!("succeed2ban-events-{}.ndjson", std::process::id()));
+    let event = StreamEvent {
+      schema: SCHEMA_VERSION,
+      id: 7,
+      at: String::from("2023-12-08T10:00:00+01:00"),
+      source: String::from("journalctl"),
+      host: String::from("web1"),
+      lines: vec![String::from("Invalid user admin from 192.0.2.1 port 22")],
+      usernames: vec![String::from("admin")],
+      ip: IP { ip: String::from("192.0.2.1"), country: String::from("Atlantis"), ..IP::default() },
+      banned: false,
+      blocked_by: vec![String::from("Country: Atlantis")],
+      block_refused: None,
+      rules: vec![String::from("subnet-burst")],
+      tags: vec![String::from("tor")],
+    };
+    let mut writer = EventWriter::open(&path).unwrap();
+    writer.write(&event).unwrap();
+    writer.write(&StreamEvent { id: 8, ..event.clone() }).unwrap();
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let lines: Vec<&str> = written.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(serde_json::from_str::<StreamEvent>(lines[0]).unwrap(), event);
+    let value: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+    assert_eq!(value["schema"], 1);
+    assert_eq!(value["ip"]["country"], "Atlantis");
+  }
+
+  /// Output whose reader went away.
+  struct Closed;
+
+  impl Write for Closed {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+      Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_stream_stops_on_failure() {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Action>();
+    let stream = EventStream::spawn(EventWriter { out: Box::new(Closed) }, Some(tx)).unwrap();
+    assert!(stream.send(StreamEvent::default()));
+    let Some(Action::InternalLog(msg)) = rx.blocking_recv() else {panic!("no failure reported")};
+    assert!(msg.contains("Event stream failed"));
+  }
+}
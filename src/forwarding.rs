@@ -0,0 +1,687 @@
+//! Forwards stored events to a SIEM, as RFC5424 syslog with structured data, GELF over UDP or TCP, or
+//! Elastic Common Schema documents through the HTTP bulk API. Configured with `forwarders` in config.json5.
+//! Every forwarder has its own worker, which holds `queue_size` events in memory. Events beyond that or while the
+//! target is down are appended to a size-capped spill file in the data dir and replayed in order once the target
+//! is reachable again.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{action::Action, eventstream::StreamEvent};
+
+/// Deliveries give up after this long.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait before the first retry, doubled after every failure up to MAX_BACKOFF.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Events delivered together, one TCP connection or bulk request per batch.
+const BATCH_SIZE: usize = 100;
+/// Largest GELF UDP chunk, bigger messages are split into up to 128 chunks.
+const GELF_CHUNK_SIZE: usize = 8192;
+/// Private enterprise number reserved for documentation (RFC5612), used for the structured data ID.
+const SD_ID: &str = "succeed2ban@32473";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardFormat {
+  /// RFC5424 with the event in structured data, octet-counted over TCP
+  #[default]
+  Syslog,
+  /// GELF 1.1, null-terminated over TCP, chunked over UDP
+  Gelf,
+  /// Elastic Common Schema documents POSTed to `<address>/_bulk`
+  Ecs,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+  #[default]
+  Udp,
+  Tcp,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct Forwarder {
+  pub name: String,
+  #[serde(default)]
+  pub format: ForwardFormat,
+  /// `host:port` for syslog and GELF, base URL like `http://elastic:9200` for ECS
+  pub address: String,
+  /// ignored by ECS, which always uses HTTP
+  #[serde(default)]
+  pub transport: Transport,
+  /// index the ECS documents are written to
+  #[serde(default = "default_index")]
+  pub index: String,
+  /// events waiting for delivery before new ones go to the spill file
+  #[serde(default = "default_queue_size")]
+  pub queue_size: usize,
+  /// undelivered events kept on disk, the oldest are dropped beyond it
+  #[serde(default = "default_spill_limit_mb")]
+  pub spill_limit_mb: u64,
+}
+
+fn default_index() -> String {
+  String::from("succeed2ban")
+}
+
+fn default_queue_size() -> usize {
+  1000
+}
+
+fn default_spill_limit_mb() -> u64 {
+  64
+}
+
+/// Checks every forwarder for errors, returns one message per problem.
+pub fn validate(forwarders: &[Forwarder]) -> Vec<String> {
+  let mut errors: Vec<String> = vec![];
+  for forwarder in forwarders {
+    if forwarder.name.is_empty() || !forwarder.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+      errors.push(format!("Forwarder {}: name may only contain letters, digits, - and _", forwarder.name));
+    }
+    if forwarder.address.is_empty() {
+      errors.push(format!("Forwarder {}: address is missing", forwarder.name));
+    }
+    if forwarder.format == ForwardFormat::Ecs && !forwarder.address.starts_with("http") {
+      errors.push(format!("Forwarder {}: address has to be an http(s) URL", forwarder.name));
+    }
+    if forwarder.queue_size == 0 {
+      errors.push(format!("Forwarder {}: queue_size has to be at least 1", forwarder.name));
+    }
+    if forwarder.spill_limit_mb == 0 {
+      errors.push(format!("Forwarder {}: spill_limit_mb has to be at least 1", forwarder.name));
+    }
+  }
+  errors
+}
+
+fn severity(event: &StreamEvent) -> u8 {
+  // warning if the event led to a ban or matched a block, notice otherwise
+  if event.banned || !event.blocked_by.is_empty() {4} else {5}
+}
+
+fn sd_escape(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+/// One RFC5424 message with facility auth.
+pub fn to_syslog(event: &StreamEvent) -> String {
+  let params = [
+    ("id", event.id.to_string()),
+    ("source", event.source.clone()),
+    ("ip", event.ip.ip.clone()),
+    ("country", event.ip.country.clone()),
+    ("region", event.ip.region.clone()),
+    ("city", event.ip.city.clone()),
+    ("isp", event.ip.isp.clone()),
+    ("banned", event.banned.to_string()),
+    ("blocked_by", event.blocked_by.join(",")),
+    ("rules", event.rules.join(",")),
+    ("usernames", event.usernames.join(",")),
+    ("tags", event.tags.join(",")),
+  ];
+  let sd: String = params.iter().map(|(key, value)| format!(" {}=\"{}\"", key, sd_escape(value))).collect();
+  let hostname = if event.host.is_empty() {"-"} else {&event.host};
+  // TIME-SECFRAC has at most 6 digits, Local::now() gives 9
+  let timestamp = chrono::DateTime::parse_from_rfc3339(&event.at).map(|at| at.to_rfc3339_opts(chrono::SecondsFormat::Micros, false)).unwrap_or_else(|_| String::from("-"));
+  format!("<{}>1 {} {} succeed2ban - {} [{}{}] {}", 4 * 8 + severity(event), timestamp, hostname, event.source, SD_ID, sd, event.lines.join(" ++++ "))
+}
+
+/// One GELF 1.1 message, the event fields are additional fields.
+pub fn to_gelf(event: &StreamEvent) -> String {
+  let timestamp = chrono::DateTime::parse_from_rfc3339(&event.at).map(|at| at.timestamp_millis()).unwrap_or_else(|_| chrono::Utc::now().timestamp_millis());
+  json!({
+    "version": "1.1",
+    "host": if event.host.is_empty() {"unknown"} else {&event.host},
+    "short_message": event.lines.first().cloned().unwrap_or_default(),
+    "full_message": event.lines.join("\n"),
+    "timestamp": timestamp as f64 / 1000.0,
+    "level": severity(event),
+    "_event_id": event.id,
+    "_source": event.source,
+    "_ip": event.ip.ip,
+    "_country": event.ip.country,
+    "_region": event.ip.region,
+    "_city": event.ip.city,
+    "_isp": event.ip.isp,
+    "_banned": event.banned,
+    "_blocked_by": event.blocked_by.join(","),
+    "_rules": event.rules.join(","),
+    "_usernames": event.usernames.join(","),
+    "_tags": event.tags.join(","),
+  }).to_string()
+}
+
+/// One Elastic Common Schema document.
+pub fn to_ecs(event: &StreamEvent) -> serde_json::Value {
+  let location = match (event.ip.lat.parse::<f64>(), event.ip.lon.parse::<f64>()) {
+    (Ok(lat), Ok(lon)) => json!({"lat": lat, "lon": lon}),
+    _ => serde_json::Value::Null,
+  };
+  json!({
+    "@timestamp": event.at,
+    "ecs": {"version": "8.11.0"},
+    "message": event.lines.join("\n"),
+    "event": {
+      "kind": "event",
+      "category": ["authentication"],
+      "dataset": format!("succeed2ban.{}", event.source),
+      "id": event.id.to_string(),
+      "severity": severity(event),
+    },
+    "host": {"name": event.host},
+    "source": {
+      "ip": event.ip.ip,
+      "geo": {
+        "country_name": event.ip.country,
+        "country_iso_code": event.ip.countrycode,
+        "region_name": event.ip.region,
+        "city_name": event.ip.city,
+        "location": location,
+      },
+      "as": {"organization": {"name": event.ip.isp}},
+    },
+    "user": {"name": event.usernames.first()},
+    "related": {"ip": [event.ip.ip], "user": event.usernames},
+    "rule": {"name": event.rules},
+    "tags": event.tags,
+    "succeed2ban": {
+      "banned": event.banned,
+      "blocked_by": event.blocked_by,
+      "block_refused": event.block_refused,
+    },
+  })
+}
+
+/// Splits a GELF message into UDP datagrams, unchunked if it fits into one.
+fn gelf_chunks(message: &[u8], id: u64) -> Result<Vec<Vec<u8>>, String> {
+  if message.len() <= GELF_CHUNK_SIZE {
+    return Ok(vec![message.to_vec()]);
+  }
+  let payload_size = GELF_CHUNK_SIZE - 12;
+  let count = message.len().div_ceil(payload_size);
+  if count > 128 {
+    return Err(format!("GELF message of {} bytes is too large for UDP", message.len()));
+  }
+  Ok(message.chunks(payload_size).enumerate().map(|(seq, chunk)| {
+    let mut datagram = vec![0x1e, 0x0f];
+    datagram.extend_from_slice(&id.to_be_bytes());
+    datagram.push(seq as u8);
+    datagram.push(count as u8);
+    datagram.extend_from_slice(chunk);
+    datagram
+  }).collect())
+}
+
+async fn send_stream(forwarder: &Forwarder, events: &[StreamEvent]) -> Result<(), String> {
+  let mut stream = TcpStream::connect(&forwarder.address).await.map_err(|e| e.to_string())?;
+  let mut payload: Vec<u8> = vec![];
+  for event in events {
+    match forwarder.format {
+      ForwardFormat::Syslog => {
+        let message = to_syslog(event);
+        payload.extend_from_slice(format!("{} {}", message.len(), message).as_bytes());
+      },
+      ForwardFormat::Gelf => {
+        payload.extend_from_slice(to_gelf(event).as_bytes());
+        payload.push(0);
+      },
+      ForwardFormat::Ecs => unreachable!("ECS is sent over HTTP"),
+    }
+  }
+  stream.write_all(&payload).await.map_err(|e| e.to_string())?;
+  stream.shutdown().await.map_err(|e| e.to_string())
+}
+
+async fn send_datagrams(forwarder: &Forwarder, events: &[StreamEvent]) -> Result<(), String> {
+  let bind = if forwarder.address.starts_with('[') {"[::]:0"} else {"0.0.0.0:0"};
+  let socket = UdpSocket::bind(bind).await.map_err(|e| e.to_string())?;
+  socket.connect(&forwarder.address).await.map_err(|e| e.to_string())?;
+  for event in events {
+    let datagrams = match forwarder.format {
+      ForwardFormat::Syslog => vec![to_syslog(event).into_bytes()],
+      ForwardFormat::Gelf => gelf_chunks(to_gelf(event).as_bytes(), rand::random())?,
+      ForwardFormat::Ecs => unreachable!("ECS is sent over HTTP"),
+    };
+    for datagram in datagrams {
+      socket.send(&datagram).await.map_err(|e| e.to_string())?;
+    }
+  }
+  Ok(())
+}
+
+async fn send_bulk(forwarder: &Forwarder, events: &[StreamEvent]) -> Result<(), String> {
+  let mut body = String::new();
+  for event in events {
+    body.push_str(&json!({"create": {"_index": forwarder.index}}).to_string());
+    body.push('\n');
+    body.push_str(&to_ecs(event).to_string());
+    body.push('\n');
+  }
+  let url = format!("{}/_bulk", forwarder.address.trim_end_matches('/'));
+  let response = reqwest::Client::new().post(&url)
+    .header("Content-Type", "application/x-ndjson")
+    .body(body)
+    .send().await.map_err(|e| e.to_string())?;
+  let status = response.status();
+  if !status.is_success() {
+    return Err(format!("{} answered {}", url, status));
+  }
+  let result: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
+  if result["errors"].as_bool().unwrap_or(false) {
+    return Err(format!("{} rejected documents", url));
+  }
+  Ok(())
+}
+
+/// Sends a batch of events to the forwarder's target.
+pub async fn deliver(forwarder: &Forwarder, events: &[StreamEvent]) -> Result<(), String> {
+  let send = async {
+    match (forwarder.format, forwarder.transport) {
+      (ForwardFormat::Ecs, _) => send_bulk(forwarder, events).await,
+      (_, Transport::Tcp) => send_stream(forwarder, events).await,
+      (_, Transport::Udp) => send_datagrams(forwarder, events).await,
+    }
+  };
+  tokio::time::timeout(DELIVERY_TIMEOUT, send).await.map_err(|_| String::from("timed out"))?
+}
+
+/// NDJSON file of events waiting for delivery, replayed from an offset that is kept next to it so
+/// delivered events are not sent again after a restart. Only the forwarder's worker touches it.
+pub struct Spill {
+  path: PathBuf,
+  offset_path: PathBuf,
+  /// start of the first undelivered event
+  offset: u64,
+  len: u64,
+  max_bytes: u64,
+}
+
+impl Spill {
+  pub fn open(path: PathBuf, max_bytes: u64) -> Self {
+    let offset_path = path.with_extension("offset");
+    let len = std::fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+    let offset = std::fs::read_to_string(&offset_path).ok().and_then(|offset| offset.trim().parse().ok()).unwrap_or(0).min(len);
+    Self { path, offset_path, offset, len, max_bytes }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.offset >= self.len
+  }
+
+  /// Appends events, then drops the oldest ones until the undelivered part fits `max_bytes` again.
+  /// Returns the number of dropped events.
+  pub fn append(&mut self, events: &[StreamEvent]) -> std::io::Result<usize> {
+    let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+    let mut lines = String::new();
+    for event in events {
+      lines.push_str(&serde_json::to_string(event)?);
+      lines.push('\n');
+    }
+    file.write_all(lines.as_bytes())?;
+    self.len += lines.len() as u64;
+    let mut dropped = 0;
+    if self.len - self.offset > self.max_bytes {
+      let mut reader = self.reader()?;
+      let mut line = String::new();
+      while self.len - self.offset > self.max_bytes {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {break;}
+        self.offset += read as u64;
+        dropped += 1;
+      }
+      self.save_offset()?;
+    }
+    // the delivered head is cut off once it is as large as the cap, which bounds the file to about twice the cap
+    if self.offset > self.max_bytes {
+      self.compact()?;
+    }
+    Ok(dropped)
+  }
+
+  /// Up to `max` events from the offset on, and the offset after them. Lines that no longer parse are skipped.
+  pub fn peek(&self, max: usize) -> std::io::Result<(Vec<StreamEvent>, u64)> {
+    let mut reader = self.reader()?;
+    let mut events: Vec<StreamEvent> = vec![];
+    let mut next = self.offset;
+    let mut line = String::new();
+    while events.len() < max {
+      line.clear();
+      let read = reader.read_line(&mut line)?;
+      if read == 0 {break;}
+      next += read as u64;
+      events.extend(serde_json::from_str(&line).ok());
+    }
+    Ok((events, next))
+  }
+
+  /// Marks everything before `offset` as delivered, the file is removed once all of it is.
+  pub fn advance(&mut self, offset: u64) -> std::io::Result<()> {
+    self.offset = offset.min(self.len);
+    if self.is_empty() {
+      return self.clear();
+    }
+    self.save_offset()
+  }
+
+  /// Drops all spilled events.
+  pub fn clear(&mut self) -> std::io::Result<()> {
+    (self.offset, self.len) = (0, 0);
+    for path in [&self.path, &self.offset_path] {
+      match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {},
+      }
+    }
+    Ok(())
+  }
+
+  fn reader(&self) -> std::io::Result<BufReader<File>> {
+    let mut file = File::open(&self.path)?;
+    file.seek(SeekFrom::Start(self.offset))?;
+    Ok(BufReader::new(file))
+  }
+
+  fn save_offset(&self) -> std::io::Result<()> {
+    std::fs::write(&self.offset_path, self.offset.to_string())
+  }
+
+  fn compact(&mut self) -> std::io::Result<()> {
+    let tmp = self.path.with_extension("tmp");
+    std::io::copy(&mut self.reader()?, &mut File::create(&tmp)?)?;
+    std::fs::rename(&tmp, &self.path)?;
+    (self.len, self.offset) = (self.len - self.offset, 0);
+    self.save_offset()
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+}
+
+/// Queue of one forwarder, sending never waits for the target or the disk.
+pub struct ForwardHandle {
+  pub name: String,
+  queue: UnboundedSender<StreamEvent>,
+}
+
+impl ForwardHandle {
+  /// Hands the event to the worker, which keeps `queue_size` events in memory and spills the rest.
+  pub fn send(&self, event: StreamEvent) -> Result<(), String> {
+    self.queue.send(event).map_err(|_| String::from("worker stopped"))
+  }
+}
+
+struct Worker {
+  forwarder: Forwarder,
+  /// events in the order they came in, all older than the spilled ones
+  pending: VecDeque<StreamEvent>,
+  spill: Spill,
+  tx: Option<UnboundedSender<Action>>,
+}
+
+impl Worker {
+  fn log(&self, message: String) {
+    if let Some(tx) = &self.tx {tx.send(Action::InternalLog(message)).unwrap_or_default();}
+  }
+
+  /// Keeps the event in memory, or spills it once the memory queue is full or events are spilled already.
+  fn receive(&mut self, event: StreamEvent) {
+    if self.spill.is_empty() && self.pending.len() < self.forwarder.queue_size {
+      self.pending.push_back(event);
+      return;
+    }
+    match self.spill.append(&[event]) {
+      Ok(0) => {},
+      Ok(dropped) => self.log(format!(" Forwarder {}: spill file full, dropped the {} oldest events", self.forwarder.name, dropped)),
+      Err(e) => self.log(format!(" Forwarder {}: event lost, spilling to {} failed: {}", self.forwarder.name, self.spill.path().display(), e)),
+    }
+  }
+
+  /// Waits out the backoff, events coming in meanwhile are queued or spilled. False once the channel closed.
+  async fn wait(&mut self, rx: &mut UnboundedReceiver<StreamEvent>, backoff: Duration) -> bool {
+    let sleep = tokio::time::sleep(backoff);
+    tokio::pin!(sleep);
+    loop {
+      tokio::select! {
+        _ = &mut sleep => return true,
+        event = rx.recv() => match event {
+          Some(event) => self.receive(event),
+          None => return false,
+        },
+      }
+    }
+  }
+
+  async fn run(mut self, mut rx: UnboundedReceiver<StreamEvent>) {
+    let mut backoff = MIN_BACKOFF;
+    let mut failing = false;
+    loop {
+      while let Ok(event) = rx.try_recv() {
+        self.receive(event);
+      }
+      // the memory queue first, it is older than the spill file
+      let (batch, spilled_until) = if !self.pending.is_empty() {
+        (self.pending.iter().take(BATCH_SIZE).cloned().collect(), None)
+      } else if !self.spill.is_empty() {
+        match self.spill.peek(BATCH_SIZE) {
+          Ok((events, next)) => (events, Some(next)),
+          Err(e) => {
+            self.log(format!(" Forwarder {}: reading {} failed, spilled events dropped: {}", self.forwarder.name, self.spill.path().display(), e));
+            self.spill.clear().unwrap_or_default();
+            continue;
+          },
+        }
+      } else {
+        let Some(event) = rx.recv().await else {break};
+        self.receive(event);
+        continue;
+      };
+      let delivered = if batch.is_empty() {Ok(())} else {deliver(&self.forwarder, &batch).await};
+      match delivered {
+        Ok(()) => {
+          if failing {self.log(format!(" Forwarder {} delivers again", self.forwarder.name));}
+          failing = false;
+          backoff = MIN_BACKOFF;
+          match spilled_until {
+            Some(next) => {
+              if let Err(e) = self.spill.advance(next) {
+                self.log(format!(" Forwarder {}: storing the replay offset failed: {}", self.forwarder.name, e));
+              }
+            },
+            None => {self.pending.drain(..batch.len());},
+          }
+        },
+        Err(e) => {
+          if !failing {self.log(format!(" Forwarder {} failed, spilling to disk until it recovers: {}", self.forwarder.name, e));}
+          failing = true;
+          if !self.wait(&mut rx, backoff).await {break;}
+          backoff = (backoff * 2).min(MAX_BACKOFF);
+        },
+      }
+    }
+  }
+}
+
+/// Starts the worker of a forwarder, its spill file lives in `spill_dir`. Failures and recoveries are logged to `tx`.
+pub fn spawn(forwarder: Forwarder, spill_dir: &Path, tx: Option<UnboundedSender<Action>>) -> ForwardHandle {
+  let (queue, rx) = mpsc::unbounded_channel::<StreamEvent>();
+  std::fs::create_dir_all(spill_dir).unwrap_or_default();
+  let spill = Spill::open(spill_dir.join(format!("forward-{}.ndjson", forwarder.name)), forwarder.spill_limit_mb.max(1) * 1024 * 1024);
+  let handle = ForwardHandle { name: forwarder.name.clone(), queue };
+  let worker = Worker { forwarder, pending: VecDeque::new(), spill, tx };
+  tokio::spawn(worker.run(rx));
+  handle
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+  use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+  use tokio::net::TcpListener;
+
+  use super::*;
+  use crate::migrations::schema::ip::IP;
+
+  fn event(id: i64) -> StreamEvent {
+    StreamEvent {
+      schema: crate::eventstream::SCHEMA_VERSION,
+      id,
+      at: String::from("2023-12-08T10:00:00.123456789+01:00"),
+      source: String::from("journalctl"),
+      host: String::from("web1"),
+      lines: vec![String::from("Invalid user admin from 192.0.2.1 port 22")],
+      usernames: vec![String::from("admin")],
+      ip: IP { ip: String::from("192.0.2.1"), country: String::from("Atlantis"), lat: String::from("3.12"), lon: String::from("59.79"), ..IP::default() },
+      banned: true,
+      blocked_by: vec![String::from("Country: \"Atlantis\"")],
+      block_refused: None,
+      rules: vec![],
+      tags: vec![],
+    }
+  }
+
+  #[test]
+  fn test_formats() {
+    let syslog = to_syslog(&event(7));
+    assert!(syslog.starts_with("<36>1 2023-12-08T10:00:00.123456+01:00 web1 succeed2ban - journalctl [succeed2ban@32473 id=\"7\""));
+    assert!(syslog.contains(" blocked_by=\"Country: \\\"Atlantis\\\"\""));
+    assert!(syslog.ends_with("] Invalid user admin from 192.0.2.1 port 22"));
+
+    let gelf: serde_json::Value = serde_json::from_str(&to_gelf(&event(7))).unwrap();
+    assert_eq!(gelf["host"], "web1");
+    assert_eq!(gelf["timestamp"], 1702026000.123);
+    assert_eq!(gelf["_country"], "Atlantis");
+
+    let ecs = to_ecs(&event(7));
+    assert_eq!(ecs["source"]["ip"], "192.0.2.1");
+    assert_eq!(ecs["source"]["geo"]["location"]["lon"], 59.79);
+    assert_eq!(ecs["succeed2ban"]["banned"], true);
+
+    let chunks = gelf_chunks(&vec![b'x'; GELF_CHUNK_SIZE * 2], 1).unwrap();
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(&chunks[2][..2], &[0x1e, 0x0f]);
+    assert_eq!((chunks[2][10], chunks[2][11]), (2, 3));
+  }
+
+  #[tokio::test]
+  async fn test_deliver() {
+    // syslog over UDP
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let forwarder = Forwarder { name: String::from("udp"), address: socket.local_addr().unwrap().to_string(), ..Forwarder::default() };
+    deliver(&forwarder, &[event(1)]).await.unwrap();
+    let mut buf = [0u8; 2048];
+    let n = socket.recv(&mut buf).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&buf[..n]), to_syslog(&event(1)));
+
+    // GELF over TCP
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let forwarder = Forwarder { name: String::from("gelf"), format: ForwardFormat::Gelf, transport: Transport::Tcp, address: listener.local_addr().unwrap().to_string(), ..Forwarder::default() };
+    let events = [event(1), event(2)];
+    let (sent, accepted) = tokio::join!(deliver(&forwarder, &events), listener.accept());
+    sent.unwrap();
+    let mut received = vec![];
+    accepted.unwrap().0.read_to_end(&mut received).await.unwrap();
+    let messages: Vec<&[u8]> = received.split(|b| *b == 0).filter(|m| !m.is_empty()).collect();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(messages[1]).unwrap()["_event_id"], 2);
+
+    // ECS bulk over HTTP
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let forwarder = Forwarder { name: String::from("ecs"), format: ForwardFormat::Ecs, address: format!("http://{}", listener.local_addr().unwrap()), index: default_index(), ..Forwarder::default() };
+    let server = tokio::spawn(async move {
+      let (stream, _) = listener.accept().await.unwrap();
+      let mut reader = BufReader::new(stream);
+      let mut request_line = String::new();
+      reader.read_line(&mut request_line).await.unwrap();
+      let mut length = 0;
+      loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.unwrap();
+        if header.trim().is_empty() {break;}
+        if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {length = value.trim().parse().unwrap();}
+      }
+      let mut body = vec![0u8; length];
+      reader.read_exact(&mut body).await.unwrap();
+      let answer = "{\"errors\":false}";
+      reader.get_mut().write_all(format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", answer.len(), answer).as_bytes()).await.unwrap();
+      (request_line, String::from_utf8(body).unwrap())
+    });
+    deliver(&forwarder, &[event(3)]).await.unwrap();
+    let (request_line, body) = server.await.unwrap();
+    assert!(request_line.starts_with("POST /_bulk "));
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines[0], "{\"create\":{\"_index\":\"succeed2ban\"}}");
+    assert_eq!(serde_json::from_str::<serde_json::Value>(lines[1]).unwrap()["event"]["id"], "3");
+  }
+
+  #[test]
+  fn test_spill_cap_and_offset() {
+    let dir = std::env::temp_dir().join(format!("succeed2ban-spill-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("forward-capped.ndjson");
+    let line_len = serde_json::to_string(&event(1)).unwrap().len() as u64 + 1;
+    let mut spill = Spill::open(path.clone(), line_len * 3);
+    assert_eq!(spill.append(&[event(1), event(2)]).unwrap(), 0);
+    // over the cap, the oldest goes
+    assert_eq!(spill.append(&[event(3), event(4)]).unwrap(), 1);
+    let (events, next) = spill.peek(2).unwrap();
+    assert_eq!(events.iter().map(|e| e.id).collect::<Vec<i64>>(), vec![2, 3]);
+    spill.advance(next).unwrap();
+
+    // replay goes on where it stopped, also after a restart
+    let mut spill = Spill::open(path.clone(), line_len * 3);
+    assert_eq!(spill.peek(10).unwrap().0.iter().map(|e| e.id).collect::<Vec<i64>>(), vec![4]);
+    // the delivered and dropped head is cut off once it outgrows the cap
+    assert_eq!(spill.append(&[event(5), event(6), event(7)]).unwrap(), 1);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), line_len * 3);
+    let (events, next) = spill.peek(10).unwrap();
+    assert_eq!(events.iter().map(|e| e.id).collect::<Vec<i64>>(), vec![5, 6, 7]);
+    spill.advance(next).unwrap();
+    assert!(spill.is_empty() && !path.exists());
+    std::fs::remove_dir_all(&dir).unwrap_or_default();
+  }
+
+  #[tokio::test]
+  async fn test_spill_and_replay() {
+    let dir = std::env::temp_dir().join(format!("succeed2ban-forward-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // reserve a port nobody listens on yet
+    let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let forwarder = Forwarder { name: String::from("down"), transport: Transport::Tcp, address: addr.to_string(), queue_size: 1, ..Forwarder::default() };
+    let handle = spawn(forwarder, &dir, None);
+    for id in 1..=5 {
+      handle.send(event(id)).unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let spill_path = dir.join("forward-down.ndjson");
+    assert!(spill_path.exists());
+
+    // the target comes up, the worker replays the spill file after its backoff
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let mut received = String::new();
+    while received.matches("succeed2ban@32473").count() < 5 {
+      let (mut stream, _) = tokio::time::timeout(Duration::from_secs(10), listener.accept()).await.unwrap().unwrap();
+      stream.read_to_string(&mut received).await.unwrap();
+    }
+    let ids: Vec<&str> = received.match_indices(" id=\"").map(|(idx, _)| &received[idx + 5..idx + 6]).collect();
+    let mut sorted = ids.clone();
+    sorted.sort();
+    assert_eq!(sorted, vec!["1", "2", "3", "4", "5"]);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!spill_path.exists());
+    std::fs::remove_dir_all(&dir).unwrap_or_default();
+  }
+}
@@ -57,6 +57,23 @@ impl<T> StatefulList<T> {
         self.items.remove(0);
     }
   }
+
+  /// Selects the item drawn at terminal `row` of a bordered list rendered in `area`, `item_height` returns the rows an item takes.
+  pub fn select_at_row(&mut self, area: Rect, row: u16, item_height: impl Fn(&T) -> usize) -> Option<usize> {
+    if row <= area.y || row >= area.y + area.height.saturating_sub(1) {
+      return None;
+    }
+    let mut rel = usize::from(row - area.y - 1);
+    for (idx, item) in self.items.iter().enumerate().skip(self.state.offset()) {
+      let height = item_height(item).max(1);
+      if rel < height {
+        self.state.select(Some(idx));
+        return Some(idx);
+      }
+      rel -= height;
+    }
+    None
+  }
 }
 
 
@@ -1,3 +1,27 @@
+//! Geolocation of IPs with ip-api.com, configured with `geolocation` in config.json5.
+//! New IPs go through one scheduler that keeps to the rate limits of the API and asks for several IPs at once
+//! with the batch endpoint. Lines of an IP wait parked until its lookup is answered, a second line of the same IP
+//! does not ask again.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
+
+use crate::{action::Action, config::GeoConfig, metrics::METRICS, migrations::schema::ip::IP};
+
+/// Lookups are given up on after this long.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Waited if the API limits a request without saying for how long.
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(60);
+/// Stale IPs looked up again per round, fits one batch request.
+pub const REFRESH_BATCH: usize = 100;
+/// Pause between the starts of two rounds of re-resolving stale IPs.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
 pub async fn fetch_geolocation(ip: &str) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
 
     let url = format!("http://ip-api.com/json/{ip}");
@@ -17,4 +41,339 @@ pub async fn fetch_home() -> Result<String, Box<dyn std::error::Error>>{
     let text = resp.text().await.unwrap();
     //println!("{:#?}", resp);
     Ok(text)  
-  }
\ No newline at end of file
+  }
+
+/// Location fields of one ip-api answer, counters and timestamps are left for the caller.
+pub fn parse_geolocation(value: &Value) -> Result<IP, String> {
+    let text = |key: &str| value[key].as_str().unwrap_or("").to_string();
+    if value["status"].as_str() != Some("success") {
+        let message = text("message");
+        return Err(if message.is_empty() {String::from("no location found")} else {message});
+    }
+    let number = |key: &str| value[key].as_number().map(|n| n.to_string()).unwrap_or_default();
+    Ok(IP {
+        ip: text("query"),
+        lat: number("lat"),
+        lon: number("lon"),
+        isp: text("isp"),
+        country: text("country"),
+        countrycode: text("countryCode"),
+        city: text("city"),
+        region: text("regionName"),
+        // `as` is like "AS64496 Example Networks"
+        asn: text("as").split_whitespace().next().filter(|asn| asn.starts_with("AS")).unwrap_or("").to_string(),
+        ..IP::default()
+    })
+}
+
+/// A line waiting for the location of its IP.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParkedLine {
+    pub line: String,
+    /// host the line was logged on
+    pub host: String,
+    pub is_banned: bool,
+    pub parked_at: String,
+}
+
+/// Lines per IP with a lookup in flight, in the order they came in.
+#[derive(Default)]
+pub struct ParkedLines {
+    lines: HashMap<String, Vec<ParkedLine>>,
+}
+
+impl ParkedLines {
+    /// Parks the line, returns true if its IP needs a lookup and false if one is in flight already.
+    pub fn park(&mut self, ip: &str, line: ParkedLine) -> bool {
+        let waiting = self.lines.entry(ip.to_string()).or_default();
+        waiting.push(line);
+        waiting.len() == 1
+    }
+
+    /// Lines of the IP in the order they were parked.
+    pub fn release(&mut self, ip: &str) -> Vec<ParkedLine> {
+        self.lines.remove(ip).unwrap_or_default()
+    }
+
+    /// (IPs waiting for a lookup, lines parked)
+    pub fn depth(&self) -> (usize, usize) {
+        (self.lines.len(), self.lines.values().map(Vec::len).sum())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RefreshOutcome {
+    Unchanged,
+    Moved,
+    Failed,
+}
+
+/// Looking up known IPs again whose location is older than the TTL, a round of up to REFRESH_BATCH IPs at a time.
+#[derive(Default)]
+pub struct GeoRefresh {
+    pending: HashSet<String>,
+    started: Option<std::time::Instant>,
+    moved: usize,
+    failed: usize,
+    total: usize,
+}
+
+impl GeoRefresh {
+    /// True if no round is running and the last one started REFRESH_INTERVAL ago.
+    pub fn is_due(&self, now: std::time::Instant) -> bool {
+        self.pending.is_empty() && self.started.is_none_or(|started| now.duration_since(started) >= REFRESH_INTERVAL)
+    }
+
+    pub fn start(&mut self, ips: &[String], now: std::time::Instant) {
+        *self = Self { pending: ips.iter().cloned().collect(), started: Some(now), total: ips.len(), ..Self::default() };
+    }
+
+    /// True if the lookup of the IP was asked for by this round.
+    pub fn is_pending(&self, ip: &str) -> bool {
+        self.pending.contains(ip)
+    }
+
+    /// Counts the answer, returns a summary once the round is complete.
+    pub fn finish(&mut self, ip: &str, outcome: RefreshOutcome) -> Option<String> {
+        if !self.pending.remove(ip) {return None;}
+        match outcome {
+            RefreshOutcome::Moved => self.moved += 1,
+            RefreshOutcome::Failed => self.failed += 1,
+            RefreshOutcome::Unchanged => {},
+        }
+        if !self.pending.is_empty() {return None;}
+        Some(format!("Looked up {} stale IPs again, {} moved, {} failed", self.total, self.moved, self.failed))
+    }
+}
+
+/// Spaces requests evenly to stay within a number per minute.
+struct RateLimit {
+    interval: Duration,
+    next: Instant,
+}
+
+impl RateLimit {
+    fn per_minute(requests: u32) -> Self {
+        Self { interval: Duration::from_secs(60) / requests.max(1), next: Instant::now() }
+    }
+
+    /// Waits for the next free slot and takes it.
+    async fn wait(&mut self) {
+        tokio::time::sleep_until(self.next).await;
+        self.next = Instant::now() + self.interval;
+    }
+
+    /// No request before `duration` passed, used once the API says the window is used up.
+    fn pause(&mut self, duration: Duration) {
+        self.next = self.next.max(Instant::now() + duration);
+    }
+}
+
+enum Answer {
+    /// one answer per IP in request order, and the time until the window resets if it is used up
+    Found(Vec<Value>, Option<Duration>),
+    /// the request was refused for exceeding the limit, retry after the duration
+    Limited(Duration),
+}
+
+/// Asks for one IP with `/json/` or for several with `/batch`.
+async fn lookup(client: &reqwest::Client, api_url: &str, ips: &[String]) -> Result<Answer, String> {
+    let api_url = api_url.trim_end_matches('/');
+    let (url, request) = if let [ip] = ips {
+        let url = format!("{}/json/{}", api_url, ip);
+        (url.clone(), client.get(url))
+    } else {
+        let url = format!("{}/batch", api_url);
+        (url.clone(), client.post(url).json(ips))
+    };
+    let response = request.timeout(LOOKUP_TIMEOUT).send().await.map_err(|e| e.to_string())?;
+    let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok()).and_then(|value| value.parse::<u64>().ok());
+    let reset = header("X-Ttl").map(Duration::from_secs).unwrap_or(DEFAULT_BACKOFF);
+    let exhausted = (header("X-Rl") == Some(0)).then_some(reset);
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Ok(Answer::Limited(reset));
+    }
+    if !response.status().is_success() {
+        return Err(format!("{} answered {}", url, response.status()));
+    }
+    let value: Value = response.json().await.map_err(|e| format!("{}: {}", url, e))?;
+    let values = if ips.len() == 1 {vec![value]} else {value.as_array().cloned().unwrap_or_default()};
+    Ok(Answer::Found(values, exhausted))
+}
+
+/// Queue of the lookup scheduler.
+#[derive(Clone)]
+pub struct GeoLookups {
+    queue: UnboundedSender<String>,
+}
+
+impl GeoLookups {
+    /// Queues the IP, the answer comes back as `Action::GotGeoLookup`.
+    pub fn request(&self, ip: &str) {
+        self.queue.send(ip.to_string()).unwrap_or_default();
+    }
+}
+
+async fn scheduler(config: GeoConfig, mut rx: UnboundedReceiver<String>, tx: Option<UnboundedSender<Action>>) {
+    let client = reqwest::Client::new();
+    let mut single = RateLimit::per_minute(config.requests_per_minute);
+    let mut batch = RateLimit::per_minute(config.batch_requests_per_minute);
+    let mut pending: VecDeque<String> = VecDeque::new();
+    loop {
+        if pending.is_empty() {
+            let Some(ip) = rx.recv().await else {return};
+            pending.push_back(ip);
+        }
+        while let Ok(ip) = rx.try_recv() {pending.push_back(ip);}
+        let use_batch = pending.len() > 1 && config.batch_size > 1;
+        let limit = if use_batch {&mut batch} else {&mut single};
+        limit.wait().await;
+        // IPs that came in while waiting share the request
+        while let Ok(ip) = rx.try_recv() {pending.push_back(ip);}
+        let count = if use_batch {pending.len().min(config.batch_size)} else {1};
+        let ips: Vec<String> = pending.drain(..count).collect();
+
+        let started = std::time::Instant::now();
+        let answer = lookup(&client, &config.api_url, &ips).await;
+        METRICS.geo_lookup.observe(started.elapsed());
+        let results: Vec<Result<IP, String>> = match answer {
+            Ok(Answer::Limited(reset)) => {
+                limit.pause(reset);
+                for ip in ips.into_iter().rev() {pending.push_front(ip);}
+                continue;
+            },
+            Ok(Answer::Found(values, exhausted)) => {
+                if let Some(reset) = exhausted {limit.pause(reset);}
+                ips.iter().enumerate().map(|(idx, _)| values.get(idx).ok_or(String::from("missing in the answer")).and_then(parse_geolocation)).collect()
+            },
+            Err(e) => ips.iter().map(|_| Err(e.clone())).collect(),
+        };
+        let Some(tx) = &tx else {continue};
+        for (ip, result) in ips.into_iter().zip(results) {
+            tx.send(Action::GotGeoLookup(ip, result)).unwrap_or_default();
+        }
+    }
+}
+
+pub fn spawn(config: GeoConfig, tx: Option<UnboundedSender<Action>>) -> GeoLookups {
+    let (queue, rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(scheduler(config, rx, tx));
+    GeoLookups { queue }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn answer(ip: &str) -> String {
+        format!(r#"{{"status":"success","country":"Exampleland","countryCode":"EX","regionName":"North","city":"Sample City","lat":52.5,"lon":13.4,"isp":"Examplenet","as":"AS64496 Example Networks","query":"{}"}}"#, ip)
+    }
+
+    fn parked(line: &str) -> ParkedLine {
+        ParkedLine { line: line.to_string(), host: String::from("localhost"), is_banned: false, parked_at: String::new() }
+    }
+
+    #[test]
+    fn test_parse_and_park() {
+        let found = parse_geolocation(&serde_json::from_str(&answer("192.0.2.7")).unwrap()).unwrap();
+        assert_eq!((found.ip.as_str(), found.lat.as_str(), found.lon.as_str(), found.region.as_str()), ("192.0.2.7", "52.5", "13.4", "North"));
+        assert_eq!(found.asn, "AS64496");
+        let failed = serde_json::from_str(r#"{"status":"fail","message":"private range","query":"10.0.0.1"}"#).unwrap();
+        assert_eq!(parse_geolocation(&failed), Err(String::from("private range")));
+
+        let mut lines = ParkedLines::default();
+        assert!(lines.park("192.0.2.7", parked("first")));
+        assert!(!lines.park("192.0.2.7", parked("second")));
+        assert!(lines.park("192.0.2.8", parked("other")));
+        assert_eq!(lines.depth(), (2, 3));
+        assert_eq!(lines.release("192.0.2.7"), vec![parked("first"), parked("second")]);
+        assert_eq!(lines.release("192.0.2.7"), vec![]);
+        assert_eq!(lines.depth(), (1, 1));
+    }
+
+    /// Reads headers and the body, which may come in separate packets.
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> String {
+        let mut request: Vec<u8> = vec![];
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = stream.read(&mut buf).await.unwrap();
+            request.extend(&buf[..len]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            let Some((head, body)) = text.split_once("\r\n\r\n") else {continue};
+            let length = head.lines().find_map(|line| line.to_lowercase().strip_prefix("content-length: ").and_then(|n| n.parse::<usize>().ok())).unwrap_or(0);
+            if body.len() >= length || len == 0 {return text;}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scheduler() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}", listener.local_addr().unwrap());
+        let (requests_tx, mut requests) = mpsc::unbounded_channel::<String>();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await;
+                let body = if let Some(ip) = request.strip_prefix("GET /json/").and_then(|rest| rest.split(' ').next()) {
+                    requests_tx.send(format!("single {}", ip)).unwrap();
+                    answer(ip)
+                } else {
+                    let ips: Vec<String> = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+                    requests_tx.send(format!("batch {}", ips.join(","))).unwrap();
+                    format!("[{}]", ips.iter().map(|ip| answer(ip)).collect::<Vec<String>>().join(","))
+                };
+                stream.write_all(format!("HTTP/1.1 200 OK\r\nX-Rl: 40\r\nX-Ttl: 60\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).as_bytes()).await.unwrap();
+            }
+        });
+
+        let config = GeoConfig { api_url, requests_per_minute: 6000, batch_requests_per_minute: 6000, batch_size: 2, ..GeoConfig::default() };
+        let (tx, mut rx) = mpsc::unbounded_channel::<Action>();
+        let lookups = spawn(config, Some(tx));
+        for ip in ["192.0.2.1", "192.0.2.2", "192.0.2.3"] {
+            lookups.request(ip);
+        }
+        let mut answered: Vec<String> = vec![];
+        while answered.len() < 3 {
+            if let Some(Action::GotGeoLookup(ip, result)) = rx.recv().await {
+                assert_eq!(result.unwrap().ip, ip);
+                answered.push(ip);
+            }
+        }
+        // answers come back in request order, at most batch_size IPs share a request
+        assert_eq!(answered, vec!["192.0.2.1", "192.0.2.2", "192.0.2.3"]);
+        assert_eq!(requests.recv().await.unwrap(), "batch 192.0.2.1,192.0.2.2");
+        assert_eq!(requests.recv().await.unwrap(), "single 192.0.2.3");
+    }
+
+    #[test]
+    fn test_refresh_round() {
+        let mut refresh = GeoRefresh::default();
+        let now = std::time::Instant::now();
+        assert!(refresh.is_due(now));
+        refresh.start(&[String::from("192.0.2.1"), String::from("192.0.2.2")], now);
+        assert!(!refresh.is_due(now + REFRESH_INTERVAL));
+        assert!(refresh.is_pending("192.0.2.1"));
+        assert_eq!(refresh.finish("192.0.2.9", RefreshOutcome::Moved), None);
+        assert_eq!(refresh.finish("192.0.2.1", RefreshOutcome::Moved), None);
+        assert_eq!(refresh.finish("192.0.2.2", RefreshOutcome::Failed), Some(String::from("Looked up 2 stale IPs again, 1 moved, 1 failed")));
+        assert!(!refresh.is_due(now));
+        assert!(refresh.is_due(now + REFRESH_INTERVAL));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let mut limit = RateLimit::per_minute(600);
+        let started = Instant::now();
+        limit.wait().await;
+        limit.wait().await;
+        limit.wait().await;
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        limit.pause(Duration::from_millis(300));
+        let paused = Instant::now();
+        limit.wait().await;
+        assert!(paused.elapsed() >= Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,84 @@
+//! What blocking a Country, Region, City, ISP or Provider would do, shown before the block is confirmed.
+
+use chrono::{Duration, Local};
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+
+use crate::migrations::schema::allowlist;
+
+/// IPs seen within this many hours count as currently active.
+pub const ACTIVE_HOURS: i64 = 24;
+/// Successful logins within this many days are listed.
+pub const RECENT_LOGIN_DAYS: i64 = 30;
+/// Number of fail2ban commands spelled out, the rest is only counted.
+pub const PREVIEW_COMMANDS: usize = 5;
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockPreview {
+  /// Country, Region, City, ISP or Provider
+  pub kind: String,
+  pub name: String,
+  pub known_ips: usize,
+  pub active_ips: usize,
+  pub already_banned: usize,
+  /// (ip, reason) of IPs inside that are never banned
+  pub allowlisted: Vec<(String, String)>,
+  /// IPs inside with a successful login in the last RECENT_LOGIN_DAYS days
+  pub recent_logins: Vec<String>,
+  /// fail2ban commands that run once the IPs show up again, the first PREVIEW_COMMANDS
+  pub commands: Vec<String>,
+  /// number of commands in total
+  pub num_commands: usize,
+}
+
+/// Expression for the kind of entity per ipmeta row, None for kinds that cannot be blocked this way.
+fn column(kind: &str) -> Option<&'static str> {
+  match kind {
+    "Country" => Some("ipmeta.country"),
+    "Region" => Some("ipmeta.region"),
+    "City" => Some("ipmeta.city"),
+    "ISP" => Some("ipmeta.isp"),
+    "Provider" => Some("(SELECT ipcloud.provider FROM ipcloud WHERE ipcloud.ip = ipmeta.ip)"),
+    _ => None,
+  }
+}
+
+pub fn preview_block(conn: &Connection, kind: &str, name: &str, jail: &str) -> Result<BlockPreview> {
+  let mut preview = BlockPreview { kind: kind.to_string(), name: name.to_string(), ..Default::default() };
+  let Some(column) = column(kind) else {return Ok(preview)};
+
+  let mut stmt = conn.prepare(&format!("SELECT ip, is_banned FROM ipmeta WHERE {} = ?1 ORDER BY ip", column))?;
+  let ips: Vec<(String, bool)> = stmt.query_map([name], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<Vec<(String, bool)>>>()?;
+  preview.known_ips = ips.len();
+
+  let active_since = (Local::now() - Duration::hours(ACTIVE_HOURS)).to_rfc3339();
+  preview.active_ips = conn.query_row(
+    &format!("SELECT COUNT(DISTINCT messages.ip) FROM messages JOIN ipmeta ON ipmeta.ip = messages.ip WHERE {} = ?1 AND messages.created_at >= ?2", column),
+    (name, &active_since),
+    |row| row.get(0),
+  )?;
+
+  let logins_since = (Local::now() - Duration::days(RECENT_LOGIN_DAYS)).to_rfc3339();
+  let mut stmt = conn.prepare(&format!(
+    "SELECT DISTINCT messages.ip FROM messages JOIN ipmeta ON ipmeta.ip = messages.ip
+    WHERE {} = ?1 AND messages.created_at >= ?2 AND messages.text LIKE '%Accepted %' ORDER BY messages.ip", column))?;
+  preview.recent_logins = stmt.query_map((name, &logins_since), |row| row.get(0))?.collect::<Result<Vec<String>>>()?;
+
+  let mut commands: Vec<String> = vec![];
+  for (ip, is_banned) in ips {
+    if is_banned {
+      preview.already_banned += 1;
+      continue;
+    }
+    if let Some(reason) = allowlist::refuse_reason(conn, &ip)? {
+      preview.allowlisted.push((ip, reason));
+      continue;
+    }
+    commands.push(format!("fail2ban-client set {} banip {}", jail, ip));
+  }
+  preview.num_commands = commands.len();
+  commands.truncate(PREVIEW_COMMANDS);
+  preview.commands = commands;
+  Ok(preview)
+}
+
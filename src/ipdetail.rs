@@ -0,0 +1,104 @@
+//! Everything known about a single IP, collected from `messages`, `ipmeta`, `bans` and the block state of its location.
+
+use std::collections::HashMap;
+
+use chrono::{Duration, Local};
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+
+use crate::migrations::schema::{ban::{self, BanRecord}, city, country, ip, ip::IP, isp, message, region, username::extract_username};
+
+/// Number of days shown in the activity sparkline.
+pub const ACTIVITY_DAYS: usize = 30;
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IPDetail {
+  pub ip: IP,
+  pub first_seen: String,
+  pub last_seen: String,
+  pub total: usize,
+  /// (source, hits), source is journalctl or fail2ban
+  pub hits_per_source: Vec<(String, usize)>,
+  /// (username, tries), most tried first
+  pub usernames: Vec<(String, usize)>,
+  /// bans by fail2ban and by us, oldest first
+  pub ban_history: Vec<BanRecord>,
+  /// hits per day for the last ACTIVITY_DAYS days, oldest first
+  pub activity: Vec<u64>,
+  /// (kind, name, is_blocked) for Country, Region, City and ISP
+  pub block_states: Vec<(String, String, bool)>,
+  /// location and ISP changes found by looking the IP up again, oldest first
+  pub location_history: Vec<ip::IPChange>,
+}
+
+fn sorted_counts(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+  let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+  counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+  counts
+}
+
+/// Collects the detail view for `ipstr`, returns a default IPDetail with only the ip set if it was never seen.
+pub fn collect_ip_detail(conn: &Connection, ipstr: &str) -> Result<IPDetail> {
+  let ipmeta = ip::select_ip(conn, ipstr)?.unwrap_or(IP { ip: ipstr.to_string(), ..Default::default() });
+  let mut msgs: Vec<message::Message> = message::select_message_by_ip(conn, ipstr)?.into_iter().flatten().collect();
+  msgs.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+  let mut sources: HashMap<String, usize> = HashMap::new();
+  let mut usernames: HashMap<String, usize> = HashMap::new();
+  let mut activity: Vec<u64> = vec![0; ACTIVITY_DAYS];
+  let today = Local::now().date_naive();
+
+  for msg in msgs.iter() {
+    let source = if msg.is_jctl {"journalctl"} else {"fail2ban"};
+    *sources.entry(source.to_string()).or_default() += 1;
+
+    // fail2ban may deliver several lines at once
+    for line in msg.text.split("++++") {
+      if let Some(user) = extract_username(line) {
+        *usernames.entry(user).or_default() += 1;
+      }
+    }
+
+    if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(&msg.created_at) {
+      let days_ago = (today - ts.with_timezone(&Local).date_naive()).num_days();
+      if days_ago >= 0 && (days_ago as usize) < ACTIVITY_DAYS {
+        activity[ACTIVITY_DAYS - 1 - days_ago as usize] += 1;
+      }
+    }
+  }
+
+  let block_states = vec![
+    (String::from("Country"), ipmeta.country.clone(), country::select_country(conn, &ipmeta.country)?.map(|c| c.is_blocked).unwrap_or(false)),
+    (String::from("Region"), ipmeta.region.clone(), region::select_region(conn, &ipmeta.region)?.map(|r| r.is_blocked).unwrap_or(false)),
+    (String::from("City"), ipmeta.city.clone(), city::select_city(conn, &ipmeta.city)?.map(|c| c.is_blocked).unwrap_or(false)),
+    (String::from("ISP"), ipmeta.isp.clone(), isp::select_isp(conn, &ipmeta.isp)?.map(|i| i.is_blocked).unwrap_or(false)),
+  ];
+
+  Ok(IPDetail {
+    ip: ipmeta,
+    first_seen: msgs.first().map(|m| m.created_at.clone()).unwrap_or_default(),
+    last_seen: msgs.last().map(|m| m.created_at.clone()).unwrap_or_default(),
+    total: msgs.len(),
+    hits_per_source: sorted_counts(sources),
+    usernames: sorted_counts(usernames),
+    ban_history: ban::get_bans_by_ip(conn, ipstr)?,
+    activity,
+    block_states,
+    location_history: ip::select_ip_history(conn, ipstr)?,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+
+  use super::*;
+
+  #[test]
+  fn test_extract_username() {
+    assert_eq!(extract_username("Invalid user admin from 1.2.3.4 port 22"), Some("admin".to_string()));
+    assert_eq!(extract_username("Failed password for root from 1.2.3.4 port 22 ssh2"), Some("root".to_string()));
+    assert_eq!(extract_username("Failed password for invalid user oracle from 1.2.3.4 port 22 ssh2"), Some("oracle".to_string()));
+    assert_eq!(extract_username("NOTICE [sshd] Ban 1.2.3.4"), None);
+  }
+}
@@ -16,6 +16,22 @@ pub mod gen_structs;
 pub mod themes;
 pub mod animations;
 pub mod migrations;
+pub mod cidr;
+pub mod query;
+pub mod ipdetail;
+pub mod alerting;
+pub mod rules;
+pub mod impact;
+pub mod notifications;
+pub mod metrics;
+pub mod eventstream;
+pub mod syslog;
+pub mod forwarding;
+pub mod threatintel;
+pub mod cloudranges;
+pub mod enrichment;
+pub mod abusereport;
+pub mod storage;
 pub mod action_handlers;
 
 use clap::Parser;
@@ -33,12 +49,74 @@ async fn tokio_main() -> Result<()> {
   initialize_panic_handler()?;
 
   let args = Cli::parse();
+  if args.test_rules {
+    return test_rules();
+  }
+  if let Some(path) = args.export_audit {
+    return export_audit(&path);
+  }
+  if args.refresh_blocklists {
+    return refresh_blocklists().await;
+  }
+  if args.recompute_statistics {
+    let conn = rusqlite::Connection::open("iplogs.db")?;
+    migrations::schema::statistics::recompute_statistics(&conn)?;
+    println!("Recomputed statistics");
+    return Ok(());
+  }
   let mut app = App::new(args.tick_rate, args.frame_rate)?;
+  if let Some(path) = args.events_out {
+    app.config.event_stream.output = Some(path);
+  }
   app.run().await?;
 
   Ok(())
 }
 
+/// Prints what the configured rules would have done with the stored history, nothing is banned.
+fn test_rules() -> Result<()> {
+  let config = config::Config::new()?;
+  for error in rules::validate(&config.rules) {
+    println!("{}", error);
+  }
+  let conn = rusqlite::Connection::open("iplogs.db")?;
+  query::register_sql_functions(&conn)?;
+  let hits = rules::replay(&conn, config.rules.clone())?;
+  for hit in hits.iter() {
+    println!("{} {}", hit.at, hit);
+  }
+  for rule in config.rules.iter() {
+    println!("{}: {} hits", rule.name, hits.iter().filter(|hit| hit.rule == rule.name).count());
+  }
+  Ok(())
+}
+
+/// Downloads the blocklists that have a url, then exits.
+async fn refresh_blocklists() -> Result<()> {
+  let config = config::Config::new()?;
+  for result in threatintel::refresh(&config.blocklists).await {
+    match result {
+      Ok(message) | Err(message) => println!("{}", message),
+    }
+  }
+  Ok(())
+}
+
+/// Writes the audit log as CSV to the file, or to stdout for `-`.
+fn export_audit(path: &std::path::Path) -> Result<()> {
+  let conn = rusqlite::Connection::open("iplogs.db")?;
+  let exported = if path.as_os_str() == "-" {
+    migrations::schema::audit::export_audit_csv(&conn, &mut std::io::stdout().lock())
+  } else {
+    migrations::schema::audit::export_audit_csv(&conn, &mut std::fs::File::create(path)?)
+  };
+  let exported = exported.map_err(|e| color_eyre::eyre::eyre!("Exporting audit log failed: {}", e))?;
+  if path.as_os_str() != "-" {
+    println!("Exported {} audit entries to {}", exported, path.display());
+  }
+  Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
   if let Err(e) = tokio_main().await {
@@ -0,0 +1,202 @@
+//! Prometheus metrics of the pipeline, served on `/metrics` if `metrics.listen` is set in config.json5.
+//! Counters are kept in memory since startup, gauges of the ban and block state are read from the DB per scrape.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use rusqlite::Connection;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::storage::Storage;
+
+/// Sum and count of observed durations, exposed as a Prometheus summary without quantiles.
+#[derive(Default)]
+pub struct Latency {
+  micros: AtomicU64,
+  count: AtomicU64,
+}
+
+impl Latency {
+  const fn new() -> Self {
+    Self { micros: AtomicU64::new(0), count: AtomicU64::new(0) }
+  }
+
+  pub fn observe(&self, elapsed: Duration) {
+    self.micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    self.count.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+  pub events_fail2ban: AtomicU64,
+  pub events_journalctl: AtomicU64,
+  pub events_syslog: AtomicU64,
+  /// lines without an IP
+  pub parse_failures: AtomicU64,
+  pub geo_db_hits: AtomicU64,
+  pub geo_fetches: AtomicU64,
+  pub geo_lookup: Latency,
+  /// bans done by succeed2ban
+  pub bans: AtomicU64,
+  /// bans fail2ban did on its own
+  pub bans_fail2ban: AtomicU64,
+  pub unbans: AtomicU64,
+  /// actions the event loop handled the last time it drained the channel, including those sent while draining
+  pub actions_drained: AtomicU64,
+  pub db_write: Latency,
+}
+
+impl Metrics {
+  const fn new() -> Self {
+    Self {
+      events_fail2ban: AtomicU64::new(0),
+      events_journalctl: AtomicU64::new(0),
+      events_syslog: AtomicU64::new(0),
+      parse_failures: AtomicU64::new(0),
+      geo_db_hits: AtomicU64::new(0),
+      geo_fetches: AtomicU64::new(0),
+      geo_lookup: Latency::new(),
+      bans: AtomicU64::new(0),
+      bans_fail2ban: AtomicU64::new(0),
+      unbans: AtomicU64::new(0),
+      actions_drained: AtomicU64::new(0),
+      db_write: Latency::new(),
+    }
+  }
+}
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// Adds one to a counter.
+pub fn inc(counter: &AtomicU64) {
+  counter.fetch_add(1, Ordering::Relaxed);
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+  writeln!(out, "# HELP {} {}", name, help).unwrap_or_default();
+  writeln!(out, "# TYPE {} {}", name, kind).unwrap_or_default();
+}
+
+fn counter(out: &mut String, name: &str, help: &str, values: &[(&str, u64)]) {
+  header(out, name, "counter", help);
+  for (labels, value) in values {
+    writeln!(out, "{}{} {}", name, labels, value).unwrap_or_default();
+  }
+}
+
+fn summary(out: &mut String, name: &str, help: &str, latency: &Latency) {
+  header(out, name, "summary", help);
+  let seconds = latency.micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+  writeln!(out, "{}_sum {}", name, seconds).unwrap_or_default();
+  writeln!(out, "{}_count {}", name, latency.count.load(Ordering::Relaxed)).unwrap_or_default();
+}
+
+/// Tables with an `is_blocked` column, also the type label they are exposed with.
+const BLOCKABLE: [&str; 6] = ["country", "region", "city", "isp", "username", "provider"];
+
+fn db_gauges(out: &mut String, conn: &Connection) -> rusqlite::Result<()> {
+  let banned: u64 = conn.query_row("SELECT COUNT(*) FROM ipmeta WHERE is_banned = 1", [], |row| row.get(0))?;
+  let mut blocked: Vec<(&str, u64)> = vec![];
+  for table in BLOCKABLE {
+    blocked.push((table, conn.query_row(&format!("SELECT COUNT(*) FROM {} WHERE is_blocked = 1", table), [], |row| row.get(0))?));
+  }
+
+  header(out, "succeed2ban_banned_ips", "gauge", "IPs currently banned.");
+  writeln!(out, "succeed2ban_banned_ips {}", banned).unwrap_or_default();
+  header(out, "succeed2ban_blocked_entities", "gauge", "Blocked countries, regions, cities, ISPs, usernames and providers.");
+  for (label, value) in blocked {
+    writeln!(out, "succeed2ban_blocked_entities{{type=\"{}\"}} {}", label, value).unwrap_or_default();
+  }
+  Ok(())
+}
+
+/// Renders all metrics in the Prometheus text format, `gauges` are the DB gauges if the DB could be read.
+pub fn render(gauges: Option<&str>) -> String {
+  let m = &METRICS;
+  let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+  let mut out = String::new();
+  counter(&mut out, "succeed2ban_events_ingested_total", "Log events stored per source.", &[
+    ("{source=\"fail2ban\"}", get(&m.events_fail2ban)),
+    ("{source=\"journalctl\"}", get(&m.events_journalctl)),
+    ("{source=\"syslog\"}", get(&m.events_syslog)),
+  ]);
+  counter(&mut out, "succeed2ban_parse_failures_total", "Log events without an IP.", &[("", get(&m.parse_failures))]);
+  counter(&mut out, "succeed2ban_geo_lookups_total", "Geolocations taken from the DB or fetched.", &[
+    ("{result=\"db\"}", get(&m.geo_db_hits)),
+    ("{result=\"fetch\"}", get(&m.geo_fetches)),
+  ]);
+  summary(&mut out, "succeed2ban_geo_lookup_seconds", "Duration of geolocation fetches.", &m.geo_lookup);
+  counter(&mut out, "succeed2ban_bans_total", "Bans by succeed2ban and by fail2ban on its own.", &[
+    ("{source=\"succeed2ban\"}", get(&m.bans)),
+    ("{source=\"fail2ban\"}", get(&m.bans_fail2ban)),
+  ]);
+  counter(&mut out, "succeed2ban_unbans_total", "Unbans by succeed2ban.", &[("", get(&m.unbans))]);
+  header(&mut out, "succeed2ban_actions_drained", "gauge", "Actions the event loop handled when it last drained its channel.");
+  writeln!(out, "succeed2ban_actions_drained {}", get(&m.actions_drained)).unwrap_or_default();
+  summary(&mut out, "succeed2ban_db_write_seconds", "Duration of storing an event.", &m.db_write);
+  if let Some(gauges) = gauges {
+    out.push_str(gauges);
+  }
+  out
+}
+
+/// Answers `GET /metrics` until the listener fails, every scrape reads the gauges through the storage service.
+pub async fn serve(listener: TcpListener, storage: Option<Storage>) -> std::io::Result<()> {
+  loop {
+    let (mut stream, _) = listener.accept().await?;
+    let storage = storage.clone();
+    tokio::spawn(async move {
+      let mut buf = [0u8; 1024];
+      let n = stream.read(&mut buf).await.unwrap_or_default();
+      let request = String::from_utf8_lossy(&buf[..n]);
+      let response = if request.starts_with("GET /metrics ") {
+        let gauges = match storage {
+          Some(storage) => storage.request(|conn| {
+            let mut gauges = String::new();
+            db_gauges(&mut gauges, conn)?;
+            Ok(gauges)
+          }).await.ok(),
+          None => None,
+        };
+        let body = render(gauges.as_deref());
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+      } else {
+        String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+      };
+      stream.write_all(response.as_bytes()).await.unwrap_or_default();
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_serve() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, None));
+    inc(&METRICS.events_fail2ban);
+    METRICS.db_write.observe(Duration::from_millis(2));
+
+    let get = |path: &'static str| async move {
+      let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+      stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.unwrap();
+      let mut response = String::new();
+      stream.read_to_string(&mut response).await.unwrap();
+      response
+    };
+    let response = get("/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("# TYPE succeed2ban_events_ingested_total counter\n"));
+    assert!(!response.contains("succeed2ban_events_ingested_total{source=\"fail2ban\"} 0\n"));
+    assert!(response.contains("succeed2ban_db_write_seconds_count "));
+    // without a DB the gauges are left out
+    assert!(!response.contains("succeed2ban_banned_ips"));
+    assert!(get("/").await.starts_with("HTTP/1.1 404"));
+  }
+}
@@ -9,6 +9,15 @@ pub mod city;
 pub mod region;
 pub mod country;
 pub mod ip;
+pub mod username;
+pub mod alert;
+pub mod allowlist;
+pub mod ban;
+pub mod statistics;
+pub mod audit;
+pub mod host;
+pub mod provider;
+pub mod enrichment;
 
 
 
@@ -17,7 +26,7 @@ pub mod ip;
 #[cfg(test)]
 mod test {
     use crate::migrations::schema;
-    use crate::migrations::schema::{message, isp, city, region, country, ip};
+    use crate::migrations::schema::{message, isp, city, region, country, ip, username, alert, allowlist, ban, statistics, audit, host, provider};
     use rusqlite::{Connection, Result};
     use serial_test::serial;
     #[test]
@@ -42,7 +51,7 @@ mod test {
         let _ = city::insert_new_city(&conn, "Humburg", "Doitschland", "Undetussen",Some(0), Some(0), false).expect("City insertion failed");
         let _ = isp::insert_new_ISP(&conn,"Telecum", Some(0), Some(0), "Doitschland", false).expect("ISP insertion failed");
         let _ = ip::insert_new_IP(&conn, "111.233.456.678", "2022-03-11 23:45:31:512", "3.12", "59.79", "Telecum", "Humburg", Some("Undetussen"), "Doitschland", Some("DDE"), 0, false, 0).expect("IP insertion failed");
-        let _ = message::insert_new_message(&conn, Option::None, "2022-03-11 23:45:31:512","OMG SUCH A MESSAGE", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum",true, false).expect("Message insertion failed");
+        let _ = message::insert_new_message(&conn, Option::None, "2022-03-11 23:45:31:512","OMG SUCH A MESSAGE", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum",true, false, "localhost").expect("Message insertion failed");
         Ok(())
     }
 
@@ -211,10 +220,525 @@ mod test {
         assert_eq!(res, ass);
         Ok(())
     }    
-}
 
+    #[test]
+    #[serial]
+    pub fn test_run_query() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        crate::query::register_sql_functions(&conn)?;
+        conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
+        conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
+        conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up Region db");
+        conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
+        conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+        conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up message db");
+        conn.execute(username::CREATE_USERNAME_DB_SQL, []).expect("Error setting up username db");
+        conn.execute(username::CREATE_USERNAME_MESSAGE_DB_SQL, []).expect("Error setting up username_message db");
+        let _ = country::insert_new_country(&conn, "Querylandia", Some("QL"), Some(0), Some(0), false).expect("Country insertion failed");
+        let _ = region::insert_new_region(&conn, "Queryregion", "Querylandia", Some(0), Some(0), false).expect("Region insertion failed");
+        let _ = city::insert_new_city(&conn, "Querytown", "Querylandia", "Queryregion", Some(0), Some(0), false).expect("City insertion failed");
+        let _ = isp::insert_new_ISP(&conn, "Querynet", Some(0), Some(0), "Querylandia", false).expect("ISP insertion failed");
+        let _ = ip::insert_new_IP(&conn, "10.20.30.40", "2023-12-01T10:00:00+01:00", "3.12", "59.79", "Querynet", "Querytown", Some("Queryregion"), "Querylandia", Some("QL"), 0, false, 0).expect("IP insertion failed");
+        let _ = message::insert_new_message(&conn, Option::None, "2023-12-01T10:00:00+01:00", "Invalid user admin from 10.20.30.40 port 22", "10.20.30.40", "Querylandia", "Queryregion", "Querytown", "Querynet", true, false, "localhost").expect("Message insertion failed");
+        username::insert_username_for_message(&conn, "admin", conn.last_insert_rowid()).expect("Username insertion failed");
+
+        let res = crate::query::run_query(&conn, "cidr:10.20.0.0/16 AND (country:ql OR isp:nowhere) user:admin since:2023-11-30 banned:false", 10).unwrap();
+        assert!(res.total >= 1);
+        assert_eq!(res.num_ips, 1);
+        assert!(res.messages.iter().all(|m| m.ip == "10.20.30.40"));
+
+        let res = crate::query::run_query(&conn, "country:Querylandia until:2023-11-30", 10).unwrap();
+        assert_eq!(res.total, 0);
+
+        // logged under another offset, 01:30 UTC on the next day
+        message::insert_new_message(&conn, Option::None, "2023-12-01T23:30:00.123456789-02:00", "Invalid user admin from 10.20.30.40 port 22", "10.20.30.40", "Querylandia", "Queryregion", "Querytown", "Querynet", true, false, "web2").expect("Message insertion failed");
+        assert_eq!(crate::query::run_query(&conn, "host:web2 since:2023-12-02T01:00:00+00:00", 10).unwrap().total, 1);
+        assert_eq!(crate::query::run_query(&conn, "host:web2 until:2023-12-02T01:00:00+00:00", 10).unwrap().total, 0);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_usernames() -> Result<()> {
+        let conn = Connection::open("test.db")?;
+        conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+        conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up message db");
+        conn.execute(username::CREATE_USERNAME_DB_SQL, []).expect("Error setting up username db");
+        conn.execute(username::CREATE_USERNAME_MESSAGE_DB_SQL, []).expect("Error setting up username_message db");
+
+        assert_eq!(username::extract_username("Accepted publickey for deploy from 1.2.3.4 port 22 ssh2"), Some("deploy".to_string()));
+        username::set_username_blocked(&conn, "usertest_blocked", true)?;
+        let user = username::select_username(&conn, "usertest_blocked")?.unwrap();
+        assert!(user.is_blocked);
+        username::set_username_blocked(&conn, "usertest_blocked", false)?;
+        let user = username::select_username(&conn, "usertest_blocked")?.unwrap();
+        assert!(!user.is_blocked);
+        assert!(username::get_all_usernames(&conn)?.iter().any(|u| u.name == "usertest_blocked"));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_login_alerts() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
+        conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
+        conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up Region db");
+        conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
+        conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+        conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up message db");
+        conn.execute(alert::CREATE_ALERT_DB_SQL, []).expect("Error setting up alert db");
+        let _ = country::insert_new_country(&conn, "Alertistan", Some("AL"), Some(0), Some(0), false).expect("Country insertion failed");
+        let _ = region::insert_new_region(&conn, "Alertregion", "Alertistan", Some(0), Some(0), false).expect("Region insertion failed");
+        let _ = city::insert_new_city(&conn, "Alerttown", "Alertistan", "Alertregion", Some(0), Some(0), false).expect("City insertion failed");
+        let _ = isp::insert_new_ISP(&conn, "Alertnet", Some(0), Some(0), "Alertistan", false).expect("ISP insertion failed");
+        let _ = ip::insert_new_IP(&conn, "10.20.30.41", "2023-12-01T10:00:00+01:00", "3.12", "59.79", "Alertnet", "Alerttown", Some("Alertregion"), "Alertistan", Some("AL"), 0, false, 0).expect("IP insertion failed");
+        let _ = message::insert_new_message(&conn, Option::None, "2023-12-01T10:00:00+01:00", "Failed password for root from 10.20.30.41 port 22 ssh2", "10.20.30.41", "Alertistan", "Alertregion", "Alerttown", "Alertnet", true, false, "localhost").expect("Message insertion failed");
+
+        let reasons = crate::alerting::classify_accepted(&conn, "10.20.30.41", "Alertistan").unwrap();
+        assert!(reasons.iter().any(|r| matches!(r, crate::alerting::AlertReason::PriorFailures(n) if *n >= 1)));
+        assert!(reasons.contains(&crate::alerting::AlertReason::NewCountry(String::from("Alertistan"))));
+
+        let _ = message::insert_new_message(&conn, Option::None, "2023-12-01T10:05:00+01:00", "Accepted password for root from 10.20.30.41 port 22 ssh2", "10.20.30.41", "Alertistan", "Alertregion", "Alerttown", "Alertnet", true, false, "localhost").expect("Message insertion failed");
+        let reasons = crate::alerting::classify_accepted(&conn, "10.20.30.41", "Alertistan").unwrap();
+        assert!(!reasons.contains(&crate::alerting::AlertReason::NewCountry(String::from("Alertistan"))));
+
+        // a repeat login of a user who never failed raises no alert
+        ip::insert_new_IP(&conn, "10.20.30.42", "2023-12-01T10:00:00+01:00", "3.12", "59.79", "Alertnet", "Alerttown", Some("Alertregion"), "Alertistan", Some("AL"), 0, false, 0).expect("IP insertion failed");
+        for (created_at, text) in [
+            ("2023-12-01T11:00:00+01:00", "Accepted publickey for deploy from 10.20.30.42 port 22 ssh2"),
+            ("2023-12-01T11:00:01+01:00", "pam_unix(sshd:session): session opened for user deploy(uid=1000) by (uid=0)"),
+            ("2023-12-01T11:30:00+01:00", "Received disconnect from 10.20.30.42 port 22:11: disconnected by user"),
+            ("2023-12-01T11:30:00+01:00", "Disconnected from user deploy 10.20.30.42 port 22"),
+        ] {
+            message::insert_new_message(&conn, Option::None, created_at, text, "10.20.30.42", "Alertistan", "Alertregion", "Alerttown", "Alertnet", true, false, "localhost").expect("Message insertion failed");
+        }
+        assert_eq!(crate::alerting::classify_accepted(&conn, "10.20.30.42", "Alertistan")?, vec![]);
+
+        let stored = alert::insert_new_alert(&conn, "2023-12-01T10:05:00+01:00", "10.20.30.41", "root", "Alertistan", "1 prior failures", "Accepted password for root").unwrap();
+        assert!(alert::get_unacknowledged_alerts(&conn)?.iter().any(|a| a.id == stored.id));
+        alert::acknowledge_alerts(&conn, stored.id)?;
+        assert!(!alert::get_unacknowledged_alerts(&conn)?.iter().any(|a| a.id == stored.id));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_allowlist() -> Result<()> {
+        let conn = Connection::open("test.db")?;
+        conn.execute(allowlist::CREATE_ALLOWLIST_DB_SQL, []).expect("Error setting up allowlist db");
+        allowlist::insert_allowlist_entry(&conn, "172.16.0.0/12", "2023-12-01T10:00:00+01:00")?;
+        allowlist::insert_allowlist_entry(&conn, "198.51.100.7", "2023-12-01T10:00:00+01:00")?;
 
+        assert_eq!(allowlist::refuse_reason(&conn, "172.20.1.1")?, Some(String::from("allowlist entry 172.16.0.0/12")));
+        assert!(allowlist::refuse_reason(&conn, "198.51.100.7")?.is_some());
+        assert_eq!(allowlist::refuse_reason(&conn, "198.51.100.8")?, None);
+        assert_eq!(allowlist::refuse_reason(&conn, "2001:db8::1")?, Some(String::from("unparseable target")));
 
+        allowlist::remove_allowlist_entry(&conn, "172.16.0.0/12")?;
+        assert_eq!(allowlist::refuse_reason(&conn, "172.20.1.1")?, None);
+        allowlist::remove_allowlist_entry(&conn, "198.51.100.7")?;
+        Ok(())
+    }
 
+    #[test]
+    #[serial]
+    pub fn test_rules_replay() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        crate::query::register_sql_functions(&conn)?;
+        conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
+        conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
+        conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up Region db");
+        conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
+        conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+        conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up message db");
+        conn.execute(username::CREATE_USERNAME_DB_SQL, []).expect("Error setting up username db");
+        conn.execute(username::CREATE_USERNAME_MESSAGE_DB_SQL, []).expect("Error setting up username message db");
+        conn.execute_batch(message::CREATE_MESSAGE_INDEXES_SQL).expect("Error setting up message indexes");
+        conn.execute(username::CREATE_USERNAME_MESSAGE_INDEX_SQL, []).expect("Error setting up username index");
+        country::insert_new_country(&conn, "Rulestan", Some("RU"), Some(0), Some(0), false).expect("Country insertion failed");
+        region::insert_new_region(&conn, "Ruleregion", "Rulestan", Some(0), Some(0), false).expect("Region insertion failed");
+        city::insert_new_city(&conn, "Ruletown", "Rulestan", "Ruleregion", Some(0), Some(0), false).expect("City insertion failed");
+        isp::insert_new_ISP(&conn, "Rulenet", Some(0), Some(0), "Rulestan", false).expect("ISP insertion failed");
+        // five hosts of one /24 within four minutes, one logged by a host in UTC, a sixth one an hour later
+        for (n, created_at) in [(1, "2023-12-02T10:00:00+01:00"), (2, "2023-12-02T10:01:00+01:00"), (3, "2023-12-02T09:02:00+00:00"), (4, "2023-12-02T10:03:00+01:00"), (5, "2023-12-02T10:04:00+01:00")] {
+            let ip = format!("203.0.113.{}", n);
+            ip::insert_new_IP(&conn, &ip, created_at, "3.12", "59.79", "Rulenet", "Ruletown", Some("Ruleregion"), "Rulestan", Some("RU"), 0, false, 1).expect("IP insertion failed");
+            ip::set_ip_asn(&conn, &ip, "AS64496")?;
+            message::insert_new_message(&conn, Option::None, created_at, "Invalid user admin", &ip, "Rulestan", "Ruleregion", "Ruletown", "Rulenet", true, false, "localhost").expect("Message insertion failed");
+        }
+        ip::insert_new_IP(&conn, "203.0.113.6", "2023-12-02T11:30:00+01:00", "3.12", "59.79", "Rulenet", "Ruletown", Some("Ruleregion"), "Rulestan", Some("RU"), 0, false, 1).expect("IP insertion failed");
+        ip::set_ip_asn(&conn, "203.0.113.6", "AS64497")?;
+        assert_eq!(ip::select_ip(&conn, "203.0.113.6")?.unwrap().asn, "AS64497");
+        message::insert_new_message(&conn, Option::None, "2023-12-02T11:30:00+01:00", "Invalid user admin", "203.0.113.6", "Rulestan", "Ruleregion", "Ruletown", "Rulenet", true, false, "localhost").expect("Message insertion failed");
 
+        let rules: Vec<crate::rules::Rule> = json5::from_str(r#"[
+            { name: "subnet-burst", filter: "cidr:203.0.113.0/24", group_by: "subnet24", threshold: 5, window: "10m", action: "ban", bantime: "1h" },
+            { name: "single-ip", filter: "ip:203.0.113.6", threshold: 2, window: "10m", action: "ban" },
+            { name: "new-asn", first_seen: "asn", action: "alert" },
+        ]"#).unwrap();
+        let hits = crate::rules::replay(&conn, rules)?;
+        let burst: Vec<&crate::rules::RuleHit> = hits.iter().filter(|hit| hit.rule == "subnet-burst").collect();
+        assert_eq!(burst.len(), 1);
+        assert_eq!(burst[0].target, "203.0.113.0/24");
+        assert_eq!(burst[0].count, 5);
+        assert_eq!(burst[0].bantime, Some(chrono::Duration::hours(1)));
+        assert!(!hits.iter().any(|hit| hit.rule == "single-ip"));
+        // the same ISP name, but the last host is in another autonomous system
+        let new_asn: Vec<&str> = hits.iter().filter(|hit| hit.rule == "new-asn").map(|hit| hit.target.as_str()).collect();
+        assert_eq!(new_asn, vec!["203.0.113.1", "203.0.113.6"]);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_active_bans() -> Result<()> {
+        let conn = Connection::open("test.db")?;
+        conn.execute(ban::CREATE_ACTIVEBAN_DB_SQL, []).expect("Error setting up activeban db");
+        let now = chrono::DateTime::parse_from_rfc3339("2023-12-03T12:00:00+01:00").unwrap();
+        let expiring = ban::ActiveBan {
+            ip: String::from("192.0.2.10"),
+            jail: String::from("sshd"),
+            banned_at: String::from("2023-12-03T10:00:00+01:00"),
+            expires_at: Some(String::from("2023-12-03T11:00:00+01:00")),
+            reason: String::from("Rule subnet-burst"),
+            origin: ban::BanOrigin::Rule,
+            escalations: 0,
+        };
+        let permanent = ban::ActiveBan { ip: String::from("192.0.2.11"), expires_at: None, origin: ban::BanOrigin::Manual, ..expiring.clone() };
+        ban::upsert_active_ban(&conn, &expiring)?;
+        ban::upsert_active_ban(&conn, &permanent)?;
 
+        assert_eq!(ban::select_active_ban(&conn, "192.0.2.10")?, Some(expiring.clone()));
+        assert_eq!(expiring.bantime(), Some(chrono::Duration::hours(1)));
+        assert_eq!(permanent.remaining(now), None);
+        let expired = ban::get_expired_bans(&conn, now)?;
+        assert!(expired.iter().any(|b| b.ip == "192.0.2.10"));
+        assert!(!expired.iter().any(|b| b.ip == "192.0.2.11"));
+
+        // extending moves it out of the expired ones
+        let extended = ban::ActiveBan { expires_at: Some(String::from("2023-12-03T13:00:00+01:00")), escalations: 1, ..expiring.clone() };
+        ban::upsert_active_ban(&conn, &extended)?;
+        assert!(!ban::get_expired_bans(&conn, now)?.iter().any(|b| b.ip == "192.0.2.10"));
+        assert_eq!(ban::format_duration(extended.remaining(now).unwrap()), "1h 0m");
+
+        ban::remove_active_ban(&conn, "192.0.2.10")?;
+        ban::remove_active_ban(&conn, "192.0.2.11")?;
+        assert_eq!(ban::select_active_ban(&conn, "192.0.2.10")?, None);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_ban_history() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
+        conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
+        conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up Region db");
+        conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
+        conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+        conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up message db");
+        conn.execute(ban::CREATE_BANS_DB_SQL, []).expect("Error setting up bans db");
+
+        assert_eq!(
+            ban::parse_fail2ban_line("2023-12-04 10:00:00,123 fail2ban.actions [812]: NOTICE [sshd] Ban 198.18.0.7"),
+            Some((String::from("sshd"), ban::Fail2banEvent::Ban, String::from("198.18.0.7")))
+        );
+        assert_eq!(
+            ban::parse_fail2ban_line("fail2ban.actions [812]: NOTICE [sshd] Restore Ban 198.18.0.7").map(|(_, event, _)| event),
+            Some(ban::Fail2banEvent::Ban)
+        );
+        assert_eq!(ban::parse_fail2ban_line("fail2ban.filter [812]: INFO [sshd] Found 198.18.0.7"), None);
+
+        country::insert_new_country(&conn, "Banland", Some("BL"), Some(7), Some(0), false).expect("Country insertion failed");
+        region::insert_new_region(&conn, "Banregion", "Banland", Some(7), Some(0), false).expect("Region insertion failed");
+        city::insert_new_city(&conn, "Bantown", "Banland", "Banregion", Some(7), Some(0), false).expect("City insertion failed");
+        isp::insert_new_ISP(&conn, "Bannet", Some(7), Some(0), "Banland", false).expect("ISP insertion failed");
+        ip::insert_new_IP(&conn, "198.18.0.7", "2023-12-04T10:00:00+01:00", "3.12", "59.79", "Bannet", "Bantown", Some("Banregion"), "Banland", Some("BL"), 5, true, 0).expect("IP insertion failed");
+        message::insert_new_message(&conn, Option::None, "2023-12-04T09:59:00+01:00", "Invalid user admin from 198.18.0.7", "198.18.0.7", "Banland", "Banregion", "Bantown", "Bannet", true, false, "localhost").expect("Message insertion failed");
+
+        // our ban and fail2ban's log line of it are one ban, the second one is a new ban
+        ban::record_ban(&conn, "198.18.0.7", "sshd", "2023-12-04T10:00:00+01:00", ban::SOURCE_FAIL2BAN, "", ban::SOURCE_FAIL2BAN)?;
+        ban::record_ban(&conn, "198.18.0.7", "sshd", "2023-12-04T10:00:01+01:00", ban::SOURCE_SUCCEED2BAN, "Ban popup", "operator")?;
+        ban::record_unban(&conn, "198.18.0.7", "sshd", "2023-12-04T11:00:00+01:00")?;
+        ban::record_ban(&conn, "198.18.0.7", "sshd", "2023-12-04T12:00:00+01:00", ban::SOURCE_FAIL2BAN, "", ban::SOURCE_FAIL2BAN)?;
+        let records = ban::get_bans_by_ip(&conn, "198.18.0.7")?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].source, ban::SOURCE_SUCCEED2BAN);
+        assert_eq!(records[0].actor, "operator");
+        assert_eq!(records[0].unbanned_at, Some(String::from("2023-12-04T11:00:00+01:00")));
+        assert_eq!(records[1].unbanned_at, None);
+
+        statistics::recompute_statistics(&conn)?;
+        let stored = ip::select_ip(&conn, "198.18.0.7")?.unwrap();
+        assert_eq!(stored.banned_times, 2);
+        assert_eq!(stored.warnings, 1);
+        // banning only flags the IP, the count comes from the history
+        ip::set_ip_banned(&conn, "198.18.0.7", true)?;
+        let stored = ip::select_ip(&conn, "198.18.0.7")?.unwrap();
+        assert!(stored.is_banned);
+        assert_eq!(stored.banned_times, 2);
+        let banland = country::select_country(&conn, "Banland")?.unwrap();
+        assert_eq!(banland.banned, 1);
+        assert_eq!(banland.warnings, 1);
+
+        // the detail view reads the history, a ban without a fail2ban line of its own is in it too
+        conn.execute(ip::CREATE_IPHISTORY_DB_SQL, []).expect("Error setting up IP history db");
+        ban::record_ban(&conn, "198.18.0.7", "recidive", "2023-12-04T13:00:00+01:00", ban::SOURCE_SUCCEED2BAN, "Rule root-new-asn", "rule")?;
+        let detail = crate::ipdetail::collect_ip_detail(&conn, "198.18.0.7")?;
+        assert_eq!(detail.ban_history.len(), 3);
+        assert_eq!(detail.ban_history[2].jail, "recidive");
+        assert_eq!(detail.ban_history[2].unbanned_at, None);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_fail2ban_unbanned() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute(ban::CREATE_ACTIVEBAN_DB_SQL, []).expect("Error setting up activeban db");
+        conn.execute(ban::CREATE_BANS_DB_SQL, []).expect("Error setting up bans db");
+        let now = chrono::DateTime::parse_from_rfc3339("2023-12-03T12:00:00+01:00").unwrap();
+        let running = ban::ActiveBan {
+            ip: String::from("192.0.2.20"),
+            jail: String::from("sshd"),
+            banned_at: String::from("2023-12-03T11:00:00+01:00"),
+            expires_at: Some(String::from("2023-12-03T15:00:00+01:00")),
+            reason: String::from("Rule root-new-isp"),
+            origin: ban::BanOrigin::Rule,
+            escalations: 0,
+        };
+        let expired = ban::ActiveBan { ip: String::from("192.0.2.21"), expires_at: Some(String::from("2023-12-03T11:30:00+01:00")), ..running.clone() };
+        for active in [&running, &expired] {
+            ban::upsert_active_ban(&conn, active)?;
+            ban::record_ban(&conn, &active.ip, "sshd", &active.banned_at, ban::SOURCE_SUCCEED2BAN, &active.reason, "rule")?;
+        }
+
+        // the jail's bantime ran out before ours, the ban stays and is handed back to be issued again
+        assert_eq!(ban::fail2ban_unbanned(&conn, "192.0.2.20", "sshd", "2023-12-03T12:00:00+01:00", now)?, Some(running.clone()));
+        assert_eq!(ban::select_active_ban(&conn, "192.0.2.20")?, Some(running));
+        assert_eq!(ban::get_bans_by_ip(&conn, "192.0.2.20")?[0].unbanned_at, None);
+
+        // ours ran out as well, the ban is over
+        assert_eq!(ban::fail2ban_unbanned(&conn, "192.0.2.21", "sshd", "2023-12-03T12:00:00+01:00", now)?, None);
+        assert_eq!(ban::select_active_ban(&conn, "192.0.2.21")?, None);
+        assert_eq!(ban::get_bans_by_ip(&conn, "192.0.2.21")?[0].unbanned_at, Some(String::from("2023-12-03T12:00:00+01:00")));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_audit_log() -> Result<()> {
+        let conn = Connection::open("test.db")?;
+        conn.execute_batch(audit::CREATE_AUDIT_DB_SQL).expect("Error setting up audit db");
+        audit::insert_audit_entry(&conn, "2023-12-05T10:00:00+01:00", "operator", "ban (manual)", "192.0.2.50", audit::RESULT_OK, "1\n")?;
+        audit::insert_audit_entry(&conn, "2023-12-05T10:01:00+01:00", "operator", "block country", "Auditland", audit::RESULT_OK, "")?;
+
+        let entries = audit::get_audit_entries(&conn, Some(2))?;
+        assert_eq!(entries[0].target, "Auditland");
+        assert_eq!(entries[1].output, "1");
+
+        // append-only
+        assert!(conn.execute("UPDATE audit SET result = 'failed'", []).is_err());
+        assert!(conn.execute("DELETE FROM audit", []).is_err());
+
+        let mut csv: Vec<u8> = vec![];
+        let exported = audit::export_audit_csv(&conn, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(exported, audit::get_audit_entries(&conn, None)?.len());
+        assert!(csv.starts_with("id,created_at,user,action,target,result,output\n"));
+        assert!(csv.contains("operator,ban (manual),192.0.2.50,ok,1"));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_block_preview() -> Result<()> {
+        let conn = Connection::open("test.db")?;
+        conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
+        conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
+        conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up Region db");
+        conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
+        conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+        conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up message db");
+        conn.execute(allowlist::CREATE_ALLOWLIST_DB_SQL, []).expect("Error setting up allowlist db");
+        allowlist::insert_allowlist_entry(&conn, "192.0.2.101", "2023-12-06T10:00:00+01:00")?;
+
+        let now = chrono::Local::now().to_rfc3339();
+        country::insert_new_country(&conn, "Previewland", Some("PL"), Some(2), Some(1), false).expect("Country insertion failed");
+        region::insert_new_region(&conn, "Previewregion", "Previewland", Some(2), Some(1), false).expect("Region insertion failed");
+        city::insert_new_city(&conn, "Previewtown", "Previewland", "Previewregion", Some(2), Some(1), false).expect("City insertion failed");
+        isp::insert_new_ISP(&conn, "Previewnet", Some(2), Some(1), "Previewland", false).expect("ISP insertion failed");
+        ip::insert_new_IP(&conn, "192.0.2.100", &now, "3.12", "59.79", "Previewnet", "Previewtown", Some("Previewregion"), "Previewland", Some("PL"), 1, true, 1).expect("IP insertion failed");
+        ip::insert_new_IP(&conn, "192.0.2.101", &now, "3.12", "59.79", "Previewnet", "Previewtown", Some("Previewregion"), "Previewland", Some("PL"), 0, false, 1).expect("IP insertion failed");
+        ip::insert_new_IP(&conn, "192.0.2.102", "2023-12-06T10:00:00+01:00", "3.12", "59.79", "Previewnet", "Previewtown", Some("Previewregion"), "Previewland", Some("PL"), 0, false, 1).expect("IP insertion failed");
+        message::insert_new_message(&conn, Option::None, &now, "Accepted publickey for operator from 192.0.2.101", "192.0.2.101", "Previewland", "Previewregion", "Previewtown", "Previewnet", true, false, "localhost").expect("Message insertion failed");
+        message::insert_new_message(&conn, Option::None, "2023-12-06T10:00:00+01:00", "Invalid user admin from 192.0.2.102", "192.0.2.102", "Previewland", "Previewregion", "Previewtown", "Previewnet", true, false, "localhost").expect("Message insertion failed");
+
+        let preview = crate::impact::preview_block(&conn, "Country", "Previewland", "sshd")?;
+        assert_eq!(preview.known_ips, 3);
+        assert_eq!(preview.active_ips, 1);
+        assert_eq!(preview.already_banned, 1);
+        assert_eq!(preview.allowlisted, vec![(String::from("192.0.2.101"), String::from("allowlist entry 192.0.2.101"))]);
+        assert_eq!(preview.recent_logins, vec![String::from("192.0.2.101")]);
+        assert_eq!(preview.commands, vec![String::from("fail2ban-client set sshd banip 192.0.2.102")]);
+        assert_eq!(preview.num_commands, 1);
+
+        // usernames cannot be blocked this way
+        assert_eq!(crate::impact::preview_block(&conn, "User", "root", "sshd")?.known_ips, 0);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_hosts() -> Result<()> {
+        // messages stored before hosts were recorded belong to the local host
+        let old = Connection::open_in_memory()?;
+        old.execute("CREATE TABLE messages (id INTEGER PRIMARY KEY AUTOINCREMENT, created_at TEXT NOT NULL, text TEXT NOT NULL, ip TEXT NOT NULL)", [])?;
+        old.execute("INSERT INTO messages (created_at, text, ip) VALUES ('2023-12-01T10:00:00+01:00', 'Invalid user admin from 192.0.2.50', '192.0.2.50')", [])?;
+        message::migrate_message_host(&old, "bastion")?;
+        message::migrate_message_host(&old, "other")?;
+        let migrated: String = old.query_row("SELECT host FROM messages", [], |row| row.get(0))?;
+        assert_eq!(migrated, "bastion");
+        message::migrate_message_ip_num(&old)?;
+        let ip_num: u32 = old.query_row("SELECT ip_num FROM messages", [], |row| row.get(0))?;
+        assert_eq!(ip_num, crate::cidr::ip_to_u32("192.0.2.50").unwrap());
+
+        let conn = Connection::open_in_memory()?;
+        crate::query::register_sql_functions(&conn)?;
+        conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
+        conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
+        conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up Region db");
+        conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
+        conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+        conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up message db");
+        conn.execute(username::CREATE_USERNAME_DB_SQL, []).expect("Error setting up username db");
+        conn.execute(username::CREATE_USERNAME_MESSAGE_DB_SQL, []).expect("Error setting up username_message db");
+        message::migrate_message_host(&conn, "localhost")?;
+        country::insert_new_country(&conn, "Hostland", Some("HL"), Some(0), Some(0), false).expect("Country insertion failed");
+        region::insert_new_region(&conn, "Hostregion", "Hostland", Some(0), Some(0), false).expect("Region insertion failed");
+        city::insert_new_city(&conn, "Hosttown", "Hostland", "Hostregion", Some(0), Some(0), false).expect("City insertion failed");
+        isp::insert_new_ISP(&conn, "Hostnet", Some(0), Some(0), "Hostland", false).expect("ISP insertion failed");
+        ip::insert_new_IP(&conn, "192.0.2.51", "2023-12-01T10:00:00+01:00", "3.12", "59.79", "Hostnet", "Hosttown", Some("Hostregion"), "Hostland", Some("HL"), 2, true, 0).expect("IP insertion failed");
+        message::insert_new_message(&conn, Option::None, "2023-12-01T10:00:00+01:00", "Invalid user admin from 192.0.2.51", "192.0.2.51", "Hostland", "Hostregion", "Hosttown", "Hostnet", true, false, "hosttest-web1").expect("Message insertion failed");
+        message::insert_new_message(&conn, Option::None, "2023-12-01T10:00:05+01:00", "Invalid user root from 192.0.2.51", "192.0.2.51", "Hostland", "Hostregion", "Hosttown", "Hostnet", true, false, "hosttest-web1").expect("Message insertion failed");
+
+        let web1 = host::get_all_hosts(&conn)?.into_iter().find(|h| h.name == "hosttest-web1").unwrap();
+        assert_eq!(web1.warnings, 2);
+        assert_eq!(web1.banned, 1);
+        assert_eq!(message::get_message_timestamps_by_host(&conn, "hosttest-web1")?.len(), 2);
+
+        let res = crate::query::run_query(&conn, "host:HOSTTEST-WEB1", 10).unwrap();
+        assert_eq!(res.total, 2);
+        assert!(res.messages.iter().all(|m| m.host == "hosttest-web1"));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_providers() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
+        conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
+        conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up Region db");
+        conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
+        conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+        conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up message db");
+        conn.execute(provider::CREATE_PROVIDER_DB_SQL, []).expect("Error setting up provider db");
+        conn.execute(provider::CREATE_IPCLOUD_DB_SQL, []).expect("Error setting up ipcloud db");
+        message::migrate_message_host(&conn, "localhost")?;
+        country::insert_new_country(&conn, "Cloudland", Some("CL"), Some(0), Some(0), false).expect("Country insertion failed");
+        region::insert_new_region(&conn, "Cloudregion", "Cloudland", Some(0), Some(0), false).expect("Region insertion failed");
+        city::insert_new_city(&conn, "Cloudtown", "Cloudland", "Cloudregion", Some(0), Some(0), false).expect("City insertion failed");
+        isp::insert_new_ISP(&conn, "Cloudnet", Some(0), Some(0), "Cloudland", false).expect("ISP insertion failed");
+        ip::insert_new_IP(&conn, "198.51.100.61", "2023-12-01T10:00:00+01:00", "3.12", "59.79", "Cloudnet", "Cloudtown", Some("Cloudregion"), "Cloudland", Some("CL"), 2, true, 0).expect("IP insertion failed");
+        message::insert_new_message(&conn, Option::None, "2023-12-01T10:00:00+01:00", "Invalid user admin from 198.51.100.61", "198.51.100.61", "Cloudland", "Cloudregion", "Cloudtown", "Cloudnet", true, false, "localhost").expect("Message insertion failed");
+        message::insert_new_message(&conn, Option::None, "2023-12-01T10:00:05+01:00", "Invalid user root from 198.51.100.61", "198.51.100.61", "Cloudland", "Cloudregion", "Cloudtown", "Cloudnet", true, false, "localhost").expect("Message insertion failed");
+
+        let range = provider::CloudRange { provider: String::from("TESTCLOUD"), region: String::from("eu-test-1"), service: String::from("VM") };
+        provider::insert_ip_cloud(&conn, "198.51.100.61", &range)?;
+        // a second lookup of the same IP keeps one row
+        provider::insert_ip_cloud(&conn, "198.51.100.61", &range)?;
+        assert_eq!(provider::select_ip_cloud(&conn, "198.51.100.61")?, Some(range.clone()));
+        assert_eq!(range.to_string(), "TESTCLOUD eu-test-1 VM");
+
+        let testcloud = provider::select_provider(&conn, "TESTCLOUD")?.unwrap();
+        assert_eq!(testcloud.warnings, 2);
+        assert_eq!(testcloud.banned, 1);
+        assert!(!testcloud.is_blocked);
+        assert!(!provider::is_provider_blocked(&conn, "TESTCLOUD")?);
+        assert_eq!(message::get_message_timestamps_by_provider(&conn, "TESTCLOUD")?.len(), 2);
+
+        provider::set_provider_blocked(&conn, "TESTCLOUD", true)?;
+        assert!(provider::get_all_providers(&conn)?.into_iter().find(|p| p.name == "TESTCLOUD").unwrap().is_blocked);
+        // the block survives the next insert
+        provider::insert_ip_cloud(&conn, "198.51.100.61", &range)?;
+        assert!(provider::select_provider(&conn, "TESTCLOUD")?.unwrap().is_blocked);
+        assert!(provider::is_provider_blocked(&conn, "TESTCLOUD")?);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    pub fn test_ip_location_history() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
+        conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
+        conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up Region db");
+        conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
+        conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
+        ip::migrate_ip_resolved_at(&conn)?;
+        ip::migrate_ip_refresh_failed_at(&conn)?;
+        conn.execute(ip::CREATE_IPHISTORY_DB_SQL, []).expect("Error setting up IP history db");
+        conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up message db");
+        message::migrate_message_host(&conn, "localhost")?;
+        conn.execute(ban::CREATE_BANS_DB_SQL, []).expect("Error setting up bans db");
+        country::insert_new_country(&conn, "Oldland", Some("OL"), Some(1), Some(1), false).expect("Country insertion failed");
+        region::insert_new_region(&conn, "Oldregion", "Oldland", Some(1), Some(1), false).expect("Region insertion failed");
+        city::insert_new_city(&conn, "Oldtown", "Oldland", "Oldregion", Some(1), Some(1), false).expect("City insertion failed");
+        isp::insert_new_ISP(&conn, "Oldnet", Some(1), Some(1), "Oldland", false).expect("ISP insertion failed");
+        ip::insert_new_IP(&conn, "198.18.0.90", "2023-01-01T10:00:00+01:00", "3.12", "59.79", "Oldnet", "Oldtown", Some("Oldregion"), "Oldland", Some("OL"), 1, true, 1).expect("IP insertion failed");
+        message::insert_new_message(&conn, Option::None, "2023-01-01T10:00:00+01:00", "Invalid user admin from 198.18.0.90", "198.18.0.90", "Oldland", "Oldregion", "Oldtown", "Oldnet", true, false, "localhost").expect("Message insertion failed");
+        ban::record_ban(&conn, "198.18.0.90", "sshd", "2023-01-01T10:00:01+01:00", ban::SOURCE_FAIL2BAN, "", ban::SOURCE_FAIL2BAN)?;
+
+        // new IPs count as resolved when first seen, storing another line keeps both timestamps
+        ip::insert_new_IP(&conn, "198.18.0.90", "2024-01-01T10:00:00+01:00", "1.0", "2.0", "Othernet", "Othertown", Some("Otherregion"), "Otherland", Some("OT"), 1, true, 2).expect("IP insertion failed");
+        let stored = ip::select_ip(&conn, "198.18.0.90")?.unwrap();
+        assert_eq!((stored.created_at.as_str(), stored.resolved_at.as_str(), stored.country.as_str(), stored.warnings), ("2023-01-01T10:00:00+01:00", "2023-01-01T10:00:00+01:00", "Oldland", 2));
+        assert!(ip::get_stale_ips(&conn, "2023-06-01T00:00:00+01:00", 1000)?.contains(&String::from("198.18.0.90")));
+        assert!(!ip::get_stale_ips(&conn, "2022-06-01T00:00:00+01:00", 1000)?.contains(&String::from("198.18.0.90")));
+        // a failed lookup waits for the next TTL
+        ip::mark_refresh_failed(&conn, "198.18.0.90", "2023-05-01T00:00:00+01:00")?;
+        assert!(!ip::get_stale_ips(&conn, "2023-04-01T00:00:00+01:00", 1000)?.contains(&String::from("198.18.0.90")));
+        assert!(ip::get_stale_ips(&conn, "2023-06-01T00:00:00+01:00", 1000)?.contains(&String::from("198.18.0.90")));
+
+        // same location, only the timestamp changes
+        let mut fresh = ip::IP { ip: String::from("198.18.0.90"), country: String::from("Oldland"), countrycode: String::from("OL"), region: String::from("Oldregion"), city: String::from("Oldtown"), isp: String::from("Oldnet"), ..ip::IP::default() };
+        assert_eq!(ip::update_ip_location(&conn, &fresh, "2023-06-01T10:00:00+01:00")?, None);
+        assert_eq!(ip::select_ip(&conn, "198.18.0.90")?.unwrap().resolved_at, "2023-06-01T10:00:00+01:00");
+
+        fresh.country = String::from("Newland");
+        fresh.countrycode = String::from("NL");
+        fresh.region = String::from("Newregion");
+        fresh.city = String::from("Newtown");
+        fresh.isp = String::from("Newnet");
+        statistics::insert_location(&conn, &fresh)?;
+        let change = ip::update_ip_location(&conn, &fresh, "2024-06-01T10:00:00+01:00")?.unwrap();
+        assert_eq!((change.old_country.as_str(), change.new_country.as_str(), change.old_isp.as_str(), change.new_isp.as_str()), ("Oldland", "Newland", "Oldnet", "Newnet"));
+        statistics::move_ip_aggregates(&conn, &change)?;
+        assert_eq!(ip::select_ip_history(&conn, "198.18.0.90")?, vec![change]);
+        assert_eq!(ip::select_ip(&conn, "198.18.0.90")?.unwrap().country, "Newland");
+
+        // the ban moves with the IP, the message stays where it was logged from, as a full recompute has it
+        let moved = |conn: &Connection| -> Result<Vec<(usize, usize)>> {
+            let old = country::select_country(conn, "Oldland")?.unwrap();
+            let new = country::select_country(conn, "Newland")?.unwrap();
+            let new_isp = isp::select_isp(conn, "Newnet")?.unwrap();
+            Ok(vec![(old.banned, old.warnings), (new.banned, new.warnings), (new_isp.banned, new_isp.warnings)])
+        };
+        assert_eq!(moved(&conn)?, vec![(0, 1), (1, 0), (1, 0)]);
+        statistics::recompute_statistics(&conn)?;
+        assert_eq!(moved(&conn)?, vec![(0, 1), (1, 0), (1, 0)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, Result};
+
+
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Alert {
+    pub id: usize,
+    pub created_at: String,
+    pub ip: String,
+    pub username: String,
+    pub country: String,
+    /// human readable reasons, joined by "; "
+    pub reason: String,
+    pub text: String,
+    pub acknowledged: bool,
+}
+pub const CREATE_ALERT_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS alert(
+    id INTEGER PRIMARY KEY,
+    created_at TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    username TEXT NOT NULL,
+    country TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    text TEXT NOT NULL,
+    acknowledged INTEGER NOT NULL
+)
+";
+
+/// stores a new unacknowledged alert and returns it with its id
+pub fn insert_new_alert(conn: &Connection, created_at: &str, ip: &str, username: &str, country: &str, reason: &str, text: &str) -> Result<Alert> {
+    conn.execute(
+        "INSERT INTO alert (created_at, ip, username, country, reason, text, acknowledged) VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0)",
+        (created_at, ip, username, country, reason, text),
+    )?;
+    Ok(Alert {
+        id: conn.last_insert_rowid() as usize,
+        created_at: created_at.to_string(),
+        ip: ip.to_string(),
+        username: username.to_string(),
+        country: country.to_string(),
+        reason: reason.to_string(),
+        text: text.to_string(),
+        acknowledged: false,
+    })
+}
+
+pub fn get_unacknowledged_alerts(conn: &Connection) -> Result<Vec<Alert>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, created_at, ip, username, country, reason, text, acknowledged FROM alert WHERE acknowledged = 0 ORDER BY id;"
+    )?;
+    let alert_iter = stmt.query_map([], |row| {
+        Ok( Alert {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            ip: row.get(2)?,
+            username: row.get(3)?,
+            country: row.get(4)?,
+            reason: row.get(5)?,
+            text: row.get(6)?,
+            acknowledged: row.get(7)?,
+        })
+    })?;
+
+    let mut results: Vec<Alert> = vec![];
+    for alert in alert_iter {
+        results.push(alert?);
+    }
+    Ok(results)
+}
+
+/// acknowledges every alert up to and including the given id
+pub fn acknowledge_alerts(conn: &Connection, up_to_id: usize) -> Result<usize> {
+    conn.execute(
+        "UPDATE alert SET acknowledged = 1 WHERE acknowledged = 0 AND id <= ?1",
+        params![up_to_id],
+    )
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, Result};
+
+use crate::cidr::Cidr;
+
+
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct AllowlistEntry {
+    /// IP or CIDR
+    pub entry: String,
+    pub created_at: String,
+}
+pub const CREATE_ALLOWLIST_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS allowlist(
+    entry TEXT NOT NULL PRIMARY KEY,
+    created_at TEXT NOT NULL
+)
+";
+
+pub fn insert_allowlist_entry(conn: &Connection, entry: &str, created_at: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO allowlist (entry, created_at) VALUES (?1, ?2)",
+        (entry, created_at),
+    )?;
+    Ok(())
+}
+
+pub fn remove_allowlist_entry(conn: &Connection, entry: &str) -> Result<()> {
+    conn.execute("DELETE FROM allowlist WHERE entry = ?1", [entry])?;
+    Ok(())
+}
+
+pub fn get_allowlist(conn: &Connection) -> Result<Vec<AllowlistEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT entry, created_at FROM allowlist ORDER BY entry;"
+    )?;
+    let entry_iter = stmt.query_map([], |row| {
+        Ok( AllowlistEntry {
+            entry: row.get(0)?,
+            created_at: row.get(1)?,
+        })
+    })?;
+
+    let mut results: Vec<AllowlistEntry> = vec![];
+    for entry in entry_iter {
+        results.push(entry?);
+    }
+    Ok(results)
+}
+
+/// IP of the operator's current SSH session, taken from SSH_CLIENT or SSH_CONNECTION
+pub fn ssh_client_ip() -> Option<String> {
+    ["SSH_CLIENT", "SSH_CONNECTION"].iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find_map(|value| value.split_whitespace().next().map(String::from))
+}
+
+/// Returns why an IP or network must never be banned, or None if banning it is fine.
+/// Targets that do not parse are refused, they cannot be checked against the allowlist.
+pub fn refuse_reason(conn: &Connection, ip: &str) -> Result<Option<String>> {
+    let Some(target) = Cidr::parse(ip) else {return Ok(Some(String::from("unparseable target")))};
+    let overlaps = |entry: &str| Cidr::parse(entry).is_some_and(|entry| entry.overlaps(&target));
+    if ssh_client_ip().is_some_and(|client| overlaps(&client)) {
+        return Ok(Some(String::from("current SSH session (SSH_CLIENT)")));
+    }
+    let hit = get_allowlist(conn)?.into_iter().find(|entry| overlaps(&entry.entry));
+    Ok(hit.map(|entry| format!("allowlist entry {}", entry.entry)))
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, Result};
+
+
+/// One operator action, entries are never changed or removed.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct AuditEntry {
+    pub id: usize,
+    pub created_at: String,
+    /// local user running succeed2ban
+    pub user: String,
+    pub action: String,
+    pub target: String,
+    pub result: String,
+    /// output of fail2ban-client, empty for actions that did not run it
+    pub output: String,
+}
+
+/// The triggers keep the table append-only.
+pub const CREATE_AUDIT_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS audit(
+    id INTEGER PRIMARY KEY,
+    created_at TEXT NOT NULL,
+    user TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target TEXT NOT NULL,
+    result TEXT NOT NULL,
+    output TEXT NOT NULL
+);
+CREATE TRIGGER IF NOT EXISTS audit_no_update BEFORE UPDATE ON audit
+BEGIN
+    SELECT RAISE(ABORT, 'audit log is append-only');
+END;
+CREATE TRIGGER IF NOT EXISTS audit_no_delete BEFORE DELETE ON audit
+BEGIN
+    SELECT RAISE(ABORT, 'audit log is append-only');
+END;
+";
+
+/// Entries shown in the audit view, the export has all of them.
+pub const AUDIT_VIEW_LIMIT: usize = 500;
+
+pub const RESULT_OK: &str = "ok";
+pub const RESULT_FAILED: &str = "failed";
+pub const RESULT_REFUSED: &str = "refused";
+
+pub fn insert_audit_entry(conn: &Connection, created_at: &str, user: &str, action: &str, target: &str, result: &str, output: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO audit (created_at, user, action, target, result, output) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (created_at, user, action, target, result, output.trim()),
+    )?;
+    Ok(())
+}
+
+/// The latest `limit` entries or all of them, newest first.
+pub fn get_audit_entries(conn: &Connection, limit: Option<usize>) -> Result<Vec<AuditEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, created_at, user, action, target, result, output FROM audit ORDER BY id DESC LIMIT ?1;"
+    )?;
+    // a negative LIMIT means no limit
+    let limit: i64 = limit.map(|limit| limit as i64).unwrap_or(-1);
+    let entry_iter = stmt.query_map([limit], |row| {
+        Ok( AuditEntry {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            user: row.get(2)?,
+            action: row.get(3)?,
+            target: row.get(4)?,
+            result: row.get(5)?,
+            output: row.get(6)?,
+        })
+    })?;
+
+    let mut results: Vec<AuditEntry> = vec![];
+    for entry in entry_iter {
+        results.push(entry?);
+    }
+    Ok(results)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes the whole audit log as CSV, oldest first.
+pub fn export_audit_csv(conn: &Connection, out: &mut impl std::io::Write) -> std::result::Result<usize, Box<dyn std::error::Error>> {
+    let mut entries = get_audit_entries(conn, None)?;
+    entries.reverse();
+    writeln!(out, "id,created_at,user,action,target,result,output")?;
+    for entry in entries.iter() {
+        let fields = [entry.id.to_string(), entry.created_at.clone(), entry.user.clone(), entry.action.clone(), entry.target.clone(), entry.result.clone(), entry.output.clone()];
+        writeln!(out, "{}", fields.iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(","))?;
+    }
+    Ok(entries.len())
+}
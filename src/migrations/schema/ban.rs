@@ -0,0 +1,313 @@
+use chrono::{DateTime, Duration, FixedOffset};
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, OptionalExtension, Result};
+
+
+/// Where a ban came from.
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BanOrigin {
+    #[default]
+    Manual,
+    Rule,
+    Block,
+}
+
+impl BanOrigin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BanOrigin::Manual => "manual",
+            BanOrigin::Rule => "rule",
+            BanOrigin::Block => "block",
+        }
+    }
+
+    pub fn parse(value: &str) -> BanOrigin {
+        match value {
+            "rule" => BanOrigin::Rule,
+            "block" => BanOrigin::Block,
+            _ => BanOrigin::Manual,
+        }
+    }
+}
+
+/// Everything a ban needs besides the IP, passed along with `Action::BanIP`.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct BanRequest {
+    /// seconds, the configured default bantime applies if None
+    pub bantime: Option<i64>,
+    pub reason: String,
+    pub origin: BanOrigin,
+}
+
+impl BanRequest {
+    pub fn manual(reason: &str) -> Self {
+        BanRequest { bantime: None, reason: reason.to_string(), origin: BanOrigin::Manual }
+    }
+}
+
+/// An IP or network that is currently banned.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct ActiveBan {
+    pub ip: String,
+    pub jail: String,
+    pub banned_at: String,
+    /// None for permanent bans
+    pub expires_at: Option<String>,
+    pub reason: String,
+    pub origin: BanOrigin,
+    /// how often the ban was prolonged because the IP kept showing up
+    pub escalations: usize,
+}
+
+impl ActiveBan {
+    /// Time left until expiry, None for permanent bans.
+    pub fn remaining(&self, now: DateTime<FixedOffset>) -> Option<Duration> {
+        let expires_at = DateTime::parse_from_rfc3339(self.expires_at.as_deref()?).ok()?;
+        Some((expires_at - now).max(Duration::zero()))
+    }
+
+    /// Length of the current ban, None for permanent bans.
+    pub fn bantime(&self) -> Option<Duration> {
+        let banned_at = DateTime::parse_from_rfc3339(&self.banned_at).ok()?;
+        let expires_at = DateTime::parse_from_rfc3339(self.expires_at.as_deref()?).ok()?;
+        Some(expires_at - banned_at)
+    }
+}
+
+/// Formats a bantime like `1d 2h` or `15m`.
+pub fn format_duration(duration: Duration) -> String {
+    let days = duration.num_days();
+    let hours = duration.num_hours() % 24;
+    let minutes = duration.num_minutes() % 60;
+    match (days, hours) {
+        (0, 0) if minutes == 0 => format!("{}s", duration.num_seconds().max(0)),
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+pub const CREATE_ACTIVEBAN_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS activebans(
+    ip TEXT NOT NULL PRIMARY KEY,
+    jail TEXT NOT NULL,
+    banned_at TEXT NOT NULL,
+    expires_at TEXT,
+    reason TEXT NOT NULL,
+    origin TEXT NOT NULL,
+    escalations INTEGER NOT NULL DEFAULT 0
+)
+";
+
+const SELECT_ACTIVEBAN_SQL: &str = "SELECT ip, jail, banned_at, expires_at, reason, origin, escalations FROM activebans";
+
+fn row_to_ban(row: &rusqlite::Row) -> Result<ActiveBan> {
+    let origin: String = row.get(5)?;
+    Ok(ActiveBan {
+        ip: row.get(0)?,
+        jail: row.get(1)?,
+        banned_at: row.get(2)?,
+        expires_at: row.get(3)?,
+        reason: row.get(4)?,
+        origin: BanOrigin::parse(&origin),
+        escalations: row.get(6)?,
+    })
+}
+
+pub fn upsert_active_ban(conn: &Connection, ban: &ActiveBan) -> Result<()> {
+    conn.execute(
+        "INSERT INTO activebans (ip, jail, banned_at, expires_at, reason, origin, escalations) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT(ip) DO UPDATE SET jail = excluded.jail, banned_at = excluded.banned_at, expires_at = excluded.expires_at,
+            reason = excluded.reason, origin = excluded.origin, escalations = excluded.escalations",
+        (&ban.ip, &ban.jail, &ban.banned_at, &ban.expires_at, &ban.reason, ban.origin.as_str(), ban.escalations),
+    )?;
+    Ok(())
+}
+
+pub fn remove_active_ban(conn: &Connection, ip: &str) -> Result<()> {
+    conn.execute("DELETE FROM activebans WHERE ip = ?1", [ip])?;
+    Ok(())
+}
+
+pub fn select_active_ban(conn: &Connection, ip: &str) -> Result<Option<ActiveBan>> {
+    conn.query_row(&format!("{} WHERE ip = ?1", SELECT_ACTIVEBAN_SQL), [ip], row_to_ban).optional()
+}
+
+/// All current bans, the ones expiring first on top and permanent ones last.
+pub fn get_active_bans(conn: &Connection) -> Result<Vec<ActiveBan>> {
+    let mut stmt = conn.prepare(&format!("{} ORDER BY expires_at IS NULL, expires_at, ip", SELECT_ACTIVEBAN_SQL))?;
+    let ban_iter = stmt.query_map([], row_to_ban)?;
+
+    let mut results: Vec<ActiveBan> = vec![];
+    for ban in ban_iter {
+        results.push(ban?);
+    }
+    Ok(results)
+}
+
+/// Bans whose expiry is at or before `now`, timestamps are RFC 3339.
+pub fn get_expired_bans(conn: &Connection, now: DateTime<FixedOffset>) -> Result<Vec<ActiveBan>> {
+    Ok(get_active_bans(conn)?.into_iter().filter(|ban| ban.remaining(now).is_some_and(|left| left.is_zero())).collect())
+}
+
+/// Number of log lines from the IP since it was banned, apart from fail2ban's own Ban/Unban notices.
+pub fn count_messages_while_banned(conn: &Connection, ban: &ActiveBan) -> Result<usize> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM messages WHERE ip = ?1 AND created_at > ?2 AND is_ban = 0 AND text NOT LIKE '%Unban%'",
+        (&ban.ip, &ban.banned_at),
+        |row| row.get(0),
+    )
+}
+
+
+/// One ban from start to end, fail2ban's own bans as well as ours.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct BanRecord {
+    pub id: usize,
+    pub ip: String,
+    pub jail: String,
+    pub banned_at: String,
+    /// None while the ban lasts
+    pub unbanned_at: Option<String>,
+    /// `fail2ban` for bans read from its log, `succeed2ban` for bans we issued
+    pub source: String,
+    pub reason: String,
+    /// local user who issued the ban, `fail2ban` for its own
+    pub actor: String,
+}
+
+pub const SOURCE_FAIL2BAN: &str = "fail2ban";
+pub const SOURCE_SUCCEED2BAN: &str = "succeed2ban";
+
+pub const CREATE_BANS_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS bans(
+    id INTEGER PRIMARY KEY,
+    ip TEXT NOT NULL,
+    jail TEXT NOT NULL,
+    banned_at TEXT NOT NULL,
+    unbanned_at TEXT,
+    source TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    actor TEXT NOT NULL
+)
+";
+
+/// Records the start of a ban. A ban that is already open for the IP and jail is kept, ours only add
+/// the reason and actor to the one fail2ban logged, so a ban is never counted twice.
+pub fn record_ban(conn: &Connection, ip: &str, jail: &str, banned_at: &str, source: &str, reason: &str, actor: &str) -> Result<()> {
+    let open: Option<(usize, String)> = conn.query_row(
+        "SELECT id, source FROM bans WHERE ip = ?1 AND jail = ?2 AND unbanned_at IS NULL ORDER BY id DESC LIMIT 1",
+        (ip, jail),
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?;
+    match open {
+        Some((id, open_source)) => {
+            if source == SOURCE_SUCCEED2BAN && open_source != SOURCE_SUCCEED2BAN {
+                conn.execute("UPDATE bans SET source = ?2, reason = ?3, actor = ?4 WHERE id = ?1", (id, source, reason, actor))?;
+            }
+        },
+        None => {
+            conn.execute(
+                "INSERT INTO bans (ip, jail, banned_at, source, reason, actor) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                (ip, jail, banned_at, source, reason, actor),
+            )?;
+        },
+    }
+    Ok(())
+}
+
+/// Records the end of all open bans of the IP in the jail.
+pub fn record_unban(conn: &Connection, ip: &str, jail: &str, unbanned_at: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE bans SET unbanned_at = ?3 WHERE ip = ?1 AND jail = ?2 AND unbanned_at IS NULL",
+        (ip, jail, unbanned_at),
+    )?;
+    Ok(())
+}
+
+/// Handles fail2ban unbanning an IP on its own when the jail's bantime ran out. An active ban of ours in
+/// that jail with time left is kept and returned so it can be issued again, otherwise the unban is recorded
+/// and a leftover active ban removed.
+pub fn fail2ban_unbanned(conn: &Connection, ip: &str, jail: &str, unbanned_at: &str, now: DateTime<FixedOffset>) -> Result<Option<ActiveBan>> {
+    if let Some(active) = select_active_ban(conn, ip)?.filter(|active| active.jail == jail) {
+        if active.remaining(now).is_none_or(|left| !left.is_zero()) {
+            return Ok(Some(active));
+        }
+        remove_active_ban(conn, ip)?;
+    }
+    record_unban(conn, ip, jail, unbanned_at)?;
+    Ok(None)
+}
+
+pub fn get_bans_by_ip(conn: &Connection, ip: &str) -> Result<Vec<BanRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, ip, jail, banned_at, unbanned_at, source, reason, actor FROM bans WHERE ip = ?1 ORDER BY banned_at, id;"
+    )?;
+    let record_iter = stmt.query_map([ip], |row| {
+        Ok( BanRecord {
+            id: row.get(0)?,
+            ip: row.get(1)?,
+            jail: row.get(2)?,
+            banned_at: row.get(3)?,
+            unbanned_at: row.get(4)?,
+            source: row.get(5)?,
+            reason: row.get(6)?,
+            actor: row.get(7)?,
+        })
+    })?;
+
+    let mut results: Vec<BanRecord> = vec![];
+    for record in record_iter {
+        results.push(record?);
+    }
+    Ok(results)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fail2banEvent {
+    Ban,
+    Unban,
+}
+
+/// Reads a fail2ban.actions line like `NOTICE [sshd] Ban 1.2.3.4`, returns the jail, what happened and the IP.
+pub fn parse_fail2ban_line(line: &str) -> Option<(String, Fail2banEvent, String)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    for (idx, word) in words.iter().enumerate() {
+        let Some(jail) = word.strip_prefix('[').and_then(|w| w.strip_suffix(']')) else {continue};
+        // `Restore Ban` is logged for bans that survived a fail2ban restart
+        let rest: Vec<&str> = words[idx + 1..].iter().copied().filter(|w| *w != "Restore").collect();
+        let event = match rest.first() {
+            Some(&"Ban") => Fail2banEvent::Ban,
+            Some(&"Unban") => Fail2banEvent::Unban,
+            _ => continue,
+        };
+        let ip = rest.get(1)?;
+        crate::cidr::ip_to_u32(ip)?;
+        return Some((jail.to_string(), event, ip.to_string()));
+    }
+    None
+}
+
+/// Fills the history from the fail2ban lines stored before it existed.
+pub fn backfill_bans(conn: &Connection) -> Result<usize> {
+    let recorded: usize = conn.query_row("SELECT COUNT(*) FROM bans", [], |row| row.get(0))?;
+    if recorded > 0 {
+        return Ok(0);
+    }
+    let mut stmt = conn.prepare("SELECT created_at, text FROM messages WHERE is_jctl = 0 ORDER BY created_at, id;")?;
+    let msgs: Vec<(String, String)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<Vec<(String, String)>>>()?;
+
+    let mut inserted: usize = 0;
+    for (created_at, text) in msgs {
+        // fail2ban may deliver several lines at once
+        for line in text.split("++++") {
+            match parse_fail2ban_line(line) {
+                Some((jail, Fail2banEvent::Ban, ip)) => {
+                    record_ban(conn, &ip, &jail, &created_at, SOURCE_FAIL2BAN, "", SOURCE_FAIL2BAN)?;
+                    inserted += 1;
+                },
+                Some((jail, Fail2banEvent::Unban, ip)) => {record_unban(conn, &ip, &jail, &created_at)?;},
+                None => {},
+            }
+        }
+    }
+    Ok(inserted)
+}
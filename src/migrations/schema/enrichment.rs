@@ -0,0 +1,109 @@
+use chrono::{DateTime, Duration, FixedOffset};
+use serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, Result};
+
+use crate::cidr::Cidr;
+
+
+/// Reverse DNS of an IP, `hostname` is empty if there is no PTR record.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Ptr {
+    pub ip: String,
+    pub hostname: String,
+    pub resolved_at: String,
+}
+
+/// Registration data of the network an IP belongs to.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Rdap {
+    pub ip: String,
+    /// allocation like `192.0.2.0/24`, `start - end` if the range is no single network
+    pub cidr: String,
+    /// network name like `EXAMPLE-NET`
+    pub name: String,
+    pub handle: String,
+    /// email of the entity with the abuse role, empty if none is published
+    pub abuse_email: String,
+    pub fetched_at: String,
+}
+
+pub const CREATE_PTR_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS ptr(
+    ip TEXT NOT NULL PRIMARY KEY,
+    hostname TEXT NOT NULL,
+    resolved_at TEXT NOT NULL
+)
+";
+pub const CREATE_RDAP_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS rdap(
+    ip TEXT NOT NULL PRIMARY KEY,
+    cidr TEXT NOT NULL,
+    name TEXT NOT NULL,
+    handle TEXT NOT NULL,
+    abuse_email TEXT NOT NULL,
+    fetched_at TEXT NOT NULL
+)
+";
+
+/// True if the entry was stored less than `ttl` before `now`.
+pub fn is_fresh(stored_at: &str, ttl: Duration, now: DateTime<FixedOffset>) -> bool {
+    DateTime::parse_from_rfc3339(stored_at).is_ok_and(|at| now - at < ttl)
+}
+
+pub fn insert_ptr(conn: &Connection, ptr: &Ptr) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO ptr (ip, hostname, resolved_at) VALUES (?1, ?2, ?3)",
+        params![ptr.ip, ptr.hostname, ptr.resolved_at],
+    )?;
+    Ok(())
+}
+
+pub fn select_ptr(conn: &Connection, ip: &str) -> Result<Option<Ptr>> {
+    let mut stmt = conn.prepare("SELECT ip, hostname, resolved_at FROM ptr WHERE ip = ?1")?;
+    let mut rows = stmt.query_map([ip], |row| {
+        Ok( Ptr {
+            ip: row.get(0)?,
+            hostname: row.get(1)?,
+            resolved_at: row.get(2)?,
+        })
+    })?;
+    rows.next().transpose()
+}
+
+pub fn insert_rdap(conn: &Connection, rdap: &Rdap) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO rdap (ip, cidr, name, handle, abuse_email, fetched_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![rdap.ip, rdap.cidr, rdap.name, rdap.handle, rdap.abuse_email, rdap.fetched_at],
+    )?;
+    Ok(())
+}
+
+fn rdap_from_row(row: &rusqlite::Row) -> Result<Rdap> {
+    Ok( Rdap {
+        ip: row.get(0)?,
+        cidr: row.get(1)?,
+        name: row.get(2)?,
+        handle: row.get(3)?,
+        abuse_email: row.get(4)?,
+        fetched_at: row.get(5)?,
+    })
+}
+
+pub fn select_rdap(conn: &Connection, ip: &str) -> Result<Option<Rdap>> {
+    let mut stmt = conn.prepare("SELECT ip, cidr, name, handle, abuse_email, fetched_at FROM rdap WHERE ip = ?1")?;
+    let mut rows = stmt.query_map([ip], rdap_from_row)?;
+    rows.next().transpose()
+}
+
+/// Most specific entry of another IP whose allocation contains `ip`, saves asking the registry again for every IP of a network.
+pub fn select_rdap_for_network(conn: &Connection, ip: &str) -> Result<Option<Rdap>> {
+    let mut stmt = conn.prepare("SELECT ip, cidr, name, handle, abuse_email, fetched_at FROM rdap WHERE cidr LIKE '%/%' ORDER BY fetched_at DESC")?;
+    let rows = stmt.query_map([], rdap_from_row)?;
+    let mut best: Option<(u8, Rdap)> = None;
+    for rdap in rows {
+        let rdap = rdap?;
+        let Some(network) = Cidr::parse(&rdap.cidr) else {continue};
+        if network.contains(ip) && best.as_ref().is_none_or(|(prefix, _)| network.prefix > *prefix) {
+            best = Some((network.prefix, rdap));
+        }
+    }
+    Ok(best.map(|(_, rdap)| rdap))
+}
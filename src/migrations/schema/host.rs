@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, Result};
+
+
+/// A host lines were logged on, the local one or a syslog sender.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Host {
+    pub name: String,
+    /// number of messages from this host
+    pub warnings: usize,
+    /// number of banned IPs seen by this host
+    pub banned: usize,
+}
+
+pub fn get_all_hosts(conn: &Connection) -> Result<Vec<Host>> {
+    let mut stmt = conn.prepare(
+        "SELECT messages.host, COUNT(messages.id), COUNT(DISTINCT CASE WHEN ipmeta.is_banned THEN messages.ip END)
+        FROM messages LEFT JOIN ipmeta ON ipmeta.ip = messages.ip
+        GROUP BY messages.host;"
+    )?;
+    let host_iter = stmt.query_map([], |row| {
+        Ok( Host {
+            name: row.get(0)?,
+            warnings: row.get(1)?,
+            banned: row.get(2)?,
+        })
+    })?;
+
+    let mut results: Vec<Host> = vec![];
+    for host in host_iter {
+        results.push(host?);
+    }
+    Ok(results)
+}
@@ -19,7 +19,27 @@ pub struct IP {
     pub banned_times: usize,
     pub is_banned: bool,
     pub warnings: usize,
+    /// when the location and ISP were last looked up
+    pub resolved_at: String,
+    /// autonomous system like AS64496, empty if unknown
+    pub asn: String,
 }
+
+/// A change of location or ISP found when an IP was looked up again.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct IPChange {
+    pub ip: String,
+    pub changed_at: String,
+    pub old_country: String,
+    pub old_region: String,
+    pub old_city: String,
+    pub old_isp: String,
+    pub new_country: String,
+    pub new_region: String,
+    pub new_city: String,
+    pub new_isp: String,
+}
+
 pub const CREATE_IP_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS ipmeta(
     ip TEXT NOT NULL PRIMARY KEY,
     created_at TEXT NOT NULL,
@@ -32,10 +52,37 @@ pub const CREATE_IP_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS ipmeta(
     countrycode TEXT,
     banned_times INTEGER NOT NULL,
     is_banned INTEGER NOT NULL,
-    warnings INTEGER NOT NULL
+    warnings INTEGER NOT NULL,
+    resolved_at TEXT NOT NULL DEFAULT '',
+    refresh_failed_at TEXT NOT NULL DEFAULT '',
+    asn TEXT NOT NULL DEFAULT ''
+)
+";
+pub const CREATE_IPHISTORY_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS iphistory(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ip TEXT NOT NULL REFERENCES ipmeta(ip),
+    changed_at TEXT NOT NULL,
+    old_country TEXT NOT NULL,
+    old_region TEXT NOT NULL,
+    old_city TEXT NOT NULL,
+    old_isp TEXT NOT NULL,
+    new_country TEXT NOT NULL,
+    new_region TEXT NOT NULL,
+    new_city TEXT NOT NULL,
+    new_isp TEXT NOT NULL
 )
 ";
 
+/// Adds resolved_at to databases created before it existed, their IPs count as resolved when first seen.
+pub fn migrate_ip_resolved_at(conn: &Connection) -> Result<()> {
+    let has_resolved_at: usize = conn.query_row("SELECT COUNT(*) FROM pragma_table_info('ipmeta') WHERE name = 'resolved_at'", [], |row| row.get(0))?;
+    if has_resolved_at == 0 {
+        conn.execute("ALTER TABLE ipmeta ADD COLUMN resolved_at TEXT NOT NULL DEFAULT ''", [])?;
+        conn.execute("UPDATE ipmeta SET resolved_at = created_at", [])?;
+    }
+    Ok(())
+}
+
 #[allow(non_snake_case)]
 pub fn insert_new_IP(conn: &Connection, 
     ip: &str, 
@@ -53,13 +100,112 @@ pub fn insert_new_IP(conn: &Connection,
 
     let _region = region.unwrap_or("");
     let _cc = countrycode.unwrap_or("");
+    // a known IP only gets its counters updated, location changes go through update_ip_location
     conn.execute(
-        "INSERT OR REPLACE INTO ipmeta (ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        "INSERT INTO ipmeta (ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings, resolved_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?2)
+        ON CONFLICT(ip) DO UPDATE SET banned_times = excluded.banned_times, is_banned = excluded.is_banned, warnings = excluded.warnings",
         (ip, created_at, lon, lat, isp, city, _region, country, _cc, num_banned, is_banned, num_warnings),
     )?;
     Ok(())
 }
 
+/// Adds refresh_failed_at to databases created before it existed.
+pub fn migrate_ip_refresh_failed_at(conn: &Connection) -> Result<()> {
+    let has_refresh_failed_at: usize = conn.query_row("SELECT COUNT(*) FROM pragma_table_info('ipmeta') WHERE name = 'refresh_failed_at'", [], |row| row.get(0))?;
+    if has_refresh_failed_at == 0 {
+        conn.execute("ALTER TABLE ipmeta ADD COLUMN refresh_failed_at TEXT NOT NULL DEFAULT ''", [])?;
+    }
+    Ok(())
+}
+
+/// Adds asn to databases created before it existed, their IPs get it when they are looked up again.
+pub fn migrate_ip_asn(conn: &Connection) -> Result<()> {
+    let has_asn: usize = conn.query_row("SELECT COUNT(*) FROM pragma_table_info('ipmeta') WHERE name = 'asn'", [], |row| row.get(0))?;
+    if has_asn == 0 {
+        conn.execute("ALTER TABLE ipmeta ADD COLUMN asn TEXT NOT NULL DEFAULT ''", [])?;
+    }
+    Ok(())
+}
+
+pub fn set_ip_asn(conn: &Connection, ip: &str, asn: &str) -> Result<()> {
+    conn.execute("UPDATE ipmeta SET asn = ?1 WHERE ip = ?2", params![asn, ip])?;
+    Ok(())
+}
+
+/// IPs whose location was last looked up before `resolved_before`, oldest first. IPs whose last lookup
+/// failed after `resolved_before` wait, so lookups that keep failing do not hold up the others.
+pub fn get_stale_ips(conn: &Connection, resolved_before: &str, limit: usize) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT ip FROM ipmeta WHERE resolved_at < ?1 AND refresh_failed_at < ?1 ORDER BY resolved_at LIMIT ?2")?;
+    let ip_iter = stmt.query_map(params![resolved_before, limit], |row| row.get(0))?;
+    ip_iter.collect()
+}
+
+/// Records a failed lookup of a stale IP, it is left out of get_stale_ips for a TTL.
+pub fn mark_refresh_failed(conn: &Connection, ip: &str, failed_at: &str) -> Result<()> {
+    conn.execute("UPDATE ipmeta SET refresh_failed_at = ?1 WHERE ip = ?2", params![failed_at, ip])?;
+    Ok(())
+}
+
+/// Stores a fresh lookup of a known IP. A changed Country, Region, City or ISP is recorded in iphistory and returned.
+pub fn update_ip_location(conn: &Connection, fresh: &IP, resolved_at: &str) -> Result<Option<IPChange>> {
+    let Some(old) = select_ip(conn, &fresh.ip)? else {return Ok(None)};
+    conn.execute(
+        "UPDATE ipmeta SET lon = ?1, lat = ?2, isp = ?3, city = ?4, region = ?5, country = ?6, countrycode = ?7, resolved_at = ?8, asn = ?10 WHERE ip = ?9",
+        params![fresh.lon, fresh.lat, fresh.isp, fresh.city, fresh.region, fresh.country, fresh.countrycode, resolved_at, fresh.ip, fresh.asn],
+    )?;
+    if (&old.country, &old.region, &old.city, &old.isp) == (&fresh.country, &fresh.region, &fresh.city, &fresh.isp) {
+        return Ok(None);
+    }
+    let change = IPChange {
+        ip: fresh.ip.clone(),
+        changed_at: resolved_at.to_string(),
+        old_country: old.country,
+        old_region: old.region,
+        old_city: old.city,
+        old_isp: old.isp,
+        new_country: fresh.country.clone(),
+        new_region: fresh.region.clone(),
+        new_city: fresh.city.clone(),
+        new_isp: fresh.isp.clone(),
+    };
+    conn.execute(
+        "INSERT INTO iphistory (ip, changed_at, old_country, old_region, old_city, old_isp, new_country, new_region, new_city, new_isp) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![change.ip, change.changed_at, change.old_country, change.old_region, change.old_city, change.old_isp, change.new_country, change.new_region, change.new_city, change.new_isp],
+    )?;
+    Ok(Some(change))
+}
+
+/// Location and ISP changes of an IP, oldest first.
+pub fn select_ip_history(conn: &Connection, ip: &str) -> Result<Vec<IPChange>> {
+    let mut stmt = conn.prepare(
+        "SELECT ip, changed_at, old_country, old_region, old_city, old_isp, new_country, new_region, new_city, new_isp FROM iphistory WHERE ip = ?1 ORDER BY id"
+    )?;
+    let change_iter = stmt.query_map([ip], |row| {
+        Ok( IPChange {
+            ip: row.get(0)?,
+            changed_at: row.get(1)?,
+            old_country: row.get(2)?,
+            old_region: row.get(3)?,
+            old_city: row.get(4)?,
+            old_isp: row.get(5)?,
+            new_country: row.get(6)?,
+            new_region: row.get(7)?,
+            new_city: row.get(8)?,
+            new_isp: row.get(9)?,
+        })
+    })?;
+    change_iter.collect()
+}
+
+/// marks ip as (un)banned, banned_times is counted from the bans table by statistics::refresh_banned_for_ip
+pub fn set_ip_banned(conn: &Connection, ip:&str, is_banned:bool) -> Result<()> {
+    conn.execute(
+        "UPDATE ipmeta SET is_banned = ?1 WHERE ip = ?2",
+        (is_banned, ip),
+    )?;
+    Ok(())
+}
+
 pub fn select_ip(conn: &Connection, ip:&str) -> Result<Option<IP>> {
     let mut stmt = conn.prepare(
         "SELECT * FROM ipmeta WHERE ip=:ip;"
@@ -78,6 +224,8 @@ pub fn select_ip(conn: &Connection, ip:&str) -> Result<Option<IP>> {
             banned_times: row.get(9)?,
             is_banned: row.get(10)?,
             warnings: row.get(11)?,
+            resolved_at: row.get(12)?,
+            asn: row.get(14)?,
         })
     })?;
 
@@ -21,6 +21,8 @@ pub struct Message {
     pub isp: String,
     pub is_jctl: bool,
     pub is_ban:bool,
+    /// host the line was logged on
+    pub host: String,
 }
 
 pub const CREATE_MESSAGE_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS messages(
@@ -33,21 +35,57 @@ pub const CREATE_MESSAGE_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS messages(
     city TEXT NOT NULL REFERENCES city(name),
     isp TEXT NOT NULL REFERENCES isp(name),
     is_jctl INTEGER NOT NULL,
-    is_ban INTEGER NOT NULL
+    is_ban INTEGER NOT NULL,
+    host TEXT NOT NULL DEFAULT '',
+    ip_num INTEGER
 )
 ";
+/// Indexes for the rules and queries run for every stored line, created after the migrations.
+/// Timestamps carry the offset of the host that logged them and are compared with julianday().
+pub const CREATE_MESSAGE_INDEXES_SQL: &str = "
+CREATE INDEX IF NOT EXISTS messages_ip ON messages(ip);
+CREATE INDEX IF NOT EXISTS messages_ip_num ON messages(ip_num);
+CREATE INDEX IF NOT EXISTS messages_created_at ON messages(julianday(created_at));
+CREATE INDEX IF NOT EXISTS messages_country ON messages(country);
+CREATE INDEX IF NOT EXISTS messages_isp ON messages(isp);
+";
 
-pub fn insert_new_message(conn: &Connection, id: Option<usize>, created_at:&str,  text:&str, ip:&str, country:&str, region:&str, city:&str, isp:&str, is_jctl:bool, is_ban:bool) -> Result<()> {
+/// Adds the host column to databases created before it existed, their lines are attributed to this host.
+pub fn migrate_message_host(conn: &Connection, local_host: &str) -> Result<()> {
+    let has_host: usize = conn.query_row("SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'host'", [], |row| row.get(0))?;
+    if has_host == 0 {
+        conn.execute("ALTER TABLE messages ADD COLUMN host TEXT NOT NULL DEFAULT ''", [])?;
+        conn.execute("UPDATE messages SET host = ?1", [local_host])?;
+    }
+    Ok(())
+}
+
+/// Adds ip_num, the IPv4 address as a number for network ranges, to databases created before it existed.
+pub fn migrate_message_ip_num(conn: &Connection) -> Result<()> {
+    let has_ip_num: usize = conn.query_row("SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'ip_num'", [], |row| row.get(0))?;
+    if has_ip_num == 0 {
+        conn.execute("ALTER TABLE messages ADD COLUMN ip_num INTEGER", [])?;
+        let mut stmt = conn.prepare("SELECT DISTINCT ip FROM messages")?;
+        let ips = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<Result<Vec<String>>>()?;
+        for ip in ips {
+            conn.execute("UPDATE messages SET ip_num = ?1 WHERE ip = ?2", (crate::cidr::ip_to_u32(&ip), &ip))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn insert_new_message(conn: &Connection, id: Option<usize>, created_at:&str,  text:&str, ip:&str, country:&str, region:&str, city:&str, isp:&str, is_jctl:bool, is_ban:bool, host:&str) -> Result<()> {
     let _id = id.unwrap_or(0);
+    let ip_num = crate::cidr::ip_to_u32(ip);
     if _id == 0 {
         conn.execute(
-            "INSERT OR REPLACE INTO messages (created_at, text, ip, country, region, city, isp, is_jctl, is_ban) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-            (created_at, text, ip, country, region, city, isp, is_jctl, is_ban),
+            "INSERT OR REPLACE INTO messages (created_at, text, ip, country, region, city, isp, is_jctl, is_ban, host, ip_num) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            (created_at, text, ip, country, region, city, isp, is_jctl, is_ban, host, ip_num),
         )?;
     } else {
         conn.execute(
-            "INSERT OR REPLACE INTO messages (id, created_at, text, ip, country, region, city, isp, is_jctl) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
-            (_id, created_at, text, ip, country, region, city, isp, is_jctl, is_ban),
+            "INSERT OR REPLACE INTO messages (id, created_at, text, ip, country, region, city, isp, is_jctl, is_ban, host, ip_num) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            (_id, created_at, text, ip, country, region, city, isp, is_jctl, is_ban, host, ip_num),
         )?;       
     }
 
@@ -70,6 +108,7 @@ pub fn select_message_by_ip(conn: &Connection, ip:&str) -> Result<Vec<Option<Mes
             isp: row.get(7)?,       
             is_jctl: row.get(8)?,
             is_ban: row.get(9)?,
+            host: row.get(10)?,
         })
     })?;
 
@@ -87,6 +126,94 @@ pub fn select_message_by_ip(conn: &Connection, ip:&str) -> Result<Vec<Option<Mes
     Ok(results)   
 }
 
+/// return messages matching a compiled query condition, newest first
+pub fn select_messages_where(conn: &Connection, condition:&str, params:&[String], limit:usize) -> Result<Vec<Message>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT messages.id, messages.created_at, messages.text, messages.ip, messages.country, messages.region, messages.city, messages.isp, messages.is_jctl, messages.is_ban, messages.host
+        FROM messages LEFT JOIN ipmeta ON ipmeta.ip = messages.ip WHERE {} ORDER BY messages.created_at DESC LIMIT {};", condition, limit)
+    )?;
+    let msg_iter = stmt.query_map(rusqlite::params_from_iter(params.iter()), |row| {
+        Ok( Message {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            text: row.get(2)?,
+            ip: row.get(3)?,
+            country: row.get(4)?,
+            region: row.get(5)?,
+            city: row.get(6)?,
+            isp: row.get(7)?,
+            is_jctl: row.get(8)?,
+            is_ban: row.get(9)?,
+            host: row.get(10)?,
+        })
+    })?;
+
+    let mut results: Vec<Message> = vec![];
+    for msg in msg_iter {
+        results.push(msg?);
+    }
+    Ok(results)
+}
+
+/// returns (messages, distinct ips, distinct countries) matching a compiled query condition
+pub fn count_messages_where(conn: &Connection, condition:&str, params:&[String]) -> Result<(usize, usize, usize)> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT COUNT(*), COUNT(DISTINCT messages.ip), COUNT(DISTINCT messages.country)
+        FROM messages LEFT JOIN ipmeta ON ipmeta.ip = messages.ip WHERE {};", condition)
+    )?;
+    stmt.query_row(rusqlite::params_from_iter(params.iter()), |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })
+}
+
+/// returns message timestamps for username
+pub fn get_message_timestamps_by_username(conn: &Connection, username:&str) -> Result<Vec<MiniMessage>> {
+    let mut stmt = conn.prepare(
+        "SELECT messages.created_at, messages.ip FROM messages JOIN username_message ON username_message.message_id = messages.id WHERE username_message.username=:username ORDER BY messages.ip, messages.created_at;"
+    )?;
+    let user_iter = stmt.query_map(&[(":username", username)], |row| {
+        Ok(MiniMessage { created_at: row.get(0)?, ip: row.get(1)? })
+    })?;
+
+    let mut results: Vec<MiniMessage> = vec![];
+    for msg in user_iter {
+        results.push(msg?);
+    }
+    Ok(results)
+}
+
+/// returns message timestamps for host
+pub fn get_message_timestamps_by_host(conn: &Connection, host:&str) -> Result<Vec<MiniMessage>> {
+    let mut stmt = conn.prepare(
+        "SELECT created_at, ip FROM messages WHERE host=:host ORDER BY ip, created_at;"
+    )?;
+    let host_iter = stmt.query_map(&[(":host", host)], |row| {
+        Ok(MiniMessage { created_at: row.get(0)?, ip: row.get(1)? })
+    })?;
+
+    let mut results: Vec<MiniMessage> = vec![];
+    for msg in host_iter {
+        results.push(msg?);
+    }
+    Ok(results)
+}
+
+/// returns message timestamps for provider
+pub fn get_message_timestamps_by_provider(conn: &Connection, provider:&str) -> Result<Vec<MiniMessage>> {
+    let mut stmt = conn.prepare(
+        "SELECT messages.created_at, messages.ip FROM messages JOIN ipcloud ON ipcloud.ip = messages.ip WHERE ipcloud.provider=:provider ORDER BY messages.ip, messages.created_at;"
+    )?;
+    let provider_iter = stmt.query_map(&[(":provider", provider)], |row| {
+        Ok(MiniMessage { created_at: row.get(0)?, ip: row.get(1)? })
+    })?;
+
+    let mut results: Vec<MiniMessage> = vec![];
+    for msg in provider_iter {
+        results.push(msg?);
+    }
+    Ok(results)
+}
+
 /// returns message timestamps for country
 pub fn get_message_timestamps_by_country(conn: &Connection, country:&str) -> Result<Vec<MiniMessage>> {
     let mut stmt = conn.prepare(
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, Result};
+
+
+/// A cloud or hosting provider, grouped from the provider range files instead of ip-api's ISP strings.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Provider {
+    pub name: String,
+    /// number of messages from IPs in this provider's ranges
+    pub warnings: usize,
+    /// number of banned IPs in this provider's ranges
+    pub banned: usize,
+    /// IPs in this provider's ranges get banned
+    pub is_blocked: bool,
+}
+
+/// Provider range an IP is in.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct CloudRange {
+    pub provider: String,
+    /// provider region like `eu-west-1`, empty if the range file has none
+    pub region: String,
+    /// service like `EC2`, empty if the range file has none
+    pub service: String,
+}
+
+impl std::fmt::Display for CloudRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<&str> = [self.provider.as_str(), self.region.as_str(), self.service.as_str()].into_iter().filter(|part| !part.is_empty()).collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+pub const CREATE_PROVIDER_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS provider(
+    name TEXT NOT NULL PRIMARY KEY,
+    is_blocked INTEGER NOT NULL
+)
+";
+pub const CREATE_IPCLOUD_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS ipcloud(
+    ip TEXT NOT NULL PRIMARY KEY,
+    provider TEXT NOT NULL REFERENCES provider(name),
+    region TEXT NOT NULL,
+    service TEXT NOT NULL
+)
+";
+
+const SELECT_PROVIDER_SQL: &str = "SELECT provider.name, COUNT(messages.id), COUNT(DISTINCT CASE WHEN ipmeta.is_banned THEN ipmeta.ip END), provider.is_blocked
+    FROM provider
+    LEFT JOIN ipcloud ON ipcloud.provider = provider.name
+    LEFT JOIN messages ON messages.ip = ipcloud.ip
+    LEFT JOIN ipmeta ON ipmeta.ip = ipcloud.ip";
+
+/// Records the provider range of an IP, creates the provider if it is new.
+pub fn insert_ip_cloud(conn: &Connection, ip: &str, range: &CloudRange) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO provider (name, is_blocked) VALUES (?1, 0)",
+        [&range.provider],
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO ipcloud (ip, provider, region, service) VALUES (?1, ?2, ?3, ?4)",
+        params![ip, range.provider, range.region, range.service],
+    )?;
+    Ok(())
+}
+
+pub fn select_ip_cloud(conn: &Connection, ip: &str) -> Result<Option<CloudRange>> {
+    let mut stmt = conn.prepare("SELECT provider, region, service FROM ipcloud WHERE ip = ?1")?;
+    let mut rows = stmt.query_map([ip], |row| {
+        Ok( CloudRange {
+            provider: row.get(0)?,
+            region: row.get(1)?,
+            service: row.get(2)?,
+        })
+    })?;
+    rows.next().transpose()
+}
+
+/// IPs in ipmeta that are not in any known provider range yet.
+pub fn get_ips_without_cloud(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT ip FROM ipmeta WHERE ip NOT IN (SELECT ip FROM ipcloud)")?;
+    let ip_iter = stmt.query_map([], |row| row.get(0))?;
+    ip_iter.collect()
+}
+
+pub fn set_provider_blocked(conn: &Connection, name: &str, is_blocked: bool) -> Result<()> {
+    conn.execute(
+        "INSERT INTO provider (name, is_blocked) VALUES (?1, ?2) ON CONFLICT(name) DO UPDATE SET is_blocked = excluded.is_blocked",
+        params![name, is_blocked],
+    )?;
+    Ok(())
+}
+
+/// Whether the provider is blocked, false for providers never blocked or unblocked.
+pub fn is_provider_blocked(conn: &Connection, name: &str) -> Result<bool> {
+    let mut stmt = conn.prepare("SELECT is_blocked FROM provider WHERE name = ?1")?;
+    let mut rows = stmt.query_map([name], |row| row.get(0))?;
+    Ok(rows.next().transpose()?.unwrap_or(false))
+}
+
+pub fn select_provider(conn: &Connection, name: &str) -> Result<Option<Provider>> {
+    let mut stmt = conn.prepare(
+        &format!("{} WHERE provider.name = :name GROUP BY provider.name;", SELECT_PROVIDER_SQL)
+    )?;
+    let mut rows = stmt.query_map(&[(":name", name)], |row| {
+        Ok( Provider {
+            name: row.get(0)?,
+            warnings: row.get(1)?,
+            banned: row.get(2)?,
+            is_blocked: row.get(3)?,
+        })
+    })?;
+    rows.next().transpose()
+}
+
+pub fn get_all_providers(conn: &Connection) -> Result<Vec<Provider>> {
+    let mut stmt = conn.prepare(
+        &format!("{} GROUP BY provider.name;", SELECT_PROVIDER_SQL)
+    )?;
+    let provider_iter = stmt.query_map([], |row| {
+        Ok( Provider {
+            name: row.get(0)?,
+            warnings: row.get(1)?,
+            banned: row.get(2)?,
+            is_blocked: row.get(3)?,
+        })
+    })?;
+
+    let mut results: Vec<Provider> = vec![];
+    for provider in provider_iter {
+        results.push(provider?);
+    }
+    Ok(results)
+}
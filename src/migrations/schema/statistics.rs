@@ -0,0 +1,72 @@
+//! Counters derived from the raw data, warnings are stored messages and banned counts come from the ban history.
+use rusqlite::{Connection, Result};
+
+use super::ip::{IPChange, IP};
+use crate::storage::savepoint;
+
+
+const RECOMPUTE_IP_WARNINGS_SQL: &str = "UPDATE ipmeta SET warnings = (SELECT COUNT(*) FROM messages WHERE messages.ip = ipmeta.ip)";
+const RECOMPUTE_IP_BANNED_SQL: &str = "UPDATE ipmeta SET banned_times = (SELECT COUNT(*) FROM bans WHERE bans.ip = ipmeta.ip)";
+
+/// `{table}` is the aggregate, also the column in messages, `{warnings}` its message counter.
+const RECOMPUTE_WARNINGS_SQL: &str = "UPDATE {table} SET {warnings} = (SELECT COUNT(*) FROM messages WHERE messages.{table} = {table}.name)";
+/// Banned counts are distinct banned IPs.
+const RECOMPUTE_BANNED_SQL: &str = "UPDATE {table} SET
+    banned = (SELECT COUNT(DISTINCT bans.ip) FROM bans JOIN ipmeta ON ipmeta.ip = bans.ip WHERE ipmeta.{table} = {table}.name)";
+
+/// Aggregate tables with their message counter, the ISP table calls it `messages`.
+const AGGREGATES: [(&str, &str); 4] = [("country", "warnings"), ("region", "warnings"), ("city", "warnings"), ("isp", "messages")];
+
+fn aggregate_sql(sql: &str, table: &str, warnings: &str) -> String {
+    sql.replace("{table}", table).replace("{warnings}", warnings)
+}
+
+/// Rebuilds the counters of every IP, Country, Region, City and ISP from the messages and the ban history.
+pub fn recompute_statistics(conn: &Connection) -> Result<()> {
+    // a savepoint, the storage service runs it inside its own transaction
+    savepoint(conn, || {
+        conn.execute(RECOMPUTE_IP_WARNINGS_SQL, [])?;
+        conn.execute(RECOMPUTE_IP_BANNED_SQL, [])?;
+        for (table, warnings) in AGGREGATES {
+            conn.execute(&aggregate_sql(RECOMPUTE_WARNINGS_SQL, table, warnings), [])?;
+            conn.execute(&aggregate_sql(RECOMPUTE_BANNED_SQL, table, warnings), [])?;
+        }
+        Ok(())
+    })
+}
+
+/// Updates the banned counters an IP contributes to, after one of its bans was recorded.
+pub fn refresh_banned_for_ip(conn: &Connection, ip: &str) -> Result<()> {
+    conn.execute(&format!("{} WHERE ip = ?1", RECOMPUTE_IP_BANNED_SQL), [ip])?;
+    for (table, warnings) in AGGREGATES {
+        conn.execute(
+            &format!("{} WHERE name = (SELECT {} FROM ipmeta WHERE ip = ?1)", aggregate_sql(RECOMPUTE_BANNED_SQL, table, warnings), table),
+            [ip],
+        )?;
+    }
+    Ok(())
+}
+
+/// Creates the Country, Region, City and ISP of a fresh location if they are new, run before pointing an IP at them.
+pub fn insert_location(conn: &Connection, fresh: &IP) -> Result<()> {
+    conn.execute("INSERT OR IGNORE INTO country (name, code, banned, warnings, is_blocked) VALUES (?1, ?2, 0, 0, 0)", (&fresh.country, &fresh.countrycode))?;
+    conn.execute("INSERT OR IGNORE INTO region (name, banned, warnings, country, is_blocked) VALUES (?1, 0, 0, ?2, 0)", (&fresh.region, &fresh.country))?;
+    conn.execute("INSERT OR IGNORE INTO city (name, banned, warnings, region, country, is_blocked) VALUES (?1, 0, 0, ?2, ?3, 0)", (&fresh.city, &fresh.region, &fresh.country))?;
+    conn.execute("INSERT OR IGNORE INTO isp (name, banned, messages, country, is_blocked) VALUES (?1, 0, 0, ?2, 0)", (&fresh.isp, &fresh.country))?;
+    Ok(())
+}
+
+/// Keeps the counters right after an IP moved. Its messages stay with the location they were logged from,
+/// so only the banned counts move from the old to the new Country, Region, City and ISP.
+pub fn move_ip_aggregates(conn: &Connection, change: &IPChange) -> Result<()> {
+    let names = [
+        (&change.old_country, &change.new_country),
+        (&change.old_region, &change.new_region),
+        (&change.old_city, &change.new_city),
+        (&change.old_isp, &change.new_isp),
+    ];
+    for ((table, warnings), (old, new)) in AGGREGATES.into_iter().zip(names) {
+        conn.execute(&format!("{} WHERE name IN (?1, ?2)", aggregate_sql(RECOMPUTE_BANNED_SQL, table, warnings)), [old, new])?;
+    }
+    Ok(())
+}
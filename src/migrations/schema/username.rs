@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, Result};
+
+
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Username {
+    pub name: String,
+    /// number of messages that tried this username
+    pub warnings: usize,
+    /// number of banned IPs that tried this username
+    pub banned: usize,
+    /// IPs trying a blocked username get banned
+    pub is_blocked: bool,
+}
+pub const CREATE_USERNAME_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS username(
+    name TEXT NOT NULL PRIMARY KEY,
+    is_blocked INTEGER NOT NULL
+)
+";
+pub const CREATE_USERNAME_MESSAGE_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS username_message(
+    message_id INTEGER NOT NULL REFERENCES messages(id),
+    username TEXT NOT NULL REFERENCES username(name),
+    PRIMARY KEY (message_id, username)
+)
+";
+pub const CREATE_USERNAME_MESSAGE_INDEX_SQL: &str = "CREATE INDEX IF NOT EXISTS username_message_username ON username_message(username)";
+
+const SELECT_USERNAME_SQL: &str = "SELECT username.name, COUNT(messages.id), COUNT(DISTINCT CASE WHEN ipmeta.is_banned THEN messages.ip END), username.is_blocked
+    FROM username
+    LEFT JOIN username_message ON username_message.username = username.name
+    LEFT JOIN messages ON messages.id = username_message.message_id
+    LEFT JOIN ipmeta ON ipmeta.ip = messages.ip";
+
+/// Extracts the username from sshd lines like "Invalid user X", "Failed password for X" and "Accepted password for X".
+pub fn extract_username(line: &str) -> Option<String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    for (idx, word) in words.iter().enumerate() {
+        match (*word, words.get(idx + 1)) {
+            ("user", Some(user)) if *user != "from" => return Some(user.to_string()),
+            ("for", Some(user)) if words.get(idx + 2) == Some(&"from") && *user != "invalid" => return Some(user.to_string()),
+            _ => {},
+        }
+    }
+    None
+}
+
+/// links a username to the message it was found in, creates the username if it is new
+pub fn insert_username_for_message(conn: &Connection, name: &str, message_id: i64) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO username (name, is_blocked) VALUES (?1, 0)",
+        [name],
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO username_message (message_id, username) VALUES (?1, ?2)",
+        params![message_id, name],
+    )?;
+    Ok(())
+}
+
+pub fn set_username_blocked(conn: &Connection, name: &str, is_blocked: bool) -> Result<()> {
+    conn.execute(
+        "INSERT INTO username (name, is_blocked) VALUES (?1, ?2) ON CONFLICT(name) DO UPDATE SET is_blocked = excluded.is_blocked",
+        params![name, is_blocked],
+    )?;
+    Ok(())
+}
+
+pub fn select_username(conn: &Connection, name:&str) -> Result<Option<Username>> {
+    let mut stmt = conn.prepare(
+        &format!("{} WHERE username.name = :name GROUP BY username.name;", SELECT_USERNAME_SQL)
+    )?;
+    let mut rows = stmt.query_map(&[(":name", name)], |row| {
+        Ok( Username {
+            name: row.get(0)?,
+            warnings: row.get(1)?,
+            banned: row.get(2)?,
+            is_blocked: row.get(3)?,
+        })
+    })?;
+    rows.next().transpose()
+}
+
+pub fn get_all_usernames(conn: &Connection) -> Result<Vec<Username>> {
+    let mut stmt = conn.prepare(
+        &format!("{} GROUP BY username.name;", SELECT_USERNAME_SQL)
+    )?;
+    let user_iter = stmt.query_map([], |row| {
+        Ok( Username {
+            name: row.get(0)?,
+            warnings: row.get(1)?,
+            banned: row.get(2)?,
+            is_blocked: row.get(3)?,
+        })
+    })?;
+
+    let mut results: Vec<Username> = vec![];
+    for user in user_iter {
+        results.push(user?);
+    }
+    Ok(results)
+}
+
+/// parses usernames out of all messages once, for databases that were filled before usernames were stored
+pub fn backfill_usernames(conn: &Connection) -> Result<usize> {
+    let linked: usize = conn.query_row("SELECT COUNT(*) FROM username_message", [], |row| row.get(0))?;
+    if linked > 0 {
+        return Ok(0);
+    }
+    let mut stmt = conn.prepare("SELECT id, text FROM messages;")?;
+    let msgs: Vec<(i64, String)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<Vec<(i64, String)>>>()?;
+
+    let mut inserted: usize = 0;
+    for (id, text) in msgs {
+        // fail2ban may deliver several lines at once
+        for line in text.split("++++") {
+            if let Some(user) = extract_username(line) {
+                insert_username_for_message(conn, &user, id)?;
+                inserted += 1;
+            }
+        }
+    }
+    Ok(inserted)
+}
@@ -0,0 +1,431 @@
+//! Notification sinks from config.json5.
+//! Bans, blocks, successful logins, rule hits and watcher failures are sent to every sink whose filters
+//! match, as JSON webhook, Slack/Mattermost message, mail over SMTP or JSON on the stdin of a command.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::query;
+
+/// Sinks give up on a delivery after this long.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Counting window of `rate_limit` if the sink sets none.
+const DEFAULT_RATE_WINDOW: Duration = Duration::from_secs(3600);
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+  /// an IP was banned, manually or by a rule
+  Ban,
+  /// an IP was banned because its location, ISP or username is blocked
+  Block,
+  /// successful login
+  Login,
+  /// a threshold rule fired
+  Rule,
+  /// a log watcher failed
+  Watcher,
+}
+
+impl std::fmt::Display for EventKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      EventKind::Ban => write!(f, "ban"),
+      EventKind::Block => write!(f, "block"),
+      EventKind::Login => write!(f, "login"),
+      EventKind::Rule => write!(f, "rule"),
+      EventKind::Watcher => write!(f, "watcher"),
+    }
+  }
+}
+
+/// What is sent to the sinks, also the JSON payload of webhooks and exec sinks.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NotifyEvent {
+  pub kind: EventKind,
+  pub at: String,
+  /// empty for events without an IP, e.g. watcher failures
+  pub ip: String,
+  pub message: String,
+}
+
+impl NotifyEvent {
+  pub fn now(kind: EventKind, ip: &str, message: String) -> Self {
+    Self { kind, at: chrono::offset::Local::now().to_rfc3339(), ip: ip.to_string(), message }
+  }
+
+  fn subject(&self) -> String {
+    if self.ip.is_empty() {
+      format!("[succeed2ban] {}", self.kind)
+    } else {
+      format!("[succeed2ban] {} {}", self.kind, self.ip)
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+  /// POSTs the event as JSON to `url`
+  #[default]
+  Webhook,
+  /// POSTs a Slack/Mattermost incoming webhook message to `url`
+  Slack,
+  /// mails `to` through the SMTP relay at `server`, without TLS or authentication
+  Smtp,
+  /// runs `command` with the event as JSON on stdin
+  Exec,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct Sink {
+  pub name: String,
+  #[serde(default)]
+  pub kind: SinkKind,
+  #[serde(default)]
+  pub url: Option<String>,
+  /// `host:port` of the SMTP relay
+  #[serde(default)]
+  pub server: Option<String>,
+  #[serde(default)]
+  pub from: Option<String>,
+  #[serde(default)]
+  pub to: Vec<String>,
+  /// program and arguments, not run through a shell
+  #[serde(default)]
+  pub command: Vec<String>,
+  /// event kinds sent to this sink, all if empty
+  #[serde(default)]
+  pub events: Vec<EventKind>,
+  /// regex the event's IP or message has to match
+  #[serde(default)]
+  pub filter: Option<String>,
+  /// at most this many notifications per `rate_window`, the rest is dropped
+  #[serde(default)]
+  pub rate_limit: Option<usize>,
+  /// window like `10m` for `rate_limit`, one hour if unset
+  #[serde(default)]
+  pub rate_window: Option<String>,
+}
+
+/// Checks every sink for errors, returns one message per problem.
+pub fn validate(sinks: &[Sink]) -> Vec<String> {
+  let mut errors: Vec<String> = vec![];
+  for sink in sinks {
+    match sink.kind {
+      SinkKind::Webhook | SinkKind::Slack => {
+        if sink.url.is_none() {errors.push(format!("Sink {}: url is missing", sink.name));}
+      },
+      SinkKind::Smtp => {
+        if sink.server.is_none() {errors.push(format!("Sink {}: server is missing", sink.name));}
+        if sink.from.is_none() {errors.push(format!("Sink {}: from is missing", sink.name));}
+        if sink.to.is_empty() {errors.push(format!("Sink {}: to is missing", sink.name));}
+      },
+      SinkKind::Exec => {
+        if sink.command.is_empty() {errors.push(format!("Sink {}: command is missing", sink.name));}
+      },
+    }
+    if let Some(filter) = &sink.filter {
+      if let Err(e) = Regex::new(filter) {errors.push(format!("Sink {}: filter: {}", sink.name, e));}
+    }
+    if let Some(window) = &sink.rate_window {
+      if let Err(e) = query::parse_duration(window) {errors.push(format!("Sink {}: rate_window: {}", sink.name, e));}
+    }
+  }
+  errors
+}
+
+/// A sink with its compiled filter.
+struct Route {
+  sink: Sink,
+  filter: Option<Regex>,
+  rate_window: Duration,
+}
+
+#[derive(Default)]
+pub struct Notifier {
+  routes: Vec<Route>,
+  /// delivery times per sink within its rate window
+  sent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Notifier {
+  /// Creates the notifier, sinks that do not validate are left out.
+  pub fn new(sinks: Vec<Sink>) -> Self {
+    let routes = sinks.into_iter()
+      .filter(|sink| validate(std::slice::from_ref(sink)).is_empty())
+      .map(|sink| Route {
+        filter: sink.filter.as_deref().and_then(|filter| Regex::new(filter).ok()),
+        rate_window: sink.rate_window.as_deref()
+          .and_then(|window| query::parse_duration(window).ok())
+          .and_then(|window| window.to_std().ok())
+          .unwrap_or(DEFAULT_RATE_WINDOW),
+        sink,
+      })
+      .collect();
+    Self { routes, sent: HashMap::new() }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.routes.is_empty()
+  }
+
+  /// Sinks the event has to be delivered to, counts the delivery against their rate limit.
+  pub fn route(&mut self, event: &NotifyEvent, now: Instant) -> Vec<Sink> {
+    let mut sinks: Vec<Sink> = vec![];
+    for route in self.routes.iter() {
+      if !route.sink.events.is_empty() && !route.sink.events.contains(&event.kind) {continue;}
+      if let Some(filter) = &route.filter {
+        if !filter.is_match(&event.ip) && !filter.is_match(&event.message) {continue;}
+      }
+      if let Some(limit) = route.sink.rate_limit {
+        let sent = self.sent.entry(route.sink.name.clone()).or_default();
+        while sent.front().is_some_and(|at| now.duration_since(*at) >= route.rate_window) {
+          sent.pop_front();
+        }
+        if sent.len() >= limit {
+          log::warn!("Sink {} rate limited, dropped: {}", route.sink.name, event.message);
+          continue;
+        }
+        sent.push_back(now);
+      }
+      sinks.push(route.sink.clone());
+    }
+    sinks
+  }
+}
+
+/// Sends the event to one sink.
+pub async fn deliver(sink: &Sink, event: &NotifyEvent) -> Result<(), String> {
+  match sink.kind {
+    SinkKind::Webhook => post_json(sink, &serde_json::to_value(event).map_err(|e| e.to_string())?).await,
+    SinkKind::Slack => {
+      let text = format!("*{}*\n{}", event.subject(), event.message);
+      post_json(sink, &serde_json::json!({ "text": text, "username": "succeed2ban" })).await
+    },
+    SinkKind::Smtp => tokio::time::timeout(DELIVERY_TIMEOUT, send_mail(sink, event)).await.map_err(|_| String::from("timed out"))?,
+    SinkKind::Exec => tokio::time::timeout(DELIVERY_TIMEOUT, run_command(sink, event)).await.map_err(|_| String::from("timed out"))?,
+  }
+}
+
+async fn post_json(sink: &Sink, payload: &serde_json::Value) -> Result<(), String> {
+  let url = sink.url.as_deref().ok_or("url is missing")?;
+  let resp = reqwest::Client::new()
+    .post(url)
+    .timeout(DELIVERY_TIMEOUT)
+    .json(payload)
+    .send()
+    .await
+    .map_err(|e| e.to_string())?;
+  if !resp.status().is_success() {
+    return Err(format!("{} answered {}", url, resp.status()));
+  }
+  Ok(())
+}
+
+/// Reads one, possibly multiline, SMTP reply and fails unless its code starts with `expected`.
+async fn smtp_reply(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>, expected: char) -> Result<(), String> {
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await.map_err(|e| e.to_string())? == 0 {
+      return Err(String::from("SMTP server closed the connection"));
+    }
+    if !line.starts_with(expected) {
+      return Err(format!("SMTP server answered {}", line.trim()));
+    }
+    // `250-` continues the reply, `250 ` ends it
+    if line.chars().nth(3) != Some('-') {
+      return Ok(());
+    }
+  }
+}
+
+async fn send_mail(sink: &Sink, event: &NotifyEvent) -> Result<(), String> {
+  let server = sink.server.as_deref().ok_or("server is missing")?;
+  let from = sink.from.as_deref().ok_or("from is missing")?;
+  let stream = TcpStream::connect(server).await.map_err(|e| e.to_string())?;
+  let (read, mut write) = stream.into_split();
+  let mut reader = BufReader::new(read);
+
+  let mut commands: Vec<(String, char)> = vec![
+    (String::from("EHLO succeed2ban\r\n"), '2'),
+    (format!("MAIL FROM:<{}>\r\n", from), '2'),
+  ];
+  for to in sink.to.iter() {
+    commands.push((format!("RCPT TO:<{}>\r\n", to), '2'));
+  }
+  commands.push((String::from("DATA\r\n"), '3'));
+
+  smtp_reply(&mut reader, '2').await?;
+  for (command, expected) in commands {
+    write.write_all(command.as_bytes()).await.map_err(|e| e.to_string())?;
+    smtp_reply(&mut reader, expected).await?;
+  }
+
+  // lines starting with a dot are escaped by doubling it
+  let body = format!("{}\n\n{}", event.message, event.at).lines()
+    .map(|line| if line.starts_with('.') {format!(".{}", line)} else {line.to_string()})
+    .collect::<Vec<String>>()
+    .join("\r\n");
+  let mail = format!(
+    "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n.\r\n",
+    from, sink.to.join(", "), event.subject(), chrono::offset::Local::now().to_rfc2822(), body,
+  );
+  write.write_all(mail.as_bytes()).await.map_err(|e| e.to_string())?;
+  smtp_reply(&mut reader, '2').await?;
+  write.write_all(b"QUIT\r\n").await.map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+async fn run_command(sink: &Sink, event: &NotifyEvent) -> Result<(), String> {
+  let (program, args) = sink.command.split_first().ok_or("command is missing")?;
+  let mut child = tokio::process::Command::new(program)
+    .args(args)
+    .stdin(std::process::Stdio::piped())
+    .stdout(std::process::Stdio::null())
+    .stderr(std::process::Stdio::piped())
+    .kill_on_drop(true)
+    .spawn()
+    .map_err(|e| format!("{}: {}", program, e))?;
+  let payload = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+  if let Some(mut stdin) = child.stdin.take() {
+    stdin.write_all(&payload).await.map_err(|e| e.to_string())?;
+  }
+  let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+  if !output.status.success() {
+    return Err(format!("{} exited with {}: {}", program, output.status, String::from_utf8_lossy(&output.stderr).trim()));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+  use tokio::io::AsyncReadExt;
+  use tokio::net::TcpListener;
+
+  use super::*;
+
+  fn event(kind: EventKind, ip: &str) -> NotifyEvent {
+    NotifyEvent { kind, at: String::from("2023-12-07T10:00:00+01:00"), ip: ip.to_string(), message: format!("Banned IP: {}", ip) }
+  }
+
+  #[test]
+  fn test_route() {
+    let sinks: Vec<Sink> = json5::from_str(r#"[
+      { name: "ops", kind: "slack", url: "http://127.0.0.1:1/hook", events: ["ban", "block"], rate_limit: 2, rate_window: "10m" },
+      { name: "mail", kind: "smtp", server: "127.0.0.1:25", from: "s2b@example.org", to: ["ops@example.org"], filter: "^192\\.0\\.2\\." },
+      { name: "broken", kind: "exec" },
+    ]"#).unwrap();
+    assert_eq!(validate(&sinks), vec![String::from("Sink broken: command is missing")]);
+
+    let mut notifier = Notifier::new(sinks);
+    let now = Instant::now();
+    let names = |sinks: Vec<Sink>| sinks.into_iter().map(|sink| sink.name).collect::<Vec<String>>();
+    assert_eq!(names(notifier.route(&event(EventKind::Ban, "192.0.2.1"), now)), vec!["ops", "mail"]);
+    assert_eq!(names(notifier.route(&event(EventKind::Login, "198.51.100.1"), now)), Vec::<String>::new());
+    assert_eq!(names(notifier.route(&event(EventKind::Block, "198.51.100.1"), now)), vec!["ops"]);
+    // ops is rate limited until the window passed
+    assert_eq!(names(notifier.route(&event(EventKind::Ban, "198.51.100.2"), now)), Vec::<String>::new());
+    assert_eq!(names(notifier.route(&event(EventKind::Ban, "198.51.100.2"), now + Duration::from_secs(600))), vec!["ops"]);
+  }
+
+  /// Accepts one HTTP request, answers 200 and returns the request body.
+  async fn http_stand_in(listener: TcpListener) -> String {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut request: Vec<u8> = vec![];
+    let mut buf = [0u8; 1024];
+    loop {
+      let n = stream.read(&mut buf).await.unwrap();
+      request.extend_from_slice(&buf[..n]);
+      let text = String::from_utf8_lossy(&request).to_string();
+      if let Some((head, body)) = text.split_once("\r\n\r\n") {
+        let length = head.lines()
+          .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|len| len.trim().parse::<usize>().unwrap()))
+          .unwrap_or(0);
+        if body.len() >= length {
+          stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+          return body.to_string();
+        }
+      }
+      if n == 0 {return String::new();}
+    }
+  }
+
+  /// Plays an SMTP relay for one mail and returns the mail data.
+  async fn smtp_stand_in(listener: TcpListener) -> String {
+    let (stream, _) = listener.accept().await.unwrap();
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    write.write_all(b"220 stand-in ESMTP\r\n").await.unwrap();
+    let mut data = String::new();
+    let mut in_data = false;
+    loop {
+      let mut line = String::new();
+      if reader.read_line(&mut line).await.unwrap() == 0 {return data;}
+      if in_data {
+        if line == ".\r\n" {
+          in_data = false;
+          write.write_all(b"250 queued\r\n").await.unwrap();
+        } else {
+          data.push_str(&line);
+        }
+      } else if line.starts_with("EHLO") {
+        write.write_all(b"250-stand-in\r\n250 8BITMIME\r\n").await.unwrap();
+      } else if line.starts_with("DATA") {
+        in_data = true;
+        write.write_all(b"354 go ahead\r\n").await.unwrap();
+      } else if line.starts_with("QUIT") {
+        write.write_all(b"221 bye\r\n").await.unwrap();
+        return data;
+      } else {
+        write.write_all(b"250 ok\r\n").await.unwrap();
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn test_deliver() {
+    let ban = event(EventKind::Ban, "192.0.2.1");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = tokio::spawn(http_stand_in(listener));
+    let webhook = Sink { name: String::from("hook"), kind: SinkKind::Webhook, url: Some(url), ..Sink::default() };
+    deliver(&webhook, &ban).await.unwrap();
+    let received: NotifyEvent = serde_json::from_str(&server.await.unwrap()).unwrap();
+    assert_eq!(received, ban);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hooks/abc", listener.local_addr().unwrap());
+    let server = tokio::spawn(http_stand_in(listener));
+    let slack = Sink { name: String::from("chat"), kind: SinkKind::Slack, url: Some(url), ..Sink::default() };
+    deliver(&slack, &ban).await.unwrap();
+    let received: serde_json::Value = serde_json::from_str(&server.await.unwrap()).unwrap();
+    assert_eq!(received["text"], "*[succeed2ban] ban 192.0.2.1*\nBanned IP: 192.0.2.1");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let smtp = Sink {
+      name: String::from("mail"),
+      kind: SinkKind::Smtp,
+      server: Some(listener.local_addr().unwrap().to_string()),
+      from: Some(String::from("s2b@example.org")),
+      to: vec![String::from("ops@example.org")],
+      ..Sink::default()
+    };
+    let server = tokio::spawn(smtp_stand_in(listener));
+    deliver(&smtp, &ban).await.unwrap();
+    let mail = server.await.unwrap();
+    assert!(mail.contains("Subject: [succeed2ban] ban 192.0.2.1\r\n"));
+    assert!(mail.contains("\r\n\r\nBanned IP: 192.0.2.1\r\n"));
+
+    let exec = Sink { name: String::from("script"), kind: SinkKind::Exec, command: vec![String::from("grep"), String::from("-q"), String::from("192.0.2.1")], ..Sink::default() };
+    deliver(&exec, &ban).await.unwrap();
+    assert!(deliver(&exec, &event(EventKind::Ban, "198.51.100.1")).await.is_err());
+  }
+}
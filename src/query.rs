@@ -0,0 +1,385 @@
+//! Query language for the Query popup.
+//!
+//! A query is a list of `key:value` terms combined with `AND` / `OR`, parentheses group terms.
+//! Adjacent terms without an operator are AND-ed, AND binds tighter than OR.
+//!
+//! ```text
+//! ip:1.2.3.4   cidr:1.2.3.0/24   country:China   isp:"Digital Ocean"   user:root   host:web1
+//! since:2023-12-01   until:2023-12-24   since:12h   banned:true
+//! country:CN AND (user:root OR user:admin) since:7d
+//! ```
+//! A bare IP is accepted as shorthand for `ip:`.
+
+use chrono::{Duration, Local, NaiveDate};
+use rusqlite::{functions::FunctionFlags, Connection};
+use serde::Serialize;
+
+use crate::cidr::{self, Cidr};
+use crate::migrations::schema::message::{self, Message};
+
+/// Maximum number of messages fetched for the result pane, counts are always complete.
+pub const QUERY_RESULT_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+  IP(String),
+  Cidr(Cidr),
+  Country(String),
+  ISP(String),
+  User(String),
+  /// host the line was logged on, the local one or a syslog sender
+  Host(String),
+  /// Lower bound for `created_at`, already resolved to a RFC3339 timestamp
+  Since(String),
+  /// Exclusive upper bound for `created_at`
+  Until(String),
+  Banned(bool),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+  Term(Term),
+  And(Box<Expr>, Box<Expr>),
+  Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+  Word(String),
+  And,
+  Or,
+  Open,
+  Close,
+}
+
+/// Result of a query as shown in Home's result pane.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QueryResult {
+  pub query: String,
+  pub messages: Vec<Message>,
+  /// Total number of matching messages, may be larger than `messages.len()`
+  pub total: usize,
+  /// Number of distinct IPs among all matches
+  pub num_ips: usize,
+  /// Number of distinct countries among all matches
+  pub num_countries: usize,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+  let mut tokens: Vec<Token> = vec![];
+  let mut current = String::new();
+  let mut in_quotes = false;
+
+  let push_word = |current: &mut String, tokens: &mut Vec<Token>| {
+    if current.is_empty() {
+      return;
+    }
+    let word = std::mem::take(current);
+    match word.to_uppercase().as_str() {
+      "AND" | "&&" => tokens.push(Token::And),
+      "OR" | "||" => tokens.push(Token::Or),
+      _ => tokens.push(Token::Word(word)),
+    }
+  };
+
+  for ch in input.chars() {
+    match ch {
+      '"' => in_quotes = !in_quotes,
+      c if in_quotes => current.push(c),
+      '(' => {
+        push_word(&mut current, &mut tokens);
+        tokens.push(Token::Open);
+      },
+      ')' => {
+        push_word(&mut current, &mut tokens);
+        tokens.push(Token::Close);
+      },
+      c if c.is_whitespace() => push_word(&mut current, &mut tokens),
+      c => current.push(c),
+    }
+  }
+  if in_quotes {
+    return Err(String::from("Unclosed quote"));
+  }
+  push_word(&mut current, &mut tokens);
+  Ok(tokens)
+}
+
+/// Parses `since:` / `until:` values, either a date, a RFC3339 timestamp or a relative duration like `30m`, `12h`, `7d`, `2w`.
+fn parse_time(value: &str, is_until: bool) -> Result<String, String> {
+  if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+    // until is inclusive for whole days, days start at local midnight
+    let date = if is_until { date.succ_opt().unwrap_or(date) } else { date };
+    let midnight = date.and_hms_opt(0, 0, 0).and_then(|midnight| midnight.and_local_timezone(Local).earliest());
+    return midnight.map(|midnight| midnight.to_rfc3339()).ok_or(format!("Invalid date: {value}"));
+  }
+  if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(value) {
+    return Ok(ts.to_rfc3339());
+  }
+  Ok((Local::now() - parse_duration(value)?).to_rfc3339())
+}
+
+/// Parses a duration like `30m`, `12h`, `7d` or `2w`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+  let (num, unit) = value.split_at(value.len().saturating_sub(1));
+  let num = num.parse::<i64>().map_err(|_| format!("Invalid time: {value}"))?;
+  match unit {
+    "m" => Ok(Duration::minutes(num)),
+    "h" => Ok(Duration::hours(num)),
+    "d" => Ok(Duration::days(num)),
+    "w" => Ok(Duration::weeks(num)),
+    _ => Err(format!("Invalid time unit: {value}")),
+  }
+}
+
+fn parse_term(word: &str) -> Result<Term, String> {
+  let Some((key, value)) = word.split_once(':') else {
+    // shorthand for a plain IP
+    if cidr::ip_to_u32(word).is_some() {
+      return Ok(Term::IP(word.to_string()));
+    }
+    return Err(format!("Unknown term: {word}"));
+  };
+  if value.is_empty() {
+    return Err(format!("Missing value for {key}:"));
+  }
+  match key.to_lowercase().as_str() {
+    "ip" => {
+      if cidr::ip_to_u32(value).is_none() {
+        return Err(format!("Invalid IP: {value}"));
+      }
+      Ok(Term::IP(value.to_string()))
+    },
+    "cidr" => Cidr::parse(value).map(Term::Cidr).ok_or(format!("Invalid CIDR: {value}")),
+    "country" => Ok(Term::Country(value.to_string())),
+    "isp" => Ok(Term::ISP(value.to_string())),
+    "user" => Ok(Term::User(value.to_string())),
+    "host" => Ok(Term::Host(value.to_string())),
+    "since" => Ok(Term::Since(parse_time(value, false)?)),
+    "until" => Ok(Term::Until(parse_time(value, true)?)),
+    "banned" => match value.to_lowercase().as_str() {
+      "true" | "yes" | "1" => Ok(Term::Banned(true)),
+      "false" | "no" | "0" => Ok(Term::Banned(false)),
+      _ => Err(format!("Invalid value for banned: {value}")),
+    },
+    _ => Err(format!("Unknown key: {key}")),
+  }
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  pos: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.pos)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.pos).cloned();
+    self.pos += 1;
+    token
+  }
+
+  // or_expr := and_expr (OR and_expr)*
+  fn parse_or(&mut self) -> Result<Expr, String> {
+    let mut lhs = self.parse_and()?;
+    while self.peek() == Some(&Token::Or) {
+      self.next();
+      let rhs = self.parse_and()?;
+      lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+    }
+    Ok(lhs)
+  }
+
+  // and_expr := primary ([AND] primary)*
+  fn parse_and(&mut self) -> Result<Expr, String> {
+    let mut lhs = self.parse_primary()?;
+    loop {
+      match self.peek() {
+        Some(Token::And) => {
+          self.next();
+        },
+        Some(Token::Word(_)) | Some(Token::Open) => {},
+        _ => break,
+      }
+      let rhs = self.parse_primary()?;
+      lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+    }
+    Ok(lhs)
+  }
+
+  fn parse_primary(&mut self) -> Result<Expr, String> {
+    match self.next() {
+      Some(Token::Word(word)) => Ok(Expr::Term(parse_term(&word)?)),
+      Some(Token::Open) => {
+        let expr = self.parse_or()?;
+        if self.next() != Some(Token::Close) {
+          return Err(String::from("Missing )"));
+        }
+        Ok(expr)
+      },
+      Some(Token::Close) => Err(String::from("Unexpected )")),
+      Some(Token::And) | Some(Token::Or) => Err(String::from("Operator without term")),
+      None => Err(String::from("Incomplete query")),
+    }
+  }
+}
+
+/// Parses a query string into an expression tree.
+pub fn parse(input: &str) -> Result<Expr, String> {
+  let tokens = tokenize(input)?;
+  if tokens.is_empty() {
+    return Err(String::from("Empty query"));
+  }
+  let mut parser = Parser { tokens, pos: 0 };
+  let expr = parser.parse_or()?;
+  if parser.pos < parser.tokens.len() {
+    return Err(String::from("Unexpected input after query"));
+  }
+  Ok(expr)
+}
+
+impl Term {
+  fn to_sql(&self, params: &mut Vec<String>) -> String {
+    match self {
+      Term::IP(ip) => {
+        params.push(ip.clone());
+        String::from("messages.ip = ?")
+      },
+      Term::Cidr(cidr) => {
+        params.push(cidr.network.to_string());
+        params.push(cidr.last().to_string());
+        String::from("messages.ip_num BETWEEN CAST(? AS INTEGER) AND CAST(? AS INTEGER)")
+      },
+      Term::Country(country) => {
+        params.push(country.clone());
+        params.push(country.clone());
+        String::from("(messages.country = ? COLLATE NOCASE OR ipmeta.countrycode = ? COLLATE NOCASE)")
+      },
+      Term::ISP(isp) => {
+        params.push(format!("%{isp}%"));
+        String::from("messages.isp LIKE ?")
+      },
+      Term::User(user) => {
+        params.push(user.clone());
+        String::from("messages.id IN (SELECT message_id FROM username_message WHERE username = ?)")
+      },
+      Term::Host(host) => {
+        params.push(host.clone());
+        String::from("messages.host = ? COLLATE NOCASE")
+      },
+      // timestamps are stored with the offset of the host that logged them, compared as instants
+      Term::Since(ts) => {
+        params.push(ts.clone());
+        String::from("julianday(messages.created_at) >= julianday(?)")
+      },
+      Term::Until(ts) => {
+        params.push(ts.clone());
+        String::from("julianday(messages.created_at) < julianday(?)")
+      },
+      Term::Banned(true) => String::from("COALESCE(ipmeta.is_banned, 0) = 1"),
+      Term::Banned(false) => String::from("COALESCE(ipmeta.is_banned, 0) = 0"),
+    }
+  }
+}
+
+impl Expr {
+  /// Compiles the expression into a WHERE condition over `messages` LEFT JOIN `ipmeta`, pushing bound values onto `params`.
+  pub fn to_sql(&self, params: &mut Vec<String>) -> String {
+    match self {
+      Expr::Term(term) => term.to_sql(params),
+      Expr::And(lhs, rhs) => format!("({} AND {})", lhs.to_sql(params), rhs.to_sql(params)),
+      Expr::Or(lhs, rhs) => format!("({} OR {})", lhs.to_sql(params), rhs.to_sql(params)),
+    }
+  }
+}
+
+/// Registers the SQL functions the compiled queries rely on, has to be called once per connection.
+pub fn register_sql_functions(conn: &Connection) -> rusqlite::Result<()> {
+  conn.create_scalar_function("ip_in_cidr", 2, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+    let ip: String = ctx.get(0)?;
+    let cidr: String = ctx.get(1)?;
+    Ok(cidr::ip_in_cidr(&ip, &cidr))
+  })
+}
+
+/// Parses, compiles and runs a query.
+pub fn run_query(conn: &Connection, input: &str, limit: usize) -> Result<QueryResult, String> {
+  let expr = parse(input)?;
+  let mut params: Vec<String> = vec![];
+  let condition = expr.to_sql(&mut params);
+
+  let (total, num_ips, num_countries) = message::count_messages_where(conn, &condition, &params).map_err(|e| e.to_string())?;
+  let messages = message::select_messages_where(conn, &condition, &params, limit).map_err(|e| e.to_string())?;
+
+  Ok(QueryResult { query: input.to_string(), messages, total, num_ips, num_countries })
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+
+  use super::*;
+
+  #[test]
+  fn test_parse_bare_ip() {
+    assert_eq!(parse("1.2.3.4").unwrap(), Expr::Term(Term::IP("1.2.3.4".to_string())));
+  }
+
+  #[test]
+  fn test_parse_implicit_and() {
+    let expr = parse("country:CN user:root").unwrap();
+    assert_eq!(
+      expr,
+      Expr::And(Box::new(Expr::Term(Term::Country("CN".to_string()))), Box::new(Expr::Term(Term::User("root".to_string()))))
+    );
+  }
+
+  #[test]
+  fn test_parse_precedence() {
+    // AND binds tighter than OR
+    let expr = parse("ip:1.1.1.1 OR ip:2.2.2.2 AND banned:true").unwrap();
+    assert_eq!(
+      expr,
+      Expr::Or(
+        Box::new(Expr::Term(Term::IP("1.1.1.1".to_string()))),
+        Box::new(Expr::And(Box::new(Expr::Term(Term::IP("2.2.2.2".to_string()))), Box::new(Expr::Term(Term::Banned(true)))))
+      )
+    );
+  }
+
+  #[test]
+  fn test_parse_parentheses_and_quotes() {
+    let expr = parse("(isp:\"Digital Ocean\" or cidr:10.0.0.0/8) and banned:false").unwrap();
+    let mut params = vec![];
+    let sql = expr.to_sql(&mut params);
+    assert_eq!(sql, "((messages.isp LIKE ? OR messages.ip_num BETWEEN CAST(? AS INTEGER) AND CAST(? AS INTEGER)) AND COALESCE(ipmeta.is_banned, 0) = 0)");
+    assert_eq!(params, vec!["%Digital Ocean%".to_string(), "167772160".to_string(), "184549375".to_string()]);
+  }
+
+  fn local_midnight(year: i32, month: u32, day: u32) -> String {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(0, 0, 0).unwrap().and_local_timezone(Local).unwrap().to_rfc3339()
+  }
+
+  #[test]
+  fn test_parse_dates() {
+    assert_eq!(parse("since:2023-12-01").unwrap(), Expr::Term(Term::Since(local_midnight(2023, 12, 1))));
+    // until includes the whole day
+    assert_eq!(parse("until:2023-12-31").unwrap(), Expr::Term(Term::Until(local_midnight(2024, 1, 1))));
+    assert!(parse("since:12h").is_ok());
+    assert!(parse("since:12x").is_err());
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    assert!(parse("").is_err());
+    assert!(parse("foo:bar").is_err());
+    assert!(parse("ip:1.2.3").is_err());
+    assert!(parse("cidr:1.2.3.4/40").is_err());
+    assert!(parse("(country:DE").is_err());
+    assert!(parse("country:DE OR").is_err());
+    assert!(parse("isp:\"unclosed").is_err());
+  }
+}
@@ -0,0 +1,302 @@
+//! Threshold rules from config.json5.
+//! Every stored event is checked against the rules, a rule fires once the number of matching events
+//! for the same IP, /24, country, ISP, autonomous system or username reaches its threshold within the window.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, FixedOffset};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Deserialize;
+
+use crate::{cidr::Cidr, query};
+
+/// What events are counted together.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+  #[default]
+  IP,
+  Subnet24,
+  Country,
+  ISP,
+  /// autonomous system of the IP as ip-api reports it, events of IPs without one are not grouped
+  ASN,
+  User,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+  /// bans the IP, or the whole /24 for rules grouped by subnet24
+  Ban,
+  /// raises a login alert banner
+  Alert,
+  /// only writes to the internal log
+  #[default]
+  Log,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct Rule {
+  pub name: String,
+  /// query language expression an event has to match, e.g. `user:root`
+  #[serde(default)]
+  pub filter: Option<String>,
+  #[serde(default)]
+  pub group_by: GroupBy,
+  #[serde(default = "default_threshold")]
+  pub threshold: usize,
+  /// counting window like `10m`, all stored events are counted if unset
+  #[serde(default)]
+  pub window: Option<String>,
+  /// only fires if the event's value for this field was never seen before, e.g. `asn` for a new network
+  #[serde(default)]
+  pub first_seen: Option<GroupBy>,
+  #[serde(default)]
+  pub action: RuleAction,
+  /// ban duration like `1h`, the jail's bantime applies if unset
+  #[serde(default)]
+  pub bantime: Option<String>,
+}
+
+fn default_threshold() -> usize {
+  1
+}
+
+/// A fired rule.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleHit {
+  pub rule: String,
+  pub action: RuleAction,
+  /// IP or network the action applies to
+  pub target: String,
+  /// grouped value that reached the threshold
+  pub key: String,
+  pub count: usize,
+  pub message_id: i64,
+  pub at: String,
+  pub bantime: Option<Duration>,
+}
+
+impl std::fmt::Display for RuleHit {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Rule {} fired: {} events for {} -> {:?} {}", self.rule, self.count, self.key, self.action, self.target)
+  }
+}
+
+/// The stored event a rule is evaluated for.
+struct Event {
+  id: i64,
+  created_at: String,
+  ip: String,
+  country: String,
+  isp: String,
+  asn: String,
+  username: Option<String>,
+}
+
+impl Event {
+  fn load(conn: &Connection, message_id: i64) -> rusqlite::Result<Option<Event>> {
+    let event = conn.query_row(
+      "SELECT messages.id, messages.created_at, messages.ip, messages.country, messages.isp, COALESCE(ipmeta.asn, '')
+      FROM messages LEFT JOIN ipmeta ON ipmeta.ip = messages.ip WHERE messages.id = ?1",
+      [message_id],
+      |row| Ok(Event { id: row.get(0)?, created_at: row.get(1)?, ip: row.get(2)?, country: row.get(3)?, isp: row.get(4)?, asn: row.get(5)?, username: None }),
+    ).optional()?;
+    let Some(mut event) = event else {return Ok(None)};
+    event.username = conn.query_row(
+      "SELECT username FROM username_message WHERE message_id = ?1",
+      [message_id],
+      |row| row.get(0),
+    ).optional()?;
+    Ok(Some(event))
+  }
+
+  /// Value of the event for the given grouping, None if the event has none, e.g. no username.
+  fn value(&self, group_by: GroupBy) -> Option<String> {
+    match group_by {
+      GroupBy::IP => Some(self.ip.clone()),
+      GroupBy::Subnet24 => Cidr::parse(&format!("{}/24", self.ip)).map(|cidr| cidr.to_string()),
+      GroupBy::Country => Some(self.country.clone()),
+      GroupBy::ISP => Some(self.isp.clone()),
+      GroupBy::ASN => Some(self.asn.clone()).filter(|asn| !asn.is_empty()),
+      GroupBy::User => self.username.clone(),
+    }
+  }
+}
+
+/// WHERE condition selecting all messages with the same value for the grouping, pushing bound values onto `params`.
+fn group_condition(group_by: GroupBy, value: &str, params: &mut Vec<String>) -> &'static str {
+  match group_by {
+    GroupBy::IP => {
+      params.push(value.to_string());
+      "messages.ip = ?"
+    },
+    GroupBy::Subnet24 => {
+      // a range over the indexed ip_num, values come from Event::value and always parse
+      let network = Cidr::parse(value).unwrap_or_default();
+      params.push(network.network.to_string());
+      params.push(network.last().to_string());
+      "messages.ip_num BETWEEN CAST(? AS INTEGER) AND CAST(? AS INTEGER)"
+    },
+    GroupBy::Country => {
+      params.push(value.to_string());
+      "messages.country = ?"
+    },
+    GroupBy::ISP => {
+      params.push(value.to_string());
+      "messages.isp = ?"
+    },
+    GroupBy::ASN => {
+      params.push(value.to_string());
+      "ipmeta.asn = ?"
+    },
+    GroupBy::User => {
+      params.push(value.to_string());
+      "messages.id IN (SELECT message_id FROM username_message WHERE username = ?)"
+    },
+  }
+}
+
+fn count_where(conn: &Connection, condition: &str, params: &[String]) -> rusqlite::Result<usize> {
+  conn.query_row(
+    &format!("SELECT COUNT(*) FROM messages LEFT JOIN ipmeta ON ipmeta.ip = messages.ip WHERE {}", condition),
+    rusqlite::params_from_iter(params.iter()),
+    |row| row.get(0),
+  )
+}
+
+/// Checks every rule for errors, returns one message per broken rule.
+pub fn validate(rules: &[Rule]) -> Vec<String> {
+  let mut errors: Vec<String> = vec![];
+  for rule in rules {
+    if let Some(filter) = &rule.filter {
+      if let Err(e) = query::parse(filter) {errors.push(format!("Rule {}: filter: {}", rule.name, e));}
+    }
+    if let Some(window) = &rule.window {
+      if let Err(e) = query::parse_duration(window) {errors.push(format!("Rule {}: window: {}", rule.name, e));}
+    }
+    if let Some(bantime) = &rule.bantime {
+      if let Err(e) = query::parse_duration(bantime) {errors.push(format!("Rule {}: bantime: {}", rule.name, e));}
+    }
+  }
+  errors
+}
+
+#[derive(Default)]
+pub struct RulesEngine {
+  rules: Vec<Rule>,
+  /// last time a rule fired per grouped value, so a rule fires once per window and not on every further event
+  fired: HashMap<(String, String), DateTime<FixedOffset>>,
+}
+
+impl RulesEngine {
+  /// Creates the engine, rules that do not validate are left out.
+  pub fn new(rules: Vec<Rule>) -> Self {
+    let rules = rules.into_iter().filter(|rule| validate(std::slice::from_ref(rule)).is_empty()).collect();
+    Self { rules, fired: HashMap::new() }
+  }
+
+  pub fn rules(&self) -> &[Rule] {
+    &self.rules
+  }
+
+  /// Evaluates all rules for a stored message, only events up to this message are counted.
+  pub fn process(&mut self, conn: &Connection, message_id: i64) -> rusqlite::Result<Vec<RuleHit>> {
+    let Some(event) = Event::load(conn, message_id)? else {return Ok(vec![])};
+    let Ok(at) = DateTime::parse_from_rfc3339(&event.created_at) else {return Ok(vec![])};
+
+    let mut hits: Vec<RuleHit> = vec![];
+    for rule in self.rules.iter() {
+      let mut condition = String::from("messages.id <= ?");
+      let mut params: Vec<String> = vec![event.id.to_string()];
+
+      if let Some(filter) = &rule.filter {
+        let Ok(expr) = query::parse(filter) else {continue};
+        let filter_sql = expr.to_sql(&mut params);
+        condition = format!("{} AND {}", condition, filter_sql);
+        // the event itself has to match
+        let mut own_params = params.clone();
+        own_params.push(event.id.to_string());
+        if count_where(conn, &format!("{} AND messages.id = ?", condition), &own_params)? == 0 {continue;}
+      }
+
+      if let Some(kind) = rule.first_seen {
+        let Some(value) = event.value(kind) else {continue};
+        let mut seen_params = vec![event.id.to_string()];
+        let seen = count_where(conn, &format!("messages.id < ? AND {}", group_condition(kind, &value, &mut seen_params)), &seen_params)?;
+        if seen > 0 {continue;}
+      }
+
+      let Some(key) = event.value(rule.group_by) else {continue};
+      condition = format!("{} AND {}", condition, group_condition(rule.group_by, &key, &mut params));
+
+      let window = rule.window.as_deref().and_then(|w| query::parse_duration(w).ok());
+      if let Some(window) = window {
+        // timestamps of other hosts may have another offset
+        condition = format!("{} AND julianday(messages.created_at) >= julianday(?)", condition);
+        params.push((at - window).to_rfc3339());
+      }
+
+      let count = count_where(conn, &condition, &params)?;
+      if count < rule.threshold {continue;}
+
+      let fired_key = (rule.name.clone(), key.clone());
+      if let Some(last) = self.fired.get(&fired_key) {
+        if window.is_none_or(|window| at - *last < window) {continue;}
+      }
+      self.fired.insert(fired_key, at);
+
+      hits.push(RuleHit {
+        rule: rule.name.clone(),
+        action: rule.action,
+        target: if rule.group_by == GroupBy::Subnet24 {key.clone()} else {event.ip.clone()},
+        key,
+        count,
+        message_id: event.id,
+        at: event.created_at.clone(),
+        bantime: rule.bantime.as_deref().and_then(|b| query::parse_duration(b).ok()),
+      });
+    }
+    Ok(hits)
+  }
+}
+
+/// Replays all stored messages through the rules without applying anything, returns what would have fired.
+pub fn replay(conn: &Connection, rules: Vec<Rule>) -> rusqlite::Result<Vec<RuleHit>> {
+  let mut engine = RulesEngine::new(rules);
+  let mut stmt = conn.prepare("SELECT id FROM messages ORDER BY julianday(created_at), id")?;
+  let ids: Vec<i64> = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<Vec<i64>>>()?;
+
+  let mut hits: Vec<RuleHit> = vec![];
+  for id in ids {
+    hits.extend(engine.process(conn, id)?);
+  }
+  Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+
+  use super::*;
+
+  #[test]
+  fn test_rule_config() {
+    let rules: Vec<Rule> = json5::from_str(r#"[
+      { name: "subnet-burst", group_by: "subnet24", threshold: 5, window: "10m", action: "ban", bantime: "1h" },
+      { name: "root-new-asn", filter: "user:root", first_seen: "asn", action: "ban" },
+      { name: "country-flood", group_by: "country", threshold: 50, window: "1h", action: "alert" },
+    ]"#).unwrap();
+    assert_eq!(rules[0].group_by, GroupBy::Subnet24);
+    assert_eq!(rules[0].action, RuleAction::Ban);
+    assert_eq!(rules[1].threshold, 1);
+    assert_eq!(rules[1].first_seen, Some(GroupBy::ASN));
+    assert_eq!(rules[2].action, RuleAction::Alert);
+    assert!(validate(&rules).is_empty());
+
+    let broken = vec![Rule { name: String::from("broken"), window: Some(String::from("10x")), ..Rule::default() }];
+    assert_eq!(validate(&broken).len(), 1);
+    assert!(RulesEngine::new(broken).rules().is_empty());
+  }
+}
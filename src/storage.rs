@@ -0,0 +1,147 @@
+//! Storage service, the one owner of the DB connection. It runs on its own thread and works through the jobs
+//! components queue, answers go back as Actions or to the caller of `request`, so `Component::update` never waits
+//! on SQLite. Jobs queued meanwhile run together in one transaction, a burst of log lines costs one commit.
+
+use std::{path::Path, sync::mpsc, time::Duration};
+
+use log::error;
+use rusqlite::{Connection, Result};
+use tokio::sync::oneshot;
+
+/// Most jobs run in one transaction, the rest wait for the next one.
+pub const BATCH_SIZE: usize = 256;
+/// How long a statement waits for a lock held by another process, like the CLI subcommands.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Job = Box<dyn FnOnce(&Connection) -> Result<()> + Send>;
+
+/// Queue of the storage thread, cheap to clone into tasks.
+#[derive(Clone)]
+pub struct Storage {
+  queue: mpsc::Sender<Job>,
+}
+
+impl Storage {
+  /// Opens the DB at `path` on a new storage thread, `setup` runs on the connection before the first job.
+  pub fn open(path: &Path, setup: impl FnOnce(&Connection) -> Result<()> + Send + 'static) -> std::io::Result<Self> {
+    let (queue, rx) = mpsc::channel::<Job>();
+    let path = path.to_path_buf();
+    std::thread::Builder::new().name(String::from("storage")).spawn(move || {
+      let conn = match Connection::open(&path) {
+        Ok(conn) => conn,
+        Err(e) => {error!("Opening {} failed: {}", path.display(), e); return;},
+      };
+      // readers of other processes do not block the commit of a batch
+      if let Err(e) = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0)).and_then(|_| conn.busy_timeout(BUSY_TIMEOUT)) {
+        error!("Setting up {} failed: {}", path.display(), e);
+      }
+      if let Err(e) = setup(&conn) {
+        error!("Setting up {} failed: {}", path.display(), e);
+      }
+      serve(&conn, rx);
+    })?;
+    Ok(Self { queue })
+  }
+
+  /// Queues a job, it runs after every job queued before it. A failing job is rolled back and logged.
+  pub fn run(&self, job: impl FnOnce(&Connection) -> Result<()> + Send + 'static) {
+    if self.queue.send(Box::new(job)).is_err() {
+      error!("Storage stopped, job dropped");
+    }
+  }
+
+  /// Queues a job and waits for its answer without blocking the thread. Meant for reads, a job that fails
+  /// hands its error to the caller and keeps what it wrote.
+  pub async fn request<T: Send + 'static>(&self, job: impl FnOnce(&Connection) -> Result<T> + Send + 'static) -> Result<T, String> {
+    let (answer, rx) = oneshot::channel();
+    self.run(move |conn| {
+      answer.send(job(conn)).unwrap_or_default();
+      Ok(())
+    });
+    rx.await.map_err(|_| String::from("storage stopped"))?.map_err(|e| e.to_string())
+  }
+}
+
+/// Runs `f` inside a savepoint, rolled back if it fails. Works inside and outside of a transaction.
+pub fn savepoint<T>(conn: &Connection, f: impl FnOnce() -> Result<T>) -> Result<T> {
+  conn.execute_batch("SAVEPOINT job")?;
+  match f() {
+    Ok(value) => {
+      conn.execute_batch("RELEASE job")?;
+      Ok(value)
+    },
+    Err(e) => {
+      conn.execute_batch("ROLLBACK TO job; RELEASE job")?;
+      Err(e)
+    },
+  }
+}
+
+fn serve(conn: &Connection, rx: mpsc::Receiver<Job>) {
+  while let Ok(job) = rx.recv() {
+    let mut batch = vec![job];
+    while batch.len() < BATCH_SIZE {
+      let Ok(job) = rx.try_recv() else {break};
+      batch.push(job);
+    }
+    if let Err(e) = conn.execute_batch("BEGIN") {
+      error!("Starting a transaction failed, running {} jobs without: {}", batch.len(), e);
+    }
+    for job in batch {
+      if let Err(e) = savepoint(conn, || job(conn)) {
+        error!("Storage job failed: {}", e);
+      }
+    }
+    if !conn.is_autocommit() {
+      if let Err(e) = conn.execute_batch("COMMIT") {
+        error!("Committing failed: {}", e);
+        conn.execute_batch("ROLLBACK").unwrap_or_default();
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn test_jobs_in_order() {
+    let storage = Storage::open(Path::new(":memory:"), |conn| conn.execute_batch("CREATE TABLE lines (line TEXT NOT NULL)")).unwrap();
+    for idx in 0..BATCH_SIZE + 10 {
+      storage.run(move |conn| conn.execute("INSERT INTO lines (line) VALUES (?1)", [format!("line {}", idx)]).map(|_| ()));
+    }
+    // fails halfway, the first insert is rolled back with it
+    storage.run(|conn| {
+      conn.execute("INSERT INTO lines (line) VALUES ('half')", [])?;
+      conn.execute("INSERT INTO missing (line) VALUES ('never')", [])?;
+      Ok(())
+    });
+    let counted = storage.request(|conn| conn.query_row("SELECT COUNT(*), MAX(rowid) FROM lines WHERE line != 'half'", [], |row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?)))).await;
+    assert_eq!(counted, Ok((BATCH_SIZE + 10, BATCH_SIZE + 10)));
+    let last: Result<String, String> = storage.request(|conn| conn.query_row("SELECT line FROM lines ORDER BY rowid DESC LIMIT 1", [], |row| row.get(0))).await;
+    assert_eq!(last, Ok(format!("line {}", BATCH_SIZE + 9)));
+    let failed = storage.request(|conn| conn.query_row("SELECT line FROM missing", [], |row| row.get::<_, String>(0))).await;
+    assert_eq!(failed, Err(String::from("no such table: missing")));
+  }
+
+  #[test]
+  fn test_savepoint() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch("CREATE TABLE lines (line TEXT NOT NULL)").unwrap();
+    let failed = savepoint(&conn, || {
+      conn.execute("INSERT INTO lines (line) VALUES ('rolled back')", [])?;
+      conn.query_row("SELECT line FROM missing", [], |row| row.get::<_, String>(0))
+    });
+    assert!(failed.is_err());
+    savepoint(&conn, || conn.execute("INSERT INTO lines (line) VALUES ('kept')", [])).unwrap();
+    // nested in an open transaction
+    conn.execute_batch("BEGIN").unwrap();
+    savepoint(&conn, || conn.execute("INSERT INTO lines (line) VALUES ('in transaction')", [])).unwrap();
+    conn.execute_batch("COMMIT").unwrap();
+    let lines: Vec<String> = conn.prepare("SELECT line FROM lines").unwrap().query_map([], |row| row.get(0)).unwrap().collect::<Result<_>>().unwrap();
+    assert_eq!(lines, vec![String::from("kept"), String::from("in transaction")]);
+  }
+}
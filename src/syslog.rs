@@ -0,0 +1,200 @@
+//! Syslog receiver format, RFC3164 and RFC5424 messages over UDP or TCP.
+//! TCP frames are either newline-terminated or octet-counted (RFC6587), a frame starting with a digit is octet-counted.
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use crate::cidr::Cidr;
+
+/// Longest octet-counted frame accepted, larger lengths are treated as a broken stream.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SyslogMessage {
+  /// sender's hostname, the peer address if the message has none
+  pub hostname: String,
+  /// program like `sshd`, empty if the message has no tag
+  pub app_name: String,
+  /// the line as journalctl would print it, `<timestamp> <host> <tag>: <msg>`
+  pub line: String,
+}
+
+fn nil(value: &str) -> Option<&str> {
+  if value == "-" || value.is_empty() {None} else {Some(value)}
+}
+
+/// Splits off the structured data of an RFC5424 message, it is `-` or a series of `[...]` elements.
+fn skip_structured_data(rest: &str) -> &str {
+  if let Some(msg) = rest.strip_prefix('-') {
+    return msg.trim_start_matches(' ');
+  }
+  let mut in_element = false;
+  let mut escaped = false;
+  for (idx, c) in rest.char_indices() {
+    match c {
+      _ if escaped => escaped = false,
+      '\\' => escaped = true,
+      '[' if !in_element => in_element = true,
+      ']' if in_element => in_element = false,
+      ' ' if !in_element => return &rest[idx + 1..],
+      _ => {},
+    }
+  }
+  ""
+}
+
+fn parse_rfc5424(rest: &str, peer: &str) -> Option<SyslogMessage> {
+  // TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+  let mut fields = rest.splitn(6, ' ');
+  let timestamp = fields.next()?;
+  let hostname = nil(fields.next()?).unwrap_or(peer);
+  let app_name = nil(fields.next()?).unwrap_or("");
+  let procid = nil(fields.next()?);
+  let _msgid = fields.next()?;
+  let msg = skip_structured_data(fields.next().unwrap_or(""));
+  let msg = msg.trim_start_matches('\u{feff}').trim_end();
+  let tag = match procid {
+    Some(procid) => format!("{}[{}]", app_name, procid),
+    None => app_name.to_string(),
+  };
+  Some(SyslogMessage {
+    hostname: hostname.to_string(),
+    app_name: app_name.to_string(),
+    line: format!("{} {} {}: {}", nil(timestamp).unwrap_or(""), hostname, tag, msg).trim_start().to_string(),
+  })
+}
+
+fn parse_rfc3164(rest: &str, peer: &str) -> SyslogMessage {
+  // Mmm dd hh:mm:ss HOSTNAME TAG: MSG, senders on the same host often leave out the hostname
+  // byte offsets, a timestamp split inside a multibyte character is no timestamp
+  let has_timestamp = rest.len() > 16 && rest.as_bytes()[3] == b' ' && rest.as_bytes()[6] == b' ' && rest.as_bytes()[9] == b':';
+  let (timestamp, rest) = match (rest.get(..15), rest.get(16..)) {
+    (Some(timestamp), Some(tail)) if has_timestamp => (timestamp, tail),
+    _ => ("", rest),
+  };
+  let (hostname, rest) = match rest.split_once(' ') {
+    Some((first, tail)) if !first.ends_with(':') && tail.contains(':') => (first, tail),
+    _ => (peer, rest),
+  };
+  let (tag, msg) = rest.split_once(": ").unwrap_or(("", rest));
+  let app_name = tag.split('[').next().unwrap_or("");
+  SyslogMessage {
+    hostname: hostname.to_string(),
+    app_name: app_name.to_string(),
+    line: format!("{} {} {}: {}", timestamp, hostname, tag, msg.trim_end()).trim_start().to_string(),
+  }
+}
+
+/// Parses one syslog message, `peer` is the sender's address. Returns None if there is no `<PRI>`.
+pub fn parse(payload: &str, peer: &str) -> Option<SyslogMessage> {
+  let payload = payload.trim_end_matches(['\r', '\n', '\0']);
+  let rest = payload.strip_prefix('<')?;
+  let (pri, rest) = rest.split_once('>')?;
+  if pri.is_empty() || pri.len() > 3 || !pri.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+  match rest.strip_prefix("1 ") {
+    Some(rest) => parse_rfc5424(rest, peer),
+    None => Some(parse_rfc3164(rest, peer)),
+  }
+}
+
+/// Reads the next frame of a TCP stream, None at the end of the stream.
+pub async fn read_frame<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<Option<String>> {
+  loop {
+    let buf = reader.fill_buf().await?;
+    let Some(first) = buf.first() else {return Ok(None)};
+    if first.is_ascii_digit() {
+      let mut len = Vec::new();
+      reader.read_until(b' ', &mut len).await?;
+      let len: usize = String::from_utf8_lossy(&len).trim().parse()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid octet count"))?;
+      if len > MAX_FRAME_LEN {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "octet count too large"));
+      }
+      let mut frame = vec![0u8; len];
+      reader.read_exact(&mut frame).await?;
+      return Ok(Some(String::from_utf8_lossy(&frame).to_string()));
+    }
+    let mut frame = Vec::new();
+    reader.read_until(b'\n', &mut frame).await?;
+    let frame = String::from_utf8_lossy(&frame).trim_end_matches(['\r', '\n']).to_string();
+    // blank lines between frames
+    if !frame.is_empty() {
+      return Ok(Some(frame));
+    }
+  }
+}
+
+/// Networks of `allowed_senders`, also returns one message per entry that is no IPv4 address or CIDR.
+pub fn parse_senders(entries: &[String]) -> (Vec<Cidr>, Vec<String>) {
+  let mut errors: Vec<String> = vec![];
+  let senders = entries.iter().filter_map(|entry| {
+    let sender = Cidr::parse(entry);
+    if sender.is_none() {errors.push(format!("syslog.allowed_senders: {} is no IPv4 address or CIDR", entry));}
+    sender
+  }).collect();
+  (senders, errors)
+}
+
+/// Whether messages from `peer` are accepted, IPv4 peers of a dual-stack socket arrive IPv4-mapped.
+pub fn is_allowed(senders: &[Cidr], peer: std::net::IpAddr) -> bool {
+  let peer = peer.to_canonical().to_string();
+  senders.iter().any(|sender| sender.contains(&peer))
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    let msg = parse("<38>Dec  8 10:00:00 web1 sshd[812]: Invalid user admin from 192.0.2.1 port 22", "10.0.0.5").unwrap();
+    assert_eq!(msg.hostname, "web1");
+    assert_eq!(msg.app_name, "sshd");
+    assert_eq!(msg.line, "Dec  8 10:00:00 web1 sshd[812]: Invalid user admin from 192.0.2.1 port 22");
+
+    // no hostname, the peer stands in
+    let msg = parse("<38>Dec  8 10:00:00 sshd[812]: Connection closed by 192.0.2.1", "10.0.0.5").unwrap();
+    assert_eq!(msg.hostname, "10.0.0.5");
+    assert_eq!(msg.app_name, "sshd");
+
+    let msg = parse("<38>1 2023-12-08T10:00:00+01:00 web2 sshd 812 - [origin ip=\"10.0.0.6\"][x a=\"]\"] \u{feff}Failed password for root from 192.0.2.1 port 22 ssh2\n", "10.0.0.6").unwrap();
+    assert_eq!(msg.hostname, "web2");
+    assert_eq!(msg.line, "2023-12-08T10:00:00+01:00 web2 sshd[812]: Failed password for root from 192.0.2.1 port 22 ssh2");
+
+    let msg = parse("<38>1 - - fail2ban - - - NOTICE [sshd] Ban 192.0.2.1", "10.0.0.7").unwrap();
+    assert_eq!(msg.hostname, "10.0.0.7");
+    assert_eq!(msg.app_name, "fail2ban");
+    assert!(parse("no priority", "10.0.0.5").is_none());
+
+    // non-ASCII where the timestamp ends must not panic
+    let msg = parse("<38>Dec  8 10:00:0é web1 sshd[1]: x", "10.0.0.5").unwrap();
+    assert_eq!(msg.line, "Dec  8 10:00:0é web1 sshd[1]: x");
+    assert!(parse("<38>Dec  8 10:00:00é web1 sshd[1]: x", "10.0.0.5").is_some());
+  }
+
+  #[test]
+  fn test_allowed_senders() {
+    let (senders, errors) = parse_senders(&[String::from("10.0.0.0/24"), String::from("192.0.2.9"), String::from("web1")]);
+    assert_eq!(senders.len(), 2);
+    assert_eq!(errors, vec![String::from("syslog.allowed_senders: web1 is no IPv4 address or CIDR")]);
+    assert!(is_allowed(&senders, "10.0.0.5".parse().unwrap()));
+    assert!(is_allowed(&senders, "::ffff:192.0.2.9".parse().unwrap()));
+    assert!(!is_allowed(&senders, "192.0.2.10".parse().unwrap()));
+    assert!(!is_allowed(&[], "10.0.0.5".parse().unwrap()));
+  }
+
+  #[tokio::test]
+  async fn test_read_frame() {
+    let first = "<38>Dec  8 10:00:00 web1 sshd[1]: one\nwith newline";
+    let stream = format!("{} {}<38>Dec  8 10:00:01 web1 sshd[1]: two\r\n\n<38>Dec  8 10:00:02 web1 sshd[1]: three", first.len(), first);
+    let mut reader = tokio::io::BufReader::new(stream.as_bytes());
+    assert_eq!(read_frame(&mut reader).await.unwrap(), Some(first.to_string()));
+    assert_eq!(read_frame(&mut reader).await.unwrap(), Some(String::from("<38>Dec  8 10:00:01 web1 sshd[1]: two")));
+    assert_eq!(read_frame(&mut reader).await.unwrap(), Some(String::from("<38>Dec  8 10:00:02 web1 sshd[1]: three")));
+    assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+  }
+}
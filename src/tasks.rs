@@ -9,7 +9,8 @@ use tokio::{
 
 use tokio_util::sync::CancellationToken;
 
-use crate::action::Action;
+use crate::{action::Action, cidr::Cidr};
+use crate::notifications::{EventKind, NotifyEvent};
 
 use tokio::io::AsyncSeekExt;
 use tokio::io::AsyncReadExt;
@@ -92,6 +93,7 @@ pub async fn monitor_journalctl(event_tx:UnboundedSender<Action>, mut cancel_rx:
 
     let argus = vec!["-n", "1", "-f", "-u", "ssh"];
     let mut command = Command::new("journalctl");
+    let failure_tx = event_tx.clone();
 
     let _joinhandle = tokio::spawn(async move {
   
@@ -136,7 +138,9 @@ pub async fn monitor_journalctl(event_tx:UnboundedSender<Action>, mut cancel_rx:
 
           }
       } else {
-        println!("Process failed to start");
+        let failure = String::from("journalctl watcher failed to start");
+        failure_tx.send(Action::InternalLog(format!(" ❌ {}", failure))).unwrap_or_default();
+        failure_tx.send(Action::Notify(NotifyEvent::now(EventKind::Watcher, "", failure))).unwrap_or_default();
       }
 
   
@@ -166,4 +170,43 @@ pub async fn monitor_journalctl(event_tx:UnboundedSender<Action>, mut cancel_rx:
        tick_interval.tick().await;
     }
 
-}
\ No newline at end of file
+}
+/// Forwards a received syslog message if it comes from an allowed sender and one of the watched programs.
+fn forward_syslog(payload: &str, peer: std::net::IpAddr, senders: &[Cidr], programs: &[String], event_tx: &UnboundedSender<Action>) {
+  if !crate::syslog::is_allowed(senders, peer) {return;}
+  let Some(msg) = crate::syslog::parse(payload, &peer.to_canonical().to_string()) else {return};
+  if programs.iter().any(|program| msg.app_name.starts_with(program.as_str())) {
+    event_tx.send(Action::SyslogLine(msg)).unwrap_or_default();
+  }
+}
+
+/// Receives syslog over UDP and TCP on the same address until the UDP socket fails. Only `senders` are listened to.
+pub async fn listen_syslog(listen: String, programs: Vec<String>, senders: Vec<Cidr>, event_tx: UnboundedSender<Action>) -> std::io::Result<()> {
+  let udp = tokio::net::UdpSocket::bind(&listen).await?;
+  let tcp = tokio::net::TcpListener::bind(&listen).await?;
+
+  let tcp_tx = event_tx.clone();
+  let tcp_programs = programs.clone();
+  let tcp_senders = senders.clone();
+  tokio::spawn(async move {
+    while let Ok((stream, peer)) = tcp.accept().await {
+      // closed right away
+      if !crate::syslog::is_allowed(&tcp_senders, peer.ip()) {continue;}
+      let tx = tcp_tx.clone();
+      let programs = tcp_programs.clone();
+      let senders = tcp_senders.clone();
+      tokio::spawn(async move {
+        let mut reader = tokio::io::BufReader::new(stream);
+        while let Ok(Some(frame)) = crate::syslog::read_frame(&mut reader).await {
+          forward_syslog(&frame, peer.ip(), &senders, &programs, &tx);
+        }
+      });
+    }
+  });
+
+  let mut buf = vec![0u8; 64 * 1024];
+  loop {
+    let (len, peer) = udp.recv_from(&mut buf).await?;
+    forward_syslog(&String::from_utf8_lossy(&buf[..len]), peer.ip(), &senders, &programs, &event_tx);
+  }
+}
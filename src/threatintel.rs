@@ -0,0 +1,261 @@
+//! Threat-intel blocklists from local files, configured with `blocklists` in config.json5.
+//! Plain IP/CIDR lists, FireHOL netsets, Spamhaus DROP/EDROP and Tor exit lists are loaded into one prefix trie,
+//! incoming IPs are tagged with the names of the lists they are on. Lists with a `url` are updated by
+//! `--refresh-blocklists` or the RefreshBlocklists action.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::cidr::{self, Cidr, PrefixTrie};
+
+/// Lists are kept as bits of a u64 per network.
+pub const MAX_LISTS: usize = 64;
+/// Downloads give up after this long.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+  /// one IP or CIDR per line, `#` starts a comment
+  #[default]
+  Plain,
+  /// FireHOL `.netset`, like plain
+  Netset,
+  /// Spamhaus DROP/EDROP, `CIDR ; SBL id` per line or the JSON lines of `drop_v4.json`
+  Spamhaus,
+  /// Tor exit list, either one IP per line or the `ExitAddress` records of `exit-addresses`
+  Tor,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct Blocklist {
+  /// tag matching IPs get
+  pub name: String,
+  pub path: PathBuf,
+  #[serde(default)]
+  pub format: ListFormat,
+  /// where the refresh downloads the list from, the file is only read if unset
+  #[serde(default)]
+  pub url: Option<String>,
+  /// ban IPs on this list like IPs of a blocked Country
+  #[serde(default)]
+  pub auto_ban: bool,
+}
+
+/// Returns the address part of one line, None for comments, blank lines and records without an address.
+fn entry(format: ListFormat, line: &str) -> Option<String> {
+  let line = line.split('#').next().unwrap_or("").trim();
+  let entry = match format {
+    ListFormat::Plain | ListFormat::Netset => line,
+    ListFormat::Spamhaus if line.starts_with('{') => {
+      // metadata records have no cidr
+      let value: serde_json::Value = serde_json::from_str(line).ok()?;
+      return value["cidr"].as_str().map(String::from);
+    },
+    ListFormat::Spamhaus => line.split(';').next().unwrap_or(""),
+    ListFormat::Tor => match line.split_once(' ') {
+      Some(("ExitAddress", rest)) => rest,
+      // ExitNode, Published and LastStatus records
+      Some(_) => return None,
+      None => line,
+    },
+  };
+  entry.split_whitespace().next().map(String::from)
+}
+
+/// Returns the network of one line, None for comments, blank lines and entries that are not IPv4.
+pub fn parse_line(format: ListFormat, line: &str) -> Option<Cidr> {
+  Cidr::parse(&entry(format, line)?)
+}
+
+/// Parses a whole list, also returns the number of entries that were skipped as not IPv4.
+pub fn parse_list(format: ListFormat, content: &str) -> (Vec<Cidr>, usize) {
+  let mut networks: Vec<Cidr> = vec![];
+  let mut skipped = 0;
+  for entry in content.lines().filter_map(|line| entry(format, line)) {
+    match Cidr::parse(&entry) {
+      Some(network) => networks.push(network),
+      None => skipped += 1,
+    }
+  }
+  (networks, skipped)
+}
+
+/// All loaded blocklists.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct ThreatIntel {
+  lists: Vec<Blocklist>,
+  /// number of networks loaded per list
+  entries: Vec<usize>,
+  /// bits of the lists per network
+  trie: PrefixTrie<u64>,
+}
+
+impl ThreatIntel {
+  /// Reads every list file, returns one message per problem. Lists that cannot be read stay empty.
+  pub fn load(lists: &[Blocklist]) -> (Self, Vec<String>) {
+    let mut errors: Vec<String> = vec![];
+    let mut intel = ThreatIntel::default();
+    if lists.len() > MAX_LISTS {
+      errors.push(format!("Blocklists: only the first {} lists are used", MAX_LISTS));
+    }
+    for (idx, list) in lists.iter().take(MAX_LISTS).enumerate() {
+      let content = match std::fs::read_to_string(&list.path) {
+        Ok(content) => content,
+        Err(e) => {
+          errors.push(format!("Blocklist {}: {}: {}", list.name, list.path.display(), e));
+          String::new()
+        },
+      };
+      let (networks, _) = parse_list(list.format, &content);
+      for network in networks.iter() {
+        *intel.trie.entry(*network, || 0) |= 1 << idx;
+      }
+      intel.entries.push(networks.len());
+      intel.lists.push(list.clone());
+    }
+    (intel, errors)
+  }
+
+  fn matching(&self, ip: &str) -> impl Iterator<Item = &Blocklist> {
+    let bits = cidr::ip_to_u32(ip).map(|ip| self.trie.matches(ip).into_iter().fold(0, |bits, lists| bits | lists)).unwrap_or(0);
+    self.lists.iter().enumerate().filter(move |(idx, _)| bits & (1 << idx) != 0).map(|(_, list)| list)
+  }
+
+  /// Names of the lists the IP is on.
+  pub fn tags(&self, ip: &str) -> Vec<String> {
+    self.matching(ip).map(|list| list.name.clone()).collect()
+  }
+
+  /// Names of the lists the IP is on whose entries are banned.
+  pub fn auto_ban(&self, ip: &str) -> Vec<String> {
+    self.matching(ip).filter(|list| list.auto_ban).map(|list| list.name.clone()).collect()
+  }
+
+  /// Name and number of networks of every list.
+  pub fn summary(&self) -> Vec<(String, usize)> {
+    self.lists.iter().zip(self.entries.iter()).map(|(list, entries)| (list.name.clone(), *entries)).collect()
+  }
+}
+
+// shown and serialized as its summary, the trie may have millions of nodes
+impl std::fmt::Debug for ThreatIntel {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_tuple("ThreatIntel").field(&self.summary()).finish()
+  }
+}
+
+impl Serialize for ThreatIntel {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.summary().serialize(serializer)
+  }
+}
+
+async fn download(list: &Blocklist, url: &str) -> Result<String, String> {
+  let response = reqwest::Client::new().get(url).timeout(DOWNLOAD_TIMEOUT).send().await.map_err(|e| e.to_string())?;
+  if !response.status().is_success() {
+    return Err(format!("{} answered {}", url, response.status()));
+  }
+  let content = response.text().await.map_err(|e| e.to_string())?;
+  let (networks, _) = parse_list(list.format, &content);
+  // an error page must not replace a working list
+  if networks.is_empty() {
+    return Err(format!("{} has no entries", url));
+  }
+  if let Some(dir) = list.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+  }
+  let tmp = list.path.with_extension("download");
+  std::fs::write(&tmp, &content).map_err(|e| e.to_string())?;
+  std::fs::rename(&tmp, &list.path).map_err(|e| e.to_string())?;
+  Ok(format!("Blocklist {}: {} entries from {}", list.name, networks.len(), url))
+}
+
+/// Downloads every list with a `url` to its path, returns one message per list.
+pub async fn refresh(lists: &[Blocklist]) -> Vec<Result<String, String>> {
+  let mut results = vec![];
+  for list in lists.iter() {
+    let Some(url) = &list.url else {continue};
+    results.push(download(list, url).await.map_err(|e| format!("Blocklist {}: {}", list.name, e)));
+  }
+  results
+}
+
+#[cfg(test)]
+mod tests {
+  use pretty_assertions::assert_eq;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    let (networks, skipped) = parse_list(ListFormat::Netset, "#\n# FireHOL level1\n#\n192.0.2.0/24\n198.51.100.7\n2001:db8::/32\n");
+    assert_eq!(networks, vec![Cidr::parse("192.0.2.0/24").unwrap(), Cidr::parse("198.51.100.7").unwrap()]);
+    assert_eq!(skipped, 1);
+
+    let drop = "; Spamhaus DROP List 2023/12/08\n203.0.113.0/24 ; SBL123456\n{\"cidr\":\"198.18.0.0/15\",\"sblid\":\"SBL1\",\"rir\":\"arin\"}\n{\"type\":\"metadata\",\"records\":1}\n";
+    let (networks, _) = parse_list(ListFormat::Spamhaus, drop);
+    assert_eq!(networks, vec![Cidr::parse("203.0.113.0/24").unwrap(), Cidr::parse("198.18.0.0/15").unwrap()]);
+
+    let exits = "ExitNode 0011BD2485AD45D984EC4159C88FC066E5E3300E\nPublished 2023-12-08 09:00:00\nLastStatus 2023-12-08 10:00:00\nExitAddress 192.0.2.44 2023-12-08 10:01:00\n";
+    let (networks, skipped) = parse_list(ListFormat::Tor, exits);
+    assert_eq!(networks, vec![Cidr::parse("192.0.2.44").unwrap()]);
+    assert_eq!(skipped, 0);
+    assert_eq!(parse_line(ListFormat::Tor, "192.0.2.45"), Cidr::parse("192.0.2.45"));
+  }
+
+  #[test]
+  fn test_lookup() {
+    let dir = std::env::temp_dir().join(format!("succeed2ban-blocklists-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("level1.netset"), "10.0.0.0/8\n192.0.2.0/24\n").unwrap();
+    std::fs::write(dir.join("tor.txt"), "192.0.2.44\n").unwrap();
+    let lists = vec![
+      Blocklist { name: String::from("firehol"), path: dir.join("level1.netset"), format: ListFormat::Netset, ..Blocklist::default() },
+      Blocklist { name: String::from("tor"), path: dir.join("tor.txt"), format: ListFormat::Tor, auto_ban: true, ..Blocklist::default() },
+      Blocklist { name: String::from("missing"), path: dir.join("missing.txt"), ..Blocklist::default() },
+    ];
+    let (intel, errors) = ThreatIntel::load(&lists);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(intel.tags("192.0.2.44"), vec![String::from("firehol"), String::from("tor")]);
+    assert_eq!(intel.tags("10.200.1.1"), vec![String::from("firehol")]);
+    assert!(intel.tags("192.0.3.1").is_empty());
+    assert!(intel.tags("not an ip").is_empty());
+    assert_eq!(intel.auto_ban("192.0.2.44"), vec![String::from("tor")]);
+    assert!(intel.auto_ban("192.0.2.43").is_empty());
+    assert_eq!(intel.summary(), vec![(String::from("firehol"), 2), (String::from("tor"), 1), (String::from("missing"), 0)]);
+  }
+
+  #[tokio::test]
+  async fn test_refresh() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/drop.txt", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+      for body in ["203.0.113.0/24 ; SBL1\n", "<html>maintenance</html>"] {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).await.unwrap();
+        stream.write_all(format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).as_bytes()).await.unwrap();
+      }
+    });
+    let path = std::env::temp_dir().join(format!("succeed2ban-drop-{}.txt", std::process::id()));
+    let lists = vec![
+      Blocklist { name: String::from("drop"), path: path.clone(), format: ListFormat::Spamhaus, url: Some(url), ..Blocklist::default() },
+      Blocklist { name: String::from("local"), path: PathBuf::from("/nonexistent"), ..Blocklist::default() },
+    ];
+    let results = refresh(&lists).await;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_ok());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "203.0.113.0/24 ; SBL1\n");
+
+    // the broken download keeps the previous file
+    assert!(refresh(&lists).await[0].is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "203.0.113.0/24 ; SBL1\n");
+    std::fs::remove_file(&path).unwrap();
+  }
+}
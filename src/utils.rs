@@ -141,6 +141,23 @@ macro_rules! trace_dbg {
     };
 }
 
+/// Name of this host, lines from the local log file and journal are stored with it.
+pub fn local_hostname() -> String {
+  std::fs::read_to_string("/proc/sys/kernel/hostname")
+    .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+    .map(|name| name.trim().to_string())
+    .ok()
+    .filter(|name| !name.is_empty())
+    .unwrap_or(String::from("localhost"))
+}
+
+/// Name of the local user running succeed2ban, recorded as the actor of bans and audit entries.
+pub fn local_user() -> String {
+  ["SUDO_USER", "USER", "LOGNAME"].iter()
+    .find_map(|var| std::env::var(var).ok().filter(|name| !name.is_empty()))
+    .unwrap_or(String::from("unknown"))
+}
+
 pub fn version() -> String {
   let author = clap::crate_authors!();
 